@@ -0,0 +1,70 @@
+//! Searches with hostile queries, directories and exclude globs, which should find the text they
+//! were made from and nothing outside of the directory
+//!
+//! Run with `cargo +nightly fuzz run search` from the repository.
+
+#![no_main]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::{Mutex, OnceLock};
+
+use camino::Utf8PathBuf as PathBuf;
+use libfuzzer_sys::fuzz_target;
+use ocrlocate::db::{OcrResult, SearchType, DB};
+
+fn db() -> &'static Mutex<DB> {
+    static DB: OnceLock<Mutex<DB>> = OnceLock::new();
+    DB.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("ocrlocate-fuzz-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = PathBuf::try_from(dir.join("index.db")).unwrap();
+        Mutex::new(DB::new(&path).unwrap())
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let data = String::from_utf8_lossy(data);
+    let mut fields = data.split('\0');
+    let (query, dir, glob) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(query), Some(dir), Some(glob)) => (query, dir, glob),
+        _ => return,
+    };
+    let dir = PathBuf::from(format!("/fuzz/{}", dir.replace('/', "")));
+    let mut db = db().lock().unwrap();
+    let result = |path: PathBuf| OcrResult {
+        path,
+        page: String::new(),
+        metadata: fs::metadata(".").unwrap(),
+        contents: format!("before {query} after"),
+        properties: BTreeMap::new(),
+        words: vec![],
+    };
+    let file = dir.join("a");
+    let sibling = PathBuf::from(format!("{}x/a", dir.as_str().trim_end_matches('/')));
+    db.save_results(vec![result(file.clone()), result(sibling.clone())])
+        .unwrap();
+
+    let results = db
+        .search(vec![query], &dir, 10, SearchType::Simple, None, None)
+        .unwrap();
+    // the trigram tokenizer can't find anything shorter
+    if query.chars().count() >= 3 {
+        assert_eq!(results.len(), 1, "{query:?} in {dir:?}");
+        assert_eq!(results[0].path, file.as_str());
+    }
+    assert!(results.iter().all(|x| x.path == file.as_str()));
+
+    let excluded = db.search(vec![query], &dir, 10, SearchType::Simple, Some(glob), None);
+    let valid = glob::Pattern::new(&format!("{glob}/**")).is_ok();
+    assert_eq!(excluded.is_ok(), valid, "{glob:?}");
+
+    // the user's own syntax can be invalid, but it's an error and not a panic
+    let _ = db.search(vec![query], &dir, 10, SearchType::Match, None, None);
+    let _ = db.search(vec![query], &dir, 10, SearchType::Regex, None, None);
+    db.search(vec![query], &dir, 10, SearchType::Glob, None, None)
+        .unwrap();
+
+    db.delete(&file).unwrap();
+    db.delete(&sibling).unwrap();
+});
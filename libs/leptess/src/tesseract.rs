@@ -10,7 +10,7 @@ use std::ffi::{CStr, CString};
 
 pub use capi::kMaxCredibleResolution as MAX_CREDIBLE_RESOLUTION;
 pub use capi::kMinCredibleResolution as MIN_CREDIBLE_RESOLUTION;
-use capi::{TessPageIteratorLevel, TessPageIteratorLevel_RIL_BLOCK};
+use capi::{TessOcrEngineMode_OEM_DEFAULT, TessPageIteratorLevel, TessPageIteratorLevel_RIL_BLOCK};
 
 #[derive(thiserror::Error, Debug, PartialEq)]
 #[error("TessInitError{{{}}}", .code)]
@@ -45,6 +45,39 @@ impl TessApi {
         }
     }
 
+    /// Like [`Self::new`], but sets variables before initializing, which init-only variables
+    /// like `user_words_file` need.
+    pub fn with_variables<'a>(
+        data_path: Option<&'a str>,
+        lang: &'a str,
+        variables: &[(&str, &str)],
+    ) -> Result<TessApi, TessInitError> {
+        let data_path_cstr = data_path.map(|dp| CString::new(dp).unwrap());
+        let lang = CString::new(lang).unwrap();
+        let variables: Vec<(CString, CString)> = variables
+            .iter()
+            .map(|(name, value)| (CString::new(*name).unwrap(), CString::new(*value).unwrap()))
+            .collect();
+        let variables: Vec<(&CStr, &CStr)> = variables
+            .iter()
+            .map(|(name, value)| (name.as_c_str(), value.as_c_str()))
+            .collect();
+
+        let mut api = TessApi {
+            raw: tesseract_plumbing::TessBaseApi::create(),
+        };
+
+        match api.raw.init_with_variables(
+            data_path_cstr.as_deref(),
+            Some(lang.as_ref()),
+            TessOcrEngineMode_OEM_DEFAULT,
+            &variables,
+        ) {
+            Err(tesseract_plumbing::TessBaseApiInitError()) => Err(TessInitError { code: -1 }),
+            Ok(()) => Ok(api),
+        }
+    }
+
     /// Provide an image for Tesseract to recognize.
     ///
     /// set_image clears all recognition results, and sets the rectangle to the full image, so it
@@ -1,9 +1,11 @@
+mod result_iterator;
 mod tess_base_api;
 mod text;
 
 use self::tesseract_sys::TessVersion;
 pub use leptonica_plumbing;
 pub use leptonica_plumbing::leptonica_sys;
+pub use result_iterator::ResultIterator;
 use std::ffi::CStr;
 pub use tess_base_api::{
     TessBaseApi, TessBaseApiGetAltoTextError, TessBaseApiGetHocrTextError,
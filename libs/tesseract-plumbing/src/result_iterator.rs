@@ -0,0 +1,83 @@
+extern crate tesseract_sys;
+
+use self::tesseract_sys::{
+    TessPageIteratorBoundingBox, TessPageIteratorLevel, TessResultIterator,
+    TessResultIteratorConfidence, TessResultIteratorDelete, TessResultIteratorGetPageIteratorConst,
+    TessResultIteratorGetUTF8Text, TessResultIteratorNext,
+};
+use crate::Text;
+use std::os::raw::c_int;
+
+/// Wrapper around `tesseract::ResultIterator`
+///
+/// Starts at the first element of the results, so the current element should be read before
+/// calling `next`.
+#[derive(Debug)]
+pub struct ResultIterator(*mut TessResultIterator);
+
+unsafe impl Send for ResultIterator {}
+
+impl Drop for ResultIterator {
+    fn drop(&mut self) {
+        unsafe { TessResultIteratorDelete(self.0) }
+    }
+}
+
+impl ResultIterator {
+    /// # Safety
+    ///
+    /// This function should only be called with a valid, non-null iterator pointer from
+    /// Tesseract. `ResultIterator` will be responsible for freeing it.
+    pub unsafe fn new(raw: *mut TessResultIterator) -> Self {
+        Self(raw)
+    }
+
+    /// Wrapper for `TessResultIteratorNext`
+    ///
+    /// Move to the start of the next element at the given level. Returns false at the end of
+    /// the page.
+    pub fn next(&mut self, level: TessPageIteratorLevel) -> bool {
+        unsafe { TessResultIteratorNext(self.0, level) != 0 }
+    }
+
+    /// Wrapper for `TessResultIteratorGetUTF8Text`
+    ///
+    /// Text of the current element at the given level, or `None` if it has no text.
+    pub fn get_utf8_text(&self, level: TessPageIteratorLevel) -> Option<Text> {
+        let ptr = unsafe { TessResultIteratorGetUTF8Text(self.0, level) };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(unsafe { Text::new(ptr) })
+        }
+    }
+
+    /// Wrapper for `TessResultIteratorConfidence`
+    ///
+    /// Confidence of the current element at the given level, from 0 to 100.
+    pub fn confidence(&self, level: TessPageIteratorLevel) -> f32 {
+        unsafe { TessResultIteratorConfidence(self.0, level) }
+    }
+
+    /// Wrapper for `TessPageIteratorBoundingBox`
+    ///
+    /// Returns `(left, top, right, bottom)` of the current element at the given level, in
+    /// pixels of the input image, or `None` if there is no element.
+    pub fn bounding_box(
+        &self,
+        level: TessPageIteratorLevel,
+    ) -> Option<(c_int, c_int, c_int, c_int)> {
+        let (mut left, mut top, mut right, mut bottom) = (0, 0, 0, 0);
+        let ret = unsafe {
+            TessPageIteratorBoundingBox(
+                TessResultIteratorGetPageIteratorConst(self.0),
+                level,
+                &mut left,
+                &mut top,
+                &mut right,
+                &mut bottom,
+            )
+        };
+        (ret != 0).then_some((left, top, right, bottom))
+    }
+}
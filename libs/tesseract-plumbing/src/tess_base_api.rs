@@ -4,23 +4,24 @@ extern crate thiserror;
 #[cfg(feature = "tesseract_5_2")]
 use self::tesseract_sys::TessBaseAPIInit5;
 use self::tesseract_sys::{
-    TessBaseAPIAllWordConfidences, TessBaseAPICreate, TessBaseAPIDelete, TessBaseAPIGetAltoText,
-    TessBaseAPIGetComponentImages, TessBaseAPIGetHOCRText, TessBaseAPIGetInputImage,
+    TessBaseAPIAllWordConfidences, TessBaseAPICreate, TessBaseAPIDelete,
+    TessBaseAPIDetectOrientationScript, TessBaseAPIGetAltoText, TessBaseAPIGetComponentImages,
+    TessBaseAPIGetHOCRText, TessBaseAPIGetInputImage, TessBaseAPIGetIterator,
     TessBaseAPIGetLSTMBoxText, TessBaseAPIGetSourceYResolution, TessBaseAPIGetTsvText,
     TessBaseAPIGetUTF8Text, TessBaseAPIGetWordStrBoxText, TessBaseAPIInit2, TessBaseAPIInit3,
-    TessBaseAPIMeanTextConf, TessBaseAPIRecognize, TessBaseAPISetImage, TessBaseAPISetImage2,
-    TessBaseAPISetPageSegMode, TessBaseAPISetRectangle, TessBaseAPISetSourceResolution,
-    TessBaseAPISetVariable, TessDeleteIntArray, TessOcrEngineMode, TessPageIteratorLevel,
-    TessPageSegMode,
+    TessBaseAPIInit4, TessBaseAPIMeanTextConf, TessBaseAPIRecognize, TessBaseAPISetImage,
+    TessBaseAPISetImage2, TessBaseAPISetPageSegMode, TessBaseAPISetRectangle,
+    TessBaseAPISetSourceResolution, TessBaseAPISetVariable, TessDeleteIntArray, TessOcrEngineMode,
+    TessPageIteratorLevel, TessPageSegMode,
 };
 
 use self::thiserror::Error;
-use crate::Text;
+use crate::{ResultIterator, Text};
 use leptonica_plumbing::Pix;
 use std::convert::TryInto;
 use std::ffi::CStr;
 use std::ops::{Deref, DerefMut};
-use std::os::raw::c_int;
+use std::os::raw::{c_char, c_int};
 use std::ptr;
 use std::slice;
 
@@ -209,6 +210,47 @@ impl TessBaseApi {
         }
     }
 
+    /// Wrapper for `TessBaseAPIInit4`
+    ///
+    /// Start tesseract with variables set before initializing, which is the only way to set
+    /// init-only variables like `user_words_file`.
+    pub fn init_with_variables(
+        &mut self,
+        datapath: Option<&CStr>,
+        language: Option<&CStr>,
+        oem: TessOcrEngineMode,
+        variables: &[(&CStr, &CStr)],
+    ) -> Result<(), TessBaseApiInitError> {
+        // tesseract only reads these, the C API just isn't const
+        let mut names: Vec<*mut c_char> = variables
+            .iter()
+            .map(|(name, _)| name.as_ptr().cast_mut())
+            .collect();
+        let mut values: Vec<*mut c_char> = variables
+            .iter()
+            .map(|(_, value)| value.as_ptr().cast_mut())
+            .collect();
+        let ret = unsafe {
+            TessBaseAPIInit4(
+                self.0,
+                datapath.map(CStr::as_ptr).unwrap_or_else(ptr::null),
+                language.map(CStr::as_ptr).unwrap_or_else(ptr::null),
+                oem,
+                ptr::null_mut(),
+                0,
+                names.as_mut_ptr(),
+                values.as_mut_ptr(),
+                variables.len(),
+                0,
+            )
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(TessBaseApiInitError {})
+        }
+    }
+
     /// Wrapper for [`SetImage-2`](https://tesseract-ocr.github.io/tessapi/5.x/a02438.html#a0c4c7f05fd58b3665b123232a05545ad)
     pub fn set_image_2(&mut self, pix: &Pix) {
         unsafe {
@@ -427,6 +469,49 @@ impl TessBaseApi {
         unsafe { TessBaseAPIMeanTextConf(self.0) }
     }
 
+    /// Wrapper for `TessBaseAPIGetIterator`
+    ///
+    /// Get an iterator over the results of the last recognition, or `None` if nothing was
+    /// recognized yet.
+    pub fn get_iterator(&mut self) -> Option<ResultIterator> {
+        let ptr = unsafe { TessBaseAPIGetIterator(self.0) };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(unsafe { ResultIterator::new(ptr) })
+        }
+    }
+
+    /// Wrapper for `TessBaseAPIDetectOrientationScript`
+    ///
+    /// Detect the orientation and script of the image, which needs the `osd` language to be
+    /// loaded. Returns the clockwise rotation of the image in degrees, the confidence of the
+    /// rotation, the name of the script, and the confidence of the script, or `None` if there
+    /// wasn't enough text to tell.
+    pub fn detect_orientation_script(&mut self) -> Option<(c_int, f32, String, f32)> {
+        let mut orient_deg = 0;
+        let mut orient_conf = 0.0;
+        let mut script_name = ptr::null();
+        let mut script_conf = 0.0;
+        let ok = unsafe {
+            TessBaseAPIDetectOrientationScript(
+                self.0,
+                &mut orient_deg,
+                &mut orient_conf,
+                &mut script_name,
+                &mut script_conf,
+            )
+        };
+        if ok == 0 || script_name.is_null() {
+            return None;
+        }
+        // the script name points into tesseract's own tables, so it isn't freed
+        let script_name = unsafe { CStr::from_ptr(script_name) }
+            .to_string_lossy()
+            .into_owned();
+        Some((orient_deg, orient_conf, script_name, script_conf))
+    }
+
     /// Wrapper for [`TessBaseAPIAllWordConfidences`](https://tesseract-ocr.github.io/tessapi/5.x/a00008.html#a7e35b5ec11f2e38e00b9fe1126cb5c66)
     ///
     /// Returns a slice of confidences for each word in the result.
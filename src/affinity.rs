@@ -0,0 +1,84 @@
+//! Pinning the OCR workers to cpu cores
+//!
+//! Tesseract is limited to one OpenMP thread per worker, so pinning the worker also pins the
+//! OCR it runs.
+
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use core_affinity::CoreId;
+
+use crate::report::{self, Code};
+
+/// Parse a list of cores like `0-3,8,10-11`, in the format used by `taskset` and sysfs
+pub fn parse_cpu_list(s: &str) -> Result<Vec<usize>> {
+    let mut cores = vec![];
+    for part in s.trim().split(',') {
+        let parse = |x: &str| {
+            x.trim()
+                .parse::<usize>()
+                .with_context(|| format!("invalid cpu list: {s}"))
+        };
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(anyhow!("invalid cpu range: {part}"));
+                }
+                cores.extend(start..=end);
+            }
+            None => cores.push(parse(part)?),
+        }
+    }
+    Ok(cores)
+}
+
+/// The efficiency cores of an Intel hybrid cpu, as listed by the kernel
+pub fn efficiency_cores() -> Result<Vec<usize>> {
+    let list = fs::read_to_string("/sys/devices/cpu_atom/cpus").context(
+        "failed to find efficiency cores, this only works for Intel hybrid cpus on Linux",
+    )?;
+    parse_cpu_list(&list)
+}
+
+/// Every core this process may run on except the first, which is kept for the thread that walks
+/// the directory and writes to the database. That thread is pinned to it
+pub fn auto_cores() -> Result<Vec<usize>> {
+    let cores: Vec<usize> = core_affinity::get_core_ids()
+        .ok_or_else(|| anyhow!("failed to get the available cores"))?
+        .into_iter()
+        .map(|x| x.id)
+        .collect();
+    match cores.split_first() {
+        Some((&main, workers)) if !workers.is_empty() => {
+            pin_current(main);
+            Ok(workers.to_vec())
+        }
+        _ => Ok(cores),
+    }
+}
+
+/// Pin the current thread to a core, printing an error if that isn't possible
+pub fn pin_current(core: usize) {
+    if !core_affinity::set_for_current(CoreId { id: core }) {
+        report::error(
+            Code::Affinity,
+            None,
+            format!("failed to pin thread to core {core}"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_list() -> Result<()> {
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n")?, [0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpu_list("5")?, [5]);
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+        Ok(())
+    }
+}
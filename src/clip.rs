@@ -0,0 +1,131 @@
+//! Embeddings of what images look like, for `ocrlocate similar`
+//!
+//! The model is the image encoder of a CLIP model, loaded from a directory with `model.onnx`, like
+//! the vision model of the ONNX exports of clip-vit-base-patch32. It takes a 224×224 image, and
+//! its `image_embeds` output (or its only output) is the embedding. onnxruntime is loaded from
+//! `ORT_DYLIB_PATH` or the library path, like for the onnx engine.
+
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+use image::imageops::FilterType;
+use image::{DynamicImage, RgbImage};
+use ort::session::Session;
+use ort::value::Tensor;
+
+/// Width and height of the images the model sees
+const SIDE: u32 = 224;
+/// Mean and standard deviation of the channels CLIP was trained with
+const MEAN: [f32; 3] = [0.48145466, 0.4578275, 0.40821073];
+const STD: [f32; 3] = [0.26862954, 0.2613026, 0.2757771];
+
+pub struct ImageEmbedder {
+    session: Session,
+    name: String,
+}
+
+impl ImageEmbedder {
+    pub fn new(dir: &Path) -> Result<Self> {
+        let dir = PathBuf::try_from(
+            fs::canonicalize(dir).with_context(|| format!("failed to find {dir}"))?,
+        )?;
+        let session = Session::builder()?
+            .commit_from_file(dir.join("model.onnx"))
+            .with_context(|| format!("failed to load {}", dir.join("model.onnx")))?;
+        Ok(ImageEmbedder {
+            session,
+            name: dir.file_name().unwrap_or(dir.as_str()).to_owned(),
+        })
+    }
+
+    /// Name that the embeddings of this model are saved with, which is the name of its directory
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The embedding of an image file, or None if it isn't an image that can be decoded
+    pub fn embed_file(&mut self, path: &Path) -> Result<Option<Vec<f32>>> {
+        match image::open(path) {
+            Ok(image) => self.embed(&image).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn embed(&mut self, image: &DynamicImage) -> Result<Vec<f32>> {
+        let outputs = self.session.run(ort::inputs![to_tensor(image)?])?;
+        let output = match outputs.get("image_embeds") {
+            Some(x) => x,
+            None => &outputs[0],
+        };
+        let (shape, values) = output.try_extract_tensor::<f32>()?;
+        if shape.len() != 2 {
+            return Err(anyhow!(
+                "unexpected output of the image embedding model: {shape:?}"
+            ));
+        }
+        Ok(values.to_vec())
+    }
+}
+
+/// The image as the 1×3×224×224 tensor of CLIP: scaled to cover 224×224, cropped to its middle,
+/// and normalized by the mean and standard deviation of every channel
+fn to_tensor(image: &DynamicImage) -> Result<Tensor<f32>> {
+    let image: RgbImage = image
+        .resize_to_fill(SIDE, SIDE, FilterType::CatmullRom)
+        .to_rgb8();
+    Ok(Tensor::from_array((
+        [1, 3, SIDE as usize, SIDE as usize],
+        pixels(&image),
+    ))?)
+}
+
+/// The normalized values of the pixels, one channel after another
+fn pixels(image: &RgbImage) -> Vec<f32> {
+    let plane = (image.width() * image.height()) as usize;
+    let mut data = vec![0.0; plane * 3];
+    for (i, pixel) in image.pixels().enumerate() {
+        for channel in 0..3 {
+            data[channel * plane + i] =
+                (pixel[channel] as f32 / 255.0 - MEAN[channel]) / STD[channel];
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preprocess() {
+        let image = RgbImage::from_fn(2, 1, |x, _| match x {
+            0 => image::Rgb([255, 0, 0]),
+            _ => image::Rgb([0, 0, 255]),
+        });
+        let data = pixels(&image);
+        let value = |x: f32, channel: usize| (x - MEAN[channel]) / STD[channel];
+        assert_eq!(
+            data,
+            [
+                value(1.0, 0),
+                value(0.0, 0),
+                value(0.0, 1),
+                value(0.0, 1),
+                value(0.0, 2),
+                value(1.0, 2)
+            ]
+        );
+
+        // a wide image is cropped to its middle
+        let wide = DynamicImage::ImageRgb8(RgbImage::from_fn(600, 200, |x, _| match x {
+            150..=449 => image::Rgb([255, 255, 255]),
+            _ => image::Rgb([0, 0, 0]),
+        }));
+        let cropped = wide
+            .resize_to_fill(SIDE, SIDE, FilterType::CatmullRom)
+            .to_rgb8();
+        assert_eq!(cropped.dimensions(), (SIDE, SIDE));
+        assert!(cropped.pixels().all(|x| x[0] > 250));
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+use glob::Pattern;
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::report::{self, Code};
+
+/// Name of the config file that is read from the indexed directory and its subdirectories
+pub const DIR_CONFIG: &str = ".ocrlocate.toml";
+
+/// The user's config file, `ocrlocate/config.toml` in the config directory by default
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Extra file types to index with external programs
+    #[serde(default)]
+    pub extractor: Vec<ExtractorConfig>,
+    /// Language to OCR files with, by a glob pattern relative to the indexed directory
+    ///
+    /// ```toml
+    /// [languages]
+    /// "manga/**" = "jpn"
+    /// ```
+    #[serde(default)]
+    pub languages: BTreeMap<String, String>,
+    /// Regular expressions whose matches are replaced in the text before it's saved
+    ///
+    /// ```toml
+    /// redact = ['\b\d{16}\b']
+    /// ```
+    #[serde(default)]
+    pub redact: Vec<String>,
+    /// Whether searching in a directory matches the paths of files in it without their case,
+    /// which is the default on macOS and Windows. Directories are indexed with the case they have
+    /// on disk when it's on
+    ///
+    /// ```toml
+    /// case_insensitive_paths = false
+    /// ```
+    pub case_insensitive_paths: Option<bool>,
+    /// Indexes with a database and options of their own, used with `--profile`
+    ///
+    /// ```toml
+    /// [profiles.screenshots]
+    /// database = "/home/me/.local/share/ocrlocate/screenshots.db"
+    /// roots = ["/home/me/Pictures/Screenshots"]
+    /// ```
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A `[profiles.<name>]` table
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub database: String,
+    /// Directories that are indexed instead of the current directory
+    #[serde(default)]
+    pub roots: Vec<String>,
+    pub lang: Option<String>,
+    /// Patterns of paths to exclude, added to `--exclude`
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// `.ocrlocate.toml` in a directory, which changes how the files in it and its subdirectories
+/// are OCRed. It can't register extractors, since running commands from a file that came with a
+/// downloaded directory would be dangerous
+///
+/// ```toml
+/// lang = "jpn"
+/// psm = 5
+/// ```
+///
+/// The closest one to the indexed directory, in it or one of its parents, is the project config,
+/// see [`DirConfig::find`]
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DirConfig {
+    pub lang: Option<String>,
+    pub psm: Option<i64>,
+    pub scale: Option<f32>,
+    /// Same as [`Config::languages`], and takes priority over it. Only read from the project
+    /// config
+    #[serde(default)]
+    pub languages: BTreeMap<String, String>,
+    /// Patterns of paths to exclude, added to `--exclude`. Only read from the project config
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Ignore images larger than `[width]x[height]`, unless `--max-size` is given. Only read
+    /// from the project config
+    pub max_size: Option<String>,
+    /// Don't index the files in this directory and its subdirectories
+    #[serde(default)]
+    pub skip: bool,
+}
+
+/// Settings of the `.ocrlocate.toml` files that apply to a directory, where the one closest to
+/// the directory wins
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Overrides {
+    pub lang: Option<String>,
+    pub psm: Option<i64>,
+    pub scale: Option<f32>,
+}
+
+/// Finds the [`Overrides`] of directories under a root, reading each `.ocrlocate.toml` once
+#[derive(Debug)]
+pub struct DirConfigs {
+    root: PathBuf,
+    cache: HashMap<PathBuf, Overrides>,
+}
+
+impl DirConfigs {
+    pub fn new(root: &Path) -> Self {
+        DirConfigs {
+            root: root.to_owned(),
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Overrides of the files in `dir`. A config that can't be read is reported and skipped
+    pub fn resolve(&mut self, dir: &Path) -> Overrides {
+        if let Some(overrides) = self.cache.get(dir) {
+            return overrides.clone();
+        }
+        let inherited = match dir.parent() {
+            Some(parent) if dir != self.root && dir.starts_with(&self.root) => self.resolve(parent),
+            _ => Overrides::default(),
+        };
+        let overrides = match DirConfig::load(dir).and_then(|config| {
+            if let Some(lang) = &config.lang {
+                crate::ocr::validate_lang(lang)
+                    .with_context(|| format!("in {}", dir.join(DIR_CONFIG)))?;
+            }
+            Ok(config)
+        }) {
+            Ok(config) => Overrides {
+                lang: config.lang.or(inherited.lang),
+                psm: config.psm.or(inherited.psm),
+                scale: config.scale.or(inherited.scale),
+            },
+            Err(e) => {
+                report::error(Code::Config, None, format!("{:#}", e));
+                inherited
+            }
+        };
+        self.cache.insert(dir.to_owned(), overrides.clone());
+        overrides
+    }
+}
+
+/// An `[[extractor]]` table
+///
+/// ```toml
+/// [[extractor]]
+/// name = "scanner"
+/// extensions = ["sc1", "sc2"]
+/// command = ["scan2txt", "--stdout", "{}"]
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtractorConfig {
+    pub name: String,
+    pub extensions: Vec<String>,
+    pub command: Vec<String>,
+}
+
+impl Config {
+    /// Load the config at `path`, or the default config if it doesn't exist
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.try_exists()? {
+            return Ok(Config::default());
+        }
+        let text =
+            fs::read_to_string(path).with_context(|| format!("failed to read config: {}", path))?;
+        toml::from_str(&text).with_context(|| format!("failed to parse config: {}", path))
+    }
+
+    /// The profile called `name`
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name).ok_or_else(|| {
+            anyhow!(
+                "no profile called {name} in the config, the profiles are: {}",
+                self.profiles.keys().join(", ")
+            )
+        })
+    }
+}
+
+impl DirConfig {
+    /// Load the config in `dir`, or the default config if it doesn't have one
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(DIR_CONFIG);
+        if !path.try_exists()? {
+            return Ok(DirConfig::default());
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read config: {}", path))?;
+        toml::from_str(&text).with_context(|| format!("failed to parse config: {}", path))
+    }
+
+    /// The project config: the closest config in `dir` or one of its parents, or the default
+    /// config if none of them have one
+    pub fn find(dir: &Path) -> Result<Self> {
+        match dir.ancestors().find(|x| x.join(DIR_CONFIG).is_file()) {
+            Some(dir) => DirConfig::load(dir),
+            None => Ok(DirConfig::default()),
+        }
+    }
+
+    /// Whether `dir` has a config that sets `skip`. A config that can't be read doesn't
+    pub fn skips(dir: &Path) -> bool {
+        DirConfig::load(dir).is_ok_and(|x| x.skip)
+    }
+
+    /// Set `skip` in the config of `dir`, creating it if it doesn't exist. Comments in it are lost
+    pub fn set_skip(dir: &Path) -> Result<()> {
+        let path = dir.join(DIR_CONFIG);
+        let mut table = if path.try_exists()? {
+            let text = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read config: {}", path))?;
+            toml::from_str(&text).with_context(|| format!("failed to parse config: {}", path))?
+        } else {
+            toml::Table::new()
+        };
+        table.insert("skip".into(), toml::Value::Boolean(true));
+        fs::write(&path, toml::to_string(&table)?)
+            .with_context(|| format!("failed to write config: {}", path))
+    }
+}
+
+/// The `[languages]` of the config files, which pick the language of each file
+#[derive(Debug, Default, Clone)]
+pub struct LanguageHints {
+    hints: Vec<(Pattern, String)>,
+}
+
+impl LanguageHints {
+    /// Hints from `(pattern, language)` pairs. A later pair with the same pattern replaces the
+    /// earlier one
+    pub fn new(hints: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let hints: BTreeMap<String, String> = hints.into_iter().collect();
+        let hints = hints
+            .into_iter()
+            .map(|(pattern, lang)| {
+                Ok((
+                    Pattern::new(&pattern)
+                        .with_context(|| format!("invalid language pattern: {pattern}"))?,
+                    lang,
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(LanguageHints { hints })
+    }
+
+    /// Language of the file at `relative`, from the longest pattern that matches it
+    pub fn resolve(&self, relative: &Path) -> Option<&str> {
+        self.hints
+            .iter()
+            .filter(|(pattern, _)| pattern.matches_path(relative.as_std_path()))
+            .max_by_key(|(pattern, _)| pattern.as_str().len())
+            .map(|(_, lang)| lang.as_str())
+    }
+
+    /// These hints with `hints` added, which replace the ones with the same pattern
+    pub fn extended(&self, hints: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        LanguageHints::new(
+            self.hints
+                .iter()
+                .map(|(pattern, lang)| (pattern.as_str().to_owned(), lang.clone()))
+                .chain(hints),
+        )
+    }
+
+    pub fn languages(&self) -> impl Iterator<Item = &str> {
+        self.hints.iter().map(|(_, lang)| lang.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [[extractor]]
+            name = "scanner"
+            extensions = ["sc1"]
+            command = ["scan2txt", "{}"]
+            "#,
+        )?;
+        assert_eq!(config.extractor.len(), 1);
+        assert_eq!(config.extractor[0].command, ["scan2txt", "{}"]);
+        Ok(())
+    }
+
+    #[test]
+    fn profiles() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [profiles.screenshots]
+            database = "/data/screenshots.db"
+            roots = ["/home/me/Pictures/Screenshots"]
+
+            [profiles.documents]
+            database = "/data/documents.db"
+            lang = "deu"
+            exclude = ["drafts"]
+            "#,
+        )?;
+        let documents = config.profile("documents")?;
+        assert_eq!(documents.database, "/data/documents.db");
+        assert_eq!(documents.lang.as_deref(), Some("deu"));
+        assert!(documents.roots.is_empty());
+        assert_eq!(config.profile("screenshots")?.roots.len(), 1);
+        let error = config.profile("photos").unwrap_err().to_string();
+        assert!(error.ends_with("documents, screenshots"), "{error}");
+        assert!(toml::from_str::<Config>("[profiles.x]\nlang = \"eng\"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn overrides() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let root = Path::from_path(temp.path()).unwrap();
+        fs::create_dir_all(root.join("manga/vol1"))?;
+        fs::create_dir_all(root.join("broken"))?;
+        fs::write(root.join(DIR_CONFIG), "psm = 3")?;
+        fs::write(
+            root.join("manga").join(DIR_CONFIG),
+            "lang = \"jpn\"\nscale = 0.5",
+        )?;
+        fs::write(root.join("manga/vol1").join(DIR_CONFIG), "scale = 1.0")?;
+        fs::write(root.join("broken").join(DIR_CONFIG), "lang = \"../x\"")?;
+
+        let mut configs = DirConfigs::new(root);
+        let vol1 = Overrides {
+            lang: Some("jpn".into()),
+            psm: Some(3),
+            scale: Some(1.0),
+        };
+        assert_eq!(configs.resolve(&root.join("manga/vol1")), vol1);
+        assert_eq!(configs.resolve(&root.join("manga")).scale, Some(0.5));
+        let inherited = Overrides {
+            psm: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(configs.resolve(&root.join("broken")), inherited);
+        assert_eq!(configs.resolve(root), inherited);
+        Ok(())
+    }
+
+    #[test]
+    fn languages() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [languages]
+            "manga/**" = "jpn"
+            "manga/translated/**" = "eng"
+            "*.de.png" = "deu"
+            "#,
+        )?;
+        let dir: DirConfig = toml::from_str("languages = { \"*.de.png\" = \"frk\" }")?;
+        let hints = LanguageHints::new(config.languages.into_iter().chain(dir.languages))?;
+        assert_eq!(hints.resolve(Path::new("manga/a/1.png")), Some("jpn"));
+        assert_eq!(
+            hints.resolve(Path::new("manga/translated/1.png")),
+            Some("eng")
+        );
+        assert_eq!(hints.resolve(Path::new("scans/letter.de.png")), Some("frk"));
+        assert_eq!(hints.resolve(Path::new("photo.png")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn project() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let root = Path::from_path(temp.path()).unwrap();
+        fs::create_dir_all(root.join("scans/2023/receipts"))?;
+        fs::write(
+            root.join(DIR_CONFIG),
+            "lang = \"deu\"\nexclude = [\"raw\", \"*.tmp.png\"]\nmax_size = \"4000x4000\"\n",
+        )?;
+        let config = DirConfig::find(&root.join("scans/2023/receipts"))?;
+        assert_eq!(config.lang.as_deref(), Some("deu"));
+        assert_eq!(config.exclude, ["raw", "*.tmp.png"]);
+        assert_eq!(config.max_size.as_deref(), Some("4000x4000"));
+
+        // the closest one wins, and isn't merged with the ones above it
+        fs::write(root.join("scans").join(DIR_CONFIG), "psm = 4")?;
+        let config = DirConfig::find(&root.join("scans/2023"))?;
+        assert_eq!(config.psm, Some(4));
+        assert!(config.lang.is_none() && config.exclude.is_empty());
+        assert_eq!(DirConfig::find(root)?.lang.as_deref(), Some("deu"));
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn skip() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = Path::from_path(temp.path()).unwrap();
+        assert!(!DirConfig::skips(dir));
+        fs::write(
+            dir.join(DIR_CONFIG),
+            "lang = \"jpn\"\n[languages]\n\"a/*\" = \"deu\"\n",
+        )?;
+        DirConfig::set_skip(dir)?;
+        assert!(DirConfig::skips(dir));
+        let config = DirConfig::load(dir)?;
+        assert_eq!(config.lang.as_deref(), Some("jpn"));
+        assert_eq!(config.languages.len(), 1);
+        temp.close()?;
+        Ok(())
+    }
+}
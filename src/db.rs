@@ -1,9 +1,20 @@
 use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::time::UNIX_EPOCH;
+use std::ops::Range;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
-use rusqlite::{Connection, OptionalExtension, ToSql};
+use anyhow::{anyhow, Context, Result};
+use itertools::Itertools;
+use rusqlite::types::Value;
+use rusqlite::{Connection, ErrorCode, OptionalExtension, ToSql};
+use serde::{Deserialize, Serialize};
+
+use crate::ocr::Word;
+use crate::paths;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum SearchType {
@@ -12,14 +23,487 @@ pub enum SearchType {
     Glob,
     #[cfg(feature = "regex")]
     Regex,
+    /// Every query on its own like a simple one, and results with more of them first
+    Any,
+    /// Text with a meaning like that of the query, by the embeddings of [`crate::semantic`]
+    #[cfg(feature = "semantic")]
+    Semantic,
+}
+
+impl FromStr for SearchType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "simple" => Ok(SearchType::Simple),
+            "match" => Ok(SearchType::Match),
+            "glob" => Ok(SearchType::Glob),
+            "any" => Ok(SearchType::Any),
+            #[cfg(feature = "regex")]
+            "regex" => Ok(SearchType::Regex),
+            #[cfg(not(feature = "regex"))]
+            "regex" => Err(anyhow!("This build was not compiled with regex support")),
+            #[cfg(feature = "semantic")]
+            "semantic" => Ok(SearchType::Semantic),
+            #[cfg(not(feature = "semantic"))]
+            "semantic" => Err(anyhow!(
+                "This build was not compiled with semantic search support"
+            )),
+            x => Err(anyhow!("Unknown search type: {x}")),
+        }
+    }
+}
+
+impl SearchType {
+    /// The words of a query that can be looked for on their own, to tell which of them a result
+    /// contains. Operators of `match` queries are left out, and glob and regex queries are a
+    /// single term since they can't be split
+    pub fn terms(self, queries: &[&str]) -> Vec<String> {
+        match self {
+            SearchType::Simple => queries
+                .iter()
+                .flat_map(|x| x.split_whitespace())
+                .map(String::from)
+                .unique()
+                .collect(),
+            SearchType::Match => match_terms(&queries.join(" ")),
+            SearchType::Any => queries.iter().map(|x| x.to_string()).unique().collect(),
+            _ => vec![queries.join(" ")],
+        }
+    }
+
+    /// The queries joined into what is compared with the text
+    fn query(self, queries: &[&str]) -> String {
+        // a string in fts5 takes everything but quotes literally, which are doubled
+        let string = |x: &str| format!(r#""{}""#, x.replace('"', r#""""#));
+        match self {
+            SearchType::Simple => string(&queries.join(" ")),
+            SearchType::Any => queries.iter().map(|x| string(x)).join(" OR "),
+            _ => queries.join(" "),
+        }
+    }
+
+    /// The sql operator that compares the text with the query
+    fn operator(self) -> &'static str {
+        match self {
+            SearchType::Simple | SearchType::Match | SearchType::Any => "MATCH",
+            SearchType::Glob => "GLOB",
+            #[cfg(feature = "regex")]
+            SearchType::Regex => "REGEXP",
+            #[cfg(feature = "semantic")]
+            SearchType::Semantic => unreachable!("semantic searches compare embeddings"),
+        }
+    }
+}
+
+/// Split an fts5 match query into its strings and bare words, without the ones that `NOT` leaves
+/// out, since a result doesn't contain them
+fn match_terms(query: &str) -> Vec<String> {
+    let mut terms = vec![];
+    let mut chars = query.chars().peekable();
+    // whether the next word, string or group is after a NOT, and the depth of the group in
+    // parentheses that is
+    let mut negated = false;
+    let mut negated_group = None;
+    let mut depth = 0;
+    while let Some(c) = chars.next() {
+        let excluded = negated || negated_group.is_some();
+        if c == '(' {
+            depth += 1;
+            if negated && negated_group.is_none() {
+                negated_group = Some(depth);
+            }
+            negated = false;
+        } else if c == ')' {
+            if negated_group == Some(depth) {
+                negated_group = None;
+            }
+            depth -= 1;
+        } else if c == '"' {
+            // a string ends at a lone quote, two quotes are an escaped one
+            let mut term = String::new();
+            while let Some(c) = chars.next() {
+                if c == '"' {
+                    if chars.peek() != Some(&'"') {
+                        break;
+                    }
+                    chars.next();
+                }
+                term.push(c);
+            }
+            if !excluded {
+                terms.push(term);
+            }
+            negated = false;
+        } else if c == ',' {
+            // the distance of a NEAR group
+            while chars
+                .peek()
+                .is_some_and(|c| c.is_whitespace() || c.is_ascii_digit())
+            {
+                chars.next();
+            }
+        } else if !c.is_whitespace() && !"()*^+:".contains(c) {
+            let mut term = c.to_string();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || "()\"*^+:,".contains(c) {
+                    break;
+                }
+                term.push(c);
+                chars.next();
+            }
+            // a word followed by a colon is a column filter
+            if chars.peek() == Some(&':') || ["AND", "OR", "NEAR"].contains(&term.as_str()) {
+                continue;
+            }
+            if term == "NOT" {
+                negated = true;
+                continue;
+            }
+            if !excluded {
+                terms.push(term);
+            }
+            negated = false;
+        }
+    }
+    terms
+        .into_iter()
+        .filter(|x| !x.is_empty())
+        .unique()
+        .collect()
+}
+
+/// Words that have to be close to each other in the text, searched for with fts5's `NEAR`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Near {
+    pub words: Vec<String>,
+    /// Most words between any two of them
+    pub distance: usize,
+}
+
+impl Near {
+    /// The words as a `NEAR` group of a match query on an index of `tokenizer`. Distances are of
+    /// tokens, so on an index of characters they are only about as many words as `distance`
+    pub fn query(&self, tokenizer: Tokenizer) -> String {
+        let words = self
+            .words
+            .iter()
+            .map(|x| format!(r#""{}""#, x.replace('"', r#""""#)))
+            .join(" ");
+        format!("NEAR({words}, {})", tokenizer.near_distance(self.distance))
+    }
+
+    /// A match query for the results of `queries` that also have the words close together
+    pub fn with(&self, kind: SearchType, queries: &[&str], tokenizer: Tokenizer) -> Result<String> {
+        let near = self.query(tokenizer);
+        Ok(match kind {
+            _ if queries.is_empty() => near,
+            SearchType::Simple => format!("{} {near}", kind.query(queries)),
+            SearchType::Match | SearchType::Any => {
+                format!("({}) {near}", kind.query(queries))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "--near can only be used with simple and match queries"
+                ))
+            }
+        })
+    }
+}
+
+/// The fts5 tokenizer of the search index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tokenizer {
+    /// The builtin trigram tokenizer
+    Trigram,
+    /// Grapheme and script aware n-grams, see [`crate::tokenizer`]
+    Script,
+    /// Whole words, and pairs of letters in CJK text, see [`crate::tokenizer::tokenize_words`]
+    Words,
+    /// The builtin unicode61 tokenizer, whole words split on spaces and punctuation
+    Unicode61,
+}
+
+impl Tokenizer {
+    /// Name of the tokenizer in `--tokenizer` and the settings table
+    pub fn name(self) -> &'static str {
+        match self {
+            Tokenizer::Trigram => "trigram",
+            Tokenizer::Script => "script",
+            Tokenizer::Words => "words",
+            Tokenizer::Unicode61 => "unicode61",
+        }
+    }
+
+    /// The `NEAR` distance for at most `words` words between the words of a group. The n-gram
+    /// tokenizers make a token of every character, so a word and the space after it are counted
+    /// as 6 tokens. The others make one of every word, except for the pairs of letters of CJK text
+    fn near_distance(self, words: usize) -> usize {
+        match self {
+            Tokenizer::Trigram | Tokenizer::Script => (words + 1) * 6,
+            Tokenizer::Words | Tokenizer::Unicode61 => words,
+        }
+    }
+
+    fn fts5_arg(self, remove_diacritics: bool) -> String {
+        let arg = match self {
+            Tokenizer::Trigram => "trigram case_sensitive 0".into(),
+            Tokenizer::Script => crate::tokenizer::NAME.to_owned(),
+            Tokenizer::Words => format!("{} words 1", crate::tokenizer::NAME),
+            // which removes diacritics unless it's told not to
+            Tokenizer::Unicode61 if !remove_diacritics => "unicode61 remove_diacritics 0".into(),
+            Tokenizer::Unicode61 => "unicode61".into(),
+        };
+        match remove_diacritics {
+            true => format!("{arg} remove_diacritics 1"),
+            false => arg,
+        }
+    }
+}
+
+impl FromStr for Tokenizer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "trigram" => Ok(Tokenizer::Trigram),
+            "script" => Ok(Tokenizer::Script),
+            "words" => Ok(Tokenizer::Words),
+            "unicode61" => Ok(Tokenizer::Unicode61),
+            x => Err(anyhow!("Unknown tokenizer: {x}")),
+        }
+    }
+}
+
+/// How the search index is built, which is kept in the settings table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexOptions {
+    pub tokenizer: Tokenizer,
+    /// Fold accented letters into the letters without the accents
+    pub remove_diacritics: bool,
+    /// Lengths of the prefixes that get an index of their own, which makes `word*` queries
+    /// faster with the tokenizers of whole words
+    pub prefix: Vec<usize>,
+    /// Leave the positions of tokens out of the index, with `detail=none`. This makes it a few
+    /// times smaller, but it can't look up phrases, so simple searches look up each of their
+    /// tokens and then check the text, and match queries are mostly unsupported
+    pub compact: bool,
+    /// Store the text compressed with zstd, and index it through the `images_text` view that
+    /// decompresses it
+    pub compress: bool,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        IndexOptions {
+            tokenizer: Tokenizer::Trigram,
+            remove_diacritics: false,
+            prefix: vec![],
+            compact: false,
+            compress: false,
+        }
+    }
+}
+
+impl IndexOptions {
+    /// The arguments of `fts5(..)` that make the search index
+    fn fts5_args(&self) -> String {
+        let tokenize = self.tokenizer.fts5_arg(self.remove_diacritics);
+        let content = if self.compress {
+            "images_text"
+        } else {
+            "images"
+        };
+        let mut args =
+            format!("content, content={content}, content_rowid=id, tokenize='{tokenize}'");
+        if !self.prefix.is_empty() {
+            args += &format!(", prefix='{}'", self.prefix.iter().join(" "));
+        }
+        if self.compact {
+            args += ", detail=none";
+        }
+        args
+    }
+}
+
+/// Schema changes applied on top of `db_create.sql`, which is version 2
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/v3.sql"),
+    include_str!("migrations/v4.sql"),
+    include_str!("migrations/v5.sql"),
+    include_str!("migrations/v6.sql"),
+    include_str!("migrations/v7.sql"),
+    include_str!("migrations/v8.sql"),
+    include_str!("migrations/v9.sql"),
+    include_str!("migrations/v10.sql"),
+    include_str!("migrations/v11.sql"),
+    include_str!("migrations/v12.sql"),
+    include_str!("migrations/v13.sql"),
+    include_str!("migrations/v14.sql"),
+];
+const SCHEMA_VERSION: i32 = 2 + MIGRATIONS.len() as i32;
+
+/// How writes are retried when another process holds the database lock
+#[derive(Debug, Clone, Copy)]
+pub struct RetryOptions {
+    /// Total tries, including the first one
+    pub attempts: u32,
+    /// Wait before the first retry, doubled after every failed retry
+    pub backoff: Duration,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        RetryOptions {
+            attempts: 5,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// How regex searches match, and how much they may cost
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone, Copy)]
+pub struct RegexOptions {
+    pub ignore_case: bool,
+    /// `^` and `$` match at the start and end of every line, and not only of the whole text
+    pub multi_line: bool,
+    /// Largest a compiled pattern may be, in bytes
+    pub size_limit: usize,
+    /// How long a search may scan the rows before it's stopped, or None to let it finish
+    pub time_limit: Option<Duration>,
+}
+
+#[cfg(feature = "regex")]
+impl Default for RegexOptions {
+    fn default() -> Self {
+        RegexOptions {
+            ignore_case: false,
+            multi_line: false,
+            size_limit: 1 << 20,
+            time_limit: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+/// How the snippets of search results are made
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnippetOptions {
+    /// Most tokens of a snippet, from 1 to 64, or twice the characters on each side of the match
+    /// on a compact index
+    pub length: usize,
+    /// Put before and after every match
+    pub open: String,
+    pub close: String,
+}
+
+impl Default for SnippetOptions {
+    fn default() -> Self {
+        SnippetOptions {
+            length: 64,
+            open: "[".into(),
+            close: "]".into(),
+        }
+    }
+}
+
+/// How search results are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Rank {
+    /// Best match first by fts5's bm25, and the newest file first among equal matches
+    #[default]
+    Bm25,
+    /// Newest file first
+    Recency,
+    /// bm25 weighed by how recent the file is, so that a match counts half as much after this
+    /// many days
+    Mixed { half_life: f64 },
+}
+
+impl Rank {
+    /// The keys of the `ORDER BY` of a search, and whether they're descending
+    fn keys(self) -> Vec<(String, bool)> {
+        match self {
+            Rank::Bm25 => vec![
+                ("images_fts.rank".into(), false),
+                ("images.modtime".into(), true),
+            ],
+            Rank::Recency => vec![
+                ("images.modtime".into(), true),
+                ("images_fts.rank".into(), false),
+            ],
+            // rank is negative, so it's made closer to 0 as the file gets older
+            Rank::Mixed { half_life } => vec![
+                (
+                    format!(
+                        "images_fts.rank / (1 + max(unixepoch() - images.modtime, 0) / {:.1})",
+                        half_life * 86400.0
+                    ),
+                    false,
+                ),
+                ("images.modtime".into(), true),
+            ],
+        }
+    }
+}
+
+/// What search results are sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sort {
+    /// How well they match, by the [`Rank`]
+    #[default]
+    Rank,
+    /// Newest file first
+    Modified,
+    /// Paths in alphabetical order, and the pages of a file in order
+    Path,
+    /// Largest file first. Files indexed before sizes were saved come last
+    Size,
+}
+
+/// Who is using a database that several users share
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Shared {
+    /// Saved rows are tagged with this user id, and it can see its private rows
+    pub user: Option<u32>,
+    /// Hide the rows saved by this connection from other users
+    pub private: bool,
+}
+
+/// The user id of this process, which rows of a shared database are tagged with
+pub fn current_user() -> Option<u32> {
+    #[cfg(unix)]
+    return Some(unsafe { libc::getuid() });
+    #[cfg(not(unix))]
+    None
 }
 
 pub struct DB {
     conn: Connection,
+    retry: RetryOptions,
+    shared: Option<Shared>,
+    case_insensitive_paths: bool,
+    rank: Rank,
+    /// And whether it's reversed
+    sort: (Sort, bool),
+    excluded: Vec<String>,
+    /// Seconds since the epoch that results are modified after and before
+    modified: (Option<i64>, Option<i64>),
+    /// Languages that results were OCRed with one of
+    langs: Vec<String>,
+    snippet: SnippetOptions,
+    #[cfg(feature = "regex")]
+    regex: RegexOptions,
+    #[cfg(feature = "semantic")]
+    embedder: Option<crate::semantic::Embedder>,
 }
 
 impl DB {
     pub fn new(path: &Path) -> Result<Self> {
+        Self::open(path, RetryOptions::default())
+    }
+
+    pub fn open(path: &Path, retry: RetryOptions) -> Result<Self> {
         if !path.try_exists()? {
             eprintln!("Note: creating new database")
         }
@@ -29,8 +513,11 @@ impl DB {
         conn.pragma_update(None, "synchronous", "normal").unwrap(); // TODO: maybe not
 
         #[cfg(feature = "regex")]
-        register_regex(&conn).unwrap();
+        register_regex(&conn, RegexOptions::default()).unwrap();
         register_glob(&conn).unwrap();
+        register_zstd(&conn)?;
+        register_like(&conn, paths::CASE_INSENSITIVE)?;
+        crate::tokenizer::register(&conn)?;
 
         let user_version: i32 = conn
             .query_row("SELECT user_version FROM pragma_user_version", [], |row| {
@@ -38,14 +525,32 @@ impl DB {
             })
             .unwrap();
 
-        let db = DB { conn };
+        let db = DB {
+            conn,
+            retry,
+            shared: None,
+            case_insensitive_paths: paths::CASE_INSENSITIVE,
+            rank: Rank::default(),
+            sort: (Sort::default(), false),
+            excluded: vec![],
+            modified: (None, None),
+            langs: vec![],
+            snippet: SnippetOptions::default(),
+            #[cfg(feature = "regex")]
+            regex: RegexOptions::default(),
+            #[cfg(feature = "semantic")]
+            embedder: None,
+        };
         match user_version {
-            0 => db.init_db()?,
+            0 => {
+                db.init_db()?;
+                db.migrate(2)?;
+            }
             1 => panic!(
                 "Your database is from a prerelease version and should be deleted, its at {}",
                 path
             ),
-            2 => (),
+            2..=SCHEMA_VERSION => db.migrate(user_version)?,
             x => panic!("Database schema version is too high: {x}"),
         };
 
@@ -55,165 +560,2106 @@ impl DB {
     fn init_db(&self) -> Result<()> {
         let conn = &self.conn;
 
-        conn.execute_batch(include_str!("db_create.sql"))
-            .context("creating tables")?;
+        retry(&self.retry, || {
+            conn.execute_batch(include_str!("db_create.sql"))
+                .inspect_err(|_| rollback(conn))
+        })
+        .context("creating tables")?;
 
         Ok(())
     }
 
-    pub fn is_indexed(&self, path: &Path, metadata: &fs::Metadata) -> bool {
-        let mut stmt = self
-            .conn
-            .prepare_cached("SELECT modtime FROM images WHERE path = ?1")
-            .unwrap();
-        let mtime = metadata_to_seconds(metadata);
-        let Some(modtime) = stmt
-            .query_row([path.as_str()], |row| row.get(0))
-            .optional()
-            .with_context(|| format!("failed to check if an image was already indexed: {}", path))
-            .unwrap()
-        else {
-            return false;
-        };
-        if mtime == modtime {
-            return true;
+    fn migrate(&self, from: i32) -> Result<()> {
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip((from - 2) as usize) {
+            retry(&self.retry, || {
+                self.conn
+                    .execute_batch(migration)
+                    .inspect_err(|_| rollback(&self.conn))
+            })
+            .with_context(|| format!("migrating database to version {}", i + 3))?;
         }
-        false
+        Ok(())
     }
 
-    pub fn save_results(&mut self, results: Vec<OcrResult>) -> Result<usize> {
-        let tx = self.conn.transaction().unwrap();
+    /// Tag saved rows with their owner, and only return search results that are visible to the
+    /// user and under paths it can read. For databases that several users share
+    pub fn set_shared(&mut self, shared: Option<Shared>) {
+        self.shared = shared;
+    }
 
-        let rowchanges: usize = {
-            let mut index_stmt = tx
-                .prepare_cached("INSERT INTO images (path, modtime, content) VALUES (?1, ?2, ?3) ON CONFLICT(path) DO UPDATE SET modtime=excluded.modtime, content=excluded.content")
-                .unwrap();
-            results
+    /// Change how [`DB::search`] orders its results
+    pub fn set_rank(&mut self, rank: Rank) {
+        self.rank = rank;
+    }
+
+    /// Change what [`DB::search`] sorts its results by, and whether the order is reversed. The
+    /// default is by [`Rank`]
+    pub fn set_sort(&mut self, sort: Sort, reverse: bool) {
+        self.sort = (sort, reverse);
+    }
+
+    /// The `ORDER BY` of a search. `matched` is how many of the queries a result of an `any`
+    /// search matches, which results are ranked by first
+    fn order(&self, matched: Option<String>) -> String {
+        let (sort, reverse) = self.sort;
+        let keys = match sort {
+            Sort::Rank => matched
+                .map(|x| (x, true))
                 .into_iter()
-                .map(|res| {
-                    index_stmt
-                        .execute((
-                            res.path.as_str(),
-                            metadata_to_seconds(&res.metadata),
-                            res.contents,
-                        ))
-                        .with_context(|| format!("failed to insert image: {}", res.path))
-                        .unwrap()
-                })
-                .sum()
+                .chain(self.rank.keys())
+                .collect(),
+            Sort::Modified => Rank::Recency.keys(),
+            // pages are text, so shorter numbers come first to sort 2 before 10
+            Sort::Path => vec![
+                ("images.path".into(), false),
+                ("length(images.page)".into(), false),
+                ("images.page".into(), false),
+            ],
+            Sort::Size => vec![
+                ("images.size".into(), true),
+                ("images.modtime".into(), true),
+            ],
         };
-        tx.commit().unwrap();
-        Ok(rowchanges)
+        keys.into_iter()
+            .map(|(key, descending)| match descending != reverse {
+                true => format!("{key} DESC NULLS LAST"),
+                false => format!("{key} ASC NULLS LAST"),
+            })
+            .join(", ")
     }
 
-    /// Mark the elements of a directory for deletion in the DB
-    pub fn mark_for_deletion(&mut self, path: &Path) {
-        if !path.is_dir() {
-            panic!(
-                "Incorrect usage of `mark_for_deletion`: Path `{}` should be a directory",
-                path
-            );
+    /// Leave out the results of searches that contain any of these terms, like a watermark that
+    /// is on every screenshot. Only simple, match, any and semantic searches can exclude terms
+    pub fn set_excluded_terms(&mut self, terms: Vec<String>) {
+        self.excluded = terms.into_iter().filter(|x| !x.trim().is_empty()).collect();
+    }
+
+    /// Only find files modified after `newer_than` and before `older_than`, in seconds since the
+    /// epoch
+    pub fn set_modified_range(&mut self, newer_than: Option<i64>, older_than: Option<i64>) {
+        self.modified = (newer_than, older_than);
+    }
+
+    /// The SQL condition of [`DB::set_modified_range`]
+    fn modified_condition(&self) -> String {
+        let (newer_than, older_than) = self.modified;
+        newer_than
+            .map(|x| format!("AND images.modtime >= {x} "))
+            .into_iter()
+            .chain(older_than.map(|x| format!("AND images.modtime < {x}")))
+            .collect()
+    }
+
+    /// Only find rows that were OCRed with one of these languages, like `jpn`. A row OCRed with
+    /// several languages joined with +, like `eng+jpn`, is found by each of them
+    pub fn set_lang_filter(&mut self, langs: Vec<String>) {
+        self.langs = langs.into_iter().filter(|x| !x.trim().is_empty()).collect();
+    }
+
+    /// The SQL condition of [`DB::set_lang_filter`]
+    fn lang_condition(&self) -> String {
+        if self.langs.is_empty() {
+            return String::new();
         }
+        let quote = |x: &str| format!("'+{}+'", x.replace('\'', "''"));
+        let langs = self
+            .langs
+            .iter()
+            .map(|x| {
+                format!(
+                    "instr('+' || json_extract(images.properties, '$.lang') || '+', {}) > 0",
+                    quote(x)
+                )
+            })
+            .join(" OR ");
+        format!("AND ({langs})")
+    }
 
+    /// A date like 2024-01-01, or a date and time like 2024-01-01 12:00, in local time as seconds
+    /// since the epoch
+    pub fn local_time(&self, date: &str) -> Result<i64> {
         self.conn
-            .execute(
-                "UPDATE images SET mark_delete = FALSE WHERE mark_delete = TRUE",
-                [],
-            )
-            .expect("failed to preliminarily unmark previously marked images for deletion");
-        let mut stmt = self
-            .conn
-            .prepare_cached("UPDATE images SET mark_delete = TRUE WHERE path LIKE ?1 ESCAPE '#'")
-            .expect("failed to preliminarily mark subdirectory for deletion");
+            .query_row("SELECT unixepoch(?1, 'utc')", [date], |row| {
+                row.get::<_, Option<i64>>(0)
+            })?
+            .ok_or_else(|| anyhow!("invalid date: {date}, should be like 2024-01-01"))
+    }
 
-        stmt.execute([path_to_like(path)]).unwrap();
+    /// Change how long the snippets of results are and what their matches are marked with
+    pub fn set_snippet_options(&mut self, options: SnippetOptions) {
+        self.snippet = options;
     }
 
-    pub fn unmark_file(&mut self, path: &Path) {
-        let mut stmt = self
-            .conn
-            .prepare_cached("UPDATE images SET mark_delete = FALSE WHERE path = ?1")
-            .with_context(|| format!("failed to unmark image for deletion: {}", path))
-            .unwrap();
+    /// The `snippet` call of the search index with [`DB::set_snippet_options`]
+    fn snippet_sql(&self) -> String {
+        let quote = |x: &str| format!("'{}'", x.replace('\'', "''"));
+        format!(
+            "snippet(images_fts, -1, {}, {}, '..', {})",
+            quote(&self.snippet.open),
+            quote(&self.snippet.close),
+            self.snippet.length.clamp(1, 64)
+        )
+    }
+
+    /// Change the flags of regex searches and how long they may take
+    #[cfg(feature = "regex")]
+    pub fn set_regex_options(&mut self, options: RegexOptions) -> Result<()> {
+        register_regex(&self.conn, options)?;
+        self.regex = options;
+        Ok(())
+    }
 
-        stmt.execute([path.as_str()]).unwrap();
+    /// The model that embeds text for semantic searches and [`DB::embed`]
+    #[cfg(feature = "semantic")]
+    pub fn set_embedder(&mut self, embedder: Option<crate::semantic::Embedder>) {
+        self.embedder = embedder;
     }
 
-    pub fn sweep_deletions(&mut self) -> usize {
+    /// Match the paths of files in a directory without their case when searching it, like
+    /// `~/pictures` for `~/Pictures`. The default is [`paths::CASE_INSENSITIVE`]
+    pub fn set_case_insensitive_paths(&mut self, case_insensitive: bool) -> Result<()> {
+        register_like(&self.conn, case_insensitive)?;
+        self.case_insensitive_paths = case_insensitive;
+        Ok(())
+    }
+
+    pub fn case_insensitive_paths(&self) -> bool {
+        self.case_insensitive_paths
+    }
+
+    /// Refuse every statement that would change the database, for connections that only search
+    pub fn set_read_only(&self, read_only: bool) -> Result<()> {
+        self.conn.pragma_update(None, "query_only", read_only)?;
+        Ok(())
+    }
+
+    /// A value of the settings table
+    pub fn setting(&self, key: &str) -> Result<Option<String>> {
         self.conn
-            .execute("DELETE FROM images WHERE mark_delete = TRUE", [])
-            .expect("failed to delete marked images")
+            .query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()
+            .with_context(|| format!("failed to read the {key} setting"))
     }
 
-    pub fn search(
-        &mut self,
-        queries: Vec<&str>,
-        path: &Path,
-        limit: usize,
-        kind: SearchType,
-        exclude_glob: Option<&str>,
-    ) -> Result<Vec<SearchResult>> {
-        let query = if kind == SearchType::Simple {
-            format!(r#""{}""#, queries.join(" ").replace('*', "\\*"))
-        } else {
-            queries.join(" ")
+    /// The options the search index was built with
+    pub fn index_options(&self) -> Result<IndexOptions> {
+        let setting = |key| -> Result<String> {
+            self.setting(key)?
+                .ok_or_else(|| anyhow!("the {key} setting is missing"))
         };
+        Ok(IndexOptions {
+            tokenizer: setting("tokenizer")?.parse()?,
+            remove_diacritics: setting("remove_diacritics")? != "0",
+            prefix: setting("prefix")?
+                .split_whitespace()
+                .map(|x| x.parse())
+                .try_collect()
+                .context("invalid prefix setting")?,
+            compact: setting("detail")? == "none",
+            compress: setting("compression")? == "zstd",
+        })
+    }
 
-        let mut stmt = self
+    /// The tokenizer the search index was created with
+    pub fn tokenizer(&self) -> Result<Tokenizer> {
+        Ok(self.index_options()?.tokenizer)
+    }
+
+    /// Whether the search index folds accented letters into the letters without the accents, so
+    /// that `cafe` finds `café`
+    pub fn remove_diacritics(&self) -> Result<bool> {
+        Ok(self.index_options()?.remove_diacritics)
+    }
+
+    /// Build the search index again with these options if they changed
+    ///
+    /// Returns whether the index was rebuilt
+    pub fn set_index_options(&mut self, options: &IndexOptions) -> Result<bool> {
+        if let Some(&x) = options.prefix.iter().find(|x| !(1..=999).contains(*x)) {
+            return Err(anyhow!("prefix lengths go from 1 to 999, not {x}"));
+        }
+        let current = self.index_options()?;
+        if current == *options {
+            return Ok(false);
+        }
+        // rewritten while there are no triggers, which would index every row again. The text
+        // stays the same, so its embeddings are put back
+        let convert = match (current.compress, options.compress) {
+            (false, true) => Some("zstd"),
+            (true, false) => Some("unzstd"),
+            _ => None,
+        };
+        let convert = convert.map_or(String::new(), |function| {
+            format!(
+                "CREATE TEMP TABLE kept_embeddings AS SELECT * FROM embeddings;
+                UPDATE images SET content = {function}(content);
+                INSERT OR IGNORE INTO embeddings SELECT * FROM kept_embeddings;
+                DROP TABLE kept_embeddings;"
+            )
+        });
+        let sql = format!(
+            "BEGIN;
+            DROP TABLE images_fts;
+            DROP TRIGGER IF EXISTS images_insert;
+            DROP TRIGGER IF EXISTS images_delete;
+            DROP TRIGGER IF EXISTS images_update;
+            {convert}
+            CREATE VIRTUAL TABLE images_fts USING fts5({});
+            {}
+            INSERT INTO images_fts (images_fts) VALUES ('rebuild');
+            INSERT OR REPLACE INTO settings (key, value) VALUES
+                ('tokenizer', '{}'), ('remove_diacritics', {}), ('prefix', '{}'), ('detail', '{}'),
+                ('compression', '{}');
+            COMMIT;",
+            options.fts5_args(),
+            triggers(options.compress),
+            options.tokenizer.name(),
+            options.remove_diacritics as u8,
+            options.prefix.iter().join(" "),
+            if options.compact { "none" } else { "full" },
+            if options.compress { "zstd" } else { "none" },
+        );
+        retry(&self.retry, || {
+            self.conn
+                .execute_batch(&sql)
+                .inspect_err(|_| rollback(&self.conn))
+        })
+        .context("failed to rebuild the search index")?;
+        Ok(true)
+    }
+
+    /// Switch the search index to another tokenizer, rebuilding it if it changed
+    ///
+    /// Returns whether the index was rebuilt
+    pub fn set_tokenizer(&mut self, tokenizer: Tokenizer) -> Result<bool> {
+        let options = IndexOptions {
+            tokenizer,
+            ..self.index_options()?
+        };
+        self.set_index_options(&options)
+    }
+
+    /// Make the search index fold accented letters or stop folding them, rebuilding it if that
+    /// changed
+    ///
+    /// Returns whether the index was rebuilt
+    pub fn set_remove_diacritics(&mut self, remove_diacritics: bool) -> Result<bool> {
+        let options = IndexOptions {
+            remove_diacritics,
+            ..self.index_options()?
+        };
+        self.set_index_options(&options)
+    }
+
+    /// Cross-check the search index against the images table
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let count = |sql: &str| -> Result<usize> {
+            self.conn
+                .query_row(sql, [], |row| row.get(0))
+                .with_context(|| format!("failed to verify index: {}", sql))
+        };
+        let mut missing_triggers = vec![];
+        for trigger in TRIGGERS {
+            let exists: bool = self.conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'trigger' AND name = ?1)",
+                [trigger],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                missing_triggers.push(trigger.to_string());
+            }
+        }
+        // with rank 1 the integrity check also compares the index to the content table
+        let integrity_error = self
             .conn
-            .prepare_cached(
-                &format!(r#"
-                SELECT snippet(images_fts, -1, '[', ']', '..', 64), images.path, images.modtime
-                    FROM images_fts
-                    INNER JOIN images ON images_fts.rowid = images.id AND images.path LIKE ?2 ESCAPE '#'
-                    WHERE images_fts.content {kind} ?1 {exclude}
-                    ORDER BY RANK, images.modtime DESC
-                    LIMIT ?3;
-                "#, kind=match kind {
-                    SearchType::Simple | SearchType::Match => "MATCH",
-                    SearchType::Glob => "GLOB",
-                    #[cfg(feature="regex")]
-                    SearchType::Regex => "REGEXP"
-                }, exclude=if exclude_glob.is_some() {"AND NOT rust_glob(?4||'/**', images.path)"} else {""}),
+            .execute(
+                "INSERT INTO images_fts (images_fts, rank) VALUES ('integrity-check', 1)",
+                [],
             )
-            .unwrap();
-        let fixed_path = path_to_like(path);
-        let mut params = vec![
-            &query as &dyn ToSql,
-            &fixed_path as &dyn ToSql,
-            &limit as &dyn ToSql,
-        ];
-        if exclude_glob.is_some() {
-            params.push(&exclude_glob as &dyn ToSql);
+            .err()
+            .map(|e| e.to_string());
+        let mut stmt = self.conn.prepare("PRAGMA integrity_check")?;
+        let database_errors = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .filter(|x| x.as_ref().map_or(true, |x| x != "ok"))
+            .collect::<rusqlite::Result<_>>()
+            .context("failed to check the database")?;
+        Ok(VerifyReport {
+            images: count("SELECT count(*) FROM images")?,
+            indexed: count("SELECT count(*) FROM images_fts_docsize")?,
+            orphaned: count(
+                "SELECT count(*) FROM images_fts_docsize WHERE id NOT IN (SELECT id FROM images)",
+            )?,
+            missing: count(
+                "SELECT count(*) FROM images WHERE id NOT IN (SELECT id FROM images_fts_docsize)",
+            )?,
+            missing_triggers,
+            integrity_error,
+            database_errors,
+        })
+    }
+
+    /// What's in the index, for `ocrlocate db stats`. Files that are marked for deletion are only
+    /// counted in [`Stats::marked_delete`] and the sizes
+    pub fn stats(&self) -> Result<Stats> {
+        let (pages, text_size, oldest, newest, marked_delete) = self.conn.query_row(
+            "SELECT count(*) FILTER (WHERE NOT mark_delete),
+                coalesce(sum(length(CAST(content AS BLOB))), 0),
+                min(modtime) FILTER (WHERE NOT mark_delete),
+                max(modtime) FILTER (WHERE NOT mark_delete), count(*) FILTER (WHERE mark_delete)
+            FROM images",
+            [],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            },
+        )?;
+        let index_size = self.conn.query_row(
+            "SELECT coalesce(sum(length(block)), 0) FROM images_fts_data",
+            [],
+            |row| row.get(0),
+        )?;
+        let mut files = 0;
+        let mut extensions = BTreeMap::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT path FROM images WHERE NOT mark_delete")?;
+        for path in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            let extension = Path::new(&path?).extension().unwrap_or("").to_lowercase();
+            *extensions.entry(extension).or_default() += 1;
+            files += 1;
         }
-        let results = stmt
-            .query_and_then(params.as_slice(), |row| {
-                Ok(SearchResult {
-                    contents: row.get(0)?,
-                    path: row.get(1)?,
-                    time: row.get(2)?,
-                })
-            })
-            .context("failed to query image index")?;
-        results.collect()
+        Ok(Stats {
+            files,
+            pages,
+            text_size,
+            index_size,
+            oldest,
+            newest,
+            marked_delete,
+            extensions,
+        })
     }
-}
 
-#[derive(Debug)]
-pub struct OcrResult {
-    pub path: PathBuf,
-    pub metadata: fs::Metadata,
-    pub contents: String,
-}
+    /// Restore missing triggers and rebuild the search index from the images table
+    pub fn repair(&mut self) -> Result<()> {
+        let triggers = triggers(self.index_options()?.compress);
+        retry(&self.retry, || {
+            self.conn
+                .execute_batch(&format!(
+                    "BEGIN;
+                    {triggers}
+                    INSERT INTO images_fts (images_fts) VALUES ('rebuild');
+                    COMMIT;"
+                ))
+                .inspect_err(|_| rollback(&self.conn))
+        })
+        .context("failed to repair the search index")
+    }
 
-#[derive(Debug)]
-pub struct SearchResult {
-    pub path: String,
-    pub time: u64,
-    pub contents: String,
-}
+    /// Remove files under the `roots` that don't exist anymore, merge the segments of the search
+    /// index, and move the write-ahead log into the database. Nothing is removed under a root that
+    /// is missing itself, which is more likely an unmounted drive than deleted files
+    pub fn maintain(&mut self, roots: &[PathBuf]) -> Result<Maintenance> {
+        let mut pruned = 0;
+        for root in roots.iter().filter(|x| x.is_dir()) {
+            let mut stmt = self
+                .conn
+                .prepare_cached("SELECT DISTINCT path FROM images WHERE path LIKE ?1 ESCAPE '#'")?;
+            let stale: Vec<PathBuf> = stmt
+                .query_map([path_to_like(root)], |row| row.get::<_, String>(0))?
+                .map(|x| x.map(PathBuf::from))
+                .filter(|x| x.as_ref().map_or(true, |x| !paths::extended(x).exists()))
+                .collect::<rusqlite::Result<_>>()?;
+            drop(stmt);
+            for path in &stale {
+                pruned += self.delete(path)?;
+            }
+        }
+        retry(&self.retry, || {
+            self.conn.execute(
+                "INSERT INTO images_fts (images_fts) VALUES ('optimize')",
+                [],
+            )
+        })
+        .context("failed to optimize the search index")?;
+        Ok(Maintenance {
+            pruned,
+            checkpointed: self.checkpoint()?,
+        })
+    }
 
-fn metadata_to_seconds(m: &fs::Metadata) -> u64 {
+    /// Merge the segments of the search index, move the write-ahead log into the database, and
+    /// rebuild the database without its free pages, for `ocrlocate db optimize`. Rebuilding it
+    /// needs about as much free disk space as the database takes
+    pub fn optimize(&mut self) -> Result<Optimized> {
+        let before = self.size()?;
+        retry(&self.retry, || {
+            self.conn.execute(
+                "INSERT INTO images_fts (images_fts) VALUES ('optimize')",
+                [],
+            )
+        })
+        .context("failed to optimize the search index")?;
+        self.checkpoint()?;
+        retry(&self.retry, || self.conn.execute("VACUUM", []))
+            .context("failed to vacuum the database")?;
+        // which went through the log
+        let checkpointed = self.checkpoint()?;
+        Ok(Optimized {
+            before,
+            after: self.size()?,
+            checkpointed,
+        })
+    }
+
+    /// Move the write-ahead log into the database and truncate it, and whether all of it was. The
+    /// log is left alone if another connection is reading it
+    fn checkpoint(&self) -> Result<bool> {
+        let busy: bool = self
+            .conn
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))
+            .context("failed to checkpoint the database")?;
+        Ok(!busy)
+    }
+
+    /// Bytes of the database, including the pages that are in the write-ahead log
+    fn size(&self) -> Result<u64> {
+        Ok(self.conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    pub fn is_indexed(&self, path: &Path, metadata: &fs::Metadata) -> bool {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT modtime FROM images WHERE path = ?1")
+            .unwrap();
+        let mtime = metadata_to_seconds(metadata);
+        let Some(modtime) = stmt
+            .query_row([stored(path)], |row| row.get::<_, u64>(0))
+            .optional()
+            .with_context(|| format!("failed to check if an image was already indexed: {}", path))
+            .unwrap()
+        else {
+            return false;
+        };
+        if mtime == modtime {
+            return true;
+        }
+        false
+    }
+
+    /// Whether a page of a file was OCRed with other settings than `settings`, see
+    /// `OcrOptions::settings`. Rows without them, like the ones from before they were saved, are
+    /// taken to match
+    pub fn settings_changed(&self, path: &Path, settings: &str) -> bool {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT 1 FROM images
+                    WHERE path = ?1 AND json_extract(properties, '$.settings') != ?2",
+            )
+            .unwrap();
+        stmt.exists((stored(path), settings))
+            .with_context(|| format!("failed to read the OCR settings of {}", path))
+            .unwrap()
+    }
+
+    pub fn save_results(&mut self, results: Vec<OcrResult>) -> Result<usize> {
+        let compressed = self.index_options()?.compress;
+        let conn = &mut self.conn;
+        let shared = self.shared.unwrap_or_default();
+        retry(&self.retry, || {
+            let tx = conn.transaction()?;
+            let rowchanges: usize = {
+                let mut index_stmt = tx.prepare_cached(SAVE_ROW)?;
+                let mut clear_words_stmt =
+                    tx.prepare_cached("DELETE FROM words WHERE image_id = ?1")?;
+                let mut word_stmt = tx.prepare_cached(SAVE_WORD)?;
+                // a document that was rescanned might have lost some pages
+                let mut stale_stmt = tx.prepare_cached(
+                    "DELETE FROM images WHERE path = ?1 AND page NOT IN (SELECT value FROM json_each(?2))",
+                )?;
+                let mut rowchanges = 0;
+                for (path, pages) in &results.iter().group_by(|res| &res.path) {
+                    let mut saved = vec![];
+                    for res in pages {
+                        let id: i64 = index_stmt.query_row(
+                            (
+                                stored(&res.path),
+                                &res.page,
+                                metadata_to_seconds(&res.metadata),
+                                match compressed.then(|| compress(&res.contents)).flatten() {
+                                    Some(x) => Value::Blob(x),
+                                    None => Value::Text(res.contents.clone()),
+                                },
+                                (!res.properties.is_empty())
+                                    .then(|| serde_json::to_string(&res.properties).unwrap()),
+                                res.confidence(),
+                                shared.user,
+                                shared.private,
+                                res.metadata.len(),
+                            ),
+                            |row| row.get(0),
+                        )?;
+                        rowchanges += 1;
+                        clear_words_stmt.execute([id])?;
+                        for word in &res.words {
+                            word_stmt.execute((
+                                id,
+                                &word.text,
+                                word.left,
+                                word.top,
+                                word.width,
+                                word.height,
+                                word.confidence,
+                            ))?;
+                        }
+                        saved.push(&res.page);
+                    }
+                    rowchanges += stale_stmt
+                        .execute((stored(path), serde_json::to_string(&saved).unwrap()))?;
+                }
+                rowchanges
+            };
+            tx.commit()?;
+            Ok(rowchanges)
+        })
+        .context("failed to save results")
+    }
+
+    /// Every row of the index, for `ocrlocate db export`. `f` is called with each one in the order
+    /// of their paths, so the whole index isn't read at once. Returns how many there were
+    pub fn export_records(&self, mut f: impl FnMut(&Record) -> Result<()>) -> Result<usize> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, path, page, modtime, size, unzstd(content), properties, confidence
+                FROM images ORDER BY path, page",
+        )?;
+        let mut words_stmt = self.conn.prepare_cached(
+            "SELECT text, left, top, width, height, confidence FROM words
+                WHERE image_id = ?1 ORDER BY rowid",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            let properties: Option<String> = row.get(6)?;
+            let record = Record {
+                path: row.get(1)?,
+                page: row.get(2)?,
+                modtime: row.get(3)?,
+                size: row.get(4)?,
+                content: row.get(5)?,
+                properties: match properties {
+                    Some(x) => serde_json::from_str(&x)?,
+                    None => BTreeMap::new(),
+                },
+                confidence: row.get(7)?,
+                words: words_stmt
+                    .query_map([row.get::<_, i64>(0)?], word)?
+                    .collect::<rusqlite::Result<_>>()?,
+            };
+            f(&record).with_context(|| format!("failed to export {}", record.path))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Save rows of `ocrlocate db export`, replacing the ones of the same pages. Their text is
+    /// saved as it is, since it was redacted when it was indexed, and compressed if the database
+    /// compresses text
+    pub fn import_records(&mut self, records: &[Record]) -> Result<usize> {
+        let compressed = self.index_options()?.compress;
+        let conn = &mut self.conn;
+        let shared = self.shared.unwrap_or_default();
+        retry(&self.retry, || {
+            let tx = conn.transaction()?;
+            {
+                let mut index_stmt = tx.prepare_cached(SAVE_ROW)?;
+                let mut clear_words_stmt =
+                    tx.prepare_cached("DELETE FROM words WHERE image_id = ?1")?;
+                let mut word_stmt = tx.prepare_cached(SAVE_WORD)?;
+                for record in records {
+                    let id: i64 = index_stmt.query_row(
+                        (
+                            stored(Path::new(&record.path)),
+                            &record.page,
+                            record.modtime,
+                            match compressed.then(|| compress(&record.content)).flatten() {
+                                Some(x) => Value::Blob(x),
+                                None => Value::Text(record.content.clone()),
+                            },
+                            (!record.properties.is_empty())
+                                .then(|| serde_json::to_string(&record.properties).unwrap()),
+                            record.confidence,
+                            shared.user,
+                            shared.private,
+                            record.size,
+                        ),
+                        |row| row.get(0),
+                    )?;
+                    clear_words_stmt.execute([id])?;
+                    for word in &record.words {
+                        word_stmt.execute((
+                            id,
+                            &word.text,
+                            word.left,
+                            word.top,
+                            word.width,
+                            word.height,
+                            word.confidence,
+                        ))?;
+                    }
+                }
+            }
+            tx.commit()?;
+            Ok(records.len())
+        })
+        .context("failed to import rows")
+    }
+
+    /// Fill in [`SearchResult::terms`] with the terms of the query that are in the text of each
+    /// result, ignoring case, and return all of the terms. Glob and regex queries are one term
+    /// which every result matched
+    pub fn matched_terms(
+        &self,
+        results: &mut [SearchResult],
+        queries: &[&str],
+        kind: SearchType,
+    ) -> Result<Vec<String>> {
+        let terms = kind.terms(queries);
+        if !matches!(
+            kind,
+            SearchType::Simple | SearchType::Match | SearchType::Any
+        ) {
+            for result in results {
+                result.terms = terms.clone();
+            }
+            return Ok(terms);
+        }
+        let fold = match self.remove_diacritics()? {
+            true => crate::tokenizer::remove_diacritics,
+            false => str::to_lowercase,
+        };
+        for result in results {
+            let content = fold(
+                &self
+                    .content(Path::new(&result.path), &result.page)?
+                    .unwrap_or_default(),
+            );
+            result.terms = terms
+                .iter()
+                .filter(|term| content.contains(&fold(term)))
+                .cloned()
+                .collect();
+        }
+        Ok(terms)
+    }
+
+    /// The lines of the stored text of a result that match the query, for output like grep's,
+    /// along with `context` lines before and after each of them. Letters match in any case like in
+    /// [`DB::matched_terms`], and a glob has to match a whole line. When a phrase goes over a line
+    /// break, so that no line matches, the snippet of the result is the line
+    pub fn matching_lines(
+        &self,
+        result: &SearchResult,
+        queries: &[&str],
+        kind: SearchType,
+        (before, after): (usize, usize),
+    ) -> Result<Vec<Line>> {
+        let content = self
+            .content(Path::new(&result.path), &result.page)?
+            .unwrap_or_default();
+        let fold = self.remove_diacritics()?;
+        let terms = kind.terms(queries);
+        let glob = match kind {
+            SearchType::Glob => Some(glob::Pattern::new(&queries.join(" "))?),
+            _ => None,
+        };
+        #[cfg(feature = "regex")]
+        let regex = match kind {
+            SearchType::Regex => Some(
+                regex::RegexBuilder::new(&queries.join(" "))
+                    .case_insensitive(self.regex.ignore_case)
+                    .size_limit(self.regex.size_limit)
+                    .build()?,
+            ),
+            _ => None,
+        };
+        let texts: Vec<&str> = content.lines().collect();
+        // the matches of every line, or None if it doesn't match
+        let mut found_lines = vec![];
+        for text in &texts {
+            let found: Vec<Range<usize>> = match kind {
+                SearchType::Glob => {
+                    let options = glob::MatchOptions {
+                        case_sensitive: false,
+                        ..Default::default()
+                    };
+                    // the whole line
+                    (glob.as_ref().unwrap().matches_with(text, options))
+                        .then_some(0..text.len())
+                        .into_iter()
+                        .collect()
+                }
+                #[cfg(feature = "regex")]
+                SearchType::Regex => regex
+                    .as_ref()
+                    .unwrap()
+                    .find_iter(text)
+                    .map(|x| x.range())
+                    .collect(),
+                #[cfg(feature = "semantic")]
+                SearchType::Semantic => {
+                    return Err(anyhow!("semantic searches don't match lines of text"))
+                }
+                _ => terms.iter().flat_map(|x| find_all(text, x, fold)).collect(),
+            };
+            if found.is_empty() {
+                found_lines.push(None);
+                continue;
+            }
+            // the matches of several terms that overlap are one, and empty ones aren't shown
+            let mut matches: Vec<Range<usize>> = vec![];
+            for x in found.into_iter().sorted_by_key(|x| x.start) {
+                match matches.last_mut() {
+                    Some(last) if x.start <= last.end => last.end = last.end.max(x.end),
+                    _ if x.is_empty() => {}
+                    _ => matches.push(x),
+                }
+            }
+            found_lines.push(Some(matches));
+        }
+        if found_lines.iter().all(Option::is_none)
+            && matches!(
+                kind,
+                SearchType::Simple | SearchType::Match | SearchType::Any
+            )
+        {
+            return Ok(self.snippet_line(result, &content).into_iter().collect());
+        }
+        let shown: Vec<bool> = (0..texts.len())
+            .map(|i| {
+                found_lines[i.saturating_sub(after)..(i + before + 1).min(texts.len())]
+                    .iter()
+                    .any(Option::is_some)
+            })
+            .collect();
+        Ok(texts
+            .into_iter()
+            .zip(found_lines)
+            .enumerate()
+            .filter(|(i, _)| shown[*i])
+            .map(|(i, (text, matches))| Line {
+                number: i + 1,
+                text: text.to_owned(),
+                context: matches.is_none(),
+                matches: matches.unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    /// The snippet of a result as a line, with the matches between its markers, numbered by the
+    /// line of `content` that it starts on
+    fn snippet_line(&self, result: &SearchResult, content: &str) -> Option<Line> {
+        let (open, close) = (&*self.snippet.open, &*self.snippet.close);
+        let mut text = String::new();
+        let mut matches = vec![];
+        let mut rest = result.contents.as_str();
+        while let Some((before, after)) = rest.split_once(open).filter(|_| !open.is_empty()) {
+            let Some((found, after)) = after.split_once(close).filter(|_| !close.is_empty()) else {
+                break;
+            };
+            text += before;
+            matches.push(text.len()..text.len() + found.len());
+            text += found;
+            rest = after;
+        }
+        text += rest;
+        // the snippet is the text between its matches as it was stored, after an ellipsis
+        let start = text.trim_start_matches("..").lines().next()?;
+        let number = content
+            .find(start)
+            .map_or(1, |at| content[..at].matches('\n').count() + 1);
+        Some(Line {
+            number,
+            text: text.replace(['\n', '\r'], " "),
+            matches,
+            context: false,
+        })
+    }
+
+    /// The stored text of an indexed file, or of one page of it
+    pub fn content(&self, path: &Path, page: &str) -> Result<Option<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT unzstd(content) FROM images WHERE path = ?1 AND page = ?2")
+            .unwrap();
+        stmt.query_row((stored(path), page), |row| row.get(0))
+            .optional()
+            .with_context(|| format!("failed to get contents of image: {}", path))
+    }
+
+    /// Find files with text like that of `path`, using its most distinctive words as a query
+    ///
+    /// Words are weighed by tf-idf, and the `terms` best ones are searched for with `OR`.
+    pub fn similar(
+        &mut self,
+        path: &Path,
+        root: &Path,
+        limit: usize,
+        terms: usize,
+    ) -> Result<Vec<SearchResult>> {
+        let content: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT group_concat(unzstd(content), ' ') FROM images WHERE path = ?1",
+                [stored(path)],
+                |row| row.get(0),
+            )
+            .with_context(|| format!("failed to get contents of image: {}", path))?;
+        let content = content.ok_or_else(|| anyhow!("not an indexed file: {}", path))?;
+        let query = self.distinctive_query(&content, terms)?;
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut results =
+            self.search(vec![&query], root, limit + 1, SearchType::Match, None, None)?;
+        results.retain(|x| x.path != stored(path));
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    /// A match query for the `terms` most distinctive words of `content` joined with `OR`, or
+    /// an empty one if none of its words are in the index. Words are weighed by tf-idf, with the
+    /// document frequency of the index
+    pub fn distinctive_query(&self, content: &str, terms: usize) -> Result<String> {
+        let total: f64 = self
+            .conn
+            .query_row("SELECT count(*) FROM images", [], |row| row.get(0))?;
+        // looking up the document frequency of every word is slow, so only the most common
+        // words of this file are considered
+        let candidates = word_counts(content)
+            .into_iter()
+            .sorted_by(|a, b| b.1.cmp(&a.1))
+            .take(terms * 5);
+        let mut df_stmt = self
+            .conn
+            .prepare_cached("SELECT count(*) FROM images_fts WHERE images_fts MATCH ?1")?;
+        let mut weighed = vec![];
+        for (word, count) in candidates {
+            let df: f64 = df_stmt.query_row([format!("\"{word}\"")], |row| row.get(0))?;
+            // which no file would be found by
+            if df > 0.0 {
+                weighed.push((word, count as f64 * (total / df).ln()));
+            }
+        }
+        drop(df_stmt);
+        Ok(weighed
+            .into_iter()
+            .filter(|(_, weight)| *weight > 0.0)
+            .sorted_by(|a, b| b.1.total_cmp(&a.1))
+            .take(terms)
+            .map(|(word, _)| format!("\"{word}\""))
+            .join(" OR "))
+    }
+
+    /// The recognized words of an indexed file or page, in reading order
+    pub fn words(&self, path: &Path, page: &str) -> Result<Vec<Word>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT words.text, words.left, words.top, words.width, words.height, words.confidence FROM words
+                INNER JOIN images ON words.image_id = images.id
+                WHERE images.path = ?1 AND images.page = ?2
+                ORDER BY words.rowid",
+        )?;
+        let words = stmt
+            .query_map((stored(path), page), word)
+            .with_context(|| format!("failed to get words of image: {}", path))?;
+        Ok(words.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Path and page of every image under `root` that has word positions stored
+    pub fn images_with_words(&self, root: &Path) -> Result<Vec<(PathBuf, String)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT path, page FROM images
+                WHERE path LIKE ?1 ESCAPE '#'
+                AND EXISTS (SELECT 1 FROM words WHERE words.image_id = images.id)
+                ORDER BY path, page",
+        )?;
+        let images = stmt
+            .query_and_then([path_to_like(root)], |row| {
+                Ok((PathBuf::from(row.get::<_, String>(0)?), row.get(1)?))
+            })
+            .context("failed to list indexed images")?;
+        images.collect()
+    }
+
+    /// Up to `count` random files under `root`, with the properties of one of their rows. Files
+    /// whose text came from somewhere else than indexing, like imported or external text, aren't
+    /// picked
+    pub fn sample(
+        &self,
+        root: &Path,
+        count: usize,
+    ) -> Result<Vec<(PathBuf, BTreeMap<String, String>)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT path, properties FROM images
+                WHERE path LIKE ?1 ESCAPE '#'
+                AND json_extract(properties, '$.source') IS NULL
+                AND json_extract(properties, '$.imported') IS NULL
+                GROUP BY path
+                ORDER BY random()
+                LIMIT ?2",
+        )?;
+        let files = stmt
+            .query_and_then((path_to_like(root), count), |row| {
+                let properties: Option<String> = row.get(1)?;
+                Ok((
+                    PathBuf::from(row.get::<_, String>(0)?),
+                    match properties {
+                        Some(x) => serde_json::from_str(&x)?,
+                        None => BTreeMap::new(),
+                    },
+                ))
+            })
+            .context("failed to sample indexed files")?;
+        files.collect()
+    }
+
+    /// Up to `count` random rows under `root` in random order, only rows that match `queries`
+    /// if there are any. Rows are picked by id instead of sorting the whole index by `random()`
+    pub fn random(
+        &mut self,
+        root: &Path,
+        count: usize,
+        queries: &[&str],
+        kind: SearchType,
+    ) -> Result<Vec<SearchResult>> {
+        #[cfg(feature = "semantic")]
+        if kind == SearchType::Semantic && !queries.is_empty() {
+            return Err(anyhow!("files can't be picked by a semantic search"));
+        }
+        let like = path_to_like(root);
+        let query = kind.query(queries);
+        let visible = match self.shared {
+            Some(shared) => format!(
+                "AND (NOT images.private OR images.owner IS {})",
+                shared.user.map_or("NULL".into(), |x| x.to_string())
+            ),
+            None => String::new(),
+        };
+
+        let probed = if queries.is_empty() {
+            self.probe_ids(&like, &visible, count)?
+        } else {
+            None
+        };
+        let mut ids = match probed {
+            Some(ids) => ids,
+            None => {
+                let mut stmt = if queries.is_empty() {
+                    self.conn.prepare_cached(&format!(
+                        "SELECT id FROM images WHERE path LIKE ?1 ESCAPE '#' {visible}"
+                    ))?
+                } else {
+                    self.conn.prepare_cached(&format!(
+                        "SELECT images.id FROM images_fts
+                            INNER JOIN images ON images_fts.rowid = images.id AND images.path LIKE ?1 ESCAPE '#'
+                            WHERE images_fts.content {} ?2 {visible}",
+                        kind.operator()
+                    ))?
+                };
+                let params: &[&dyn ToSql] = if queries.is_empty() {
+                    &[&like]
+                } else {
+                    &[&like, &query]
+                };
+                let ids = stmt
+                    .query_map(params, |row| row.get(0))
+                    .context("failed to list rows to sample")?;
+                fastrand::choose_multiple(ids, count)
+                    .into_iter()
+                    .collect::<rusqlite::Result<Vec<i64>>>()?
+            }
+        };
+        fastrand::shuffle(&mut ids);
+
+        let mut results = vec![];
+        for id in ids {
+            let result = if queries.is_empty() {
+                self.conn
+                    .prepare_cached(&format!(
+                        "SELECT substr(unzstd(content), 1, {PREVIEW}), path, modtime, page, confidence, size FROM images
+                            WHERE id = ?1"
+                    ))?
+                    .query_row([id], search_result)
+            } else {
+                self.conn
+                    .prepare_cached(&format!(
+                        "SELECT {}, images.path, images.modtime, images.page, images.confidence, images.size
+                            FROM images_fts
+                            INNER JOIN images ON images_fts.rowid = images.id
+                            WHERE images_fts.content {} ?1 AND images_fts.rowid = ?2",
+                        self.snippet_sql(),
+                        kind.operator()
+                    ))?
+                    .query_row((&query, id), search_result)
+            }
+            .context("failed to read sampled row")?;
+            // like in search, rows of files the user can't read are dropped
+            if self.shared.is_none() || is_readable(Path::new(&result.path)) {
+                results.push(result);
+            }
+        }
+        for result in &mut results {
+            result.notes = self.notes(Path::new(&result.path))?;
+        }
+        Ok(results)
+    }
+
+    /// Pick `count` ids of rows under `root` by trying random ids between the smallest and
+    /// largest one. `None` if too few of the tries hit a row, when the rows under `root` are a
+    /// small part of the index or there are fewer than `count`
+    fn probe_ids(&self, like: &str, visible: &str, count: usize) -> Result<Option<Vec<i64>>> {
+        let (min, max): (Option<i64>, Option<i64>) =
+            self.conn
+                .query_row("SELECT min(id), max(id) FROM images", [], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?;
+        let Some((min, max)) = min.zip(max) else {
+            return Ok(Some(vec![]));
+        };
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT 1 FROM images WHERE id = ?1 AND path LIKE ?2 ESCAPE '#' {visible}"
+        ))?;
+        let mut tried = HashSet::new();
+        let mut ids = vec![];
+        for _ in 0..count.saturating_mul(PROBES) {
+            if ids.len() == count {
+                break;
+            }
+            let id = fastrand::i64(min..=max);
+            if tried.insert(id) && stmt.exists((id, like))? {
+                ids.push(id);
+            }
+        }
+        Ok((ids.len() == count).then_some(ids))
+    }
+
+    /// Rows under `root` whose `key` property isn't `value`, with their text and properties
+    pub fn property_mismatches(
+        &self,
+        root: &Path,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<Vec<PropertyRow>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT path, page, unzstd(content), properties FROM images
+                WHERE path LIKE ?1 ESCAPE '#' AND json_extract(properties, '$.' || ?2) IS NOT ?3",
+        )?;
+        let rows = stmt
+            .query_and_then((path_to_like(root), key, value), |row| {
+                let properties: Option<String> = row.get(3)?;
+                Ok(PropertyRow {
+                    path: PathBuf::from(row.get::<_, String>(0)?),
+                    page: row.get(1)?,
+                    contents: row.get(2)?,
+                    properties: match properties {
+                        Some(x) => serde_json::from_str(&x)?,
+                        None => BTreeMap::new(),
+                    },
+                })
+            })
+            .context("failed to read properties")?;
+        rows.collect()
+    }
+
+    pub fn set_properties(
+        &mut self,
+        path: &Path,
+        page: &str,
+        properties: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let properties =
+            (!properties.is_empty()).then(|| serde_json::to_string(properties).unwrap());
+        retry(&self.retry, || {
+            self.conn.execute(
+                "UPDATE images SET properties = ?3 WHERE path = ?1 AND page = ?2",
+                (stored(path), page, &properties),
+            )
+        })
+        .with_context(|| format!("failed to save properties of {}", path))?;
+        Ok(())
+    }
+
+    /// Make the next index OCR a file again, even if it didn't change
+    pub fn invalidate(&mut self, path: &Path) -> Result<()> {
+        retry(&self.retry, || {
+            self.conn.execute(
+                "UPDATE images SET modtime = 0 WHERE path = ?1",
+                [stored(path)],
+            )
+        })
+        .with_context(|| format!("failed to invalidate {}", path))?;
+        Ok(())
+    }
+
+    /// Whether any page of a file is in the index
+    pub fn is_path_indexed(&self, path: &Path) -> Result<bool> {
+        self.conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM images WHERE path = ?1)",
+                [stored(path)],
+                |row| row.get(0),
+            )
+            .with_context(|| format!("failed to check if a file is indexed: {}", path))
+    }
+
+    /// Mark the elements of a directory for deletion in the DB
+    pub fn mark_for_deletion(&mut self, path: &Path) {
+        if !path.is_dir() {
+            panic!(
+                "Incorrect usage of `mark_for_deletion`: Path `{}` should be a directory",
+                path
+            );
+        }
+
+        retry(&self.retry, || {
+            self.conn.execute(
+                "UPDATE images SET mark_delete = FALSE WHERE mark_delete = TRUE",
+                [],
+            )
+        })
+        .expect("failed to preliminarily unmark previously marked images for deletion");
+        let mut stmt = self
+            .conn
+            .prepare_cached("UPDATE images SET mark_delete = TRUE WHERE path LIKE ?1 ESCAPE '#'")
+            .expect("failed to preliminarily mark subdirectory for deletion");
+
+        retry(&self.retry, || stmt.execute([path_to_like(path)])).unwrap();
+    }
+
+    pub fn unmark_file(&mut self, path: &Path) {
+        let mut stmt = self
+            .conn
+            .prepare_cached("UPDATE images SET mark_delete = FALSE WHERE path = ?1")
+            .with_context(|| format!("failed to unmark image for deletion: {}", path))
+            .unwrap();
+
+        retry(&self.retry, || stmt.execute([stored(path)])).unwrap();
+    }
+
+    /// Files that are still marked by [`DB::mark_for_deletion`]
+    pub fn marked_for_deletion(&self) -> Result<Vec<PathBuf>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT DISTINCT path FROM images WHERE mark_delete = TRUE")?;
+        let paths = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|x| x.map(PathBuf::from))
+            .try_collect()?;
+        Ok(paths)
+    }
+
+    pub fn sweep_deletions(&mut self) -> usize {
+        retry(&self.retry, || {
+            self.conn
+                .execute("DELETE FROM images WHERE mark_delete = TRUE", [])
+        })
+        .expect("failed to delete marked images")
+    }
+
+    /// Remove every page of a file from the index
+    pub fn delete(&mut self, path: &Path) -> Result<usize> {
+        retry(&self.retry, || {
+            self.conn
+                .execute("DELETE FROM images WHERE path = ?1", [stored(path)])
+        })
+        .with_context(|| format!("failed to delete from index: {}", path))
+    }
+
+    /// Remove every file under `dir` from the index
+    pub fn delete_under(&mut self, dir: &Path) -> Result<usize> {
+        let like = path_to_like(dir);
+        retry(&self.retry, || {
+            self.conn
+                .execute("DELETE FROM images WHERE path LIKE ?1 ESCAPE '#'", [&like])
+        })
+        .with_context(|| format!("failed to delete from index: {}", dir))
+    }
+
+    /// Replace the links of every note under `root` with `links`, which are the images that each
+    /// note embeds
+    pub fn save_note_links(
+        &mut self,
+        root: &Path,
+        links: &BTreeMap<PathBuf, Vec<PathBuf>>,
+    ) -> Result<()> {
+        let root = path_to_like(root);
+        let conn = &mut self.conn;
+        retry(&self.retry, || {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "DELETE FROM note_links WHERE note LIKE ?1 ESCAPE '#'",
+                [&root],
+            )?;
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT OR IGNORE INTO note_links (note, image) VALUES (?1, ?2)",
+                )?;
+                for (note, images) in links {
+                    for image in images {
+                        stmt.execute([stored(note), stored(image)])?;
+                    }
+                }
+            }
+            tx.commit()
+        })
+        .context("failed to save note links")
+    }
+
+    /// The notes that embed an image
+    pub fn notes(&self, image: &Path) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT note FROM note_links WHERE image = ?1 ORDER BY note")
+            .unwrap();
+        let notes = stmt.query_map([stored(image)], |row| row.get(0))?;
+        Ok(notes.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Save the embeddings of the model of [`DB::set_embedder`] for the rows under `root` that
+    /// don't have one yet, returning how many rows were embedded
+    #[cfg(feature = "semantic")]
+    pub fn embed(&mut self, root: &Path) -> Result<usize> {
+        let mut embedder = self
+            .embedder
+            .take()
+            .ok_or_else(|| anyhow!("embedding text needs an embedding model"))?;
+        let model = embedder.name().to_owned();
+        let embedded = self.embed_missing(root, &model, |texts| embedder.embed(texts));
+        self.embedder = Some(embedder);
+        embedded
+    }
+
+    /// Save the embeddings that `embed` makes of the text of the rows under `root` that don't
+    /// have one of `model` yet, [`EMBED_BATCH`] rows at a time. Returns how many rows were
+    /// embedded
+    pub fn embed_missing(
+        &mut self,
+        root: &Path,
+        model: &str,
+        mut embed: impl FnMut(&[&str]) -> Result<Vec<Vec<f32>>>,
+    ) -> Result<usize> {
+        let ids: Vec<i64> = self
+            .conn
+            .prepare(
+                "SELECT images.id FROM images
+                    LEFT JOIN embeddings ON embeddings.image_id = images.id AND embeddings.model = ?2
+                    WHERE images.path LIKE ?1 ESCAPE '#' AND embeddings.image_id IS NULL",
+            )?
+            .query_map((path_to_like(root), model), |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()
+            .context("failed to list rows without embeddings")?;
+        for batch in ids.chunks(EMBED_BATCH) {
+            let mut stmt = self
+                .conn
+                .prepare_cached("SELECT unzstd(content) FROM images WHERE id = ?1")?;
+            let texts = batch
+                .iter()
+                .map(|id| stmt.query_row([id], |row| row.get(0)))
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            drop(stmt);
+            let vectors = embed(&texts.iter().map(|x| x.as_str()).collect_vec())?;
+            let conn = &mut self.conn;
+            retry(&self.retry, || {
+                let tx = conn.transaction()?;
+                {
+                    let mut stmt = tx.prepare_cached(
+                        "INSERT OR REPLACE INTO embeddings (image_id, model, vector) VALUES (?1, ?2, ?3)",
+                    )?;
+                    for (id, vector) in batch.iter().zip(&vectors) {
+                        stmt.execute((id, model, vector_to_blob(vector)))?;
+                    }
+                }
+                tx.commit()
+            })
+            .context("failed to save embeddings")?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Save the embeddings that `embed` makes of the files of the rows under `root` that aren't
+    /// pages of documents and don't have one of `model` yet. `embed` returns None for files that
+    /// aren't images, which are saved without an embedding so they aren't tried again. Returns
+    /// how many rows were embedded
+    pub fn embed_images(
+        &mut self,
+        root: &Path,
+        model: &str,
+        mut embed: impl FnMut(&Path) -> Result<Option<Vec<f32>>>,
+    ) -> Result<usize> {
+        let rows: Vec<(i64, String)> = self
+            .conn
+            .prepare(
+                "SELECT images.id, images.path FROM images
+                    LEFT JOIN image_embeddings ON image_embeddings.image_id = images.id AND image_embeddings.model = ?2
+                    WHERE images.path LIKE ?1 ESCAPE '#' AND images.page = '' AND image_embeddings.image_id IS NULL",
+            )?
+            .query_map((path_to_like(root), model), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()
+            .context("failed to list images without embeddings")?;
+        for batch in rows.chunks(EMBED_BATCH) {
+            let vectors = batch
+                .iter()
+                .map(|(_, path)| embed(Path::new(path)))
+                .collect::<Result<Vec<_>>>()?;
+            let conn = &mut self.conn;
+            retry(&self.retry, || {
+                let tx = conn.transaction()?;
+                {
+                    let mut stmt = tx.prepare_cached(
+                        "INSERT OR REPLACE INTO image_embeddings (image_id, model, vector) VALUES (?1, ?2, ?3)",
+                    )?;
+                    for ((id, _), vector) in batch.iter().zip(&vectors) {
+                        let blob = vector.as_deref().map_or(vec![], vector_to_blob);
+                        stmt.execute((id, model, blob))?;
+                    }
+                }
+                tx.commit()
+            })
+            .context("failed to save image embeddings")?;
+        }
+        Ok(rows.len())
+    }
+
+    /// Rows under `path` ordered by the cosine similarity of their embedding of `model` with
+    /// `query`. Rows without an embedding aren't found
+    pub fn semantic_search(
+        &mut self,
+        query: &[f32],
+        model: &str,
+        path: &Path,
+        limit: usize,
+        exclude_glob: Option<&str>,
+        min_confidence: Option<f32>,
+    ) -> Result<Vec<SearchResult>> {
+        let filters = (exclude_glob, min_confidence);
+        self.nearest("embeddings", query, model, path, limit, filters)
+    }
+
+    /// Images under `path` ordered by the cosine similarity of their embedding of `model` with
+    /// `query`, the embedding of what an image looks like, see [`DB::embed_images`]
+    pub fn similar_images(
+        &mut self,
+        query: &[f32],
+        model: &str,
+        path: &Path,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>> {
+        self.nearest("image_embeddings", query, model, path, limit, (None, None))
+    }
+
+    /// Rows under `path` ordered by the cosine similarity of their embedding of `model` in
+    /// `table` with `query`, and filtered by an exclude glob, a minimum confidence and the excluded
+    /// terms like in [`DB::search`]
+    fn nearest(
+        &mut self,
+        table: &str,
+        query: &[f32],
+        model: &str,
+        path: &Path,
+        limit: usize,
+        (exclude_glob, min_confidence): (Option<&str>, Option<f32>),
+    ) -> Result<Vec<SearchResult>> {
+        let query = normalized(query);
+        if let Some(glob) = exclude_glob {
+            glob::Pattern::new(&format!("{glob}/**"))
+                .with_context(|| format!("invalid exclude pattern: {glob}"))?;
+        }
+        let mut stmt = self
+            .conn
+            .prepare_cached(&format!(r#"
+                SELECT unzstd(images.content), images.path, images.modtime, images.page, images.confidence, images.size, {table}.vector
+                    FROM {table}
+                    INNER JOIN images ON {table}.image_id = images.id AND images.path LIKE ?1 ESCAPE '#'
+                    WHERE {table}.model = ?2 AND length({table}.vector) > 0 {exclude} {confidence} {visible} {modified} {lang}
+                "#,
+                modified=self.modified_condition(), lang=self.lang_condition(),
+                exclude=if exclude_glob.is_some() {"AND NOT rust_glob(?3||'/**', images.path)"} else {""},
+                confidence=if min_confidence.is_some() {"AND (images.confidence IS NULL OR images.confidence >= ?4)"} else {""},
+                visible=if self.shared.is_some() {"AND (NOT images.private OR images.owner IS ?5)"} else {""}),
+            )
+            .unwrap();
+        let fixed_path = path_to_like(path);
+        let user = self.shared.and_then(|x| x.user);
+        let mut params = vec![&fixed_path as &dyn ToSql, &model as &dyn ToSql];
+        if exclude_glob.is_some() || min_confidence.is_some() || self.shared.is_some() {
+            params.push(&exclude_glob as &dyn ToSql);
+        }
+        if min_confidence.is_some() || self.shared.is_some() {
+            params.push(&min_confidence as &dyn ToSql);
+        }
+        if self.shared.is_some() {
+            params.push(&user as &dyn ToSql);
+        }
+        let rows = stmt
+            .query_map(params.as_slice(), |row| {
+                Ok((
+                    search_result(row)?,
+                    blob_to_vector(&row.get::<_, Vec<u8>>(6)?),
+                ))
+            })
+            .context("failed to query embeddings")?;
+        let mut scored = vec![];
+        for row in rows {
+            let (result, vector) = row?;
+            if vector.len() != query.len() {
+                return Err(anyhow!(
+                    "the embeddings of {model} have {} dimensions, but the query has {}",
+                    vector.len(),
+                    query.len()
+                ));
+            }
+            let similarity: f32 = vector.iter().zip(&query).map(|(a, b)| a * b).sum();
+            scored.push((similarity, result));
+        }
+        drop(stmt);
+        let fold = self.remove_diacritics()?;
+        let mut results: Vec<SearchResult> = scored
+            .into_iter()
+            .sorted_by(|a, b| b.0.total_cmp(&a.0).then(b.1.time.cmp(&a.1.time)))
+            // the excluded terms can't be part of the query, so they're looked for in the text
+            .filter(|(_, x)| {
+                !self
+                    .excluded
+                    .iter()
+                    .any(|term| snippet(&x.contents, term, fold, &self.snippet).is_some())
+            })
+            .map(|(_, mut result)| {
+                result.contents = preview(&result.contents);
+                result
+            })
+            // like in search, rows of files the user can't read are dropped
+            .filter(|x| self.shared.is_none() || is_readable(Path::new(&x.path)))
+            .take(limit)
+            .collect();
+        for result in &mut results {
+            result.notes = self.notes(Path::new(&result.path))?;
+        }
+        Ok(results)
+    }
+
+    pub fn search(
+        &mut self,
+        queries: Vec<&str>,
+        path: &Path,
+        limit: usize,
+        kind: SearchType,
+        exclude_glob: Option<&str>,
+        min_confidence: Option<f32>,
+    ) -> Result<Vec<SearchResult>> {
+        #[cfg(feature = "semantic")]
+        if kind == SearchType::Semantic {
+            let mut embedder = self
+                .embedder
+                .take()
+                .ok_or_else(|| anyhow!("semantic searches need an embedding model"))?;
+            let embedded = embedder.embed(&[&queries.join(" ")]);
+            let model = embedder.name().to_owned();
+            self.embedder = Some(embedder);
+            let query = embedded?.pop().unwrap();
+            return self.semantic_search(&query, &model, path, limit, exclude_glob, min_confidence);
+        }
+        let options = self.index_options()?;
+        let mut query = kind.query(&queries);
+        // the phrases that a compact index can only find the tokens of, checked after the query
+        let phrases = match kind {
+            _ if !options.compact => vec![],
+            SearchType::Simple => vec![queries.join(" ")],
+            SearchType::Any => queries.iter().map(|x| x.to_string()).collect(),
+            _ => vec![],
+        };
+        // the query of each phrase of an `any` search, whose results are ordered by how many of
+        // them they match
+        let mut groups: Vec<String> = match kind {
+            SearchType::Any => queries
+                .iter()
+                .map(|x| SearchType::Simple.query(&[x]))
+                .collect(),
+            _ => vec![],
+        };
+        if !phrases.is_empty() {
+            groups = phrases
+                .iter()
+                .map(|x| index_terms(options.tokenizer, x))
+                .filter(|x| !x.is_empty())
+                .map(|terms| {
+                    terms
+                        .iter()
+                        .map(|x| format!(r#""{}""#, x.replace('"', r#""""#)))
+                        .join(" ")
+                })
+                .collect();
+            if groups.is_empty() {
+                return Ok(vec![]);
+            }
+            query = groups.iter().map(|x| format!("({x})")).join(" OR ");
+        }
+        if !self.excluded.is_empty() {
+            if !matches!(
+                kind,
+                SearchType::Simple | SearchType::Match | SearchType::Any
+            ) {
+                return Err(anyhow!(
+                    "terms can only be excluded from simple, match and any searches"
+                ));
+            }
+            // which a compact index can't look for either, so they're checked after the query
+            if !options.compact {
+                let excluded =
+                    SearchType::Any.query(&self.excluded.iter().map(|x| &**x).collect_vec());
+                query = format!("({query}) NOT ({excluded})");
+            }
+        }
+        // rust_glob only fails on the rows it gets to
+        if let Some(glob) = exclude_glob {
+            glob::Pattern::new(&format!("{glob}/**"))
+                .with_context(|| format!("invalid exclude pattern: {glob}"))?;
+        }
+
+        // a regex runs on every row, which can take long enough for a big database that it's
+        // stopped
+        #[cfg(feature = "regex")]
+        let _deadline = match (kind, self.regex.time_limit) {
+            (SearchType::Regex, Some(limit)) => Some(Deadline::new(&self.conn, limit)),
+            _ => None,
+        };
+
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                &format!(r#"
+                SELECT {snippet}, images.path, images.modtime, images.page, images.confidence, images.size
+                    FROM images_fts
+                    INNER JOIN images ON images_fts.rowid = images.id AND images.path LIKE ?2 ESCAPE '#'
+                    WHERE {column} {kind} ?1 {exclude} {confidence} {visible} {modified} {lang}
+                    ORDER BY {order}
+                    LIMIT ?3;
+                "#, kind=kind.operator(), modified=self.modified_condition(), lang=self.lang_condition(),
+                order=self.order((kind == SearchType::Any).then(|| matched_count(&groups))),
+                // which is the same for the one column, but a compact index can't be queried by
+                // column
+                column=if options.compact && matches!(kind, SearchType::Simple | SearchType::Match | SearchType::Any) {"images_fts"} else {"images_fts.content"},
+                // a compact index doesn't know where its tokens are, so the snippet is made here
+                snippet=if options.compact {"unzstd(images.content)".into()} else {self.snippet_sql()},
+                exclude=if exclude_glob.is_some() {"AND NOT rust_glob(?4||'/**', images.path)"} else {""},
+                // files that weren't OCRed don't have a confidence
+                confidence=if min_confidence.is_some() {"AND (images.confidence IS NULL OR images.confidence >= ?5)"} else {""},
+                visible=if self.shared.is_some() {"AND (NOT images.private OR images.owner IS ?6)"} else {""}),
+            )
+            .unwrap();
+        let fixed_path = path_to_like(path);
+        let user = self.shared.and_then(|x| x.user);
+        // results that the user can't read or that don't have the phrase are dropped after the
+        // query
+        let filtered = self.shared.is_some() || options.compact;
+        let sql_limit = if filtered { -1 } else { limit as i64 };
+        let mut params = vec![
+            &query as &dyn ToSql,
+            &fixed_path as &dyn ToSql,
+            &sql_limit as &dyn ToSql,
+        ];
+        if exclude_glob.is_some() || min_confidence.is_some() || self.shared.is_some() {
+            params.push(&exclude_glob as &dyn ToSql);
+        }
+        if min_confidence.is_some() || self.shared.is_some() {
+            params.push(&min_confidence as &dyn ToSql);
+        }
+        if self.shared.is_some() {
+            params.push(&user as &dyn ToSql);
+        }
+        let results = stmt
+            .query_and_then(params.as_slice(), |row| Ok(search_result(row)?))
+            .with_context(|| match options.compact {
+                true => "failed to query image index, which is compact and only finds simple, glob and regex searches",
+                false => "failed to query image index",
+            })?;
+        let mut results: Vec<SearchResult> = if filtered {
+            results
+                .filter_map(|x| match x {
+                    Ok(mut x) if options.compact => {
+                        if self.excluded.iter().any(|term| {
+                            snippet(&x.contents, term, options.remove_diacritics, &self.snippet)
+                                .is_some()
+                        }) {
+                            return None;
+                        }
+                        x.contents = match phrases.is_empty() {
+                            false => phrases.iter().find_map(|phrase| {
+                                snippet(
+                                    &x.contents,
+                                    phrase,
+                                    options.remove_diacritics,
+                                    &self.snippet,
+                                )
+                            })?,
+                            true => preview(&x.contents),
+                        };
+                        Some(Ok(x))
+                    }
+                    x => Some(x),
+                })
+                .filter(|x| {
+                    self.shared.is_none()
+                        || x.as_ref().map_or(true, |x| is_readable(Path::new(&x.path)))
+                })
+                .take(limit)
+                .collect::<Result<_>>()
+                .map_err(timed_out)?
+        } else {
+            results.collect::<Result<_>>().map_err(timed_out)?
+        };
+        drop(stmt);
+        for result in &mut results {
+            result.notes = self.notes(Path::new(&result.path))?;
+        }
+        Ok(results)
+    }
+}
+
+/// The clearer error of a search that a [`Deadline`] stopped
+fn timed_out(e: anyhow::Error) -> anyhow::Error {
+    match e.downcast_ref::<rusqlite::Error>() {
+        Some(x) if x.sqlite_error_code() == Some(ErrorCode::OperationInterrupted) => e.context(
+            "the regex search took too long and was stopped, search a smaller directory or raise the time limit",
+        ),
+        _ => e,
+    }
+}
+
+/// Stops the statements of a connection once a time limit has passed, until it's dropped
+#[cfg(feature = "regex")]
+struct Deadline<'a>(&'a Connection);
+
+#[cfg(feature = "regex")]
+impl<'a> Deadline<'a> {
+    fn new(conn: &'a Connection, limit: Duration) -> Self {
+        let end = std::time::Instant::now() + limit;
+        conn.progress_handler(PROGRESS_OPS, Some(move || std::time::Instant::now() >= end));
+        Deadline(conn)
+    }
+}
+
+#[cfg(feature = "regex")]
+impl Drop for Deadline<'_> {
+    fn drop(&mut self) {
+        self.0.progress_handler(0, None::<fn() -> bool>);
+    }
+}
+
+/// Instructions sqlite runs between the checks of a [`Deadline`]
+#[cfg(feature = "regex")]
+const PROGRESS_OPS: i32 = 100;
+
+/// Random ids tried for every row that [`DB::random`] picks, before it lists all of the rows
+const PROBES: usize = 20;
+/// Characters of the text shown for rows that [`DB::random`] picked without a query
+const PREVIEW: usize = 100;
+
+/// Rows that [`DB::embed_missing`] embeds at once
+pub const EMBED_BATCH: usize = 16;
+
+/// Saving a page of a file, or replacing it if it was saved before, see [`DB::save_results`]
+const SAVE_ROW: &str = "INSERT INTO images (path, page, modtime, content, properties, confidence, owner, private, size) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9) ON CONFLICT(path, page) DO UPDATE SET mark_delete=FALSE, modtime=excluded.modtime, content=excluded.content, properties=excluded.properties, confidence=excluded.confidence, owner=excluded.owner, private=excluded.private, size=excluded.size RETURNING id";
+const SAVE_WORD: &str = "INSERT INTO words (image_id, text, left, top, width, height, confidence) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+
+/// Triggers that keep `images_fts` in sync with `images`
+const TRIGGERS: [&str; 3] = ["images_insert", "images_delete", "images_update"];
+
+/// Result of [`DB::property_mismatches`]
+#[derive(Debug)]
+pub struct PropertyRow {
+    pub path: PathBuf,
+    pub page: String,
+    pub contents: String,
+    pub properties: BTreeMap<String, String>,
+}
+
+/// Result of [`DB::maintain`]
+#[derive(Debug, Clone, Serialize)]
+pub struct Maintenance {
+    /// Rows of files that don't exist anymore that were removed
+    pub pruned: usize,
+    /// Whether the whole write-ahead log was moved into the database
+    pub checkpointed: bool,
+}
+
+/// Result of [`DB::optimize`]
+#[derive(Debug)]
+pub struct Optimized {
+    /// Bytes of the database before and after
+    pub before: u64,
+    pub after: u64,
+    /// Whether the whole write-ahead log was moved into the database
+    pub checkpointed: bool,
+}
+
+/// Result of [`DB::verify`]
+#[derive(Debug)]
+pub struct VerifyReport {
+    /// Rows in the images table
+    pub images: usize,
+    /// Rows in the search index
+    pub indexed: usize,
+    /// Index entries whose image doesn't exist anymore
+    pub orphaned: usize,
+    /// Images that aren't in the index
+    pub missing: usize,
+    pub missing_triggers: Vec<String>,
+    /// Error from the fts5 integrity check
+    pub integrity_error: Option<String>,
+    /// Problems that `PRAGMA integrity_check` found in the database file itself, which rebuilding
+    /// the search index can't fix
+    pub database_errors: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.images == self.indexed
+            && self.orphaned == 0
+            && self.missing == 0
+            && self.missing_triggers.is_empty()
+            && self.integrity_error.is_none()
+            && self.database_errors.is_empty()
+    }
+}
+
+/// Result of [`DB::stats`]
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    /// Files in the index, counting a document once however many pages it has
+    pub files: usize,
+    /// Rows in the images table, one for every page of a document
+    pub pages: usize,
+    /// Bytes of the stored text, after compressing it if the database does, also of the pages that
+    /// are marked for deletion
+    pub text_size: u64,
+    /// Bytes of the blocks of the search index
+    pub index_size: u64,
+    /// Earliest and latest modified time of the files, in seconds since the epoch
+    pub oldest: Option<i64>,
+    pub newest: Option<i64>,
+    /// Pages of files that weren't found while indexing, which --cleanup checks again and deletes
+    pub marked_delete: usize,
+    /// Files by their lowercase extension, which is empty for files without one
+    pub extensions: BTreeMap<String, usize>,
+}
+
+/// A row of the index as a line of `ocrlocate db export`, with everything that's needed to
+/// import it into another database without OCRing the file again
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub path: String,
+    #[serde(default)]
+    pub page: String,
+    /// Seconds since the epoch, which indexing compares to the file to see if it changed
+    pub modtime: i64,
+    #[serde(default)]
+    pub size: Option<u64>,
+    pub content: String,
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+    #[serde(default)]
+    pub confidence: Option<f32>,
+    #[serde(default)]
+    pub words: Vec<Word>,
+}
+
+#[derive(Debug)]
+pub struct OcrResult {
+    pub path: PathBuf,
+    /// Page of a document, empty for single images
+    pub page: String,
+    pub metadata: fs::Metadata,
+    pub contents: String,
+    pub properties: BTreeMap<String, String>,
+    pub words: Vec<Word>,
+}
+
+impl OcrResult {
+    /// Mean confidence of the words, which is what tesseract reports as the confidence of the
+    /// whole image. `None` if nothing was recognized, or the file wasn't OCRed
+    pub fn confidence(&self) -> Option<f32> {
+        (!self.words.is_empty())
+            .then(|| self.words.iter().map(|x| x.confidence).sum::<f32>() / self.words.len() as f32)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub path: String,
+    pub page: String,
+    pub time: u64,
+    /// Bytes of the file, or None if it was indexed before sizes were saved
+    pub size: Option<u64>,
+    pub contents: String,
+    /// Mean confidence of the words, from 0 to 100
+    pub confidence: Option<f32>,
+    /// Terms of the query that the text contains, once filled in by [`DB::matched_terms`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub terms: Vec<String>,
+    /// Notes that embed the file, see [`crate::notes`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+/// A line of the text of a result, see [`DB::matching_lines`]
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    /// From 1, and from the start of the page for documents
+    pub number: usize,
+    pub text: String,
+    /// Byte ranges of the text that match the query
+    pub matches: Vec<Range<usize>>,
+    /// A line around the ones that match, which doesn't match itself
+    pub context: bool,
+}
+
+/// A [`SearchResult`] from a row of the snippet, path, modtime, page, confidence and size
+fn search_result(row: &rusqlite::Row) -> rusqlite::Result<SearchResult> {
+    Ok(SearchResult {
+        contents: row.get(0)?,
+        path: row.get(1)?,
+        time: row.get(2)?,
+        page: row.get(3)?,
+        confidence: row.get(4)?,
+        size: row.get(5)?,
+        terms: vec![],
+        notes: vec![],
+    })
+}
+
+/// The parts of `query` that are each a single token of an index with this tokenizer, which a
+/// compact index looks up instead of the phrase of all of them
+fn index_terms(tokenizer: Tokenizer, query: &str) -> Vec<String> {
+    let mut ranges = vec![];
+    let mut push = |_: &str, start, end| -> Result<(), ()> {
+        ranges.push((start, end));
+        Ok(())
+    };
+    let _ = match tokenizer {
+        Tokenizer::Trigram => {
+            let bounds = query.char_indices().map(|(i, _)| i).chain([query.len()]);
+            let bounds: Vec<usize> = bounds.collect();
+            bounds.windows(4).try_for_each(|x| push("", x[0], x[3]))
+        }
+        Tokenizer::Script => crate::tokenizer::tokenize(query, false, push),
+        Tokenizer::Words => crate::tokenizer::tokenize_words(query, false, push),
+        Tokenizer::Unicode61 => query
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|x| !x.is_empty())
+            .try_for_each(|x| {
+                let start = x.as_ptr() as usize - query.as_ptr() as usize;
+                push(x, start, start + x.len())
+            }),
+    };
+    ranges
+        .into_iter()
+        .map(|(start, end)| query[start..end].to_owned())
+        .unique()
+        .collect()
+}
+
+/// The text around the first place where `content` has `phrase`, with the phrase between the
+/// markers of `options` like the snippets of the search index, or `None` if it doesn't have it.
+/// Letters match in any case, and without their accents with `fold`
+fn snippet(content: &str, phrase: &str, fold: bool, options: &SnippetOptions) -> Option<String> {
+    let chars: Vec<(usize, char)> = content.char_indices().collect();
+    let (haystack, origin) = normalized_text(content, fold);
+    let needle = normalized_text(phrase, fold).0;
+    if needle.is_empty() {
+        return None;
+    }
+    let at = haystack.find(&needle)?;
+    let (first, last) = (origin[at], origin[at + needle.len() - 1] + 1);
+    let context = options.length / 2;
+    let (from, to) = (
+        first.saturating_sub(context),
+        (last + context).min(chars.len()),
+    );
+    let text = |a: usize, b: usize| {
+        let end = chars.get(b).map_or(content.len(), |x| x.0);
+        &content[chars.get(a).map_or(end, |x| x.0)..end]
+    };
+    Some(format!(
+        "{}{}{}{}{}{}{}",
+        if from > 0 { ".." } else { "" },
+        text(from, first),
+        options.open,
+        text(first, last),
+        options.close,
+        text(last, to),
+        if to < chars.len() { ".." } else { "" },
+    ))
+}
+
+/// The byte ranges of every place where `content` has `phrase`, with letters in any case and
+/// without their accents with `fold`
+fn find_all(content: &str, phrase: &str, fold: bool) -> Vec<Range<usize>> {
+    let chars: Vec<usize> = content.char_indices().map(|x| x.0).collect();
+    let (haystack, origin) = normalized_text(content, fold);
+    let needle = normalized_text(phrase, fold).0;
+    if needle.is_empty() {
+        return vec![];
+    }
+    haystack
+        .match_indices(&needle)
+        .map(|(at, _)| {
+            let last = origin[at + needle.len() - 1] + 1;
+            chars[origin[at]]..chars.get(last).copied().unwrap_or(content.len())
+        })
+        .collect()
+}
+
+/// `text` in lowercase, and without accents with `fold`, along with the index of the character
+/// of `text` that each of its bytes came from
+fn normalized_text(text: &str, fold: bool) -> (String, Vec<usize>) {
+    let mut origin = vec![];
+    let mut normal = String::new();
+    for (i, c) in text.chars().enumerate() {
+        let lower: String = c.to_lowercase().collect();
+        let c = match fold {
+            true => crate::tokenizer::remove_diacritics(&lower),
+            false => lower,
+        };
+        origin.extend(std::iter::repeat_n(i, c.len()));
+        normal += &c;
+    }
+    (normal, origin)
+}
+
+/// SQL of how many of the fts5 queries a row of `images` matches
+fn matched_count(queries: &[String]) -> String {
+    queries
+        .iter()
+        .map(|x| {
+            format!(
+                "(images.id IN (SELECT rowid FROM images_fts WHERE images_fts MATCH '{}'))",
+                x.replace('\'', "''")
+            )
+        })
+        .join(" + ")
+}
+
+/// The start of `content`, for results of a compact index that aren't of a phrase and of
+/// semantic searches
+fn preview(content: &str) -> String {
+    match content.char_indices().nth(PREVIEW) {
+        Some((i, _)) => format!("{}..", &content[..i]),
+        None => content.to_owned(),
+    }
+}
+
+/// An embedding as it's stored, normalized to a length of 1 so that the cosine similarity of two
+/// of them is their dot product, in little-endian f32s
+fn vector_to_blob(vector: &[f32]) -> Vec<u8> {
+    normalized(vector)
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect()
+}
+
+/// The vector with a length of 1, or all zeroes if it was
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let length = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    match length > 0.0 {
+        true => vector.iter().map(|x| x / length).collect(),
+        false => vector.to_vec(),
+    }
+}
+
+fn blob_to_vector(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+        .collect()
+}
+
+/// Whether this process can read a file, checked by opening it so that every permission applies
+fn is_readable(path: &Path) -> bool {
+    fs::File::open(path).is_ok()
+}
+
+/// Whether an error is caused by another connection holding a lock, and might go away by itself
+pub fn is_transient(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Run `f` until it succeeds, fails with an error that isn't transient, or runs out of attempts
+fn retry<T>(
+    options: &RetryOptions,
+    mut f: impl FnMut() -> rusqlite::Result<T>,
+) -> rusqlite::Result<T> {
+    let mut backoff = options.backoff;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(e) if is_transient(&e) && attempt < options.attempts => {
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Roll back a transaction that an `execute_batch` left open after failing halfway through
+fn rollback(conn: &Connection) {
+    if !conn.is_autocommit() {
+        let _ = conn.execute_batch("ROLLBACK");
+    }
+}
+
+/// How much of the words of two texts are the same, from 0 to 1
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (word_counts(a), word_counts(b));
+    let total = a.values().sum::<usize>().max(b.values().sum());
+    if total == 0 {
+        return 1.0;
+    }
+    let shared: usize = a
+        .iter()
+        .filter_map(|(word, count)| b.get(word).map(|x| x.min(count)))
+        .sum();
+    shared as f64 / total as f64
+}
+
+/// Lowercase words of `text` that are long enough for the trigram index, and how often they occur
+fn word_counts(text: &str) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        if word.chars().count() >= 3 {
+            *counts.entry(word.to_lowercase()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+fn metadata_to_seconds(m: &fs::Metadata) -> u64 {
     m.modified()
         .expect("unable to get file time")
         .duration_since(UNIX_EPOCH)
@@ -221,149 +2667,1851 @@ fn metadata_to_seconds(m: &fs::Metadata) -> u64 {
         .as_secs()
 }
 
-fn path_to_like(s: &Path) -> String {
-    let s = s.as_str();
-    format!(
-        "{}%",
-        s.replace('#', "##").replace('%', "#%").replace('_', "#_")
-    )
-}
+/// The form a path is stored in, see [`crate::paths`]
+fn stored(path: &Path) -> Cow<'_, str> {
+    paths::normalize(path.as_str())
+}
+
+/// A word from a row of text, left, top, width, height and confidence
+fn word(row: &rusqlite::Row) -> rusqlite::Result<Word> {
+    Ok(Word {
+        text: row.get(0)?,
+        left: row.get(1)?,
+        top: row.get(2)?,
+        width: row.get(3)?,
+        height: row.get(4)?,
+        confidence: row.get(5)?,
+    })
+}
+
+/// A LIKE pattern for the paths under the directory `dir`, but not the ones that only start with
+/// its name, like `/a/bc` for `/a/b`
+fn path_to_like(dir: &Path) -> String {
+    let mut s = stored(dir).into_owned();
+    let separator = paths::separator(&s);
+    if !s.is_empty() && !s.ends_with(separator) {
+        s.push(separator);
+    }
+    format!(
+        "{}%",
+        s.replace('#', "##").replace('%', "#%").replace('_', "#_")
+    )
+}
+
+/// The triggers of `triggers.sql`, which decompress the text first in a compressed database
+fn triggers(compress: bool) -> String {
+    let sql = include_str!("triggers.sql");
+    match compress {
+        true => sql
+            .replace("new.content)", "unzstd(new.content))")
+            .replace("old.content)", "unzstd(old.content))"),
+        false => sql.to_owned(),
+    }
+}
+
+/// Compression level of stored text, where higher levels barely make OCR text any smaller
+const ZSTD_LEVEL: i32 = 3;
+
+/// `text` compressed with zstd, or `None` if that doesn't make it any smaller, like for short text
+fn compress(text: &str) -> Option<Vec<u8>> {
+    let compressed = zstd::encode_all(text.as_bytes(), ZSTD_LEVEL).ok()?;
+    (compressed.len() < text.len()).then_some(compressed)
+}
+
+/// Register `zstd(text)`, which compresses text when that makes it smaller, and `unzstd(content)`,
+/// which decompresses it again and returns text as it is
+fn register_zstd(db: &Connection) -> Result<()> {
+    use rusqlite::functions::FunctionFlags;
+    use rusqlite::types::ValueRef;
+    let flags = FunctionFlags::SQLITE_UTF8
+        | FunctionFlags::SQLITE_DETERMINISTIC
+        | FunctionFlags::SQLITE_INNOCUOUS;
+    db.create_scalar_function("zstd", 1, flags, |ctx| {
+        Ok(match ctx.get_raw(0) {
+            ValueRef::Text(x) => match std::str::from_utf8(x).ok().and_then(compress) {
+                Some(compressed) => Value::Blob(compressed),
+                None => Value::Text(String::from_utf8_lossy(x).into_owned()),
+            },
+            x => Value::from(x),
+        })
+    })?;
+    db.create_scalar_function("unzstd", 1, flags, |ctx| {
+        Ok(match ctx.get_raw(0) {
+            ValueRef::Blob(x) => zstd::decode_all(x)
+                .map_err(anyhow::Error::from)
+                .and_then(|x| Ok(String::from_utf8(x)?))
+                .map(Value::Text)
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?,
+            x => Value::from(x),
+        })
+    })?;
+    Ok(())
+}
+
+#[cfg(feature = "regex")]
+fn register_regex(db: &Connection, options: RegexOptions) -> Result<()> {
+    use regex::{Regex, RegexBuilder};
+    use rusqlite::functions::FunctionFlags;
+    use std::sync::Arc;
+    db.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            assert_eq!(ctx.len(), 2, "called with unexpected number of arguments");
+            let regexp: Arc<Regex> = ctx.get_or_create_aux(0, |vr| -> Result<_> {
+                Ok(RegexBuilder::new(vr.as_str()?)
+                    .case_insensitive(options.ignore_case)
+                    .multi_line(options.multi_line)
+                    .size_limit(options.size_limit)
+                    .build()?)
+            })?;
+            let is_match = {
+                let text = ctx
+                    .get_raw(1)
+                    .as_str()
+                    .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+
+                regexp.is_match(text)
+            };
+
+            Ok(is_match)
+        },
+    )?;
+    Ok(())
+}
+
+fn register_glob(db: &Connection) -> Result<()> {
+    use glob::Pattern;
+    use rusqlite::functions::FunctionFlags;
+    use std::sync::Arc;
+    db.create_scalar_function(
+        "rust_glob",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            assert_eq!(ctx.len(), 2, "called with unexpected number of arguments");
+            let pattern: Arc<Pattern> =
+                ctx.get_or_create_aux(0, |vr| -> Result<_> { Ok(Pattern::new(vr.as_str()?)?) })?;
+            let is_match = {
+                let text = ctx
+                    .get_raw(1)
+                    .as_str()
+                    .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+
+                pattern.matches(text)
+            };
+
+            Ok(is_match)
+        },
+    )?;
+    Ok(())
+}
+
+/// Replace the `LIKE ... ESCAPE` that directories are matched with by [`like`], since SQLite's
+/// own ignores the case of ASCII letters only, on every platform
+fn register_like(db: &Connection, case_insensitive: bool) -> Result<()> {
+    use rusqlite::functions::FunctionFlags;
+    db.create_scalar_function(
+        "like",
+        3,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            assert_eq!(ctx.len(), 3, "called with unexpected number of arguments");
+            let pattern = ctx.get::<Option<String>>(0)?;
+            let text = ctx.get::<Option<String>>(1)?;
+            let escape = ctx.get::<String>(2)?.chars().next();
+            Ok(pattern
+                .zip(text)
+                .map(|(pattern, text)| like(&pattern, &text, escape, case_insensitive)))
+        },
+    )?;
+    Ok(())
+}
+
+/// Whether `text` matches a `LIKE` pattern, where `%` is any text, `_` is any character and
+/// `escape` makes the next character literal. Letters of any case match if `case_insensitive`
+fn like(pattern: &str, text: &str, escape: Option<char>, case_insensitive: bool) -> bool {
+    enum Token {
+        Char(char),
+        One,
+        Any,
+    }
+    let mut tokens = vec![];
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            c if Some(c) == escape => Token::Char(chars.next().unwrap_or(c)),
+            '%' => Token::Any,
+            '_' => Token::One,
+            c => Token::Char(c),
+        });
+    }
+    let same =
+        |a: char, b: char| a == b || case_insensitive && a.to_lowercase().eq(b.to_lowercase());
+
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // the last `%`, and where in the text it stopped matching, to match one more character
+    // with it when the rest of the pattern fails
+    let mut any = None;
+    while t < text.len() {
+        match tokens.get(p) {
+            Some(Token::Any) => {
+                any = Some((p, t));
+                p += 1;
+            }
+            Some(Token::One) => (p, t) = (p + 1, t + 1),
+            Some(Token::Char(c)) if same(*c, text[t]) => (p, t) = (p + 1, t + 1),
+            _ => match any {
+                Some((any_p, any_t)) => {
+                    any = Some((any_p, any_t + 1));
+                    (p, t) = (any_p + 1, any_t + 1);
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[p..].iter().all(|x| matches!(x, Token::Any))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::TempDir;
+
+    fn test_db() -> Result<(TempDir, DB)> {
+        let temp = TempDir::new()?;
+        let db = DB::new(&PathBuf::try_from(temp.path().join("temp.db"))?)?;
+        Ok((temp, db))
+    }
+
+    /// A result with `contents` for the file `name` in `temp`, which doesn't have to exist
+    fn result_in(temp: &TempDir, name: &str, contents: &str) -> Result<OcrResult> {
+        Ok(OcrResult {
+            path: PathBuf::try_from(temp.path().join(name))?,
+            metadata: fs::metadata(".")?,
+            contents: contents.into(),
+            properties: BTreeMap::new(),
+            words: vec![],
+            page: String::new(),
+        })
+    }
+
+    #[test]
+    fn is_indexed() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        let dummy = PathBuf::try_from(temp.path().join("dummy"))?;
+        File::create(&dummy)?;
+        let dummy_metadata = fs::metadata(&dummy).unwrap();
+        db.save_results(vec![OcrResult {
+            path: dummy.clone(),
+            metadata: dummy_metadata.clone(),
+            contents: "nothing".into(),
+            properties: BTreeMap::new(),
+            words: vec![],
+            page: String::new(),
+        }])?;
+        assert!(db.is_indexed(&dummy, &dummy_metadata));
+        assert!(!db.settings_changed(&dummy, "lang=eng engine=onnx"));
+
+        let settings = "lang=deu engine=onnx";
+        db.save_results(vec![OcrResult {
+            path: dummy.clone(),
+            metadata: dummy_metadata.clone(),
+            contents: "nichts".into(),
+            properties: BTreeMap::from([("settings".into(), settings.into())]),
+            words: vec![],
+            page: String::new(),
+        }])?;
+        assert!(!db.settings_changed(&dummy, settings));
+        assert!(db.settings_changed(&dummy, "lang=eng engine=onnx"));
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn deletion() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        let not_deleted = PathBuf::try_from(temp.path().join("im_not_going_away"))?;
+        let deleted = PathBuf::try_from(temp.path().join("im_going_away"))?;
+        File::create(&not_deleted)?;
+        File::create(&deleted)?;
+        db.save_results(vec![
+            OcrResult {
+                metadata: fs::metadata(&not_deleted)?,
+                path: not_deleted.clone(),
+                contents: "".into(),
+                properties: BTreeMap::new(),
+                words: vec![],
+                page: String::new(),
+            },
+            OcrResult {
+                metadata: fs::metadata(&deleted)?,
+                path: deleted.clone(),
+                contents: "".into(),
+                properties: BTreeMap::new(),
+                words: vec![],
+                page: String::new(),
+            },
+        ])?;
+        assert_eq!(db.sweep_deletions(), 0);
+        db.mark_for_deletion(Path::from_path(temp.path()).unwrap());
+        db.unmark_file(&not_deleted);
+        assert_eq!(db.sweep_deletions(), 1);
+
+        temp.close()?;
+        Ok(())
+    }
 
-#[cfg(feature = "regex")]
-fn register_regex(db: &Connection) -> Result<()> {
-    use regex::Regex;
-    use rusqlite::functions::FunctionFlags;
-    use std::sync::Arc;
-    db.create_scalar_function(
-        "regexp",
-        2,
-        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
-        move |ctx| {
-            assert_eq!(ctx.len(), 2, "called with unexpected number of arguments");
-            let regexp: Arc<Regex> =
-                ctx.get_or_create_aux(0, |vr| -> Result<_> { Ok(Regex::new(vr.as_str()?)?) })?;
-            let is_match = {
-                let text = ctx
-                    .get_raw(1)
-                    .as_str()
-                    .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+    #[test]
+    fn retry_transient() {
+        let busy = || {
+            rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+                None,
+            )
+        };
+        let options = RetryOptions {
+            attempts: 3,
+            backoff: Duration::ZERO,
+        };
+
+        let mut calls = 0;
+        let res = retry(&options, || {
+            calls += 1;
+            if calls < 3 {
+                Err(busy())
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(res.unwrap(), 3);
+
+        calls = 0;
+        let res: rusqlite::Result<()> = retry(&options, || {
+            calls += 1;
+            Err(busy())
+        });
+        assert!(res.is_err_and(|e| is_transient(&e)));
+        assert_eq!(calls, 3);
+
+        calls = 0;
+        let res: rusqlite::Result<()> = retry(&options, || {
+            calls += 1;
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        });
+        assert!(res.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn tokenizer() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        db.save_results(vec![result_in(&temp, "korean", "한국어 텍스트")?])?;
+        let search = |db: &mut DB| {
+            db.search(
+                vec!["국어"],
+                Path::new("/"),
+                40,
+                SearchType::Simple,
+                None,
+                None,
+            )
+            .map(|x| x.len())
+        };
+        assert_eq!(db.tokenizer()?, Tokenizer::Trigram);
+        assert_eq!(search(&mut db)?, 0);
+
+        assert!(db.set_tokenizer(Tokenizer::Script)?);
+        assert!(!db.set_tokenizer(Tokenizer::Script)?);
+        assert_eq!(db.tokenizer()?, Tokenizer::Script);
+        assert_eq!(search(&mut db)?, 1);
+
+        assert!(db.set_tokenizer(Tokenizer::Words)?);
+        assert_eq!(db.tokenizer()?, Tokenizer::Words);
+        assert_eq!(search(&mut db)?, 1);
+        let mut single = |query, kind| {
+            db.search(vec![query], Path::new("/"), 40, kind, None, None)
+                .map(|x| x.len())
+        };
+        assert_eq!(single("국", SearchType::Simple)?, 0);
+        assert_eq!(single("국*", SearchType::Match)?, 1);
+        assert!(db.set_remove_diacritics(true)?);
+        assert_eq!(db.tokenizer()?, Tokenizer::Words);
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn index_options() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        db.save_results(vec![result_in(
+            &temp,
+            "receipt",
+            "Café receipt, TOTAL 3,50",
+        )?])?;
+        assert_eq!(db.index_options()?, IndexOptions::default());
+        let options = IndexOptions {
+            tokenizer: Tokenizer::Unicode61,
+            remove_diacritics: false,
+            prefix: vec![2, 3],
+            compact: false,
+            compress: false,
+        };
+        assert!(db.set_index_options(&options)?);
+        assert!(!db.set_index_options(&options)?);
+        let bad = IndexOptions {
+            prefix: vec![0],
+            ..options.clone()
+        };
+        assert!(db.set_index_options(&bad).is_err());
+
+        let path = PathBuf::try_from(temp.path().join("temp.db"))?;
+        drop(db);
+        let mut db = DB::new(&path)?;
+        assert_eq!(db.index_options()?, options);
+        let mut search = |query, kind| {
+            db.search(vec![query], Path::new("/"), 40, kind, None, None)
+                .map(|x| x.len())
+        };
+        assert_eq!(search("to*", SearchType::Match)?, 1);
+        assert_eq!(search("otal", SearchType::Simple)?, 0);
+        assert_eq!(search("cafe", SearchType::Simple)?, 0);
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn compact() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        db.save_results(vec![
+            result_in(&temp, "receipt", "Grocery receipt\nTOTAL 12,50")?,
+            result_in(&temp, "shuffled", "total receipt, 50 12")?,
+            result_in(&temp, "korean", "한국어 텍스트")?,
+        ])?;
+        for tokenizer in [Tokenizer::Trigram, Tokenizer::Script, Tokenizer::Words] {
+            let options = IndexOptions {
+                tokenizer,
+                compact: true,
+                ..Default::default()
+            };
+            assert!(db.set_index_options(&options)?);
+            let mut search = |query, kind| -> Result<Vec<String>> {
+                let results = db.search(vec![query], Path::new("/"), 40, kind, None, None)?;
+                Ok(results.into_iter().map(|x| x.contents).collect())
+            };
+            assert_eq!(
+                search("total 12", SearchType::Simple)?,
+                ["Grocery receipt\n[TOTAL 12],50"]
+            );
+            assert_eq!(search("receipt", SearchType::Simple)?.len(), 2);
+            assert_eq!(search("텍스트", SearchType::Simple)?, ["한국어 [텍스트]"]);
+            assert_eq!(search("*12,50", SearchType::Glob)?.len(), 1);
+            let limited = db.search(
+                vec!["receipt"],
+                Path::new("/"),
+                1,
+                SearchType::Simple,
+                None,
+                None,
+            );
+            assert_eq!(limited?.len(), 1);
+        }
+        assert!(db
+            .search(
+                vec!["NEAR(receipt total)"],
+                Path::new("/"),
+                40,
+                SearchType::Match,
+                None,
+                None
+            )
+            .is_err());
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn compress() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        let path = |name: &str| PathBuf::try_from(temp.path().join(name));
+        let long = "Grocery receipt\n".repeat(20) + "TOTAL 12,50";
+        db.save_results(vec![result_in(&temp, "long", &long)?])?;
+        let stored = |db: &DB| -> Result<Vec<String>> {
+            let mut stmt = db
+                .conn
+                .prepare("SELECT typeof(content) FROM images ORDER BY path")?;
+            let types = stmt.query_map([], |row| row.get(0))?;
+            Ok(types.collect::<rusqlite::Result<_>>()?)
+        };
+        assert_eq!(stored(&db)?, ["text"]);
+
+        let options = IndexOptions {
+            compress: true,
+            ..Default::default()
+        };
+        assert!(db.set_index_options(&options)?);
+        db.save_results(vec![
+            result_in(&temp, "short", "Total")?,
+            result_in(&temp, "updated", &long)?,
+        ])?;
+        db.save_results(vec![result_in(
+            &temp,
+            "updated",
+            &long.replace("12,50", "9,99"),
+        )?])?;
+        assert_eq!(stored(&db)?, ["blob", "text", "blob"]);
+        assert_eq!(db.content(&path("long")?, "")?.as_deref(), Some(&*long));
+        let mut search = |query, kind| -> Result<Vec<String>> {
+            let results = db.search(vec![query], &path("")?, 40, kind, None, None)?;
+            Ok(results.into_iter().map(|x| x.contents).collect())
+        };
+        let found = search("total 12", SearchType::Simple)?;
+        assert!(found.len() == 1 && found[0].ends_with("receipt\n[TOTAL 12],50"));
+        assert_eq!(search("9,99", SearchType::Simple)?.len(), 1);
+        assert_eq!(search("*TOTAL*", SearchType::Glob)?.len(), 2);
+        #[cfg(feature = "regex")]
+        assert_eq!(search(r"\d,\d\d", SearchType::Regex)?.len(), 2);
+        assert!(db.verify()?.is_ok());
+        db.delete(&path("updated")?)?;
+        assert!(db.verify()?.is_ok());
+
+        assert!(db.set_index_options(&IndexOptions::default())?);
+        assert_eq!(stored(&db)?, ["text", "text"]);
+        assert!(db.verify()?.is_ok());
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn embeddings() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        let path = |name: &str| PathBuf::try_from(temp.path().join(name));
+        db.save_results(vec![
+            result_in(&temp, "receipt", "grocery receipt")?,
+            result_in(&temp, "weather", "sunny weather")?,
+            result_in(&temp, "both", "receipt of the weather station")?,
+        ])?;
+        // a model that knows two words
+        let embed = |texts: &[&str]| -> Result<Vec<Vec<f32>>> {
+            Ok(texts
+                .iter()
+                .map(|x| match (x.contains("receipt"), x.contains("weather")) {
+                    (true, true) => vec![0.6, 0.8],
+                    (true, false) => vec![1.0, 0.0],
+                    _ => vec![0.0, 1.0],
+                })
+                .collect())
+        };
+        assert_eq!(db.embed_missing(&path("")?, "words", embed)?, 3);
+        assert_eq!(db.embed_missing(&path("")?, "words", embed)?, 0);
+        let mut search = |query: &[f32], limit| -> Result<Vec<String>> {
+            let results = db.semantic_search(query, "words", &path("")?, limit, None, None)?;
+            Ok(results.into_iter().map(|x| x.contents).collect())
+        };
+        assert_eq!(
+            search(&[1.0, 0.0], 2)?,
+            ["grocery receipt", "receipt of the weather station"]
+        );
+        assert!(search(&[1.0, 0.0, 0.0], 2).is_err());
+        let other = db.semantic_search(&[1.0, 0.0], "other", &path("")?, 2, None, None)?;
+        assert!(other.is_empty());
+        db.set_excluded_terms(vec!["GROCERY".into()]);
+        let results = db.semantic_search(&[1.0, 0.0], "words", &path("")?, 2, None, None)?;
+        assert_eq!(
+            results.into_iter().map(|x| x.contents).collect_vec(),
+            ["receipt of the weather station", "sunny weather"]
+        );
+        db.set_excluded_terms(vec![]);
+
+        // changed text is embedded again, but compressing it keeps the embeddings
+        db.save_results(vec![result_in(&temp, "weather", "rainy weather")?])?;
+        db.set_index_options(&IndexOptions {
+            compress: true,
+            ..Default::default()
+        })?;
+        assert_eq!(db.embed_missing(&path("")?, "words", embed)?, 1);
+        db.delete(&path("both")?)?;
+        let count: usize = db
+            .conn
+            .query_row("SELECT count(*) FROM embeddings", [], |row| row.get(0))?;
+        assert_eq!(count, 2);
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn image_embeddings() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        let path = |name: &str| PathBuf::try_from(temp.path().join(name));
+        let result = |name: &str, page: &str| -> Result<OcrResult> {
+            Ok(OcrResult {
+                page: page.into(),
+                ..result_in(&temp, name, &format!("text of {name}"))?
+            })
+        };
+        db.save_results(vec![result("red.png", "")?])?;
+        db.save_results(vec![result("pink.png", "")?])?;
+        db.save_results(vec![result("notes.txt", "")?])?;
+        db.save_results(vec![result("scan.pdf", "1")?])?;
+        let embed = |path: &Path| -> Result<Option<Vec<f32>>> {
+            Ok(match path.file_name() {
+                Some("red.png") => Some(vec![2.0, 0.0]),
+                Some("pink.png") => Some(vec![1.0, 1.0]),
+                _ => None,
+            })
+        };
+        // documents are left out, and files that aren't images aren't tried again
+        assert_eq!(db.embed_images(&path("")?, "clip", embed)?, 3);
+        assert_eq!(db.embed_images(&path("")?, "clip", embed)?, 0);
+        let found = db.similar_images(&[1.0, 0.1], "clip", &path("")?, 10)?;
+        let found: Vec<_> = found.into_iter().map(|x| x.contents).collect();
+        assert_eq!(found, ["text of red.png", "text of pink.png"]);
+
+        // a file that changed is embedded again
+        db.conn.execute(
+            "UPDATE images SET modtime = modtime + 1 WHERE path = ?1",
+            [path("pink.png")?.as_str()],
+        )?;
+        assert_eq!(db.embed_images(&path("")?, "clip", embed)?, 1);
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn compact_snippet() {
+        let options = SnippetOptions::default();
+        assert_eq!(
+            snippet("Café Crème", "CREME", true, &options).unwrap(),
+            "Café [Crème]"
+        );
+        assert_eq!(snippet("Café Crème", "creme", false, &options), None);
+        let long = format!("{}needle{}", "a".repeat(40), "b".repeat(40));
+        let found = snippet(&long, "needle", false, &options).unwrap();
+        assert_eq!(
+            found,
+            format!("..{}[needle]{}..", "a".repeat(32), "b".repeat(32))
+        );
+        let markers = SnippetOptions {
+            length: 4,
+            open: "<<".into(),
+            close: ">>".into(),
+        };
+        assert_eq!(
+            snippet(&long, "needle", false, &markers).unwrap(),
+            "..aa<<needle>>bb.."
+        );
+        // a letter that is longer in lowercase
+        assert_eq!(
+            snippet("İstanbul", "stan", false, &options).unwrap(),
+            "İ[stan]bul"
+        );
+        assert_eq!(index_terms(Tokenizer::Trigram, "abcd"), ["abc", "bcd"]);
+        assert_eq!(index_terms(Tokenizer::Trigram, "ab"), Vec::<String>::new());
+        assert_eq!(index_terms(Tokenizer::Unicode61, "a-b a"), ["a", "b"]);
+    }
+
+    #[test]
+    fn settings_migration() -> Result<()> {
+        let temp = TempDir::new()?;
+        let path = PathBuf::try_from(temp.path().join("temp.db"))?;
+        // a version 8 database, whose search index was rebuilt with the script tokenizer
+        let conn = Connection::open(&path)?;
+        crate::tokenizer::register(&conn)?;
+        conn.execute_batch(include_str!("db_create.sql"))?;
+        for migration in &MIGRATIONS[..6] {
+            conn.execute_batch(migration)?;
+        }
+        conn.execute_batch(
+            "DROP TABLE images_fts;
+            CREATE VIRTUAL TABLE images_fts USING fts5(content, content=images, content_rowid=id, tokenize='ocrlocate remove_diacritics 1');",
+        )?;
+        drop(conn);
+        let db = DB::new(&path)?;
+        let options = db.index_options()?;
+        assert_eq!(options.tokenizer, Tokenizer::Script);
+        assert!(options.remove_diacritics);
+        assert!(options.prefix.is_empty());
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn diacritics() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        db.save_results(vec![result_in(&temp, "menu", "Café Crème 3,50")?])?;
+        let search = |db: &mut DB, query, kind| {
+            let mut results = db.search(vec![query], Path::new("/"), 40, kind, None, None)?;
+            db.matched_terms(&mut results, &[query], kind)?;
+            Ok::<_, anyhow::Error>(results.first().map(|x| x.terms.clone()))
+        };
+        assert!(!db.remove_diacritics()?);
+        assert_eq!(search(&mut db, "cafe", SearchType::Simple)?, None);
+
+        assert!(db.set_remove_diacritics(true)?);
+        assert!(db.remove_diacritics()?);
+        for query in ["cafe", "CAFÉ", "creme"] {
+            let found = search(&mut db, query, SearchType::Simple)?;
+            assert_eq!(found, Some(vec![query.to_owned()]));
+        }
+        assert!(search(&mut db, "cafe creme", SearchType::Match)?.is_some());
+
+        // switching the tokenizer keeps the folding
+        assert!(db.set_tokenizer(Tokenizer::Script)?);
+        assert!(db.remove_diacritics()?);
+        assert!(search(&mut db, "creme", SearchType::Simple)?.is_some());
+        assert!(db.set_remove_diacritics(false)?);
+        assert_eq!(db.tokenizer()?, Tokenizer::Script);
+        assert_eq!(search(&mut db, "creme", SearchType::Simple)?, None);
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn verify() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        let x = |name: &str| result_in(&temp, name, &format!("contents of {name}"));
+        db.save_results(vec![x("one")?, x("two")?])?;
+        assert!(db.verify()?.is_ok());
+
+        db.conn.execute_batch(
+            "DROP TRIGGER images_insert;
+            DROP TRIGGER images_delete;
+            INSERT INTO images (path, modtime, content) VALUES ('three', 0, 'unindexed');
+            DELETE FROM images WHERE path LIKE '%one';",
+        )?;
+        let report = db.verify()?;
+        assert!(!report.is_ok());
+        assert_eq!((report.images, report.indexed), (2, 2));
+        assert_eq!((report.orphaned, report.missing), (1, 1));
+        assert_eq!(report.missing_triggers, ["images_insert", "images_delete"]);
+        assert!(report.integrity_error.is_some());
+        assert!(report.database_errors.is_empty());
+
+        db.repair()?;
+        assert!(db.verify()?.is_ok());
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn pages() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        let book = PathBuf::try_from(temp.path().join("book.cbz"))?;
+        let page = |page: &str| -> Result<OcrResult> {
+            Ok(OcrResult {
+                page: page.into(),
+                ..result_in(&temp, "book.cbz", &format!("text of page {page}"))?
+            })
+        };
+        db.save_results(vec![page("1")?, page("2")?, page("3")?])?;
+        assert_eq!(db.content(&book, "2")?.unwrap(), "text of page 2");
+        let results = db.search(
+            vec!["page 3"],
+            Path::new("/"),
+            40,
+            SearchType::Simple,
+            None,
+            None,
+        )?;
+        assert_eq!(results[0].page, "3");
+
+        db.save_results(vec![page("1")?])?;
+        assert!(db.content(&book, "2")?.is_none());
+        assert!(db.verify()?.is_ok());
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn terms() -> Result<()> {
+        assert_eq!(
+            SearchType::Simple.terms(&["one two", "one"]),
+            ["one", "two"]
+        );
+        assert_eq!(
+            SearchType::Match.terms(&[r#"(needle OR "say ""hi""") NOT content:hay* AND ^start"#]),
+            ["needle", r#"say "hi""#, "start"]
+        );
+        assert_eq!(SearchType::Glob.terms(&["*a?b*"]), ["*a?b*"]);
+
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let queries = ["total OR ledger OR nowhere"];
+        let mut results =
+            seeded
+                .db
+                .search(queries.to_vec(), &root, 40, SearchType::Match, None, None)?;
+        let terms = seeded
+            .db
+            .matched_terms(&mut results, &queries, SearchType::Match)?;
+        assert_eq!(terms, ["total", "ledger", "nowhere"]);
+        let mut matched: Vec<_> = results
+            .iter()
+            .map(|x| (x.page.as_str(), x.terms.join(",")))
+            .collect();
+        matched.sort();
+        assert_eq!(
+            matched,
+            [
+                ("", "total".to_string()),
+                ("", "total".to_string()),
+                ("chapter 1/page_001.png", "ledger".to_string()),
+                ("chapter 1/page_002.png", "total".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn similar() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        db.save_results(vec![
+            result_in(
+                &temp,
+                "invoice1",
+                "Invoice ACME Corp total due 42 thank you",
+            )?,
+            result_in(
+                &temp,
+                "invoice2",
+                "Invoice ACME Corp total due 17 thank you",
+            )?,
+            result_in(&temp, "chat", "thank you see you tomorrow")?,
+            result_in(&temp, "other", "weather forecast tomorrow")?,
+        ])?;
+        let root = Path::from_path(temp.path()).unwrap();
+        let results = db.similar(&root.join("invoice1"), root, 10, 3)?;
+        assert_eq!(results[0].path, root.join("invoice2").as_str());
+        assert!(results.iter().all(|x| !x.path.ends_with("invoice1")));
+        assert!(db.similar(&root.join("missing"), root, 10, 3).is_err());
+        // words that aren't in the index can't find anything
+        let query = db.distinctive_query("ACME zebra zebra zebra", 2)?;
+        assert_eq!(query, r#""acme""#);
+        assert_eq!(db.distinctive_query("zebra", 2)?, "");
+
+        assert_eq!(
+            word_counts("The cat, the CAT; a dog"),
+            BTreeMap::from([("cat".into(), 2), ("dog".into(), 1), ("the".into(), 2)])
+        );
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn words() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        let path = PathBuf::try_from(temp.path().join("words"))?;
+        let word = |text: &str, left| Word {
+            text: text.into(),
+            left,
+            top: 10,
+            width: 40,
+            height: 12,
+            confidence: 90.5,
+        };
+        let result = |words| -> Result<OcrResult> {
+            Ok(OcrResult {
+                words,
+                ..result_in(&temp, "words", "hello world")?
+            })
+        };
+        db.save_results(vec![result(vec![word("hello", 0), word("world", 50)])?])?;
+        assert_eq!(db.words(&path, "")?, [word("hello", 0), word("world", 50)]);
+
+        db.save_results(vec![result(vec![word("rescanned", 0)])?])?;
+        assert_eq!(db.words(&path, "")?, [word("rescanned", 0)]);
+        assert_eq!(
+            db.images_with_words(Path::from_path(temp.path()).unwrap())?,
+            [(path.clone(), String::new())]
+        );
+
+        db.conn.execute("DELETE FROM images", [])?;
+        let count: usize = db
+            .conn
+            .query_row("SELECT count(*) FROM words", [], |row| row.get(0))?;
+        assert_eq!(count, 0);
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn search() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        let x = |contents: &str| result_in(&temp, &contents.replace(' ', "_"), contents).unwrap();
+        assert_eq!(
+            db.save_results(vec![
+                x("haystack haystack haystack"),
+                x("haystack haystack needle"),
+                x("haystack hayneedle haystack"),
+            ])?,
+            3
+        );
+        let results = db.search(
+            vec!["needle"],
+            Path::new("/"),
+            40,
+            SearchType::Simple,
+            None,
+            None,
+        )?;
+        println!("{:?}", results);
+        assert_eq!(results.len(), 2);
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex_options() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        db.save_results(vec![result_in(
+            &temp,
+            "receipt",
+            "Coffee 3.50\nTOTAL 3.50",
+        )?])?;
+        let search = |db: &mut DB, pattern: &str| {
+            db.search(
+                vec![pattern],
+                Path::new("/"),
+                40,
+                SearchType::Regex,
+                None,
+                None,
+            )
+            .map(|x| x.len())
+        };
+        assert_eq!(search(&mut db, "^total")?, 0);
+
+        db.set_regex_options(RegexOptions {
+            ignore_case: true,
+            multi_line: true,
+            ..Default::default()
+        })?;
+        assert_eq!(search(&mut db, "^total")?, 1);
+
+        // patterns that compile to more than the limit aren't run
+        db.set_regex_options(RegexOptions {
+            size_limit: 1000,
+            ..Default::default()
+        })?;
+        assert!(search(&mut db, r"\w{100}").is_err());
+
+        // enough rows for the scan to be checked on
+        db.save_results(
+            (0..100)
+                .map(|i| result_in(&temp, &format!("page{i}"), &format!("page {i}")))
+                .collect::<Result<_>>()?,
+        )?;
+        db.set_regex_options(RegexOptions {
+            time_limit: Some(Duration::ZERO),
+            ..Default::default()
+        })?;
+        let error = search(&mut db, "TOTAL").unwrap_err();
+        assert!(error.to_string().contains("took too long"), "{error}");
+        // the limit is only for regex searches
+        db.search(
+            vec!["total"],
+            Path::new("/"),
+            40,
+            SearchType::Simple,
+            None,
+            None,
+        )?;
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn stats() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let stats = seeded.db.stats()?;
+        assert_eq!((stats.files, stats.pages), (4, 5));
+        let text: usize = seeded.fixture.image.iter().map(|x| x.contents.len()).sum();
+        assert_eq!(stats.text_size, text as u64);
+        assert!(stats.index_size > 0);
+        assert_eq!(stats.oldest, Some(1600000000));
+        assert_eq!(stats.newest, Some(1710000000));
+        assert_eq!(stats.marked_delete, 0);
+        assert_eq!(
+            stats.extensions.keys().collect_vec(),
+            ["cbz", "jpg", "png", "webp"]
+        );
+        assert!(stats.extensions.values().all(|&x| x == 1));
+
+        // files that weren't found while indexing aren't counted until they're found again
+        let root = seeded.root.clone();
+        seeded.db.mark_for_deletion(&root);
+        for path in seeded.db.marked_for_deletion()? {
+            if !path.as_str().ends_with("older total.jpg") {
+                seeded.db.unmark_file(&path);
+            }
+        }
+        let stats = seeded.db.stats()?;
+        assert_eq!((stats.files, stats.pages, stats.marked_delete), (3, 4, 1));
+        assert_eq!(stats.text_size, text as u64);
+        assert_eq!(stats.oldest, Some(1650000000));
+        assert!(!stats.extensions.contains_key("jpg"));
+        Ok(())
+    }
 
-                regexp.is_match(text)
+    #[test]
+    fn any() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        for compact in [false, true] {
+            let options = IndexOptions {
+                compact,
+                ..Default::default()
+            };
+            seeded.db.set_index_options(&options)?;
+            let mut search = |queries: &[&str]| -> Result<Vec<String>> {
+                let results =
+                    seeded
+                        .db
+                        .search(queries.to_vec(), &root, 40, SearchType::Any, None, None)?;
+                Ok(results
+                    .into_iter()
+                    .map(|x| x.path.rsplit('/').next().unwrap().to_owned())
+                    .collect())
             };
+            // the files with two of the words come before the one with only total
+            let found = search(&["total", "tax", "milk"])?;
+            assert_eq!(found.len(), 3, "{found:?}");
+            assert_eq!(found[2], "ledger.cbz");
+            assert_eq!(search(&["grand total", "ledger"])?, ["ledger.cbz"; 2]);
+            assert!(search(&["o'clock", "\"quoted\""])?.is_empty());
+        }
+        Ok(())
+    }
 
-            Ok(is_match)
-        },
-    )?;
-    Ok(())
-}
+    #[test]
+    fn lang_filter() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let lang = |x: &str| BTreeMap::from([("lang".to_string(), x.to_string())]);
+        seeded
+            .db
+            .set_properties(&root.join("receipts/grocery.png"), "", &lang("eng+jpn"))?;
+        seeded.db.set_properties(
+            &root.join("receipts/older total.jpg"),
+            "",
+            &lang("jpn_vert"),
+        )?;
+        let mut search = |langs: &[&str]| -> Result<Vec<String>> {
+            seeded
+                .db
+                .set_lang_filter(langs.iter().map(|x| x.to_string()).collect());
+            let results =
+                seeded
+                    .db
+                    .search(vec!["total"], &root, 40, SearchType::Simple, None, None)?;
+            Ok(results
+                .into_iter()
+                .map(|x| x.path.rsplit('/').next().unwrap().to_owned())
+                .sorted()
+                .collect())
+        };
+        assert_eq!(search(&["jpn"])?, ["grocery.png"]);
+        assert_eq!(
+            search(&["jpn", "jpn_vert"])?,
+            ["grocery.png", "older total.jpg"]
+        );
+        assert!(search(&["deu"])?.is_empty());
+        assert!(search(&["it's"])?.is_empty());
+        assert_eq!(search(&[])?.len(), search(&[" "])?.len());
+        Ok(())
+    }
 
-fn register_glob(db: &Connection) -> Result<()> {
-    use glob::Pattern;
-    use rusqlite::functions::FunctionFlags;
-    use std::sync::Arc;
-    db.create_scalar_function(
-        "rust_glob",
-        2,
-        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
-        move |ctx| {
-            assert_eq!(ctx.len(), 2, "called with unexpected number of arguments");
-            let pattern: Arc<Pattern> =
-                ctx.get_or_create_aux(0, |vr| -> Result<_> { Ok(Pattern::new(vr.as_str()?)?) })?;
-            let is_match = {
-                let text = ctx
-                    .get_raw(1)
-                    .as_str()
-                    .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+    #[test]
+    fn excluded_terms() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        seeded.db.set_excluded_terms(vec![
+            "TAX".into(),
+            "grand total".into(),
+            r#"o'clock "quoted""#.into(),
+            " ".into(),
+        ]);
+        for compact in [false, true] {
+            let options = IndexOptions {
+                compact,
+                ..Default::default()
+            };
+            seeded.db.set_index_options(&options)?;
+            let mut search = |query, kind| -> Result<Vec<String>> {
+                let results = seeded.db.search(vec![query], &root, 40, kind, None, None)?;
+                Ok(results
+                    .into_iter()
+                    .map(|x| x.path.rsplit('/').next().unwrap().to_owned())
+                    .collect())
+            };
+            assert_eq!(search("total", SearchType::Simple)?, ["grocery.png"]);
+            if !compact {
+                assert_eq!(search("milk OR tax", SearchType::Match)?, ["grocery.png"]);
+            }
+            assert!(search("*total*", SearchType::Glob).is_err());
+        }
+        Ok(())
+    }
 
-                pattern.matches(text)
+    #[test]
+    fn snippet_options() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        seeded.db.set_snippet_options(SnippetOptions {
+            length: 2,
+            open: "<'".into(),
+            close: "'>".into(),
+        });
+        for compact in [false, true] {
+            let options = IndexOptions {
+                compact,
+                ..Default::default()
             };
+            seeded.db.set_index_options(&options)?;
+            let results =
+                seeded
+                    .db
+                    .search(vec!["bread"], &root, 40, SearchType::Simple, None, None)?;
+            let found = &results[0].contents;
+            if compact {
+                assert_eq!(found, "..\n<'bread'>\t..");
+            } else {
+                // the index counts the tokens of its tokenizer, which may be trigrams
+                assert!(
+                    found.starts_with("..<'b") && found.ends_with("'>.."),
+                    "{found}"
+                );
+            }
+        }
+        Ok(())
+    }
 
-            Ok(is_match)
-        },
-    )?;
-    Ok(())
-}
+    #[test]
+    fn sort() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        let mut results = vec![];
+        for (name, size) in [("b", 30), ("c", 10), ("a", 20)] {
+            let path = PathBuf::try_from(temp.path().join(name))?;
+            fs::write(&path, vec![0; size])?;
+            results.push(OcrResult {
+                metadata: fs::metadata(&path)?,
+                path,
+                contents: "total".into(),
+                properties: BTreeMap::new(),
+                words: vec![],
+                page: String::new(),
+            });
+        }
+        db.save_results(results)?;
+        let mut search = |sort, reverse| -> Result<String> {
+            db.set_sort(sort, reverse);
+            let results = db.search(
+                vec!["total"],
+                Path::new("/"),
+                40,
+                SearchType::Simple,
+                None,
+                None,
+            )?;
+            Ok(results
+                .into_iter()
+                .map(|x| x.path.rsplit(['/', '\\']).next().unwrap().to_owned())
+                .collect())
+        };
+        assert_eq!(search(Sort::Path, false)?, "abc");
+        assert_eq!(search(Sort::Path, true)?, "cba");
+        assert_eq!(search(Sort::Size, false)?, "bac");
+        assert_eq!(search(Sort::Size, true)?, "cab");
+        assert_eq!(search(Sort::Rank, false)?.len(), 3);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs::File;
-    use tempfile::TempDir;
+        // the pages of a document are in the order of their numbers
+        let path = PathBuf::try_from(temp.path().join("d.pdf"))?;
+        fs::write(&path, "")?;
+        let pages = (1..=12).map(|x| x.to_string()).collect::<Vec<_>>();
+        db.save_results(
+            pages
+                .iter()
+                .map(|page| {
+                    Ok(OcrResult {
+                        metadata: fs::metadata(&path)?,
+                        path: path.clone(),
+                        contents: "page".into(),
+                        properties: BTreeMap::new(),
+                        words: vec![],
+                        page: page.clone(),
+                    })
+                })
+                .collect::<Result<_>>()?,
+        )?;
+        db.set_sort(Sort::Path, false);
+        let results = db.search(
+            vec!["page"],
+            Path::new("/"),
+            40,
+            SearchType::Simple,
+            None,
+            None,
+        )?;
+        assert_eq!(
+            results.iter().map(|x| &x.page).collect::<Vec<_>>(),
+            pages.iter().collect::<Vec<_>>()
+        );
 
-    fn test_db() -> Result<(TempDir, DB)> {
-        let temp = TempDir::new()?;
-        let db = DB::new(&PathBuf::try_from(temp.path().join("temp.db"))?)?;
-        Ok((temp, db))
+        temp.close()?;
+        Ok(())
     }
 
     #[test]
-    fn is_indexed() -> Result<()> {
+    fn sample() -> Result<()> {
         let (temp, mut db) = test_db()?;
-        let dummy = PathBuf::try_from(temp.path().join("dummy"))?;
-        File::create(&dummy)?;
-        let dummy_metadata = fs::metadata(&dummy).unwrap();
-        db.save_results(vec![OcrResult {
-            path: dummy.clone(),
-            metadata: dummy_metadata.clone(),
-            contents: "nothing".into(),
-        }])?;
-        assert!(db.is_indexed(&dummy, &dummy_metadata));
+        let mut results = vec![];
+        for (name, source) in [("a.png", None), ("b.png", Some("external"))] {
+            let path = PathBuf::try_from(temp.path().join(name))?;
+            fs::write(&path, "")?;
+            results.push(OcrResult {
+                metadata: fs::metadata(&path)?,
+                path,
+                contents: "total".into(),
+                properties: source
+                    .map(|x| ("source".to_owned(), x.to_owned()))
+                    .into_iter()
+                    .collect(),
+                words: vec![],
+                page: String::new(),
+            });
+        }
+        db.save_results(results)?;
+        // text that wasn't OCRed can't be checked by OCRing the file again
+        let files = db.sample(Path::new("/"), 10)?;
+        assert_eq!(files.len(), 1);
+        assert!(files[0].0.ends_with("a.png"));
+
         temp.close()?;
         Ok(())
     }
 
     #[test]
-    fn deletion() -> Result<()> {
-        let (temp, mut db) = test_db()?;
-        let not_deleted = PathBuf::try_from(temp.path().join("im_not_going_away"))?;
-        let deleted = PathBuf::try_from(temp.path().join("im_going_away"))?;
-        File::create(&not_deleted)?;
-        File::create(&deleted)?;
+    fn matching_lines() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let results =
+            seeded
+                .db
+                .search(vec!["market"], &root, 40, SearchType::Simple, None, None)?;
+        // the numbers of the lines with the text of their matches
+        let lines = |queries: &[&str], kind| -> Result<Vec<(usize, String)>> {
+            let lines = seeded
+                .db
+                .matching_lines(&results[0], queries, kind, (0, 0))?;
+            Ok(lines
+                .into_iter()
+                .map(|x| {
+                    let found = x.matches.iter().map(|m| &x.text[m.clone()]).join("|");
+                    (x.number, found)
+                })
+                .collect())
+        };
+        assert_eq!(
+            lines(&["milk OR bread"], SearchType::Match)?,
+            [(2, "milk".into()), (3, "bread".into())]
+        );
+        // overlapping matches are one
+        assert_eq!(
+            lines(&["mark", "ket"], SearchType::Any)?,
+            [(1, "MARKET".into())]
+        );
+        assert_eq!(
+            lines(&["total*"], SearchType::Glob)?,
+            [(4, "TOTAL\t5.59".into())]
+        );
+        #[cfg(feature = "regex")]
+        assert_eq!(lines(&[r"\d\.1"], SearchType::Regex)?, [(3, "3.1".into())]);
+
+        let context = |context| -> Result<Vec<(usize, bool)>> {
+            let lines =
+                seeded
+                    .db
+                    .matching_lines(&results[0], &["milk"], SearchType::Simple, context)?;
+            Ok(lines.into_iter().map(|x| (x.number, x.context)).collect())
+        };
+        assert_eq!(context((1, 0))?, [(1, true), (2, false)]);
+        assert_eq!(context((0, 5))?, [(2, false), (3, true), (4, true)]);
+
+        // the terms after NOT aren't in the result
+        assert_eq!(
+            lines(&["milk NOT (bread OR butter)"], SearchType::Match)?,
+            [(2, "milk".into())]
+        );
+        // a phrase over a line break, which an index of words finds
+        seeded.db.set_index_options(&IndexOptions {
+            tokenizer: Tokenizer::Words,
+            ..Default::default()
+        })?;
+        let query = [r#""market milk""#];
+        let results = seeded
+            .db
+            .search(query.to_vec(), &root, 40, SearchType::Match, None, None)?;
+        let lines = seeded
+            .db
+            .matching_lines(&results[0], &query, SearchType::Match, (0, 0))?;
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].number, 1);
+        assert!(!lines[0].text.contains('\n'));
+        let found = lines[0]
+            .matches
+            .iter()
+            .map(|m| &lines[0].text[m.clone()])
+            .join("|");
+        assert_eq!(found, "MARKET milk");
+        Ok(())
+    }
+
+    #[test]
+    fn everywhere() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let mut search = |dir: &Path| {
+            seeded
+                .db
+                .search(vec!["total"], dir, 40, SearchType::Simple, None, None)
+                .map(|x| x.len())
+        };
+        assert_eq!(search(&seeded.root.join("receipts"))?, 2);
+        // an empty directory is the parent of every path
+        assert_eq!(search(Path::new(""))?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn modified_range() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let mut search = |range: (Option<i64>, Option<i64>)| -> Result<Vec<String>> {
+            seeded.db.set_modified_range(range.0, range.1);
+            let results =
+                seeded
+                    .db
+                    .search(vec!["total"], &root, 40, SearchType::Simple, None, None)?;
+            Ok(results
+                .into_iter()
+                .map(|x| x.path.rsplit('/').next().unwrap().to_owned())
+                .sorted()
+                .collect())
+        };
+        assert_eq!(search((None, None))?.len(), 3);
+        assert_eq!(
+            search((Some(1_650_000_000), None))?,
+            ["grocery.png", "ledger.cbz"]
+        );
+        assert_eq!(
+            search((Some(1_650_000_000), Some(1_700_000_000)))?,
+            ["ledger.cbz"]
+        );
+        assert_eq!(search((None, Some(1_650_000_000)))?, ["older total.jpg"]);
+
+        // midnight of the local time zone, which is at most 14 hours from UTC
+        let midnight = seeded.db.local_time("2024-01-01")?;
+        assert!((midnight - 1_704_067_200).abs() <= 14 * 60 * 60);
+        assert!(seeded.db.local_time("3d").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn random() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let mut pick = |dir: &str, count, queries: &[&str]| -> Result<Vec<(String, String)>> {
+            let results = seeded
+                .db
+                .random(&root.join(dir), count, queries, SearchType::Simple)?;
+            Ok(results
+                .into_iter()
+                .map(|x| (x.path.replace(root.as_str(), ""), x.page))
+                .sorted()
+                .collect())
+        };
+
+        let picked = pick("", 3, &[])?;
+        assert_eq!(picked.len(), 3);
+        assert!(picked.iter().all_unique());
+        // more than there are lists all of them
+        assert_eq!(pick("", 100, &[])?.len(), 5);
+        assert_eq!(
+            pick("receipts", 10, &[])?,
+            [
+                ("/receipts/grocery.png".into(), String::new()),
+                ("/receipts/older total.jpg".into(), String::new())
+            ]
+        );
+        let matching = pick("", 10, &["total"])?;
+        assert_eq!(matching.len(), 3);
+        assert!(matching
+            .iter()
+            .all(|(path, _)| path != "/photos/100%_cat.webp"));
+        assert_eq!(pick("", 1, &["total"])?.len(), 1);
+        assert!(pick("photos", 10, &["total"])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn min_confidence() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let mut search = |min| {
+            seeded
+                .db
+                .search(vec!["total"], &root, 40, SearchType::Simple, None, min)
+                .unwrap()
+                .into_iter()
+                .map(|x| {
+                    (
+                        x.path.replace(root.as_str(), ""),
+                        x.confidence.map(|x| x.round()),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(search(None).len(), 3);
+        // the document has no words, so it is kept
+        assert_eq!(
+            search(Some(80.0)),
+            [
+                ("/books/ledger.cbz".to_string(), None),
+                ("/receipts/grocery.png".to_string(), Some(92.0))
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn note_links() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let image = root.join("receipts/grocery.png");
+        let note = root.join("notes/shopping.md");
+        seeded.db.save_note_links(
+            &root,
+            &BTreeMap::from([(note.clone(), vec![image.clone()])]),
+        )?;
+        let results = seeded
+            .db
+            .search(vec!["total"], &root, 40, SearchType::Simple, None, None)?;
+        for result in results {
+            let expected = if result.path == image.as_str() {
+                vec![note.to_string()]
+            } else {
+                vec![]
+            };
+            assert_eq!(result.notes, expected);
+        }
+
+        // notes that no longer embed anything lose their links
+        seeded.db.save_note_links(&root, &BTreeMap::new())?;
+        assert!(seeded.db.notes(&image)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn shared() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let grocery = root.join("receipts/grocery.png");
+        seeded.db.conn.execute(
+            "UPDATE images SET owner = 1, private = TRUE WHERE path = ?1",
+            [grocery.as_str()],
+        )?;
+        // stands in for a file that the user has no permission to read
+        fs::remove_file(root.join("books/ledger.cbz"))?;
+
+        let mut search = |shared: Option<Shared>| {
+            seeded.db.set_shared(shared);
+            seeded
+                .db
+                .search(vec!["total"], &root, 40, SearchType::Simple, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|x| x.path.replace(root.as_str(), ""))
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(search(None).len(), 3);
+        let user = |user| {
+            Some(Shared {
+                user: Some(user),
+                private: false,
+            })
+        };
+        assert_eq!(search(user(2)), ["/receipts/older total.jpg"]);
+        assert_eq!(
+            search(user(1)),
+            ["/receipts/grocery.png", "/receipts/older total.jpg"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn similarity() {
+        assert_eq!(super::similarity("", ""), 1.0);
+        assert_eq!(super::similarity("Total 4.32 paid", "total 4.32 PAID"), 1.0);
+        assert_eq!(super::similarity("total paid", "t0tal paid"), 0.5);
+        assert_eq!(super::similarity("receipt", ""), 0.0);
+    }
+
+    #[test]
+    fn windows_paths() -> Result<()> {
+        let (_temp, mut db) = test_db()?;
+        let result = |path: &str| -> Result<OcrResult> {
+            Ok(OcrResult {
+                path: path.into(),
+                page: String::new(),
+                metadata: fs::metadata(".")?,
+                contents: "receipt total".into(),
+                properties: BTreeMap::new(),
+                words: vec![],
+            })
+        };
         db.save_results(vec![
-            OcrResult {
-                metadata: fs::metadata(&not_deleted)?,
-                path: not_deleted.clone(),
-                contents: "".into(),
-            },
-            OcrResult {
-                metadata: fs::metadata(&deleted)?,
-                path: deleted.clone(),
-                contents: "".into(),
-            },
+            result(r"\\?\C:\shots\a.png")?,
+            result(r"\\?\UNC\nas\share\b.png")?,
+            result(r"C:\shots_old\c.png")?,
         ])?;
-        assert_eq!(db.sweep_deletions(), 0);
-        db.mark_for_deletion(Path::from_path(temp.path()).unwrap());
-        db.unmark_file(&not_deleted);
-        assert_eq!(db.sweep_deletions(), 1);
+        assert!(db.is_path_indexed(Path::new(r"C:\shots\a.png"))?);
+        assert!(db.is_path_indexed(Path::new(r"\\nas\share\b.png"))?);
+        assert!(db.is_path_indexed(Path::new(r"\\?\C:\shots_old\c.png"))?);
 
-        temp.close()?;
+        let mut search = |root: &str| -> Result<Vec<String>> {
+            let results = db.search(
+                vec!["total"],
+                Path::new(root),
+                10,
+                SearchType::Simple,
+                None,
+                None,
+            )?;
+            Ok(results.into_iter().map(|x| x.path).sorted().collect())
+        };
+        assert_eq!(search(r"\\?\C:\shots\")?, [r"C:\shots\a.png"]);
+        assert_eq!(search(r"C:\shots")?, [r"C:\shots\a.png"]);
+        assert_eq!(search(r"\\nas\share")?, [r"\\nas\share\b.png"]);
+        assert_eq!(search(r"\\?\UNC\nas\")?, [r"\\nas\share\b.png"]);
         Ok(())
     }
 
     #[test]
-    fn search() -> Result<()> {
+    fn maintain() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        fs::remove_file(root.join("books/ledger.cbz"))?;
+        // a missing root prunes nothing
+        let report = seeded.db.maintain(&[root.join("unmounted")])?;
+        assert_eq!(report.pruned, 0);
+
+        let report = seeded
+            .db
+            .maintain(&[root.join("unmounted"), root.clone()])?;
+        assert_eq!(report.pruned, 2);
+        assert!(report.checkpointed);
+        assert!(!seeded.db.is_path_indexed(&root.join("books/ledger.cbz"))?);
+        assert!(seeded.db.verify()?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn records() -> Result<()> {
+        let seeded = crate::fixture::seed("search")?;
+        let mut records = vec![];
+        let count = seeded.db.export_records(|x| {
+            records.push(serde_json::to_string(x)?);
+            Ok(())
+        })?;
+        assert_eq!(count, seeded.fixture.image.len());
+        assert!(records[0].contains(r#""page":"chapter 1/page_001.png""#));
+
         let (temp, mut db) = test_db()?;
-        let mock_metadata = fs::metadata(".").unwrap();
-        let x = |contents: &'static str| -> OcrResult {
-            OcrResult {
-                path: PathBuf::try_from(temp.path().join(contents.replace(' ', "_"))).unwrap(),
-                metadata: mock_metadata.clone(),
-                contents: contents.into(),
-            }
+        db.set_index_options(&IndexOptions {
+            compress: true,
+            ..Default::default()
+        })?;
+        let parsed: Vec<Record> = records
+            .iter()
+            .map(|x| serde_json::from_str(x))
+            .try_collect()?;
+        assert_eq!(db.import_records(&parsed)?, count);
+        let mut imported = vec![];
+        db.export_records(|x| {
+            imported.push(serde_json::to_string(x)?);
+            Ok(())
+        })?;
+        assert_eq!(imported, records);
+        let grocery = seeded.root.join("receipts/grocery.png");
+        assert_eq!(db.words(&grocery, "")?, seeded.db.words(&grocery, "")?);
+        let results = db.search(
+            vec!["ledger"],
+            &PathBuf::new(),
+            10,
+            SearchType::Simple,
+            None,
+            None,
+        )?;
+        assert_eq!(results.len(), 1);
+        assert!(db.verify()?.is_ok());
+
+        // importing again replaces the rows
+        db.import_records(&parsed[..1])?;
+        assert_eq!(db.stats()?.pages, count);
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn optimize() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let big = OcrResult {
+            path: root.join("big.png"),
+            page: String::new(),
+            metadata: fs::metadata(&root)?,
+            contents: (0..20_000).map(|x| format!("word{x} ")).collect(),
+            properties: BTreeMap::new(),
+            words: vec![],
+        };
+        seeded.db.save_results(vec![big])?;
+        seeded.db.delete(&root.join("big.png"))?;
+
+        let report = seeded.db.optimize()?;
+        assert!(report.after < report.before, "{report:?}");
+        assert!(report.checkpointed);
+        assert!(seeded.db.verify()?.is_ok());
+        assert_eq!(seeded.db.stats()?.pages, seeded.fixture.image.len());
+        Ok(())
+    }
+
+    #[test]
+    fn properties() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let grocery = root.join("receipts/grocery.png");
+        let rows = seeded.db.property_mismatches(&root, "symbols", None)?.len();
+        assert_eq!(rows, 0);
+
+        let properties = BTreeMap::from([("symbols".to_string(), "strip".to_string())]);
+        seeded.db.set_properties(&grocery, "", &properties)?;
+        let mismatches = seeded.db.property_mismatches(&root, "symbols", None)?;
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].path, grocery);
+        assert_eq!(mismatches[0].properties, properties);
+        let all = seeded
+            .db
+            .property_mismatches(&root, "symbols", Some("strip"))?;
+        assert_eq!(all.len(), seeded.fixture.image.len() - 1);
+
+        assert!(seeded.db.is_indexed(&grocery, &fs::metadata(&grocery)?));
+        seeded.db.invalidate(&grocery)?;
+        assert!(!seeded.db.is_indexed(&grocery, &fs::metadata(&grocery)?));
+        Ok(())
+    }
+
+    #[test]
+    fn path_case() -> Result<()> {
+        // APFS and NTFS ignore case in all of Unicode, and ext4 doesn't at all
+        assert!(like(
+            "/Users/ÄB/Pictures/%",
+            "/users/äb/pictures/a.png",
+            Some('#'),
+            true
+        ));
+        assert!(!like(
+            "/Users/ÄB/Pictures/%",
+            "/users/äb/pictures/a.png",
+            Some('#'),
+            false
+        ));
+        assert!(like("/a/100#%/%", "/a/100%/b", Some('#'), false));
+        assert!(!like("/a/100#%/%", "/a/1000/b", Some('#'), false));
+        assert!(like("%b_d%", "/abcde", None, false));
+        assert!(!like("%b_d", "/abcde", None, false));
+
+        let mut seeded = crate::fixture::seed("search")?;
+        let shouting = PathBuf::from(seeded.root.as_str().to_uppercase());
+        let search = |db: &mut DB| {
+            db.search(vec!["total"], &shouting, 40, SearchType::Simple, None, None)
+                .map(|x| x.len())
+        };
+        seeded.db.set_case_insensitive_paths(false)?;
+        assert_eq!(search(&mut seeded.db)?, 0);
+        seeded.db.set_case_insensitive_paths(true)?;
+        assert!(search(&mut seeded.db)? > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn near() -> Result<()> {
+        let near = |words: &str, distance| Near {
+            words: words.split_whitespace().map(String::from).collect(),
+            distance,
         };
         assert_eq!(
-            db.save_results(vec![
-                x("haystack haystack haystack"),
-                x("haystack haystack needle"),
-                x("haystack hayneedle haystack"),
-            ])?,
-            3
+            near(r#"say "hi""#, 1).query(Tokenizer::Trigram),
+            r#"NEAR("say" """hi""", 12)"#
         );
-        let results = db.search(vec!["needle"], Path::new("/"), 40, SearchType::Simple, None)?;
-        println!("{:?}", results);
-        assert_eq!(results.len(), 2);
+        assert_eq!(
+            near("a b", 1).query(Tokenizer::Words),
+            r#"NEAR("a" "b", 1)"#
+        );
+        let query = near("a b", 0).with(SearchType::Match, &["x OR y"], Tokenizer::Trigram)?;
+        assert_eq!(SearchType::Match.terms(&[&query]), ["x", "y", "a", "b"]);
+        assert!(near("a b", 0)
+            .with(SearchType::Glob, &["*a*"], Tokenizer::Trigram)
+            .is_err());
 
-        temp.close()?;
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let grocery = root.join("receipts/grocery.png").to_string();
+        let mut search = |near: Near, queries: &[&str]| -> Result<Vec<String>> {
+            let query = near.with(SearchType::Simple, queries, seeded.db.tokenizer()?)?;
+            let results =
+                seeded
+                    .db
+                    .search(vec![&query], &root, 40, SearchType::Match, None, None)?;
+            Ok(results.into_iter().map(|x| x.path).sorted().collect())
+        };
+        assert_eq!(search(near("milk total", 3), &[])?, [grocery.as_str()]);
+        assert!(search(near("fresh total", 1), &[])?.is_empty());
+        assert_eq!(
+            search(near("fresh total", 5), &["bread"])?,
+            [grocery.as_str()]
+        );
+        assert!(search(near("fresh total", 5), &["tax"])?.is_empty());
+
+        // an index of words counts every word as one token
+        let options = IndexOptions {
+            tokenizer: Tokenizer::Words,
+            ..Default::default()
+        };
+        seeded.db.set_index_options(&options)?;
+        let query = near("fresh milk", 1).query(seeded.db.tokenizer()?);
+        let search = |db: &mut DB, query: &str| -> Result<usize> {
+            Ok(db
+                .search(vec![query], &root, 40, SearchType::Match, None, None)?
+                .len())
+        };
+        assert_eq!(search(&mut seeded.db, &query)?, 1);
+        let query = near("fresh milk", 0).query(seeded.db.tokenizer()?);
+        assert_eq!(search(&mut seeded.db, &query)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn rank() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let mut search = |rank, kind, query| -> Result<Vec<String>> {
+            seeded.db.set_rank(rank);
+            let results = seeded.db.search(vec![query], &root, 40, kind, None, None)?;
+            Ok(results
+                .into_iter()
+                .map(|x| x.path.replace(root.as_str(), ""))
+                .collect())
+        };
+        let newest = search(Rank::Recency, SearchType::Simple, "total")?;
+        assert_eq!(newest.len(), 3);
+        assert!(newest[0].ends_with("grocery.png"));
+        assert!(newest[2].ends_with("older total.jpg"));
+
+        // barely weighed by age
+        let bm25 = search(Rank::Bm25, SearchType::Simple, "total")?;
+        let mixed = Rank::Mixed { half_life: 1e9 };
+        assert_eq!(search(mixed, SearchType::Simple, "total")?, bm25);
+        let glob = search(Rank::Bm25, SearchType::Glob, "*total*")?;
+        assert_eq!(glob.len(), 2);
+        assert_eq!(search(mixed, SearchType::Glob, "*total*")?, glob);
+        Ok(())
+    }
+
+    /// Characters with a meaning in fts5 queries, LIKE patterns, globs, regexes or paths
+    const HOSTILE: &[char] = &[
+        '"', '\'', '*', '%', '_', '#', '\\', '[', ']', '?', '{', '}', '(', ')', ':', '^', '-', '+',
+        '.', '$', '|', ';', '\n', '\t', ' ', 'a', 'B', 'é', 'ß', '0', '🦀', '表', 'N', 'O', 'T',
+    ];
+
+    fn hostile(rng: &mut fastrand::Rng, len: usize) -> String {
+        (0..len)
+            .map(|_| HOSTILE[rng.usize(..HOSTILE.len())])
+            .collect()
+    }
+
+    #[test]
+    fn hostile_queries() -> Result<()> {
+        let (temp, mut db) = test_db()?;
+        let root = PathBuf::try_from(temp.path().to_owned())?;
+        let mut rng = fastrand::Rng::with_seed(1302);
+        for i in 0..300 {
+            let dir = root.join(format!("{i}{}", hostile(&mut rng, 6)).replace('/', ""));
+            let text = hostile(&mut rng, 12);
+            let result = |path: PathBuf| OcrResult {
+                path,
+                metadata: fs::metadata(".").unwrap(),
+                contents: format!("before {text} after"),
+                properties: BTreeMap::new(),
+                words: vec![],
+                page: String::new(),
+            };
+            // files in directories that start with the name of `dir`, or match its wildcards
+            let other = PathBuf::from(format!("{dir}{}", hostile(&mut rng, 2).replace('/', "")));
+            let wildcards = root.join(dir.file_name().unwrap().replace(['%', '_'], "x"));
+            let mut saved = vec![result(dir.join("a")), result(other.join("a"))];
+            if wildcards != dir {
+                saved.push(result(wildcards.join("b")));
+            }
+            db.save_results(saved)?;
+
+            let chars: Vec<char> = text.chars().collect();
+            let start = rng.usize(..chars.len() - 3);
+            let query: String = chars[start..rng.usize(start + 3..=chars.len())]
+                .iter()
+                .collect();
+            let results = db.search(vec![&query], &dir, 40, SearchType::Simple, None, None)?;
+            assert_eq!(results.len(), 1, "{query:?} in {dir:?}");
+            assert_eq!(results[0].path, dir.join("a").as_str());
+
+            let glob = hostile(&mut rng, 4);
+            let excluded = db.search(
+                vec![&query],
+                &dir,
+                40,
+                SearchType::Simple,
+                Some(&glob),
+                None,
+            );
+            let valid = glob::Pattern::new(&format!("{glob}/**")).is_ok();
+            assert_eq!(excluded.is_ok(), valid, "{glob:?}");
+
+            // the user's own syntax can be invalid, but it's an error and not a panic
+            let pattern = hostile(&mut rng, 8);
+            db.search(vec![&pattern], &dir, 40, SearchType::Glob, None, None)?;
+            let _ = db.search(vec![&pattern], &dir, 40, SearchType::Match, None, None);
+            #[cfg(feature = "regex")]
+            assert_eq!(
+                db.search(vec![&pattern], &dir, 40, SearchType::Regex, None, None)
+                    .is_ok(),
+                regex::RegexBuilder::new(&pattern)
+                    .size_limit(RegexOptions::default().size_limit)
+                    .build()
+                    .is_ok(),
+                "{pattern:?}"
+            );
+        }
         Ok(())
     }
 }
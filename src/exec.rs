@@ -0,0 +1,177 @@
+//! Running commands on the files of search results, for `--exec` and `--open`
+
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path as Path;
+use itertools::Itertools;
+
+use crate::db::SearchResult;
+use crate::report::{self, Code};
+
+/// Most files that are opened at once, unless `--limit` was given, so that a broad search doesn't
+/// start a program for every result
+pub const MAX_OPEN: usize = 10;
+
+/// Split a command line into its words at whitespace, with quotes keeping words with spaces
+/// together. Nothing else of a shell is done, so the paths that replace `{}` don't need quoting
+pub fn parse_command(line: &str) -> Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.get_or_insert_with(String::new).push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            None if c.is_whitespace() => words.extend(word.take()),
+            None => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(anyhow!("unclosed quote in command: {line}"));
+    }
+    words.extend(word);
+    if words.is_empty() {
+        return Err(anyhow!("the command is empty"));
+    }
+    Ok(words)
+}
+
+/// The words of `command` for a file: `{}` is replaced with its path, or the path comes last if
+/// none of the words have `{}`
+fn arguments(command: &[String], path: &Path) -> Vec<String> {
+    let mut args = command
+        .iter()
+        .map(|x| x.replace("{}", path.as_str()))
+        .collect_vec();
+    if !command.iter().any(|x| x.contains("{}")) {
+        args.push(path.to_string());
+    }
+    args
+}
+
+/// The files of the results in their order, once each even if several of their pages matched
+fn files(results: &[SearchResult]) -> Vec<&Path> {
+    results
+        .iter()
+        .map(|x| Path::new(&x.path))
+        .unique()
+        .collect()
+}
+
+/// Run `command` on the file of every result, one after another, see [`arguments`]. Commands that
+/// fail are reported, and how many did is returned
+pub fn exec(command: &[String], results: &[SearchResult]) -> Result<usize> {
+    let mut failed = 0;
+    for path in files(results) {
+        let args = arguments(command, path);
+        let status = Command::new(&args[0])
+            .args(&args[1..])
+            .status()
+            .with_context(|| format!("failed to run {}", args[0]))?;
+        if !status.success() {
+            report::error(
+                Code::Exec,
+                Some(path.as_str()),
+                format!("{} failed with {status}", args[0]),
+            );
+            failed += 1;
+        }
+    }
+    Ok(failed)
+}
+
+/// Open the file of every result with the default program for it, or none of them if there are
+/// more than `max`
+pub fn open_all(results: &[SearchResult], max: usize) -> Result<()> {
+    let files = files(results);
+    if files.len() > max {
+        return Err(anyhow!(
+            "the results are in {} files, more than the {max} that are opened at once, narrow the search or raise --limit",
+            files.len()
+        ));
+    }
+    for path in files {
+        open(path).with_context(|| format!("failed to open {path}"))?;
+    }
+    Ok(())
+}
+
+/// Open a file or directory with the default program for it
+pub fn open(path: &Path) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        // not `cmd /C start`, which would run the `&` and `|` of file names as commands
+        Command::new("explorer")
+    } else {
+        Command::new("xdg-open")
+    };
+    command
+        .arg(path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::SearchType;
+
+    #[test]
+    fn commands() -> Result<()> {
+        assert_eq!(
+            parse_command(r#"  magick {} -resize "50 %" '{}.small.png' "" "#)?,
+            ["magick", "{}", "-resize", "50 %", "{}.small.png", ""]
+        );
+        assert!(parse_command("echo 'unclosed").is_err());
+        assert!(parse_command("  ").is_err());
+
+        let path = Path::new("/a b/c.png");
+        let command = parse_command("cp {} {}.bak")?;
+        assert_eq!(
+            arguments(&command, path),
+            ["cp", "/a b/c.png", "/a b/c.png.bak"]
+        );
+        assert_eq!(
+            arguments(&parse_command("ls -l")?, path),
+            ["ls", "-l", "/a b/c.png"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn open_limit() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let results =
+            seeded
+                .db
+                .search(vec!["total"], &root, 100, SearchType::Simple, None, None)?;
+        assert_eq!(files(&results).len(), 3);
+        // refused before anything is opened
+        assert!(open_all(&results, 2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let results =
+            seeded
+                .db
+                .search(vec!["total"], &root, 100, SearchType::Simple, None, None)?;
+        assert_eq!(exec(&parse_command("test -f {}")?, &results)?, 0);
+        assert_eq!(exec(&parse_command("test -d")?, &results)?, 3);
+        assert!(exec(&parse_command("no-such-program-ocrlocate")?, &results).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! Saving text that was recognized somewhere else, like by a cloud OCR batch job
+//!
+//! The text goes through the same redaction and symbol policy as indexing, and is saved with the
+//! modification time of the file, so indexing skips the file until it changes.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path as Path;
+use serde::Deserialize;
+
+use crate::db::{OcrResult, DB};
+use crate::ocr::Word;
+use crate::paths;
+use crate::redact::Redactor;
+use crate::symbols::Symbols;
+
+/// Text of one page of a file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Page {
+    /// Page of a document, empty for single images
+    #[serde(default)]
+    pub page: String,
+    pub contents: String,
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+    #[serde(default)]
+    pub words: Vec<Word>,
+}
+
+/// Replace what is indexed for `path`, an absolute path to an existing file, with `pages`, in
+/// one transaction. Pages that were indexed before and aren't in `pages` are removed. Returns
+/// how many rows changed
+pub fn upsert(
+    db: &mut DB,
+    path: &Path,
+    pages: Vec<Page>,
+    redact: &Redactor,
+    symbols: Symbols,
+) -> Result<usize> {
+    if !path.is_absolute() {
+        return Err(anyhow!("{path} isn't an absolute path"));
+    }
+    let metadata =
+        fs::metadata(&*paths::extended(path)).with_context(|| format!("failed to read {path}"))?;
+    if !metadata.is_file() {
+        return Err(anyhow!("{path} isn't a file"));
+    }
+    if pages.is_empty() {
+        return Err(anyhow!("no pages were given for {path}"));
+    }
+    let mut seen = HashSet::new();
+    if let Some(page) = pages.iter().find(|x| !seen.insert(&x.page)) {
+        return Err(anyhow!("page {:?} of {path} was given twice", page.page));
+    }
+    if let Some(word) = pages
+        .iter()
+        .flat_map(|x| &x.words)
+        .find(|x| x.width < 0 || x.height < 0 || !(0.0..=100.0).contains(&x.confidence))
+    {
+        return Err(anyhow!("invalid word {:?} for {path}", word.text));
+    }
+
+    let results = pages
+        .into_iter()
+        .map(|page| {
+            let mut properties = page.properties;
+            properties.insert("source".into(), "external".into());
+            let mut result = OcrResult {
+                path: path.to_owned(),
+                page: page.page,
+                metadata: metadata.clone(),
+                contents: page.contents,
+                properties,
+                words: page.words,
+            };
+            symbols.apply(&mut result);
+            redact.apply(&mut result);
+            result
+        })
+        .collect();
+    db.save_results(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_pages() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let ledger = root.join("books/ledger.cbz");
+        let page = |page: &str, contents: &str| Page {
+            page: page.into(),
+            contents: contents.into(),
+            ..Default::default()
+        };
+        let (redact, symbols) = (Redactor::default(), Symbols::default());
+
+        upsert(
+            &mut seeded.db,
+            &ledger,
+            vec![page("chapter 1/page_001.png", "rewritten → ledger")],
+            &redact,
+            Symbols {
+                transliterate: true,
+                strip: false,
+            },
+        )?;
+        let contents = seeded.db.content(&ledger, "chapter 1/page_001.png")?;
+        assert_eq!(contents.as_deref(), Some("rewritten -> ledger"));
+        assert_eq!(seeded.db.content(&ledger, "chapter 1/page_002.png")?, None);
+        let rows = seeded
+            .db
+            .property_mismatches(&root, "source", Some("external"))?;
+        assert!(rows.iter().all(|x| x.path != ledger));
+        assert!(seeded.db.verify()?.is_ok());
+
+        let twice = vec![page("1.png", "a"), page("1.png", "b")];
+        assert!(upsert(&mut seeded.db, &ledger, twice, &redact, symbols).is_err());
+        let missing = root.join("missing.png");
+        assert!(upsert(
+            &mut seeded.db,
+            &missing,
+            vec![page("", "a")],
+            &redact,
+            symbols
+        )
+        .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,662 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::process::Command;
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path as Path;
+use itertools::Itertools;
+
+use crate::config::ExtractorConfig;
+use crate::ocr::{Ocr, Word};
+
+/// Text pulled out of a file by an [`Extractor`]
+#[derive(Debug, Default)]
+pub struct Extracted {
+    pub contents: String,
+    /// Extra key/value pairs saved alongside the text
+    pub properties: BTreeMap<String, String>,
+    /// Positions of the words, for extractors that OCR an image
+    pub words: Vec<Word>,
+}
+
+/// Turns a file into searchable text
+///
+/// Extractors are shared between the indexing threads, and each call is handed that thread's
+/// [`Ocr`] instance so that extractors which can decode images can reuse it.
+pub trait Extractor: Send + Sync {
+    /// Name used in debug output and stored with the results
+    fn name(&self) -> &str;
+
+    /// Lowercase file extensions this extractor handles
+    fn extensions(&self) -> &[String];
+
+    /// Whether this extractor should handle `path`. Defaults to an extension check
+    fn matches(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| self.extensions().contains(&ext.to_ascii_lowercase()))
+    }
+
+    /// Width and height of the file, if it is an image. Used for `--max-size`
+    fn image_size(&self, _path: &Path) -> Option<Result<(usize, usize)>> {
+        None
+    }
+
+    fn extract(&self, path: &Path, ocr: &mut Ocr) -> Result<Extracted>;
+
+    /// Text of each page of a document, along with an identifier of the page. Files that aren't
+    /// documents are a single page with an empty identifier, which is the default
+    fn extract_pages(&self, path: &Path, ocr: &mut Ocr) -> Result<Vec<(String, Extracted)>> {
+        Ok(vec![(String::new(), self.extract(path, ocr)?)])
+    }
+}
+
+/// Join the pages of a document, for [`Extractor::extract`] on document extractors
+fn join_pages(pages: Vec<(String, Extracted)>) -> Extracted {
+    Extracted {
+        contents: pages.into_iter().map(|(_, x)| x.contents).join("\n\n"),
+        ..Default::default()
+    }
+}
+
+/// The set of extractors used while indexing
+///
+/// Extractors registered later take priority, so users can override the builtin ones.
+pub struct Registry {
+    extractors: Vec<Box<dyn Extractor>>,
+}
+
+impl Registry {
+    /// A registry without any extractors
+    pub fn empty() -> Self {
+        Registry { extractors: vec![] }
+    }
+
+    /// A registry with the builtin extractors for this build
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Box::new(ImageExtractor::new()));
+        registry.register(Box::new(RawExtractor::new()));
+        registry.register(Box::new(ArchiveExtractor::new()));
+        // ddjvu writes pnm, which only leptonica reads
+        #[cfg(feature = "ocr")]
+        registry.register(Box::new(DjvuExtractor::new()));
+        #[cfg(feature = "office")]
+        registry.register(Box::new(OfficeExtractor::new()));
+        #[cfg(feature = "heif")]
+        registry.register(Box::new(HeifExtractor::new()));
+        #[cfg(feature = "svg")]
+        registry.register(Box::new(SvgExtractor::new()));
+        registry
+    }
+
+    pub fn register(&mut self, extractor: Box<dyn Extractor>) {
+        self.extractors.push(extractor);
+    }
+
+    /// Register the command extractors described in the config file
+    pub fn register_config(&mut self, configs: &[ExtractorConfig]) -> Result<()> {
+        for config in configs {
+            self.register(Box::new(CommandExtractor::new(config)?));
+        }
+        Ok(())
+    }
+
+    pub fn find(&self, path: &Path) -> Option<&dyn Extractor> {
+        self.extractors
+            .iter()
+            .rev()
+            .find(|x| x.matches(path))
+            .map(|x| x.as_ref())
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::with_builtins()
+    }
+}
+
+/// OCRs the image formats leptonica can read
+pub struct ImageExtractor {
+    extensions: Vec<String>,
+}
+
+impl ImageExtractor {
+    pub fn new() -> Self {
+        ImageExtractor {
+            extensions: ["png", "jpeg", "jpg", "gif", "webp"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+impl Default for ImageExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Extractor for ImageExtractor {
+    fn name(&self) -> &str {
+        "image"
+    }
+
+    fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    fn image_size(&self, path: &Path) -> Option<Result<(usize, usize)>> {
+        Some(
+            imagesize::size(path)
+                .map(|size| (size.width, size.height))
+                .map_err(|e| anyhow!(e)),
+        )
+    }
+
+    fn extract(&self, path: &Path, ocr: &mut Ocr) -> Result<Extracted> {
+        Ok(Extracted {
+            contents: ocr.scan(path)?,
+            words: ocr.words(),
+            properties: ocr.properties(),
+        })
+    }
+}
+
+/// OCRs the JPEG preview embedded in camera RAW files, see [`crate::raw`]
+pub struct RawExtractor {
+    extensions: Vec<String>,
+}
+
+impl RawExtractor {
+    pub fn new() -> Self {
+        RawExtractor {
+            extensions: ["cr2", "nef", "arw"].map(String::from).to_vec(),
+        }
+    }
+
+    fn preview(path: &Path) -> Result<Vec<u8>> {
+        let data = fs::read(path)?;
+        Ok(crate::raw::embedded_preview(&data)
+            .ok_or_else(|| anyhow!("no embedded preview found"))?
+            .to_vec())
+    }
+}
+
+impl Default for RawExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Extractor for RawExtractor {
+    fn name(&self) -> &str {
+        "raw"
+    }
+
+    fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    fn image_size(&self, path: &Path) -> Option<Result<(usize, usize)>> {
+        Some(Self::preview(path).and_then(|preview| {
+            imagesize::blob_size(&preview)
+                .map(|size| (size.width, size.height))
+                .map_err(|e| anyhow!(e))
+        }))
+    }
+
+    fn extract(&self, path: &Path, ocr: &mut Ocr) -> Result<Extracted> {
+        Ok(Extracted {
+            contents: ocr.scan_mem(&Self::preview(path)?)?,
+            words: ocr.words(),
+            properties: ocr.properties(),
+        })
+    }
+}
+
+/// OCRs every image of a comic book archive or an image based EPUB, in the order of their names
+///
+/// The page identifiers are the names of the images inside of the archive. Archives without images
+/// are stored as a single empty page so they aren't opened again on every run.
+pub struct ArchiveExtractor {
+    extensions: Vec<String>,
+    images: ImageExtractor,
+}
+
+impl ArchiveExtractor {
+    pub fn new() -> Self {
+        ArchiveExtractor {
+            extensions: ["cbz", "epub"].map(String::from).to_vec(),
+            images: ImageExtractor::new(),
+        }
+    }
+}
+
+impl Default for ArchiveExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Extractor for ArchiveExtractor {
+    fn name(&self) -> &str {
+        "archive"
+    }
+
+    fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    fn extract(&self, path: &Path, ocr: &mut Ocr) -> Result<Extracted> {
+        Ok(join_pages(self.extract_pages(path, ocr)?))
+    }
+
+    fn extract_pages(&self, path: &Path, ocr: &mut Ocr) -> Result<Vec<(String, Extracted)>> {
+        scan_members(path, ocr, |name| self.images.matches(Path::new(name)))
+    }
+}
+
+/// OCR the images in a zip file whose names pass `filter`, in the order of their names, or a single
+/// empty page without any
+fn scan_members(
+    path: &Path,
+    ocr: &mut Ocr,
+    filter: impl Fn(&str) -> bool,
+) -> Result<Vec<(String, Extracted)>> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    let names: Vec<String> = archive
+        .file_names()
+        .filter(|name| filter(name))
+        .map(String::from)
+        .sorted()
+        .collect();
+    let mut pages = vec![];
+    for name in names {
+        let mut data = vec![];
+        archive.by_name(&name)?.read_to_end(&mut data)?;
+        let contents = ocr
+            .scan_mem(&data)
+            .with_context(|| format!("failed to read page {}", name))?;
+        pages.push((
+            name,
+            Extracted {
+                contents,
+                words: ocr.words(),
+                properties: ocr.properties(),
+            },
+        ));
+    }
+    if pages.is_empty() {
+        pages.push((String::new(), Extracted::default()));
+    }
+    Ok(pages)
+}
+
+/// OCRs the images embedded in Word, PowerPoint and OpenDocument text files
+///
+/// Each image is a page, identified by its path inside of the document. Documents without images
+/// are stored as a single empty page so they aren't opened again on every run.
+#[cfg(feature = "office")]
+pub struct OfficeExtractor {
+    extensions: Vec<String>,
+    images: ImageExtractor,
+}
+
+#[cfg(feature = "office")]
+impl OfficeExtractor {
+    /// Directories the formats keep their embedded media in
+    const MEDIA_DIRS: [&'static str; 3] = ["word/media/", "ppt/media/", "Pictures/"];
+
+    pub fn new() -> Self {
+        OfficeExtractor {
+            extensions: ["docx", "pptx", "odt"].map(String::from).to_vec(),
+            images: ImageExtractor::new(),
+        }
+    }
+}
+
+#[cfg(feature = "office")]
+impl Default for OfficeExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "office")]
+impl Extractor for OfficeExtractor {
+    fn name(&self) -> &str {
+        "office"
+    }
+
+    fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    fn extract(&self, path: &Path, ocr: &mut Ocr) -> Result<Extracted> {
+        Ok(join_pages(self.extract_pages(path, ocr)?))
+    }
+
+    fn extract_pages(&self, path: &Path, ocr: &mut Ocr) -> Result<Vec<(String, Extracted)>> {
+        scan_members(path, ocr, |name| {
+            Self::MEDIA_DIRS.iter().any(|dir| name.starts_with(dir))
+                && self.images.matches(Path::new(name))
+        })
+    }
+}
+
+/// OCRs the pages of DjVu scans, rendered with `ddjvu` from djvulibre
+///
+/// The page identifiers are page numbers starting at 1.
+pub struct DjvuExtractor {
+    extensions: Vec<String>,
+}
+
+impl DjvuExtractor {
+    pub fn new() -> Self {
+        DjvuExtractor {
+            extensions: ["djvu", "djv"].map(String::from).to_vec(),
+        }
+    }
+}
+
+impl Default for DjvuExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run a djvulibre tool and return its output
+fn djvulibre(program: &str, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("failed to run {} (is djvulibre installed?)", program))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+impl Extractor for DjvuExtractor {
+    fn name(&self) -> &str {
+        "djvu"
+    }
+
+    fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    fn extract(&self, path: &Path, ocr: &mut Ocr) -> Result<Extracted> {
+        Ok(join_pages(self.extract_pages(path, ocr)?))
+    }
+
+    fn extract_pages(&self, path: &Path, ocr: &mut Ocr) -> Result<Vec<(String, Extracted)>> {
+        let count: usize = String::from_utf8(djvulibre("djvused", &[path.as_str(), "-e", "n"])?)?
+            .trim()
+            .parse()
+            .context("djvused returned an invalid page count")?;
+        let mut pages = vec![];
+        for page in 1..=count {
+            let image = djvulibre(
+                "ddjvu",
+                &["-format=pnm", &format!("-page={page}"), path.as_str()],
+            )?;
+            let contents = ocr
+                .scan_mem(&image)
+                .with_context(|| format!("failed to read page {}", page))?;
+            pages.push((
+                page.to_string(),
+                Extracted {
+                    contents,
+                    words: ocr.words(),
+                    properties: ocr.properties(),
+                },
+            ));
+        }
+        Ok(pages)
+    }
+}
+
+/// Decodes HEIC and AVIF images with libheif, which leptonica can't read
+#[cfg(feature = "heif")]
+pub struct HeifExtractor {
+    extensions: Vec<String>,
+}
+
+#[cfg(feature = "heif")]
+impl HeifExtractor {
+    pub fn new() -> Self {
+        HeifExtractor {
+            extensions: ["heic", "heif", "avif"].map(String::from).to_vec(),
+        }
+    }
+}
+
+#[cfg(feature = "heif")]
+impl Default for HeifExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "heif")]
+impl Extractor for HeifExtractor {
+    fn name(&self) -> &str {
+        "heif"
+    }
+
+    fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    fn image_size(&self, path: &Path) -> Option<Result<(usize, usize)>> {
+        use libheif_rs::HeifContext;
+        Some((|| -> Result<_> {
+            let handle = HeifContext::read_from_file(path.as_str())?.primary_image_handle()?;
+            Ok((handle.width() as usize, handle.height() as usize))
+        })())
+    }
+
+    fn extract(&self, path: &Path, ocr: &mut Ocr) -> Result<Extracted> {
+        use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+        let ctx = HeifContext::read_from_file(path.as_str())?;
+        let image = LibHeif::new().decode(
+            &ctx.primary_image_handle()?,
+            ColorSpace::Rgb(RgbChroma::Rgb),
+            None,
+        )?;
+        let plane = image
+            .planes()
+            .interleaved
+            .ok_or_else(|| anyhow!("libheif did not return interleaved rgb"))?;
+        let pix = crate::ocr::pix_from_rgb(plane.width, plane.height, plane.stride, plane.data)?;
+        Ok(Extracted {
+            contents: ocr.scan_pix(pix)?,
+            words: ocr.words(),
+            properties: ocr.properties(),
+        })
+    }
+}
+
+/// Rasterizes SVG files with resvg, including their embedded raster images and text
+#[cfg(feature = "svg")]
+pub struct SvgExtractor {
+    extensions: Vec<String>,
+    /// System fonts for rendering text, loaded once since it is slow
+    fontdb: std::sync::Arc<resvg::usvg::fontdb::Database>,
+}
+
+#[cfg(feature = "svg")]
+impl SvgExtractor {
+    /// Drawings are scaled so that their longest side is about this long, since tesseract does
+    /// badly on the small sizes that svgs are often saved with
+    const TARGET_SIZE: f32 = 2000.0;
+
+    pub fn new() -> Self {
+        let mut fontdb = resvg::usvg::fontdb::Database::new();
+        fontdb.load_system_fonts();
+        SvgExtractor {
+            extensions: ["svg", "svgz"].map(String::from).to_vec(),
+            fontdb: fontdb.into(),
+        }
+    }
+
+    fn parse(&self, path: &Path) -> Result<resvg::usvg::Tree> {
+        let options = resvg::usvg::Options {
+            resources_dir: path.parent().map(|x| x.as_std_path().to_owned()),
+            fontdb: self.fontdb.clone(),
+            ..Default::default()
+        };
+        Ok(resvg::usvg::Tree::from_data(&fs::read(path)?, &options)?)
+    }
+}
+
+#[cfg(feature = "svg")]
+impl Default for SvgExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "svg")]
+impl Extractor for SvgExtractor {
+    fn name(&self) -> &str {
+        "svg"
+    }
+
+    fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    fn image_size(&self, path: &Path) -> Option<Result<(usize, usize)>> {
+        Some(self.parse(path).map(|tree| {
+            let size = tree.size();
+            (size.width() as usize, size.height() as usize)
+        }))
+    }
+
+    fn extract(&self, path: &Path, ocr: &mut Ocr) -> Result<Extracted> {
+        use resvg::tiny_skia::{Color, Pixmap, Transform};
+        let tree = self.parse(path)?;
+        let size = tree.size();
+        let scale = (Self::TARGET_SIZE / size.width().max(size.height())).clamp(1.0, 4.0);
+        let (width, height) = (
+            (size.width() * scale).ceil() as u32,
+            (size.height() * scale).ceil() as u32,
+        );
+        let mut pixmap = Pixmap::new(width, height)
+            .ok_or_else(|| anyhow!("invalid svg size {}x{}", width, height))?;
+        // transparent areas would turn black once the alpha channel is dropped
+        pixmap.fill(Color::WHITE);
+        resvg::render(
+            &tree,
+            Transform::from_scale(scale, scale),
+            &mut pixmap.as_mut(),
+        );
+        let rgb: Vec<u8> = pixmap
+            .data()
+            .chunks_exact(4)
+            .flat_map(|x| &x[..3])
+            .copied()
+            .collect();
+        let pix = crate::ocr::pix_from_rgb(width, height, width as usize * 3, &rgb)?;
+        Ok(Extracted {
+            contents: ocr.scan_pix(pix)?,
+            words: ocr.words(),
+            properties: ocr.properties(),
+        })
+    }
+}
+
+/// Runs an external program and uses its standard output as the text of the file
+///
+/// Configured with `[[extractor]]` tables in the config file. `{}` in the arguments is replaced
+/// with the path of the file.
+pub struct CommandExtractor {
+    name: String,
+    extensions: Vec<String>,
+    command: Vec<String>,
+}
+
+impl CommandExtractor {
+    pub fn new(config: &ExtractorConfig) -> Result<Self> {
+        if config.command.is_empty() {
+            return Err(anyhow!("extractor {} has an empty command", config.name));
+        }
+        Ok(CommandExtractor {
+            name: config.name.clone(),
+            extensions: config
+                .extensions
+                .iter()
+                .map(|x| x.trim_start_matches('.').to_ascii_lowercase())
+                .collect(),
+            command: config.command.clone(),
+        })
+    }
+}
+
+impl Extractor for CommandExtractor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    fn extract(&self, path: &Path, _ocr: &mut Ocr) -> Result<Extracted> {
+        let output = Command::new(&self.command[0])
+            .args(
+                self.command[1..]
+                    .iter()
+                    .map(|x| x.replace("{}", path.as_str())),
+            )
+            .output()
+            .with_context(|| format!("failed to run extractor {}", self.name))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "extractor {} failed with {}: {}",
+                self.name,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(Extracted {
+            contents: String::from_utf8_lossy(&output.stdout).into_owned(),
+            properties: BTreeMap::from([("extractor".into(), self.name.clone())]),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_priority() -> Result<()> {
+        let mut registry = Registry::with_builtins();
+        assert_eq!(registry.find(Path::new("a.PNG")).unwrap().name(), "image");
+        assert!(registry.find(Path::new("a.txt")).is_none());
+        assert_eq!(registry.find(Path::new("a.NEF")).unwrap().name(), "raw");
+        assert_eq!(registry.find(Path::new("a.cbz")).unwrap().name(), "archive");
+        #[cfg(feature = "office")]
+        assert_eq!(registry.find(Path::new("a.docx")).unwrap().name(), "office");
+
+        registry.register_config(&[ExtractorConfig {
+            name: "custom".into(),
+            extensions: vec![".png".into(), "txt".into()],
+            command: vec!["cat".into(), "{}".into()],
+        }])?;
+        assert_eq!(registry.find(Path::new("a.png")).unwrap().name(), "custom");
+        assert_eq!(registry.find(Path::new("a.txt")).unwrap().name(), "custom");
+        Ok(())
+    }
+}
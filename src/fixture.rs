@@ -0,0 +1,119 @@
+//! Test databases described by the files in `tests/fixtures`, and golden files to compare output
+//! against in `tests/golden`
+//!
+//! Run the tests with `UPDATE_GOLDEN=1` to rewrite the golden files after an intended change to
+//! an output format, and check the diff before committing them.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File, FileTimes};
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf as PathBuf;
+use serde::Deserialize;
+use tempfile::TempDir;
+
+use crate::db::{OcrResult, DB};
+use crate::ocr::Word;
+
+const DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests");
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixture {
+    pub image: Vec<Image>,
+}
+
+/// An `[[image]]` table, an indexed file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Image {
+    /// Relative to the root of the fixture
+    pub path: String,
+    #[serde(default)]
+    pub page: String,
+    /// Seconds since the epoch, set as the modification time of the file
+    #[serde(default)]
+    pub modtime: u64,
+    /// Bytes of the file, which is empty otherwise
+    #[serde(default)]
+    pub size: u64,
+    pub contents: String,
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+    #[serde(default)]
+    pub words: Vec<Word>,
+}
+
+/// A database seeded from a fixture, along with files for each of its images
+pub struct Seeded {
+    pub db: DB,
+    /// Directory the images were created in
+    pub root: PathBuf,
+    pub fixture: Fixture,
+    _temp: TempDir,
+}
+
+impl Seeded {
+    /// Replace the temporary root with `$ROOT`, so that output can be compared between runs
+    pub fn redact(&self, s: &str) -> String {
+        s.replace(self.root.as_str(), "$ROOT")
+    }
+}
+
+/// Load `tests/fixtures/{name}.toml` into a new database
+pub fn seed(name: &str) -> Result<Seeded> {
+    let path = format!("{DIR}/fixtures/{name}.toml");
+    let text = fs::read_to_string(&path).with_context(|| format!("failed to read {path}"))?;
+    let fixture: Fixture =
+        toml::from_str(&text).with_context(|| format!("failed to parse {path}"))?;
+
+    let temp = TempDir::new()?;
+    let root = PathBuf::try_from(temp.path().to_path_buf())?;
+    let mut db = DB::new(&root.join("index.db"))?;
+    let mut results = vec![];
+    for image in &fixture.image {
+        let path = root.join(&image.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::create(&path)?;
+        file.set_len(image.size)?;
+        file.set_times(
+            FileTimes::new().set_modified(UNIX_EPOCH + Duration::from_secs(image.modtime)),
+        )?;
+        results.push(OcrResult {
+            metadata: file.metadata()?,
+            path,
+            page: image.page.clone(),
+            contents: image.contents.clone(),
+            properties: image.properties.clone(),
+            words: image.words.clone(),
+        });
+    }
+    db.save_results(results)?;
+
+    Ok(Seeded {
+        db,
+        root,
+        fixture,
+        _temp: temp,
+    })
+}
+
+/// Compare `actual` to `tests/golden/{name}`, or overwrite it when `UPDATE_GOLDEN` is set
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = format!("{DIR}/golden/{name}");
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!("failed to read {path}: {e}, run with UPDATE_GOLDEN=1 to create it")
+    });
+    assert!(
+        expected == actual,
+        "output doesn't match {path}, run with UPDATE_GOLDEN=1 to update it\n--- expected\n{expected}\n--- actual\n{actual}"
+    );
+}
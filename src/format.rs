@@ -0,0 +1,407 @@
+//! Formatting search results for the terminal
+
+use std::cmp::Reverse;
+use std::io::{self, Write};
+
+use itertools::Itertools;
+
+use crate::db::{Line, SearchResult, SnippetOptions, Stats};
+
+/// ANSI escapes of matches, of paths, and back to the normal text
+const MATCH: &str = "\x1b[1;31m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// One result per line: the snippet with its control characters escaped, the path, and the page
+/// if the file is a document. With `confidence` every line starts with the confidence of the OCR,
+/// or `-` for files that weren't OCRed. With `long`, the time in seconds since the epoch, how long
+/// before it the file was modified and the size of the file come next. Notes that embed the file
+/// follow on lines that start with a tab. With `color`, the matches between the markers of the
+/// snippets are bold and red instead, and the paths are dim
+pub fn write_plain(
+    out: &mut impl Write,
+    results: &[SearchResult],
+    confidence: bool,
+    long: Option<u64>,
+    color: Option<&SnippetOptions>,
+) -> io::Result<()> {
+    let (dim, reset) = match color {
+        Some(_) => (DIM, RESET),
+        None => ("", ""),
+    };
+    for x in results {
+        if confidence {
+            match x.confidence {
+                Some(confidence) => write!(out, "{confidence:.0}\t")?,
+                None => write!(out, "-\t")?,
+            }
+        }
+        if let Some(now) = long {
+            let size = x.size.map_or("-".into(), human_size);
+            write!(out, "{}\t{size}\t", age(now.saturating_sub(x.time)))?;
+        }
+        write_snippet(out, &x.contents, color)?;
+        if x.page.is_empty() {
+            writeln!(out, "\t{dim}{}{reset}", x.path)?;
+        } else {
+            writeln!(out, "\t{dim}{}\t{}{reset}", x.path, x.page)?;
+        }
+        for note in &x.notes {
+            writeln!(out, "\tin {}", note)?;
+        }
+    }
+    Ok(())
+}
+
+/// How long `seconds` is in the largest unit that it has one of, like `3d ago`
+fn age(seconds: u64) -> String {
+    const UNITS: [(u64, &str); 6] = [
+        (365 * 24 * 60 * 60, "y"),
+        (30 * 24 * 60 * 60, "mo"),
+        (7 * 24 * 60 * 60, "w"),
+        (24 * 60 * 60, "d"),
+        (60 * 60, "h"),
+        (60, "m"),
+    ];
+    UNITS
+        .iter()
+        .find(|(unit, _)| seconds >= *unit)
+        .map_or("now".into(), |(unit, name)| {
+            format!("{}{name} ago", seconds / unit)
+        })
+}
+
+/// Bytes in the largest binary unit that it has one of, like `1.5 MiB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{size:.1} {}", UNITS[unit]),
+    }
+}
+
+/// The snippet options to search with when the results are colored, with markers that are control
+/// characters so that brackets in the text aren't taken for matches
+pub fn color_markers(snippet: &SnippetOptions) -> SnippetOptions {
+    SnippetOptions {
+        open: "\x02".into(),
+        close: "\x03".into(),
+        ..snippet.clone()
+    }
+}
+
+/// The snippet with its control characters escaped, and with `color` its matches colored in place
+/// of their markers. Markers without a closing one are left as they are
+fn write_snippet(
+    out: &mut impl Write,
+    contents: &str,
+    color: Option<&SnippetOptions>,
+) -> io::Result<()> {
+    let Some(markers) = color.filter(|x| !x.open.is_empty() && !x.close.is_empty()) else {
+        return write!(out, "{}", contents.escape_debug());
+    };
+    let mut rest = contents;
+    while let Some((before, after)) = rest.split_once(&*markers.open) {
+        let Some((found, after)) = after.split_once(&*markers.close) else {
+            break;
+        };
+        write!(
+            out,
+            "{}{MATCH}{}{RESET}",
+            before.escape_debug(),
+            found.escape_debug()
+        )?;
+        rest = after;
+    }
+    write!(out, "{}", rest.escape_debug())
+}
+
+/// One result per line for fzf, in a format that won't change: the path, the page, and the snippet
+/// with its control characters escaped, separated by tabs. With `color` the snippet is colored
+/// like in [`write_plain`]
+pub fn write_fzf(
+    out: &mut impl Write,
+    results: &[SearchResult],
+    color: Option<&SnippetOptions>,
+) -> io::Result<()> {
+    for x in results {
+        write!(out, "{}\t{}\t", x.path, x.page)?;
+        write_snippet(out, &x.contents, color)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// The results under a heading for every term, with the number of results that contain it.
+/// Terms without any results are listed too, and results with several terms are under each of them
+pub fn write_grouped(
+    out: &mut impl Write,
+    results: &[SearchResult],
+    terms: &[String],
+    confidence: bool,
+    long: Option<u64>,
+    color: Option<&SnippetOptions>,
+) -> io::Result<()> {
+    for (i, term) in terms.iter().enumerate() {
+        let matched: Vec<&SearchResult> =
+            results.iter().filter(|x| x.terms.contains(term)).collect();
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, "{} ({})", term.escape_debug(), matched.len())?;
+        for x in matched {
+            write!(out, "  ")?;
+            write_plain(out, std::slice::from_ref(x), confidence, long, color)?;
+        }
+    }
+    Ok(())
+}
+
+/// Every matching line of the results as `path:line:text` like grep, which editors can jump to.
+/// The text of a page of a document starts with the page, like `path:line:page: text`. With
+/// `color` the matches are bold and red and the paths dim. With `context`, lines around the
+/// matches are written as `path-line-text`, and groups of lines that aren't next to each other are
+/// separated by `--`
+pub fn write_grep(
+    out: &mut impl Write,
+    results: &[(SearchResult, Vec<Line>)],
+    color: bool,
+    context: bool,
+) -> io::Result<()> {
+    let (dim, reset) = match color {
+        true => (DIM, RESET),
+        false => ("", ""),
+    };
+    let mut previous: Option<(&SearchResult, usize)> = None;
+    for (x, lines) in results {
+        for line in lines {
+            if context && previous.is_some_and(|(p, n)| !std::ptr::eq(p, x) || n + 1 != line.number)
+            {
+                writeln!(out, "--")?;
+            }
+            previous = Some((x, line.number));
+            let separator = if line.context { '-' } else { ':' };
+            write!(
+                out,
+                "{dim}{}{reset}{separator}{}{separator}",
+                x.path, line.number
+            )?;
+            if !x.page.is_empty() {
+                write!(out, "{}: ", x.page)?;
+            }
+            let mut at = 0;
+            for found in line.matches.iter().filter(|_| color) {
+                write!(
+                    out,
+                    "{}{MATCH}{}{RESET}",
+                    &line.text[at..found.start],
+                    &line.text[found.clone()]
+                )?;
+                at = found.end;
+            }
+            writeln!(out, "{}", &line.text[at..])?;
+        }
+    }
+    Ok(())
+}
+
+/// The statistics of `ocrlocate db stats`, with the modified times as how long before `now` they
+/// were, and the extensions from the most files to the least
+pub fn write_stats(out: &mut impl Write, stats: &Stats, now: u64) -> io::Result<()> {
+    writeln!(out, "{} files, {} pages", stats.files, stats.pages)?;
+    writeln!(
+        out,
+        "{} of text, {} of search index",
+        human_size(stats.text_size),
+        human_size(stats.index_size)
+    )?;
+    if let Some((oldest, newest)) = stats.oldest.zip(stats.newest) {
+        let ago = |time: i64| age(now.saturating_sub(time.max(0) as u64));
+        writeln!(out, "modified from {} to {}", ago(oldest), ago(newest))?;
+    }
+    writeln!(out, "{} pages marked for deletion", stats.marked_delete)?;
+    for (extension, count) in stats
+        .extensions
+        .iter()
+        .sorted_by_key(|(_, &count)| Reverse(count))
+    {
+        match extension.as_str() {
+            "" => writeln!(out, "{count}\twithout an extension")?,
+            _ => writeln!(out, "{count}\t.{extension}")?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::SearchType;
+    use crate::fixture;
+    use anyhow::Result;
+
+    fn search(query: &str) -> Result<(fixture::Seeded, Vec<SearchResult>)> {
+        let mut seeded = fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let results = seeded
+            .db
+            .search(vec![query], &root, 100, SearchType::Simple, None, None)?;
+        Ok((seeded, results))
+    }
+
+    #[test]
+    fn plain() -> Result<()> {
+        let (seeded, results) = search("total")?;
+        let mut out = vec![];
+        write_plain(&mut out, &results, false, None, None)?;
+        fixture::assert_golden("search.txt", &seeded.redact(&String::from_utf8(out)?));
+
+        let mut out = vec![];
+        write_plain(&mut out, &results, true, None, None)?;
+        fixture::assert_golden(
+            "search-confidence.txt",
+            &seeded.redact(&String::from_utf8(out)?),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn long() -> Result<()> {
+        let (seeded, results) = search("total")?;
+        let mut out = vec![];
+        // a day and an hour after the newest of them was modified
+        write_plain(&mut out, &results, false, Some(1_700_090_000), None)?;
+        fixture::assert_golden("search-long.txt", &seeded.redact(&String::from_utf8(out)?));
+
+        assert_eq!(age(59), "now");
+        assert_eq!(age(2 * 60 * 60 + 1), "2h ago");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        Ok(())
+    }
+
+    #[test]
+    fn color() -> Result<()> {
+        let (_seeded, results) = search("market")?;
+        let markers = SnippetOptions::default();
+        let mut out = vec![];
+        write_plain(&mut out, &results, false, None, Some(&markers))?;
+        let out = String::from_utf8(out)?;
+        assert!(out.starts_with(&format!("FRESH {MATCH}MARKET{RESET}\\nmilk\\t2.49")));
+        assert!(out.contains(&format!("\t{DIM}")));
+        assert!(out.ends_with(&format!("grocery.png{RESET}\n")));
+
+        // markers that aren't closed, and ones that the escaping would change
+        let markers = color_markers(&SnippetOptions::default());
+        let mut out = vec![];
+        write_snippet(&mut out, "a\x02b\x03 [c\x02d", Some(&markers))?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            format!("a{MATCH}b{RESET} [c\\u{{2}}d")
+        );
+
+        // brackets in the text are left as they are
+        let mut out = vec![];
+        write_snippet(&mut out, "[x] \x02done\x03 [", Some(&markers))?;
+        assert_eq!(String::from_utf8(out)?, format!("[x] {MATCH}done{RESET} ["));
+        Ok(())
+    }
+
+    #[test]
+    fn grouped() -> Result<()> {
+        let mut seeded = fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let queries = ["total OR market OR nowhere"];
+        let mut results =
+            seeded
+                .db
+                .search(queries.to_vec(), &root, 100, SearchType::Match, None, None)?;
+        let terms = seeded
+            .db
+            .matched_terms(&mut results, &queries, SearchType::Match)?;
+        let mut out = vec![];
+        write_grouped(&mut out, &results, &terms, false, None, None)?;
+        fixture::assert_golden(
+            "search-grouped.txt",
+            &seeded.redact(&String::from_utf8(out)?),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn grep() -> Result<()> {
+        let (seeded, results) = search("total")?;
+        let results = results
+            .into_iter()
+            .map(|x| {
+                let lines = seeded
+                    .db
+                    .matching_lines(&x, &["total"], SearchType::Simple, (1, 0))?;
+                Ok((x, lines))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut out = vec![];
+        write_grep(&mut out, &results, false, true)?;
+        fixture::assert_golden("search-grep.txt", &seeded.redact(&String::from_utf8(out)?));
+
+        let mut out = vec![];
+        write_grep(&mut out, &results[..1], true, false)?;
+        let out = String::from_utf8(out)?;
+        assert!(out.starts_with(DIM));
+        assert!(out.ends_with(&format!(
+            "{RESET}:1:sub{MATCH}total{RESET} \"4.00\" & tax <0.32> {MATCH}total{RESET} 4.32\n"
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn stats() -> Result<()> {
+        let stats = Stats {
+            files: 3,
+            pages: 12,
+            text_size: 20_000,
+            index_size: 3_500_000,
+            oldest: Some(1_600_000_000),
+            newest: Some(1_700_000_000),
+            marked_delete: 1,
+            extensions: [("png".into(), 1), ("".into(), 1), ("cbz".into(), 2)].into(),
+        };
+        let mut out = vec![];
+        write_stats(&mut out, &stats, 1_700_090_000)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "3 files, 12 pages
+19.5 KiB of text, 3.3 MiB of search index
+modified from 3y ago to 1d ago
+1 pages marked for deletion
+2\t.cbz
+1\twithout an extension
+1\t.png
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn fzf() -> Result<()> {
+        let (seeded, results) = search("total")?;
+        let mut out = vec![];
+        write_fzf(&mut out, &results, None)?;
+        fixture::assert_golden("search-fzf.txt", &seeded.redact(&String::from_utf8(out)?));
+        Ok(())
+    }
+
+    #[test]
+    fn json() -> Result<()> {
+        let (seeded, results) = search("total")?;
+        let out = serde_json::to_string_pretty(&results)?;
+        fixture::assert_golden("search.json", &seeded.redact(&out));
+        Ok(())
+    }
+}
@@ -0,0 +1,367 @@
+//! Importing the text that other OCR and search tools made, so switching to ocrlocate doesn't
+//! mean OCRing everything again
+//!
+//! The text of each file is saved with [`external::upsert`], so it goes through the same
+//! redaction and symbol policy as indexing and indexing skips the file until it changes. Files
+//! that are already indexed and haven't changed since are left alone.
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+use rusqlite::{Connection, OpenFlags};
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::db::DB;
+use crate::external::{self, Page};
+use crate::redact::Redactor;
+use crate::symbols::Symbols;
+
+/// A tool to import text from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The `--sidecar` text files of ocrmypdf, next to their PDFs as `name.txt` or `name.pdf.txt`
+    OcrmypdfSidecars,
+    /// A directory made by the `document_exporter` of paperless-ngx, with its `manifest.json`
+    Paperless,
+    /// The `cache.sqlite3` that ripgrep-all keeps the text it extracted in
+    RgaCache,
+}
+
+impl Source {
+    /// Name saved as the `imported` property of the text
+    fn name(self) -> &'static str {
+        match self {
+            Source::OcrmypdfSidecars => "ocrmypdf",
+            Source::Paperless => "paperless",
+            Source::RgaCache => "rga",
+        }
+    }
+}
+
+/// How many files an import saved text for, and how many it left alone
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Imported {
+    pub files: usize,
+    /// Files that were already indexed and haven't changed since
+    pub indexed: usize,
+    /// Files that don't exist anymore, that changed since the text was made, or that the text of
+    /// some pages is missing of
+    pub outdated: usize,
+}
+
+/// Save the text that `source` made for the files it knows of. `path` is the directory with the
+/// sidecars or the paperless export, or the rga cache or its directory
+pub fn import(
+    db: &mut DB,
+    source: Source,
+    path: &Path,
+    redact: &Redactor,
+    symbols: Symbols,
+) -> Result<Imported> {
+    let path = PathBuf::try_from(
+        fs::canonicalize(path).with_context(|| format!("failed to find {path}"))?,
+    )?;
+    let found = match source {
+        Source::OcrmypdfSidecars => sidecars(&path)?,
+        Source::Paperless => paperless(&path)?,
+        Source::RgaCache => rga_cache(&path)?,
+    };
+
+    let mut imported = Imported::default();
+    for (file, pages) in found {
+        let (Some(mut pages), Ok(metadata)) = (pages, fs::metadata(&file)) else {
+            imported.outdated += 1;
+            continue;
+        };
+        if db.is_indexed(&file, &metadata) {
+            imported.indexed += 1;
+            continue;
+        }
+        for page in &mut pages {
+            page.properties
+                .insert("imported".into(), source.name().into());
+        }
+        external::upsert(db, &file, pages, redact, symbols)?;
+        imported.files += 1;
+    }
+    Ok(imported)
+}
+
+/// Files with the text of their pages, or None if the text is of an older version of the file
+type Found = Vec<(PathBuf, Option<Vec<Page>>)>;
+
+/// Files next to ocrmypdf sidecars, with the text of their pages
+fn sidecars(dir: &Path) -> Result<Found> {
+    let mut found = vec![];
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry?;
+        let Some(sidecar) = Path::from_path(entry.path()) else {
+            continue;
+        };
+        if !entry.file_type().is_file() || sidecar.extension() != Some("txt") {
+            continue;
+        }
+        // `name.pdf.txt`, or `name.txt` for `name.pdf`
+        let file = match sidecar.with_extension("") {
+            file if file.extension().is_some() => file,
+            file => file.with_extension("pdf"),
+        };
+        if !file.is_file() {
+            continue;
+        }
+        let text =
+            fs::read_to_string(sidecar).with_context(|| format!("failed to read {sidecar}"))?;
+        found.push((file, split_pages(&text)));
+    }
+    Ok(found)
+}
+
+/// A `manifest.json` record of a paperless-ngx export
+#[derive(Deserialize)]
+struct Record {
+    model: String,
+    #[serde(default)]
+    fields: Fields,
+    #[serde(rename = "__exported_file_name__")]
+    file: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct Fields {
+    #[serde(default)]
+    content: String,
+    /// MD5 of the original file
+    checksum: Option<String>,
+}
+
+/// Documents of a paperless-ngx export, with the text of their pages
+fn paperless(dir: &Path) -> Result<Found> {
+    let manifest = dir.join("manifest.json");
+    let records: Vec<Record> = serde_json::from_str(
+        &fs::read_to_string(&manifest)
+            .with_context(|| format!("failed to read {manifest}, is it a paperless export?"))?,
+    )
+    .with_context(|| format!("invalid {manifest}"))?;
+    Ok(records
+        .into_iter()
+        .filter(|x| x.model == "documents.document")
+        .filter_map(|record| {
+            let mut pages = split_pages(&record.fields.content);
+            if let Some(checksum) = &record.fields.checksum {
+                for page in pages.iter_mut().flatten() {
+                    page.properties.insert("md5".into(), checksum.clone());
+                }
+            }
+            Some((dir.join(record.file?), pages))
+        })
+        .collect())
+}
+
+/// Files in the cache of ripgrep-all, with the text of their pages. Only the newest text of each
+/// file is used
+fn rga_cache(path: &Path) -> Result<Found> {
+    let path = match path.is_dir() {
+        true => path.join("cache.sqlite3"),
+        false => path.to_owned(),
+    };
+    let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("failed to open {path}"))?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT file_path, file_mtime_unix_ms, adapter, text_content_zstd FROM preproc_cache
+                ORDER BY created_unix_ms",
+        )
+        .with_context(|| format!("{path} isn't a ripgrep-all cache"))?;
+    let mut newest = HashMap::new();
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            (
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Vec<u8>>(3)?,
+            ),
+        ))
+    })?;
+    for row in rows {
+        let (file, text) = row?;
+        newest.insert(file, text);
+    }
+
+    let mut found = vec![];
+    for (file, (mtime, adapter, compressed)) in newest {
+        let file = PathBuf::from(file);
+        let modified = fs::metadata(&file)
+            .and_then(|x| x.modified())
+            .ok()
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok());
+        if !file.is_absolute() || modified.map(|x| x.as_millis() as i64) != Some(mtime) {
+            found.push((file, None));
+            continue;
+        }
+        let mut pages = rga_pages(&unzstd(&compressed)?);
+        for page in &mut pages {
+            page.properties.insert("adapter".into(), adapter.clone());
+        }
+        found.push((file, Some(pages)));
+    }
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(found)
+}
+
+/// Decompress the text of the rga cache
+fn unzstd(data: &[u8]) -> Result<String> {
+    let text = zstd::decode_all(data).context("failed to decompress text of the rga cache")?;
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
+
+/// Pages of text that ends every page with a form feed, like the sidecars of ocrmypdf and the
+/// content of paperless. Pages are numbered from 1, and text of one page is a single image.
+/// `None` if ocrmypdf left out the text of a page because the page already had some, so that the
+/// file is indexed instead of saved without it
+fn split_pages(text: &str) -> Option<Vec<Page>> {
+    let mut pages: Vec<&str> = text.split('\x0c').collect();
+    if pages.len() > 1 && pages.last().is_some_and(|x| x.trim().is_empty()) {
+        pages.pop();
+    }
+    // ocrmypdf leaves this in place of pages that already had text
+    if pages
+        .iter()
+        .any(|x| x.trim().starts_with("[OCR skipped on page"))
+    {
+        return None;
+    }
+    let numbered = pages.len() > 1;
+    let pages = pages
+        .into_iter()
+        .enumerate()
+        .map(|(i, contents)| Page {
+            page: if numbered {
+                (i + 1).to_string()
+            } else {
+                String::new()
+            },
+            contents: contents.trim().to_owned(),
+            ..Default::default()
+        })
+        .collect();
+    Some(pages)
+}
+
+/// Pages of the text rga extracted, which starts every line of a document with `Page N: `
+fn rga_pages(text: &str) -> Vec<Page> {
+    let mut pages: Vec<Page> = vec![];
+    for line in text.lines() {
+        let (page, line) = line
+            .strip_prefix("Page ")
+            .and_then(|x| x.split_once(':'))
+            .filter(|(number, _)| number.parse::<usize>().is_ok())
+            .map(|(number, line)| (number, line.strip_prefix(' ').unwrap_or(line)))
+            .unwrap_or(("", line));
+        match pages.last_mut() {
+            Some(last) if last.page == page || page.is_empty() => {
+                last.contents.push('\n');
+                last.contents.push_str(line);
+            }
+            _ => pages.push(Page {
+                page: page.to_owned(),
+                contents: line.to_owned(),
+                ..Default::default()
+            }),
+        }
+    }
+    if pages.is_empty() {
+        pages.push(Page::default());
+    }
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages() {
+        let pages = split_pages("first\n\x0csecond\n\x0cthird\n\x0c").unwrap();
+        let pages: Vec<_> = pages.iter().map(|x| (&*x.page, &*x.contents)).collect();
+        assert_eq!(pages, [("1", "first"), ("2", "second"), ("3", "third")]);
+        // the text of a page that ocrmypdf skipped isn't in the sidecar
+        assert!(split_pages("first\n\x0c[OCR skipped on page(s) 2]\n\x0cthird\n\x0c").is_none());
+        let pages = split_pages("only page\x0c").unwrap();
+        assert_eq!((&*pages[0].page, &*pages[0].contents), ("", "only page"));
+
+        let pages = rga_pages("Page 1: a\nPage 1: b\nPage 2: c\ntrailing");
+        let pages: Vec<_> = pages.iter().map(|x| (&*x.page, &*x.contents)).collect();
+        assert_eq!(pages, [("1", "a\nb"), ("2", "c\ntrailing")]);
+        let pages = rga_pages("plain\ntext");
+        assert_eq!((&*pages[0].page, &*pages[0].contents), ("", "plain\ntext"));
+    }
+
+    #[test]
+    fn sidecars_and_paperless() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let root = seeded.root.clone();
+        let (redact, symbols) = (Redactor::default(), Symbols::default());
+
+        fs::write(root.join("scan.pdf"), "")?;
+        fs::write(root.join("scan.txt"), "first page\x0csecond page\x0c")?;
+        fs::write(root.join("notes.txt"), "not a sidecar")?;
+        fs::write(root.join("mixed.pdf"), "")?;
+        fs::write(
+            root.join("mixed.txt"),
+            "first\x0c[OCR skipped on page(s) 2]\x0c",
+        )?;
+        let imported = import(
+            &mut seeded.db,
+            Source::OcrmypdfSidecars,
+            &root,
+            &redact,
+            symbols,
+        )?;
+        assert_eq!((imported.files, imported.outdated), (1, 1));
+        assert!(!seeded.db.is_path_indexed(&root.join("mixed.pdf"))?);
+        let scan = root.join("scan.pdf");
+        let contents = seeded.db.content(&scan, "2")?;
+        assert_eq!(contents.as_deref(), Some("second page"));
+        // already imported, and unchanged
+        let again = import(
+            &mut seeded.db,
+            Source::OcrmypdfSidecars,
+            &root,
+            &redact,
+            symbols,
+        )?;
+        assert_eq!(again.indexed, 1);
+
+        fs::write(root.join("receipt.png"), "")?;
+        fs::write(
+            root.join("manifest.json"),
+            r#"[
+                {"model": "documents.tag", "pk": 1, "fields": {"name": "tax"}},
+                {"model": "documents.document", "pk": 1, "fields": {"content": "paid in full", "checksum": "abc"},
+                 "__exported_file_name__": "receipt.png"},
+                {"model": "documents.document", "pk": 2, "fields": {"content": "gone"},
+                 "__exported_file_name__": "deleted.pdf"}
+            ]"#,
+        )?;
+        let imported = import(&mut seeded.db, Source::Paperless, &root, &redact, symbols)?;
+        assert_eq!(
+            imported,
+            Imported {
+                files: 1,
+                indexed: 0,
+                outdated: 1
+            }
+        );
+        let receipt = root.join("receipt.png");
+        let contents = seeded.db.content(&receipt, "")?;
+        assert_eq!(contents.as_deref(), Some("paid in full"));
+        let rows = seeded.db.property_mismatches(&root, "md5", Some("abc"))?;
+        assert!(rows.iter().all(|x| x.path != receipt));
+        Ok(())
+    }
+}
@@ -1,20 +1,35 @@
-use std::sync::{Arc, Mutex};
-use std::{env, iter};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, Metadata};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{io, iter};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
 use glob::Pattern;
 use itertools::{Either, Itertools};
-use kdam::{BarBuilder, BarExt};
+use kdam::{Bar, BarBuilder, BarExt};
 use rayon::prelude::*;
 use walkdir::WalkDir;
 
+use crate::config::{DirConfig, DirConfigs, LanguageHints, Overrides, DIR_CONFIG};
 use crate::db::OcrResult;
-use crate::ocr;
-use crate::{db::DB, ocr::Ocr};
+use crate::db::DB;
+use crate::extract::{Extracted, Registry};
+use crate::notes;
+use crate::ocr::{self, Ocr, OcrOptions};
+use crate::paths;
+use crate::redact::Redactor;
+use crate::report::{self, Code, Report, Severity};
+use crate::symbols::Symbols;
 
 pub struct IndexOptions {
-    pub lang: String,
+    pub ocr: OcrOptions,
+    /// Whether the language of `ocr` was chosen, so that the `lang` of the project config doesn't
+    /// replace it
+    pub lang_given: bool,
     pub debug: bool,
     pub limit: Option<usize>,
     pub exclude: Vec<Pattern>,
@@ -23,14 +38,152 @@ pub struct IndexOptions {
     pub chunksize: usize,
     pub cleanup: bool,
     pub max_dimensions: Option<(usize, usize)>,
-    pub scale: Option<f32>,
-    pub binarization: Option<ocr::Binarization>,
-    pub psm: Option<i64>,
+    pub extractors: Registry,
+    /// Stop starting new files after this long, and save what was done so far
+    pub max_duration: Option<Duration>,
+    /// Languages of files, instead of the one in `ocr`
+    pub languages: LanguageHints,
+    /// Count the files before indexing them, so the progress bar has the right total from the
+    /// start
+    pub discover: bool,
+    /// Only index the images that markdown and html notes embed, and remember which notes embed
+    /// them
+    pub notes: bool,
+    /// Read the files of the next chunk while the current one is OCRed, up to this many bytes, so
+    /// that the workers don't wait on a slow disk
+    pub prefetch: Option<u64>,
+    /// Applied to the text of every file before it's saved
+    pub redact: Redactor,
+    pub symbols: Symbols,
 }
 
-pub fn index_dir(db: &mut DB, path: &Path, options: IndexOptions) -> Result<()> {
-    let indexed_filetypes = ["png", "jpeg", "jpg", "gif", "webp"];
+/// What [`index_dir`] is doing, sent to a [`Progress`]
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// Files that were saved with another symbol policy, and are OCRed again
+    Reconciled(usize),
+    /// Counting the files before indexing them, with [`IndexOptions::discover`]
+    Counting,
+    /// Files that were found, to add to the total
+    Found(usize),
+    /// A file that won't be scanned, because it was already indexed or couldn't be checked, or
+    /// because of its width and height, which are larger than [`IndexOptions::max_dimensions`]
+    Skipped(&'a Path, Option<(usize, usize)>),
+    /// A file is being scanned with a language
+    Started(&'a Path, &'a str),
+    /// A file was scanned, whether or not that worked
+    Finished(&'a Path),
+    /// An error or warning, indexing carries on after it
+    Report(Report<'a>),
+    /// Rows that were changed by saving a chunk of files
+    Saved(usize),
+    /// Indexing stopped before every file was scanned, after [`IndexOptions::max_duration`] or
+    /// because it was cancelled
+    Stopped { remaining: usize, cancelled: bool },
+    /// Rows of files that don't exist anymore or are excluded that were removed, with
+    /// [`IndexOptions::cleanup`]. Files the walk didn't reach that still exist are `kept`, and
+    /// files that couldn't be checked are `unchecked` and kept too
+    Swept {
+        deleted: usize,
+        kept: usize,
+        unchecked: usize,
+    },
+}
+
+/// Receives the [`Event`]s of [`index_dir`], from the threads that scan files
+pub trait Progress: Sync {
+    fn event(&self, event: Event);
+}
 
+impl<F: Fn(Event) + Sync> Progress for F {
+    fn event(&self, event: Event) {
+        self(event)
+    }
+}
+
+/// Shows the progress of indexing with a progress bar on stderr, and prints reports and, with
+/// `debug`, what was done
+pub struct Terminal {
+    bar: Mutex<Bar>,
+    debug: bool,
+}
+
+impl Terminal {
+    pub fn new(debug: bool) -> Self {
+        Terminal {
+            bar: Mutex::new(
+                BarBuilder::default()
+                    // so that stderr only has json lines
+                    .disable(report::is_json())
+                    .build()
+                    .unwrap(),
+            ),
+            debug,
+        }
+    }
+}
+
+impl Progress for Terminal {
+    fn event(&self, event: Event) {
+        match event {
+            Event::Reconciled(changed) if self.debug && changed > 0 => {
+                eprintln!(
+                    "{changed} files were saved with another symbol policy, OCRing them again"
+                );
+            }
+            Event::Counting if !report::is_json() => eprintln!("Counting files..."),
+            Event::Found(count) => self.bar.lock().unwrap().total += count,
+            Event::Skipped(path, Some((width, height))) => {
+                if self.debug {
+                    eprintln!("skipping image: {path} with dimensions {width}x{height}");
+                }
+                self.bar.lock().unwrap().update(1).unwrap();
+            }
+            Event::Skipped(..) | Event::Finished(_) => {
+                self.bar.lock().unwrap().update(1).unwrap();
+            }
+            Event::Started(path, lang) if self.debug => {
+                eprintln!("now working on {} with {}", path, lang);
+            }
+            Event::Report(report) => report.print(),
+            Event::Saved(count) if self.debug => eprintln!("{count} rows modified"),
+            Event::Stopped {
+                remaining,
+                cancelled,
+            } => {
+                self.bar.lock().unwrap().clear().unwrap();
+                let message = match cancelled {
+                    true => format!("Cancelled, {remaining} files are left to scan"),
+                    false => {
+                        format!("Stopped after --max-duration, {remaining} files are left to scan")
+                    }
+                };
+                report::warning(Code::Incomplete, None, message);
+            }
+            Event::Swept {
+                deleted,
+                kept,
+                unchecked,
+            } => {
+                if self.debug {
+                    eprintln!("Deleted {deleted} stale entries");
+                }
+                if kept + unchecked > 0 && !report::is_json() {
+                    eprintln!("Kept {kept} files that weren't found while indexing but still exist, and {unchecked} that couldn't be checked");
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Files under `path` that have an extractor and aren't excluded, and are in `linked` if it's given
+fn candidates<'a>(
+    path: &Path,
+    options: &'a IndexOptions,
+    linked: Option<&'a HashSet<&'a PathBuf>>,
+    progress: &'a dyn Progress,
+) -> impl Iterator<Item = PathBuf> + 'a {
     let mut wd = WalkDir::new(path).follow_links(true);
     if !options.subdirs {
         wd = wd.max_depth(1);
@@ -38,12 +191,26 @@ pub fn index_dir(db: &mut DB, path: &Path, options: IndexOptions) -> Result<()>
 
     let it = wd
         .into_iter()
-        .filter_entry(|entry| !options.exclude.iter().any(|x| x.matches_path(entry.path())))
+        .filter_entry(|entry| {
+            // patterns are matched against the form that is stored, without `\\?\`
+            let excluded = entry.path().to_str().is_some_and(|path| {
+                let path = paths::normalize(path);
+                options.exclude.iter().any(|x| x.matches(&path))
+            });
+            let skipped = entry.file_type().is_dir()
+                && Path::from_path(entry.path()).is_some_and(DirConfig::skips);
+            !excluded && !skipped
+        })
         .filter_map(|res| {
             let file = match res {
                 Ok(file) => file,
                 Err(e) => {
-                    eprintln!("[Error] collecting files: {}", e);
+                    progress.event(Event::Report(Report::new(
+                        Severity::Error,
+                        Code::Walk,
+                        None,
+                        format!("collecting files: {}", e),
+                    )));
                     return None;
                 }
             };
@@ -51,25 +218,262 @@ pub fn index_dir(db: &mut DB, path: &Path, options: IndexOptions) -> Result<()>
                 return None;
             };
             let path = PathBuf::try_from(file.into_path()).unwrap();
-            if let Some(ext) = path.extension() {
-                if indexed_filetypes.contains(&ext) {
-                    return Some(path);
-                }
-            }
-            None
-        });
+            options.extractors.find(&path).map(|_| path)
+        })
+        .filter(move |path| linked.is_none_or(|x| x.contains(path)));
 
-    let it = if let Some(limit) = options.limit {
+    if let Some(limit) = options.limit {
         Either::Left(it.take(limit))
     } else {
         Either::Right(it)
+    }
+}
+
+/// Read `files` into the page cache in the background, until `budget` bytes were read
+fn prefetch(files: Vec<PathBuf>, budget: u64) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut total = 0;
+        for file in files {
+            if total >= budget {
+                break;
+            }
+            // errors are reported when the file is actually read
+            if let Ok(mut f) = File::open(&file) {
+                total += io::copy(&mut f, &mut io::sink()).unwrap_or(0);
+            }
+        }
+    })
+}
+
+/// The rows to save for the pages extracted from a file, with their text in the Unicode form
+/// of `options`
+fn to_results(
+    path: &Path,
+    metadata: &Metadata,
+    pages: Vec<(String, Extracted)>,
+    options: &OcrOptions,
+) -> Vec<OcrResult> {
+    let compatibility = options.normalize.compatibility;
+    pages
+        .into_iter()
+        .map(|(page, mut res)| {
+            res.properties
+                .entry("lang".into())
+                .or_insert_with(|| options.lang.clone());
+            res.properties.insert("settings".into(), options.settings());
+            for word in &mut res.words {
+                word.text = ocr::normalize_unicode(&word.text, compatibility);
+            }
+            OcrResult {
+                path: path.to_owned(),
+                page,
+                metadata: metadata.clone(),
+                contents: ocr::normalize_unicode(&res.contents, compatibility),
+                properties: res.properties,
+                words: res.words,
+            }
+        })
+        .collect()
+}
+
+/// The options of `file` under `root`, with the language its path matches a pattern of, and the
+/// overrides of the `.ocrlocate.toml` files of its directory
+fn options_of(
+    file: &Path,
+    root: &Path,
+    options: &OcrOptions,
+    languages: &LanguageHints,
+    overrides: Overrides,
+) -> OcrOptions {
+    let lang = file
+        .strip_prefix(root)
+        .ok()
+        .and_then(|x| languages.resolve(x))
+        .map(String::from)
+        .or(overrides.lang)
+        .unwrap_or_else(|| options.lang.clone());
+    OcrOptions {
+        lang,
+        psm: overrides.psm.or(options.psm),
+        scale: overrides.scale.or(options.scale),
+        ..options.clone()
+    }
+}
+
+/// The options of one file like indexing the outermost directory with a `.ocrlocate.toml` above it
+/// picks them, from the language patterns of `languages` and that config, and the
+/// `.ocrlocate.toml` files of its directory, for OCRing it outside of [`index_dir`]
+pub fn file_options(
+    path: &Path,
+    options: &OcrOptions,
+    languages: &LanguageHints,
+) -> Result<OcrOptions> {
+    let Some(dir) = path.parent() else {
+        return Ok(options.clone());
+    };
+    // the project config of the directories the file could have been indexed with
+    let root = dir
+        .ancestors()
+        .filter(|x| x.join(DIR_CONFIG).is_file())
+        .last()
+        .unwrap_or(dir);
+    let languages = languages.extended(DirConfig::load(root)?.languages)?;
+    let overrides = DirConfigs::new(root).resolve(dir);
+    let options = options_of(path, root, options, &languages, overrides);
+    ocr::validate_lang(&options.lang)?;
+    Ok(options)
+}
+
+/// OCR one file again and save it, whether or not it changed
+pub fn rescan_file(
+    db: &mut DB,
+    path: &Path,
+    extractors: &Registry,
+    options: &OcrOptions,
+    redact: &Redactor,
+    symbols: Symbols,
+) -> Result<usize> {
+    let results = extract_file(path, extractors, options, redact, symbols)?;
+    db.save_results(results)
+}
+
+/// The rows that indexing one file would save, without saving them
+pub fn extract_file(
+    path: &Path,
+    extractors: &Registry,
+    options: &OcrOptions,
+    redact: &Redactor,
+    symbols: Symbols,
+) -> Result<Vec<OcrResult>> {
+    let extractor = extractors
+        .find(path)
+        .ok_or_else(|| anyhow!("no extractor for {}", path))?;
+    let long = paths::extended(path);
+    let metadata = long.metadata()?;
+    let mut ocr = Ocr::new(options)?;
+    let pages = extractor.extract_pages(&long, &mut ocr)?;
+    let mut results = to_results(path, &metadata, pages, options);
+    for result in &mut results {
+        symbols.apply(result);
+        redact.apply(result);
+    }
+    Ok(results)
+}
+
+/// Make the files under `root` that were saved with another symbol policy OCR again, and return
+/// how many. Text that was saved as it was recognized is only OCRed again if the policy changes it
+fn reconcile_symbols(db: &mut DB, root: &Path, symbols: Symbols) -> Result<usize> {
+    let mut invalidated = HashSet::new();
+    for mut row in db.property_mismatches(root, "symbols", symbols.policy())? {
+        if invalidated.contains(&row.path) {
+            continue;
+        }
+        let raw = !row.properties.contains_key("symbols_replaced");
+        if raw && symbols.apply_text(&row.contents).1 == 0 {
+            match symbols.policy() {
+                Some(policy) => row.properties.insert("symbols".into(), policy.into()),
+                None => row.properties.remove("symbols"),
+            };
+            db.set_properties(&row.path, &row.page, &row.properties)?;
+        } else {
+            db.invalidate(&row.path)?;
+            invalidated.insert(row.path);
+        }
+    }
+    Ok(invalidated.len())
+}
+
+/// The settings that can differ between files
+type OcrKey = (String, Option<i64>, Option<u32>);
+
+fn ocr_key(options: &OcrOptions) -> OcrKey {
+    (
+        options.lang.clone(),
+        options.psm,
+        options.scale.map(f32::to_bits),
+    )
+}
+
+/// `options` with the project config of `root` applied, see [`DirConfig::find`]. It adds to the
+/// excludes and language patterns, and gives the defaults of the language and the max size
+fn with_project_config(root: &Path, mut options: IndexOptions) -> Result<IndexOptions> {
+    let project = DirConfig::find(root)?;
+    options.languages = options.languages.extended(project.languages)?;
+    for lang in options.languages.languages() {
+        ocr::validate_lang(lang)?;
+    }
+    for pattern in &project.exclude {
+        options.exclude.push(exclude_pattern(pattern)?);
+    }
+    if let (None, Some(size)) = (options.max_dimensions, &project.max_size) {
+        options.max_dimensions = Some(parse_max_size(size)?);
+    }
+    if let Some(lang) = project.lang.filter(|_| !options.lang_given) {
+        ocr::validate_lang(&lang)?;
+        options.ocr.lang = lang;
+    }
+    Ok(options)
+}
+
+/// Parse an `--exclude` pattern, which matches paths at any depth
+pub fn exclude_pattern(s: &str) -> Result<Pattern> {
+    Pattern::new(&format!("*/{s}")).with_context(|| format!("invalid pattern: {s}"))
+}
+
+/// Parse a `--max-size` like `4000x3000`
+pub fn parse_max_size(s: &str) -> Result<(usize, usize)> {
+    s.split('x')
+        .map(|x| x.parse().ok())
+        .collect_tuple()
+        .and_then(|(width, height)| width.zip(height))
+        .ok_or_else(|| anyhow!("invalid max-size: {s}, should be [width]x[height]"))
+}
+
+pub fn index_dir(
+    db: &mut DB,
+    path: &Path,
+    options: IndexOptions,
+    progress: &dyn Progress,
+    cancel: &AtomicBool,
+) -> Result<()> {
+    // a directory that was entered as `~/pictures` is stored as `~/Pictures`
+    let on_disk;
+    let path = match db.case_insensitive_paths() {
+        true => {
+            on_disk = paths::on_disk(path);
+            &on_disk
+        }
+        false => path,
     };
+    let path = &*paths::extended(path);
+    let options = with_project_config(path, options)?;
+    let changed = reconcile_symbols(db, path, options.symbols)?;
+    progress.event(Event::Reconciled(changed));
+    let extractors = &options.extractors;
+    let deadline = options.max_duration.map(|x| Instant::now() + x);
+    let cancelled = || cancel.load(Ordering::Relaxed);
+    let past_deadline = || cancelled() || deadline.is_some_and(|x| Instant::now() >= x);
+
+    let links = options.notes.then(|| {
+        let links = notes::scan(path, options.subdirs, &options.exclude);
+        db.save_note_links(path, &links).map(|_| links)
+    });
+    let links = links.transpose()?;
+    let linked: Option<HashSet<&PathBuf>> = links.as_ref().map(|x| x.values().flatten().collect());
+
+    let it = candidates(path, &options, linked.as_ref(), progress);
+    let discovered = options.discover.then(|| {
+        progress.event(Event::Counting);
+        let count = candidates(path, &options, linked.as_ref(), progress).count();
+        progress.event(Event::Found(count));
+        count
+    });
 
     if options.cleanup {
-        db.mark_for_deletion(Path::from_path(&env::current_dir().unwrap()).unwrap());
+        db.mark_for_deletion(path);
     }
 
-    let arcbar = Arc::new(Mutex::new(BarBuilder::default().total(0).build().unwrap()));
+    let mut dir_configs = DirConfigs::new(path);
 
     // the chunking starves the rayon pool but its fine
     let chunks = it.chunks(options.chunksize);
@@ -80,104 +484,398 @@ pub fn index_dir(db: &mut DB, path: &Path, options: IndexOptions) -> Result<()>
         .tuple_windows::<(_, _)>();
 
     let mut first_iter = true;
+    // files that weren't scanned because the deadline passed
+    let mut remaining = 0;
+    let mut prefetching: Option<JoinHandle<()>> = None;
     for (c1, c2) in tup {
+        if past_deadline() {
+            remaining += c1
+                .into_iter()
+                .filter(|file: &PathBuf| {
+                    options.rescan
+                        || !file
+                            .metadata()
+                            .is_ok_and(|metadata| db.is_indexed(file, &metadata))
+                })
+                .count();
+            continue;
+        }
+
+        // only one chunk is read ahead, so the files don't fall out of the cache before their turn
+        if let Some(budget) = options.prefetch {
+            if let Some(handle) = prefetching.take() {
+                handle.join().unwrap();
+            }
+            let next = c2
+                .iter()
+                .filter(|file: &&PathBuf| {
+                    options.rescan
+                        || !file
+                            .metadata()
+                            .is_ok_and(|metadata| db.is_indexed(file, &metadata))
+                })
+                .cloned()
+                .collect();
+            prefetching = Some(prefetch(next, budget));
+        }
+
         let chunk: Vec<_> = c1
             .into_iter()
             .filter_map(move |file| match file.metadata() {
                 Ok(metadata) => Some((file, metadata)),
                 Err(e) => {
-                    eprintln!("Error fetching metadata: {}", e);
+                    progress.event(Event::Report(Report::new(
+                        Severity::Error,
+                        Code::Metadata,
+                        Some(file.as_str()),
+                        format!("fetching metadata: {}", e),
+                    )));
                     None
                 }
             })
             .collect();
 
-        arcbar.lock().unwrap().total += if first_iter {
-            first_iter = false;
-            chunk.len() + c2.len()
-        } else {
-            c2.len()
-        };
+        // without a count the total grows a chunk ahead of the files being scanned
+        if discovered.is_none() {
+            progress.event(Event::Found(if first_iter {
+                first_iter = false;
+                chunk.len() + c2.len()
+            } else {
+                c2.len()
+            }));
+        }
+
+        // the settings of each file, from the language patterns and .ocrlocate.toml files
+        let chunk: Vec<(PathBuf, Metadata, OcrOptions)> = chunk
+            .into_iter()
+            .map(|(file, metadata)| {
+                let overrides = file
+                    .parent()
+                    .map(|dir| dir_configs.resolve(dir))
+                    .unwrap_or_default();
+                let ocr = options_of(&file, path, &options.ocr, &options.languages, overrides);
+                (file, metadata, ocr)
+            })
+            .collect();
 
-        let abar = arcbar.clone();
-        let chunk: Vec<_> = chunk
+        // files are OCRed again when they changed, or when they were OCRed with other settings
+        let chunk: Vec<(PathBuf, Metadata, OcrOptions)> = chunk
             .into_iter()
             .filter(|p| {
-                if !options.rescan && db.is_indexed(&p.0, &p.1) {
+                if !options.rescan
+                    && db.is_indexed(&p.0, &p.1)
+                    && !db.settings_changed(&p.0, &p.2.settings())
+                {
                     db.unmark_file(&p.0);
-                    abar.lock().unwrap().update(1).unwrap();
+                    progress.event(Event::Skipped(&p.0, None));
                     return false;
                 }
                 if let Some((max_width, max_height)) = options.max_dimensions {
-                    let img = imagesize::size(&p.0);
-                    match img {
-                        Err(_) => {
-                            eprintln!("Failed to read image to check dimensions: {}", p.0);
+                    let extractor = extractors.find(&p.0).unwrap();
+                    match extractor.image_size(&p.0) {
+                        Some(Err(_)) => {
+                            progress.event(Event::Report(Report::new(
+                                Severity::Error,
+                                Code::ImageSize,
+                                Some(p.0.as_str()),
+                                "failed to read image to check dimensions",
+                            )));
+                            progress.event(Event::Skipped(&p.0, None));
                             return false;
                         }
-                        Ok(size) => {
-                            if size.width > max_width || size.height > max_height {
-                                if options.debug {
-                                    eprintln!(
-                                        "skipping image: {} with dimensions {}x{}",
-                                        p.0, size.width, size.height
-                                    );
-                                }
-                                return false;
-                            }
+                        Some(Ok(size)) if size.0 > max_width || size.1 > max_height => {
+                            progress.event(Event::Skipped(&p.0, Some(size)));
+                            return false;
                         }
+                        _ => (),
                     };
                 }
                 true
             })
             .collect();
 
-        let abar = arcbar.clone();
-        let results: Vec<OcrResult> = chunk
+        let results: Vec<Option<Vec<OcrResult>>> = chunk
             .par_iter()
-            .map_init(
-                || {
-                    Ocr::new(
-                        &options.lang,
-                        options.debug,
-                        options.scale,
-                        options.binarization,
-                        options.psm,
-                    )
-                    .unwrap()
-                },
-                move |ocr, ele| {
-                    if options.debug {
-                        eprintln!("now working on {}", &ele.0);
+            .map_init(HashMap::<OcrKey, Result<Ocr>>::new, move |ocrs, ele| {
+                // files that were already started still get finished
+                if past_deadline() {
+                    return None;
+                }
+                let lang = &ele.2.lang;
+                progress.event(Event::Started(&ele.0, lang));
+                // every thread keeps one instance for each combination of settings it ran into, or
+                // why it couldn't be loaded, like a language from a .ocrlocate.toml that isn't
+                // installed
+                let ocr = match ocrs
+                    .entry(ocr_key(&ele.2))
+                    .or_insert_with(|| Ocr::new(&ele.2))
+                {
+                    Ok(ocr) => ocr,
+                    Err(e) => {
+                        progress.event(Event::Finished(&ele.0));
+                        progress.event(Event::Report(Report::new(
+                            Severity::Error,
+                            Code::Extract,
+                            Some(ele.0.as_str()),
+                            format!("failed to load the OCR engine for {lang}: {e:#}"),
+                        )));
+                        return Some(vec![]);
                     }
-                    let res = ocr.scan(&ele.0);
-                    abar.lock().unwrap().update(1).unwrap();
-                    match res {
-                        Ok(res) => Some(OcrResult {
-                            path: ele.0.clone(),
-                            metadata: ele.1.clone(),
-                            contents: res,
-                        }),
-                        Err(e) => {
-                            eprintln!("[Error] ocr: {} {}", e, &ele.0);
-                            None
-                        }
+                };
+                let extractor = extractors.find(&ele.0).unwrap();
+                let res = extractor.extract_pages(&ele.0, ocr);
+                progress.event(Event::Finished(&ele.0));
+                Some(match res {
+                    Ok(pages) => to_results(&ele.0, &ele.1, pages, &ele.2),
+                    Err(e) => {
+                        progress.event(Event::Report(Report::new(
+                            Severity::Error,
+                            Code::Extract,
+                            Some(ele.0.as_str()),
+                            format!("{}: {}", extractor.name(), e),
+                        )));
+                        vec![]
                     }
-                },
-            )
-            .filter_map(|x| x)
+                })
+            })
             .collect();
+        remaining += results.iter().filter(|x| x.is_none()).count();
+        let mut results: Vec<OcrResult> = results.into_iter().flatten().flatten().collect();
+        for result in &mut results {
+            options.symbols.apply(result);
+            options.redact.apply(result);
+        }
 
-        let count = db.save_results(results)?;
-        if options.debug {
-            eprintln!("{count} rows modified");
+        progress.event(Event::Saved(db.save_results(results)?));
+    }
+
+    if past_deadline() {
+        progress.event(Event::Stopped {
+            remaining,
+            cancelled: cancelled(),
+        });
+        // files that weren't reached are still marked, so they can't be swept
+        if options.cleanup {
+            progress.event(Event::Report(Report::new(
+                Severity::Warning,
+                Code::Incomplete,
+                None,
+                "Skipped --cleanup since not every file was checked",
+            )));
         }
+        return Ok(());
     }
 
-    let deleted = db.sweep_deletions();
-    if options.debug {
-        eprintln!("Deleted {deleted} stale entries");
+    if options.cleanup {
+        let (kept, unchecked) = verify_deletions(db, path, &options, progress)?;
+        progress.event(Event::Swept {
+            deleted: db.sweep_deletions(),
+            kept,
+            unchecked,
+        });
     }
 
     Ok(())
 }
+
+/// Unmark the files that are still marked for deletion after a walk but shouldn't be deleted:
+/// the ones that exist and aren't excluded, which the walk can miss when a directory couldn't be
+/// read for a moment, and the ones that couldn't be checked. They are checked in parallel, a
+/// chunk at a time. Returns how many files were kept and how many couldn't be checked
+fn verify_deletions(
+    db: &mut DB,
+    root: &Path,
+    options: &IndexOptions,
+    progress: &dyn Progress,
+) -> Result<(usize, usize)> {
+    let marked = db.marked_for_deletion()?;
+    let (mut kept, mut unchecked) = (0, 0);
+    for chunk in marked.chunks(options.chunksize.max(1)) {
+        let checked: Vec<_> = chunk
+            .par_iter()
+            .map(|path| is_stale(path, root, &options.exclude))
+            .collect();
+        for (path, stale) in chunk.iter().zip(checked) {
+            match stale {
+                Ok(true) => continue,
+                Ok(false) => kept += 1,
+                Err(e) => {
+                    progress.event(Event::Report(Report::new(
+                        Severity::Warning,
+                        Code::Metadata,
+                        Some(path.as_str()),
+                        format!("kept in the index, since it couldn't be checked: {e}"),
+                    )));
+                    unchecked += 1;
+                }
+            }
+            db.unmark_file(path);
+        }
+    }
+    Ok((kept, unchecked))
+}
+
+/// Whether a file under `root` should be removed from the index, because it doesn't exist
+/// anymore or is excluded like [`candidates`] excludes it
+fn is_stale(path: &Path, root: &Path, exclude: &[Pattern]) -> io::Result<bool> {
+    match fs::metadata(&*paths::extended(path)) {
+        Ok(metadata) if metadata.is_file() => (),
+        Ok(_) => return Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    }
+    let root = paths::normalize(root.as_str());
+    let excluded = path
+        .ancestors()
+        .take_while(|x| x.starts_with(&*root))
+        .any(|x| {
+            exclude.iter().any(|pattern| pattern.matches(x.as_str()))
+                || (x != path && DirConfig::skips(x))
+        });
+    Ok(excluded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn stale() -> Result<()> {
+        let temp = TempDir::new()?;
+        let root = PathBuf::try_from(temp.path().to_owned())?;
+        for dir in ["kept", "excluded", "skipped"] {
+            fs::create_dir(root.join(dir))?;
+            File::create(root.join(dir).join("a.png"))?;
+        }
+        DirConfig::set_skip(&root.join("skipped"))?;
+        let exclude = [Pattern::new("*/excluded")?];
+
+        let stale = |path: &str| is_stale(&root.join(path), &root, &exclude).unwrap();
+        assert!(!stale("kept/a.png"));
+        assert!(stale("kept/gone.png"));
+        assert!(stale("excluded/a.png"));
+        assert!(stale("skipped/a.png"));
+        // a directory where a file was
+        assert!(stale("kept"));
+        Ok(())
+    }
+
+    #[test]
+    fn file_options() -> Result<()> {
+        let temp = TempDir::new()?;
+        let root = PathBuf::try_from(temp.path().to_owned())?;
+        fs::create_dir_all(root.join("manga/vol1"))?;
+        fs::write(
+            root.join(DIR_CONFIG),
+            "psm = 4\n[languages]\n\"scans/*\" = \"deu\"\n\"manga/vol1/*\" = \"chi_sim\"",
+        )?;
+        fs::write(root.join("manga").join(DIR_CONFIG), "lang = \"jpn\"")?;
+        let options = OcrOptions::default();
+        let languages = LanguageHints::new([("manga/vol1/*".to_owned(), "kor".to_owned())])?;
+
+        // the pattern of the project config wins over the one of the config
+        let file = super::file_options(&root.join("manga/vol1/a.png"), &options, &languages)?;
+        assert_eq!((file.lang.as_str(), file.psm), ("chi_sim", Some(4)));
+        let file = super::file_options(&root.join("manga/b.png"), &options, &languages)?;
+        assert_eq!((file.lang.as_str(), file.psm), ("jpn", Some(4)));
+        let file = super::file_options(&root.join("c.png"), &options, &languages)?;
+        assert_eq!((file.lang.as_str(), file.psm), ("eng", Some(4)));
+        Ok(())
+    }
+
+    #[test]
+    fn project_config() -> Result<()> {
+        let temp = TempDir::new()?;
+        let root = PathBuf::try_from(temp.path().to_owned())?;
+        fs::create_dir(root.join("docs"))?;
+        fs::write(
+            root.join(DIR_CONFIG),
+            "lang = \"jpn\"\nexclude = [\"drafts\"]\nmax_size = \"100x50\"",
+        )?;
+        let options = |lang_given| IndexOptions {
+            ocr: OcrOptions::default(),
+            lang_given,
+            debug: false,
+            limit: None,
+            exclude: vec![],
+            rescan: false,
+            subdirs: true,
+            chunksize: 10,
+            cleanup: false,
+            max_dimensions: None,
+            extractors: Registry::with_builtins(),
+            max_duration: None,
+            languages: LanguageHints::default(),
+            discover: false,
+            notes: false,
+            prefetch: None,
+            redact: Redactor::default(),
+            symbols: Symbols::default(),
+        };
+        // from a subdirectory too
+        let applied = with_project_config(&root.join("docs"), options(false))?;
+        assert_eq!(applied.ocr.lang, "jpn");
+        assert_eq!(applied.max_dimensions, Some((100, 50)));
+        assert!(applied.exclude[0].matches_path(root.join("drafts").as_std_path()));
+        let applied = with_project_config(&root, options(true))?;
+        assert_eq!(applied.ocr.lang, "eng");
+        Ok(())
+    }
+
+    #[cfg(feature = "ocr")]
+    #[test]
+    fn uninstalled_lang() -> Result<()> {
+        let temp = TempDir::new()?;
+        let root = PathBuf::try_from(temp.path().to_owned())?;
+        let tessdata = root.join("tessdata");
+        fs::create_dir(&tessdata)?;
+        fs::create_dir(root.join("xyz"))?;
+        fs::write(root.join("xyz/.ocrlocate.toml"), "lang = \"xyz\"")?;
+        File::create(root.join("xyz/a.png"))?;
+        let mut db = DB::new(&root.join("index.db"))?;
+
+        let reports = Mutex::new(vec![]);
+        let progress = |event: Event| {
+            if let Event::Report(report) = event {
+                let path = report.path.map(String::from);
+                reports
+                    .lock()
+                    .unwrap()
+                    .push((report.code, path, report.message));
+            }
+        };
+        let options = IndexOptions {
+            ocr: OcrOptions {
+                tessdata: Some(tessdata),
+                ..Default::default()
+            },
+            lang_given: false,
+            debug: false,
+            limit: None,
+            exclude: vec![],
+            rescan: false,
+            subdirs: true,
+            chunksize: 10,
+            cleanup: false,
+            max_dimensions: None,
+            extractors: Registry::with_builtins(),
+            max_duration: None,
+            languages: LanguageHints::default(),
+            discover: false,
+            notes: false,
+            prefetch: None,
+            redact: Redactor::default(),
+            symbols: Symbols::default(),
+        };
+        index_dir(&mut db, &root, options, &progress, &AtomicBool::new(false))?;
+        let reports = reports.into_inner().unwrap();
+        assert_eq!(reports.len(), 1, "{reports:?}");
+        let (code, path, message) = &reports[0];
+        assert_eq!(*code, Code::Extract);
+        assert_eq!(path.as_deref(), Some(root.join("xyz/a.png").as_str()));
+        assert!(message.contains("xyz.traineddata"), "{message}");
+        assert!(!db.is_path_indexed(&root.join("xyz/a.png"))?);
+        Ok(())
+    }
+}
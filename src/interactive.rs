@@ -0,0 +1,250 @@
+//! Picking a search result in the terminal and acting on it, like opening it or removing it from
+//! the index
+
+use std::io::{self, BufRead, Write};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Result};
+use camino::Utf8Path as Path;
+
+use crate::config::{DirConfig, LanguageHints};
+use crate::db::{SearchResult, DB};
+use crate::exec::open;
+use crate::extract::Registry;
+use crate::index;
+use crate::ocr::OcrOptions;
+use crate::redact::Redactor;
+use crate::symbols::Symbols;
+
+/// What the actions need besides the results
+pub struct Context<'a> {
+    pub db: &'a mut DB,
+    /// To OCR a result again
+    pub extractors: &'a Registry,
+    pub ocr: &'a OcrOptions,
+    /// The `[languages]` of the config, which the project config of the result adds to
+    pub languages: &'a LanguageHints,
+    pub redact: &'a Redactor,
+    pub symbols: Symbols,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Open,
+    /// Open the directory of the file
+    Reveal,
+    CopyPath,
+    CopyText,
+    Rescan,
+    /// Stop indexing the directory of the file, and remove it from the index
+    ExcludeDir,
+    Delete,
+}
+
+impl Action {
+    const ALL: [Action; 7] = [
+        Action::Open,
+        Action::Reveal,
+        Action::CopyPath,
+        Action::CopyText,
+        Action::Rescan,
+        Action::ExcludeDir,
+        Action::Delete,
+    ];
+
+    fn key(self) -> char {
+        match self {
+            Action::Open => 'o',
+            Action::Reveal => 'r',
+            Action::CopyPath => 'p',
+            Action::CopyText => 't',
+            Action::Rescan => 's',
+            Action::ExcludeDir => 'x',
+            Action::Delete => 'd',
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Action::Open => "open",
+            Action::Reveal => "reveal in folder",
+            Action::CopyPath => "copy path",
+            Action::CopyText => "copy text",
+            Action::Rescan => "OCR again",
+            Action::ExcludeDir => "exclude directory",
+            Action::Delete => "delete from index",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Action> {
+        let mut chars = key.chars();
+        let key = chars.next().filter(|_| chars.next().is_none())?;
+        Action::ALL.into_iter().find(|x| x.key() == key)
+    }
+}
+
+/// List the results and ask which one to act on, until the user quits
+pub fn run(mut results: Vec<SearchResult>, context: &mut Context) -> Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut prompt = |text: &str| -> Result<Option<String>> {
+        eprint!("{text}");
+        io::stderr().flush()?;
+        Ok(lines.next().transpose()?.map(|x| x.trim().to_owned()))
+    };
+
+    loop {
+        if results.is_empty() {
+            eprintln!("No results");
+            return Ok(());
+        }
+        for (i, x) in results.iter().enumerate() {
+            let page = if x.page.is_empty() {
+                String::new()
+            } else {
+                format!(" (page {})", x.page)
+            };
+            println!(
+                "{:>3}  {}\t{}{}",
+                i + 1,
+                x.contents.escape_debug(),
+                x.path,
+                page
+            );
+        }
+        let Some(choice) = prompt("Result, or q to quit: ")? else {
+            return Ok(());
+        };
+        if choice.is_empty() || choice == "q" {
+            return Ok(());
+        }
+        let Some(index) = choice
+            .parse::<usize>()
+            .ok()
+            .filter(|x| (1..=results.len()).contains(x))
+        else {
+            eprintln!("No result {choice:?}");
+            continue;
+        };
+
+        let menu = Action::ALL
+            .iter()
+            .map(|x| format!("[{}] {}", x.key(), x.label()))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let Some(key) = prompt(&format!("{menu}  [b] back: "))? else {
+            return Ok(());
+        };
+        let Some(action) = Action::from_key(&key) else {
+            continue;
+        };
+        let path = Path::new(&results[index - 1].path).to_owned();
+        match apply(action, &results[index - 1], context) {
+            Ok(message) => eprintln!("{message}"),
+            Err(e) => eprintln!("[Error] {:#}", e),
+        }
+        // the results that are no longer in the index
+        match action {
+            Action::Delete => results.retain(|x| x.path != path),
+            Action::ExcludeDir => {
+                let dir = path.parent().unwrap_or(&path);
+                results.retain(|x| !Path::new(&x.path).starts_with(dir));
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Run an action on a result, returning what happened
+pub fn apply(action: Action, result: &SearchResult, context: &mut Context) -> Result<String> {
+    let path = Path::new(&result.path);
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("{} has no directory", path))?;
+    match action {
+        Action::Open => {
+            open(path)?;
+            Ok(format!("Opened {path}"))
+        }
+        Action::Reveal => {
+            open(dir)?;
+            Ok(format!("Opened {dir}"))
+        }
+        Action::CopyPath => copy(path.as_str()),
+        Action::CopyText => {
+            let text = context
+                .db
+                .content(path, &result.page)?
+                .ok_or_else(|| anyhow!("{} is not in the index", path))?;
+            copy(&text)
+        }
+        Action::Rescan => {
+            // with the settings that indexing its directory would use
+            let options = index::file_options(path, context.ocr, context.languages)?;
+            index::rescan_file(
+                context.db,
+                path,
+                context.extractors,
+                &options,
+                context.redact,
+                context.symbols,
+            )?;
+            let text = context.db.content(path, &result.page)?.unwrap_or_default();
+            Ok(format!("OCRed {path} again:\n{text}"))
+        }
+        Action::ExcludeDir => {
+            DirConfig::set_skip(dir)?;
+            let count = context.db.delete_under(dir)?;
+            Ok(format!(
+                "Excluded {dir}, and removed {count} entries under it from the index"
+            ))
+        }
+        Action::Delete => {
+            let count = context.db.delete(path)?;
+            Ok(format!("Removed {count} entries of {path} from the index"))
+        }
+    }
+}
+
+/// Copy text with the first clipboard program that is installed, or print it if there is none
+fn copy(text: &str) -> Result<String> {
+    const PROGRAMS: [&[&str]; 4] = [
+        &["wl-copy"],
+        &["xclip", "-selection", "clipboard"],
+        &["pbcopy"],
+        &["clip"],
+    ];
+    for program in PROGRAMS {
+        let Ok(mut child) = Command::new(program[0])
+            .args(&program[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+        child.stdin.take().unwrap().write_all(text.as_bytes())?;
+        if child.wait()?.success() {
+            return Ok(format!("Copied with {}", program[0]));
+        }
+    }
+    println!("{text}");
+    Ok("No clipboard program found, printed it instead".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(Action::from_key("d"), Some(Action::Delete));
+        assert_eq!(Action::from_key("dd"), None);
+        assert_eq!(Action::from_key("b"), None);
+        let mut keys: Vec<char> = Action::ALL.iter().map(|x| x.key()).collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), Action::ALL.len());
+    }
+}
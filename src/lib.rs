@@ -0,0 +1,62 @@
+//! Index and search a directory of images with OCR (optical character recognition)
+//!
+//! The `ocrlocate` binary is a thin wrapper around this crate. Library users can register their
+//! own [`extract::Extractor`]s to index file types that ocrlocate doesn't know about, and follow
+//! or cancel indexing with an `index::Progress` and a cancellation flag instead of the terminal
+//! progress bar. Images that only exist in memory, like the captures of a screenshot tool, can be
+//! OCRed with `ocr::Ocr::scan_bytes`, or many at once with `ocr::scan_many`.
+
+#[cfg(all(feature = "windows-ocr", not(windows)))]
+compile_error!("the windows-ocr feature only works on Windows");
+#[cfg(all(feature = "windows-ocr", feature = "ocr"))]
+compile_error!("windows-ocr replaces tesseract, build it with --no-default-features");
+#[cfg(all(feature = "vision", not(target_os = "macos")))]
+compile_error!("the vision feature only works on macOS");
+#[cfg(all(feature = "vision", any(feature = "ocr", feature = "windows-ocr")))]
+compile_error!("vision replaces the other engines, build it with --no-default-features");
+#[cfg(all(
+    feature = "cloud",
+    any(feature = "ocr", feature = "windows-ocr", feature = "vision")
+))]
+compile_error!("cloud replaces the other engines, build it with --no-default-features");
+#[cfg(all(
+    feature = "onnx",
+    any(
+        feature = "ocr",
+        feature = "windows-ocr",
+        feature = "vision",
+        feature = "cloud"
+    )
+))]
+compile_error!("onnx replaces the other engines, build it with --no-default-features");
+
+pub mod affinity;
+#[cfg(feature = "clip")]
+pub mod clip;
+pub mod config;
+pub mod db;
+pub mod exec;
+pub mod external;
+#[cfg(feature = "engine")]
+pub mod extract;
+#[cfg(test)]
+mod fixture;
+pub mod format;
+pub mod import;
+#[cfg(feature = "engine")]
+pub mod index;
+#[cfg(feature = "engine")]
+pub mod interactive;
+pub mod native_host;
+pub mod notes;
+pub mod ocr;
+pub mod paths;
+pub mod raw;
+pub mod redact;
+pub mod report;
+#[cfg(feature = "semantic")]
+pub mod semantic;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod symbols;
+pub mod tokenizer;
@@ -1,19 +1,60 @@
-mod db;
-mod index;
-mod ocr;
-
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{BufRead, IsTerminal, Write};
+use std::process::ExitCode;
+#[cfg(feature = "engine")]
+use std::sync::atomic::AtomicBool;
 use std::sync::OnceLock;
-use std::{env, fs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{env, fs, io};
 
 use anyhow::{anyhow, Context, Result};
 use camino::Utf8PathBuf as PathBuf;
 use clap::builder::{PossibleValuesParser, TypedValueParser};
-use clap::{arg, crate_description, crate_version, value_parser, ArgAction, Command};
+use clap::parser::ValueSource;
+use clap::{
+    arg, crate_description, crate_version, value_parser, ArgAction, ArgMatches, Command, ValueHint,
+};
+use clap_complete::Shell;
+#[cfg(feature = "engine")]
 use glob::Pattern;
 use itertools::Itertools;
 
-use crate::db::{SearchType, DB};
-use crate::ocr::{Binarization, Ocr};
+use ocrlocate::affinity;
+#[cfg(feature = "clip")]
+use ocrlocate::clip::ImageEmbedder;
+use ocrlocate::config::Config;
+#[cfg(feature = "engine")]
+use ocrlocate::config::{DirConfigs, LanguageHints};
+#[cfg(feature = "regex")]
+use ocrlocate::db::RegexOptions;
+use ocrlocate::db::{
+    self, IndexOptions, Near, Rank, Record, RetryOptions, SearchResult, SearchType, Shared,
+    SnippetOptions, Sort, Tokenizer, DB,
+};
+use ocrlocate::exec;
+use ocrlocate::external;
+#[cfg(feature = "engine")]
+use ocrlocate::extract::Registry;
+use ocrlocate::format;
+use ocrlocate::import;
+#[cfg(feature = "engine")]
+use ocrlocate::index;
+#[cfg(feature = "engine")]
+use ocrlocate::interactive;
+use ocrlocate::native_host;
+#[cfg(feature = "ocr")]
+use ocrlocate::ocr::Ocr;
+#[cfg(feature = "engine")]
+use ocrlocate::ocr::OcrOptions;
+use ocrlocate::ocr::{self, Binarization, Edges, Model, Normalize, Resize};
+use ocrlocate::redact::Redactor;
+use ocrlocate::report;
+#[cfg(feature = "semantic")]
+use ocrlocate::semantic::Embedder;
+#[cfg(feature = "serve")]
+use ocrlocate::serve;
+use ocrlocate::symbols::Symbols;
 
 // reading those images eats so much memory
 #[cfg(not(target_env = "msvc"))]
@@ -25,106 +66,823 @@ use tikv_jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
-fn main() -> Result<()> {
-    let matches = cli().get_matches();
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            report::fatal(&e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<()> {
+    let matches = cli().get_matches_from(expand_args(env::args_os())?);
+    report::set_json(matches.get_one::<String>("output").unwrap() == "json");
+    if let Some(("completions", sub)) = matches.subcommand() {
+        completions(
+            *sub.get_one::<Shell>("SHELL").unwrap(),
+            matches.get_one::<PathBuf>("tessdata").map(|x| x.as_path()),
+            &mut io::stdout(),
+        );
+        return Ok(());
+    }
+
+    let debug = matches.get_flag("verbose");
+    let config = Config::load(matches.get_one::<PathBuf>("config").unwrap())?;
+    let profile = match matches.get_one::<String>("profile") {
+        Some(name) => Some(config.profile(name)?.clone()),
+        None => None,
+    };
+    // --lang on the command line takes priority over the profile, which does over .ocrlocate.toml
+    #[cfg(feature = "engine")]
+    let profile_lang = profile
+        .as_ref()
+        .and_then(|x| x.lang.clone())
+        .filter(|_| matches.value_source("lang") != Some(ValueSource::CommandLine));
+    #[cfg(not(feature = "ocr"))]
+    if matches.get_flag("dump-scan") {
+        return Err(anyhow!(
+            "--dump-scan needs tesseract, which this ocrlocate was built without"
+        ));
+    }
+    #[cfg(not(feature = "engine"))]
+    if matches.get_flag("interactive") || matches.contains_id("like") {
+        return Err(anyhow!(
+            "this ocrlocate was built without OCR, and can only search an existing index"
+        ));
+    }
+    let symbols = Symbols {
+        transliterate: matches.get_flag("transliterate"),
+        strip: matches.get_flag("strip-symbols"),
+    };
+    #[cfg(feature = "engine")]
+    let tessdata = matches.get_one::<PathBuf>("tessdata").cloned();
+    #[cfg(feature = "engine")]
+    let tessdata = match matches.get_one::<Model>("model") {
+        Some(model) => Some(model.dir(tessdata.as_deref())?),
+        None => tessdata,
+    };
+    #[cfg(feature = "engine")]
+    let ocr_options = OcrOptions {
+        lang: match &profile_lang {
+            Some(lang) => {
+                ocr::validate_lang(lang)?;
+                lang.to_owned()
+            }
+            None => matches.get_one::<String>("lang").unwrap().to_owned(),
+        },
+        debug,
+        scale: matches.get_one::<f32>("scale").copied(),
+        resize: matches.get_one::<Resize>("resize").copied(),
+        binarization: matches.get_one::<Binarization>("binarization").copied(),
+        psm: matches.get_one::<i64>("psm").copied(),
+        dpi: matches.get_one::<u32>("dpi").copied(),
+        char_whitelist: matches.get_one::<String>("char-whitelist").cloned(),
+        char_blacklist: matches.get_one::<String>("char-blacklist").cloned(),
+        user_words: matches.get_one::<PathBuf>("user-words").cloned(),
+        user_patterns: matches.get_one::<PathBuf>("user-patterns").cloned(),
+        tessdata,
+        tables: matches.get_flag("tables"),
+        normalize: matches
+            .get_one::<Normalize>("normalize")
+            .copied()
+            .unwrap_or_default(),
+        min_word_confidence: matches.get_one::<f32>("min-word-confidence").copied(),
+        osd: matches.get_one::<BTreeMap<String, String>>("osd").cloned(),
+        auto_rotate: matches.get_flag("auto-rotate"),
+        denoise: matches.get_flag("denoise"),
+        trim: matches.get_flag("trim"),
+        crop_edges: matches.get_one::<Edges>("crop-edges").copied(),
+        detect_text: matches.get_flag("detect-text"),
+        annotations: matches.get_flag("annotations"),
+        #[cfg(feature = "cloud")]
+        cloud: matches
+            .get_one::<ocr::Provider>("cloud")
+            .map(|provider| ocr::Cloud {
+                provider: *provider,
+                key: matches.get_one::<String>("cloud-key").unwrap().to_owned(),
+                endpoint: matches.get_one::<String>("cloud-endpoint").cloned(),
+                rate: *matches.get_one::<f32>("cloud-rate").unwrap(),
+            }),
+        #[cfg(not(feature = "cloud"))]
+        cloud: None,
+        #[cfg(feature = "onnx")]
+        onnx_models: matches.get_one::<PathBuf>("onnx-models").cloned(),
+        #[cfg(not(feature = "onnx"))]
+        onnx_models: None,
+        #[cfg(feature = "onnx")]
+        gpu: matches.get_one::<ocr::Gpu>("gpu").copied(),
+        #[cfg(not(feature = "onnx"))]
+        gpu: None,
+    };
 
+    #[cfg(feature = "ocr")]
     if matches.get_flag("dump-scan") {
-        let mut o = Ocr::new(
-            matches.get_one::<String>("lang").unwrap(),
-            true,
-            matches.get_one::<f32>("scale").copied(),
-            matches.get_one::<Binarization>("binarization").copied(),
-            matches.get_one::<i64>("psm").copied(),
-        )?;
+        let mut o = Ocr::new(&OcrOptions {
+            debug: true,
+            ..ocr_options
+        })?;
         let path = PathBuf::from(
             matches
                 .get_one::<String>("QUERIES")
                 .expect("queries shouldnt be empty"),
         );
-        let res = o.scan(&path)?;
+        let res = match matches.get_one::<String>("format").unwrap().as_str() {
+            "hocr" => o.scan_hocr(&path)?,
+            "alto" => o.scan_alto(&path)?,
+            _ => o.scan(&path)?,
+        };
         println!("{}", res);
         return Ok(());
     }
 
-    let dbpath: &PathBuf = matches.get_one("database").unwrap();
-    if let Some(parent) = dbpath.parent() {
-        if !parent.try_exists().expect("state dir should be exist") {
-            fs::create_dir(parent).unwrap();
+    let dbpath = match &profile {
+        Some(profile) if matches.value_source("database") != Some(ValueSource::CommandLine) => {
+            PathBuf::from(&profile.database)
         }
+        _ => matches.get_one::<PathBuf>("database").unwrap().to_owned(),
+    };
+    if let Some(parent) = dbpath.parent().filter(|x| !x.as_str().is_empty()) {
+        fs::create_dir_all(parent).with_context(|| format!("failed to create {parent}"))?;
     }
 
     if let Some(pwd) = matches.get_one::<String>("pwd") {
         env::set_current_dir(pwd).unwrap();
     }
 
-    if let Some(threads) = matches.get_one::<usize>("threads") {
-        let mut builder = rayon::ThreadPoolBuilder::new().num_threads(*threads);
-        if *threads == 1 {
+    let cores = if matches.get_flag("efficiency-cores-only") {
+        Some(affinity::efficiency_cores()?)
+    } else {
+        match matches.get_one::<String>("cpu-set").map(|x| x.as_str()) {
+            Some("auto") => Some(affinity::auto_cores()?),
+            Some(list) => Some(affinity::parse_cpu_list(list)?),
+            None => None,
+        }
+    };
+    let threads = matches
+        .get_one::<usize>("threads")
+        .copied()
+        .or(cores.as_ref().map(|x| x.len()));
+    if let Some(threads) = threads {
+        let mut builder = rayon::ThreadPoolBuilder::new().num_threads(threads);
+        if threads == 1 {
             builder = builder.use_current_thread();
         }
+        if let Some(cores) = cores {
+            builder = builder.start_handler(move |i| affinity::pin_current(cores[i % cores.len()]));
+        }
         builder.build_global().unwrap();
     }
 
+    #[cfg(feature = "engine")]
     let mut exclude = Vec::from(["*/.cache", "*/.thumb*"].map(|x| Pattern::new(x).unwrap()));
+    #[cfg(feature = "engine")]
     if let Some(patterns) = matches.get_many::<String>("exclude") {
-        let mut parsed: Vec<Pattern> = patterns
-            .map(|x| {
-                Pattern::new(&format!("*/{x}"))
-                    .with_context(|| format!("invalid pattern: {x}"))
-                    .unwrap()
-            })
-            .collect();
+        let mut parsed = patterns
+            .map(|x| index::exclude_pattern(x))
+            .collect::<Result<Vec<_>>>()?;
         exclude.append(&mut parsed);
     }
+    #[cfg(feature = "engine")]
+    for pattern in profile.iter().flat_map(|x| &x.exclude) {
+        exclude.push(index::exclude_pattern(pattern)?);
+    }
 
+    #[cfg(feature = "engine")]
     let scan_limit = matches.get_one::<usize>("scan-limit").copied();
-    let debug = matches.get_flag("verbose");
-    let max_size = matches.get_one::<String>("max-size").map(|x| {
-        const ERR: &str = "invalid max-size: should be [width]x[height]";
-        x.split('x')
-            .map(|x| x.parse().expect(ERR))
-            .collect_tuple::<(_, _)>()
-            .expect(ERR)
+    #[cfg(feature = "engine")]
+    let max_size = matches
+        .get_one::<String>("max-size")
+        .map(|x| index::parse_max_size(x))
+        .transpose()?;
+
+    let redact = if matches.get_flag("no-redact") {
+        Redactor::default()
+    } else {
+        Redactor::new(&config.redact)?
+    };
+
+    let mut db = DB::open(
+        &dbpath,
+        RetryOptions {
+            attempts: *matches.get_one::<u32>("db-retries").unwrap(),
+            backoff: Duration::from_millis(*matches.get_one::<u64>("db-backoff").unwrap()),
+        },
+    )?;
+    if let Some(case_insensitive) = config.case_insensitive_paths {
+        db.set_case_insensitive_paths(case_insensitive)?;
+    }
+    if matches.get_flag("shared-index") {
+        let user = db::current_user();
+        let private = matches.get_flag("private");
+        if private && user.is_none() {
+            return Err(anyhow!(
+                "--private needs a user id, which this platform doesn't have"
+            ));
+        }
+        db.set_shared(Some(Shared { user, private }));
+    }
+    db.set_rank(match matches.get_one::<String>("rank").unwrap().as_str() {
+        "recency" => Rank::Recency,
+        "mixed" => {
+            let half_life = *matches.get_one::<f64>("half-life").unwrap();
+            if half_life <= 0.0 {
+                return Err(anyhow!("--half-life should be more than 0 days"));
+            }
+            Rank::Mixed { half_life }
+        }
+        _ => Rank::Bm25,
     });
+    db.set_sort(
+        match matches.get_one::<String>("sort").unwrap().as_str() {
+            "mtime" => Sort::Modified,
+            "path" => Sort::Path,
+            "size" => Sort::Size,
+            _ => Sort::Rank,
+        },
+        matches.get_flag("reverse"),
+    );
+    db.set_excluded_terms(
+        matches
+            .get_many::<String>("not")
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect(),
+    );
+    let snippet = SnippetOptions {
+        length: *matches.get_one::<u64>("snippet-length").unwrap() as usize,
+        open: matches.get_one::<String>("highlight-open").unwrap().clone(),
+        close: matches
+            .get_one::<String>("highlight-close")
+            .unwrap()
+            .clone(),
+    };
+    // the matches that are colored are found by the markers of the snippets, which are control
+    // characters then so that brackets in the text aren't colored
+    let color = match matches.get_one::<String>("color").unwrap().as_str() {
+        "always" => true,
+        "never" => false,
+        _ => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|x| x.is_empty()),
+    }
+    .then(|| format::color_markers(&snippet));
+    db.set_snippet_options(color.clone().unwrap_or(snippet));
+    let long = matches.get_flag("long").then(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    });
+    let time = |name| -> Result<Option<i64>> {
+        matches
+            .get_one::<String>(name)
+            .map(|x| parse_time(&db, x).with_context(|| format!("invalid --{name}")))
+            .transpose()
+    };
+    let (newer_than, older_than) = (time("newer-than")?, time("older-than")?);
+    db.set_modified_range(newer_than, older_than);
+    db.set_lang_filter(
+        matches
+            .get_many::<String>("lang-filter")
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect(),
+    );
+    #[cfg(feature = "regex")]
+    db.set_regex_options(RegexOptions {
+        ignore_case: matches.get_flag("regex-ignore-case"),
+        multi_line: matches.get_flag("regex-multiline"),
+        time_limit: Some(*matches.get_one::<Duration>("regex-timeout").unwrap())
+            .filter(|x| !x.is_zero()),
+        ..Default::default()
+    })?;
+    if let Some(tokenizer) = matches.get_one::<Tokenizer>("tokenizer") {
+        if db.set_tokenizer(*tokenizer)? && debug {
+            eprintln!("Rebuilt the search index with the {tokenizer:?} tokenizer");
+        }
+    }
+    if let Some(diacritics) = matches.get_one::<String>("diacritics") {
+        if db.set_remove_diacritics(diacritics == "ignore")? && debug {
+            eprintln!("Rebuilt the search index to {diacritics} diacritics");
+        }
+    }
+    #[cfg(feature = "semantic")]
+    let embedding = matches.get_one::<PathBuf>("embedding-model").is_some();
+    #[cfg(feature = "semantic")]
+    if let Some(dir) = matches.get_one::<PathBuf>("embedding-model") {
+        db.set_embedder(Some(Embedder::new(dir)?));
+    }
 
-    let mut db = DB::new(dbpath)?;
-    if matches.get_flag("index") {
-        env::set_var("OMP_THREAD_LIMIT", "1");
-        index::index_dir(
+    // an empty directory is the parent of every path. The database of a profile with roots only
+    // has the files in them, so all of it is searched
+    let everywhere = profile.as_ref().is_some_and(|x| !x.roots.is_empty());
+    let search_root = match matches.get_flag("everywhere") || everywhere {
+        true => PathBuf::new(),
+        false => PathBuf::try_from(env::current_dir().unwrap()).unwrap(),
+    };
+    // the directories that are indexed: the roots of the profile, or the current directory
+    let roots = || -> Result<Vec<PathBuf>> {
+        let mut roots = vec![];
+        for root in profile.iter().flat_map(|x| &x.roots) {
+            roots.push(PathBuf::try_from(
+                fs::canonicalize(root).with_context(|| format!("failed to find {root}"))?,
+            )?);
+        }
+        if roots.is_empty() {
+            roots.push(PathBuf::try_from(env::current_dir().unwrap()).unwrap());
+        }
+        Ok(roots)
+    };
+
+    if let Some(("similar-text", sub)) = matches.subcommand() {
+        let path = PathBuf::try_from(
+            fs::canonicalize(sub.get_one::<String>("PATH").unwrap())
+                .context("failed to find file")?,
+        )?;
+        let results = db.similar(
+            &path,
+            &search_root,
+            *sub.get_one::<usize>("limit").unwrap(),
+            *sub.get_one::<usize>("terms").unwrap(),
+        )?;
+        print_results(
+            results,
+            debug,
+            matches.get_flag("show-confidence"),
+            long,
+            color.as_ref(),
+        );
+        return Ok(());
+    }
+
+    #[cfg(feature = "clip")]
+    if let Some(("similar", sub)) = matches.subcommand() {
+        let path = PathBuf::try_from(
+            fs::canonicalize(sub.get_one::<String>("IMAGE").unwrap())
+                .context("failed to find file")?,
+        )?;
+        let dir = matches
+            .get_one::<PathBuf>("clip-model")
+            .ok_or_else(|| anyhow!("--clip-model is needed to compare images"))?;
+        let mut model = ImageEmbedder::new(dir)?;
+        let query = model
+            .embed_file(&path)?
+            .ok_or_else(|| anyhow!("not an image that can be read: {}", path))?;
+        let mut results = db.similar_images(
+            &query,
+            model.name(),
+            &search_root,
+            *sub.get_one::<usize>("limit").unwrap() + 1,
+        )?;
+        results.retain(|x| x.path != path.as_str());
+        results.truncate(*sub.get_one::<usize>("limit").unwrap());
+        print_results(
+            results,
+            debug,
+            matches.get_flag("show-confidence"),
+            long,
+            None,
+        );
+        return Ok(());
+    }
+
+    if let Some(("random", sub)) = matches.subcommand() {
+        let queries: Vec<&str> = sub
+            .get_one::<String>("matching")
+            .map(|x| x.as_str())
+            .into_iter()
+            .collect();
+        let results = db.random(
+            &search_root,
+            *sub.get_one::<usize>("count").unwrap(),
+            &queries,
+            *matches.get_one::<SearchType>("search-type").unwrap(),
+        )?;
+        // without a query the results are the start of the text, which has no matches
+        print_results(
+            results,
+            debug,
+            matches.get_flag("show-confidence"),
+            long,
+            color.as_ref().filter(|_| !queries.is_empty()),
+        );
+        return Ok(());
+    }
+
+    #[cfg(feature = "ocr")]
+    if let Some(("crop", sub)) = matches.subcommand() {
+        let path = PathBuf::try_from(
+            fs::canonicalize(sub.get_one::<String>("PATH").unwrap())
+                .context("failed to find file")?,
+        )?;
+        let query = sub.get_one::<String>("QUERY").unwrap();
+        let mut words = db.words(&path, "")?;
+        if words.is_empty() {
+            let mut o = Ocr::new(&ocr_options)?;
+            o.scan(&path)?;
+            words = o.words();
+        }
+        let (left, top, width, height) = ocr::find_words(&words, query)
+            .ok_or_else(|| anyhow!("{:?} was not found in {}", query, path))?;
+        let padding = *sub.get_one::<i32>("padding").unwrap();
+        let output = sub
+            .get_one::<PathBuf>("output")
+            .cloned()
+            .unwrap_or_else(|| {
+                PathBuf::from(format!("{}-crop.png", path.file_stem().unwrap_or("image")))
+            });
+        ocr::crop(
+            &path,
+            (
+                (left - padding).max(0),
+                (top - padding).max(0),
+                width + padding * 2,
+                height + padding * 2,
+            ),
+            &output,
+        )?;
+        println!("{}", output);
+        return Ok(());
+    }
+
+    #[cfg(feature = "engine")]
+    if let Some(("verify-ocr", sub)) = matches.subcommand() {
+        let mut extractors = Registry::with_builtins();
+        extractors.register_config(&config.extractor)?;
+        return verify_ocr(
+            &db,
+            &search_root,
+            *sub.get_one::<usize>("sample").unwrap(),
+            &extractors,
+            &ocr_options,
+            &redact,
+            symbols,
+        );
+    }
+
+    if let Some(("add", sub)) = matches.subcommand() {
+        let path = PathBuf::try_from(
+            fs::canonicalize(sub.get_one::<String>("PATH").unwrap())
+                .context("failed to find file")?,
+        )?;
+        let text = match sub.get_one::<PathBuf>("text-from").unwrap().as_str() {
+            "-" => io::read_to_string(io::stdin())?,
+            file => fs::read_to_string(file).with_context(|| format!("failed to read {file}"))?,
+        };
+        let pages = if sub.get_flag("json") {
+            serde_json::from_str(&text).context("invalid pages")?
+        } else {
+            vec![external::Page {
+                contents: text,
+                ..Default::default()
+            }]
+        };
+        let changed = external::upsert(&mut db, &path, pages, &redact, symbols)?;
+        if debug {
+            eprintln!("{changed} rows changed");
+        }
+        #[cfg(feature = "semantic")]
+        if embedding {
+            embed(&mut db, path.parent().unwrap_or(&path), debug)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(("import", sub)) = matches.subcommand() {
+        let imported = import::import(
             &mut db,
-            &PathBuf::try_from(env::current_dir().unwrap()).unwrap(),
-            index::IndexOptions {
-                lang: matches.get_one::<String>("lang").unwrap().to_owned(),
-                debug,
-                limit: scan_limit,
-                exclude,
-                rescan: matches.get_flag("rescan"),
-                subdirs: matches.get_flag("subdirs"),
-                chunksize: *matches.get_one::<usize>("chunk-size").unwrap(),
-                cleanup: matches.get_flag("cleanup"),
-                max_dimensions: max_size,
-                scale: matches.get_one::<f32>("scale").copied(),
-                binarization: matches.get_one::<Binarization>("binarization").copied(),
-                psm: matches.get_one::<i64>("psm").copied(),
-            },
+            *sub.get_one::<import::Source>("from").unwrap(),
+            sub.get_one::<PathBuf>("PATH").unwrap(),
+            &redact,
+            symbols,
         )?;
+        eprintln!(
+            "Imported the text of {} files, {} were already indexed and {} are missing, changed or incomplete",
+            imported.files, imported.indexed, imported.outdated
+        );
+        #[cfg(feature = "semantic")]
+        if embedding {
+            embed(&mut db, &PathBuf::new(), debug)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(("native-host", sub)) = matches.subcommand() {
+        #[cfg(feature = "engine")]
+        let mut extractors = Registry::with_builtins();
+        #[cfg(feature = "engine")]
+        extractors.register_config(&config.extractor)?;
+        return native_host::run(
+            &mut db,
+            &native_host::HostOptions {
+                root: match sub.get_one::<PathBuf>("root") {
+                    Some(root) => PathBuf::try_from(
+                        fs::canonicalize(root).with_context(|| format!("failed to find {root}"))?,
+                    )?,
+                    None => PathBuf::new(),
+                },
+                limit: *sub.get_one::<usize>("limit").unwrap(),
+                redact,
+                symbols,
+                #[cfg(feature = "engine")]
+                extractors,
+                #[cfg(feature = "engine")]
+                ocr: ocr_options,
+                #[cfg(feature = "engine")]
+                languages: LanguageHints::new(config.languages.clone())?,
+            },
+            io::stdin().lock(),
+            io::stdout().lock(),
+        );
+    }
+
+    if let Some(("export", sub)) = matches.subcommand() {
+        let output = sub.get_one::<PathBuf>("output").unwrap();
+        for root in &roots()? {
+            export(&db, root, output)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(("db", sub)) = matches.subcommand() {
+        return match sub.subcommand() {
+            Some(("verify", sub)) => verify(&mut db, sub.get_flag("repair")),
+            Some(("optimize", _)) => {
+                let report = db.optimize()?;
+                println!(
+                    "Optimized the database from {} to {}",
+                    format::human_size(report.before),
+                    format::human_size(report.after)
+                );
+                if !report.checkpointed {
+                    eprintln!("Another process is reading the database, so its write-ahead log wasn't emptied");
+                }
+                Ok(())
+            }
+            Some(("export", _)) => export_records(&db),
+            Some(("import", sub)) => {
+                import_records(&mut db, sub.get_one::<PathBuf>("FILE").unwrap())?;
+                #[cfg(feature = "semantic")]
+                if embedding {
+                    embed(&mut db, &PathBuf::new(), debug)?;
+                }
+                Ok(())
+            }
+            Some(("stats", sub)) => {
+                let stats = db.stats()?;
+                if sub.get_flag("json") {
+                    println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+                } else {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                    format::write_stats(&mut io::stdout().lock(), &stats, now)?;
+                }
+                Ok(())
+            }
+            Some(("init", sub)) => {
+                let options = IndexOptions {
+                    tokenizer: *sub.get_one::<Tokenizer>("tokenizer").unwrap(),
+                    remove_diacritics: sub.get_one::<String>("diacritics").unwrap() == "ignore",
+                    prefix: sub
+                        .get_many::<usize>("prefix")
+                        .map_or(vec![], |x| x.copied().collect()),
+                    compact: sub.get_flag("compact"),
+                    compress: sub.get_flag("compress"),
+                };
+                if db.set_index_options(&options)? {
+                    println!("Rebuilt the search index");
+                } else {
+                    println!("The search index already has these options");
+                }
+                Ok(())
+            }
+            _ => unreachable!("a db subcommand is required"),
+        };
+    }
+
+    #[cfg(feature = "serve")]
+    if let Some(("serve", sub)) = matches.subcommand() {
+        return serve::serve(
+            &mut db,
+            &serve::ServeOptions {
+                listen: sub.get_one::<String>("listen").unwrap().to_owned(),
+                root: search_root.clone(),
+                roots: roots()?,
+                limit: *sub.get_one::<usize>("limit").unwrap(),
+                auth: serve_auth(sub)?,
+                #[cfg(feature = "tls")]
+                tls: sub
+                    .get_one::<String>("tls-cert")
+                    .zip(sub.get_one::<String>("tls-key"))
+                    .map(|(cert, key)| (PathBuf::from(cert), PathBuf::from(key))),
+                read_only: !sub.get_flag("allow-writes"),
+                maintain_after: sub.get_one::<Duration>("maintain-after").copied(),
+            },
+        );
+    }
+
+    // which runs for every change of the query, so it doesn't index
+    if matches.get_flag("fzf") {
+        return fzf(&mut db, &matches, &search_root, color.as_ref());
+    }
+
+    #[cfg(feature = "engine")]
+    if matches.get_flag("index") {
+        env::set_var("OMP_THREAD_LIMIT", "1");
+        for root in &roots()? {
+            let mut extractors = Registry::with_builtins();
+            extractors.register_config(&config.extractor)?;
+
+            // the project config of the root is applied by index_dir
+            index::index_dir(
+                &mut db,
+                root,
+                index::IndexOptions {
+                    ocr: ocr_options.clone(),
+                    lang_given: matches.value_source("lang") != Some(ValueSource::DefaultValue)
+                        || profile_lang.is_some(),
+                    debug,
+                    limit: scan_limit,
+                    exclude: exclude.clone(),
+                    rescan: matches.get_flag("rescan"),
+                    subdirs: matches.get_flag("subdirs"),
+                    chunksize: *matches.get_one::<usize>("chunk-size").unwrap(),
+                    cleanup: matches.get_flag("cleanup"),
+                    max_dimensions: max_size,
+                    extractors,
+                    max_duration: matches.get_one::<Duration>("max-duration").copied(),
+                    languages: LanguageHints::new(config.languages.clone())?,
+                    discover: matches.get_flag("count-first"),
+                    notes: matches.get_flag("notes"),
+                    prefetch: matches.get_one::<u64>("prefetch").map(|x| x * 1024 * 1024),
+                    redact: redact.clone(),
+                    symbols,
+                },
+                &index::Terminal::new(debug),
+                &AtomicBool::new(false),
+            )?;
+            #[cfg(feature = "semantic")]
+            if embedding {
+                embed(&mut db, root, debug)?;
+            }
+            #[cfg(feature = "clip")]
+            if let Some(dir) = matches.get_one::<PathBuf>("clip-model") {
+                let mut model = ImageEmbedder::new(dir)?;
+                let name = model.name().to_owned();
+                let embedded = db.embed_images(root, &name, |path| model.embed_file(path))?;
+                if debug {
+                    eprintln!("Embedded {embedded} images");
+                }
+            }
+        }
     }
 
     let queries = matches.get_many::<String>("QUERIES");
-    if let Some(queries) = queries {
-        let results = db.search(
-            queries.map(|x| x.as_ref()).collect(),
-            &PathBuf::try_from(env::current_dir().unwrap()).unwrap(),
-            *matches.get_one::<usize>("limit").unwrap(),
-            *matches.get_one::<SearchType>("search-type").unwrap(),
+    let near = matches.get_one::<String>("near").map(|words| Near {
+        words: words.split_whitespace().map(String::from).collect(),
+        distance: *matches.get_one::<usize>("distance").unwrap(),
+    });
+    #[cfg(feature = "engine")]
+    let like = match matches.get_one::<PathBuf>("like") {
+        Some(image) => Some(PathBuf::try_from(
+            fs::canonicalize(image).with_context(|| format!("failed to find {image}"))?,
+        )?),
+        None => None,
+    };
+    #[cfg(not(feature = "engine"))]
+    let like: Option<PathBuf> = None;
+    if queries.is_some() || near.is_some() || like.is_some() {
+        let mut queries: Vec<&str> = queries.into_iter().flatten().map(|x| x.as_ref()).collect();
+        let mut kind = *matches.get_one::<SearchType>("search-type").unwrap();
+        if matches.get_flag("any") {
+            if kind != SearchType::Simple {
+                return Err(anyhow!("--any can only be used with simple queries"));
+            }
+            kind = SearchType::Any;
+        }
+        let with_near;
+        if let Some(near) = &near {
+            with_near = near.with(kind, &queries, db.tokenizer()?)?;
+            (queries, kind) = (vec![with_near.as_str()], SearchType::Match);
+        }
+        #[cfg(feature = "engine")]
+        let like_query;
+        #[cfg(feature = "engine")]
+        if let Some(image) = &like {
+            let mut extractors = Registry::with_builtins();
+            extractors.register_config(&config.extractor)?;
+            let text = index::extract_file(image, &extractors, &ocr_options, &redact, symbols)?
+                .into_iter()
+                .map(|x| x.contents)
+                .join("\n");
+            like_query = db.distinctive_query(&text, LIKE_TERMS)?;
+            if like_query.is_empty() {
+                return Err(anyhow!("none of the text of {image} is in the index"));
+            }
+            (queries, kind) = (vec![like_query.as_str()], SearchType::Match);
+        }
+        // checked before searching, which can take a while
+        let command = matches
+            .get_one::<String>("exec")
+            .map(|x| exec::parse_command(x).context("invalid --exec"))
+            .transpose()?;
+        let limit = *matches.get_one::<usize>("limit").unwrap();
+        let mut results = db.search(
+            queries.clone(),
+            &search_root,
+            // the image itself might be found too
+            limit + like.is_some() as usize,
+            kind,
             matches.get_one::<String>("exclude").map(|x| x.as_str()),
+            matches.get_one::<f32>("min-confidence").copied(),
         )?;
-        if cfg!(debug_assertions) && debug {
-            println!("{:#?}", results)
-        } else {
-            for x in results {
-                println!("{}\t{}", x.contents.escape_debug(), x.path);
+        if let Some(image) = &like {
+            results.retain(|x| x.path != image.as_str());
+            results.truncate(limit);
+        }
+        let show_confidence = matches.get_flag("show-confidence");
+        // semantic results are the whole text, which has no matches
+        #[cfg(feature = "semantic")]
+        let color = color.filter(|_| kind != SearchType::Semantic);
+        #[cfg(feature = "engine")]
+        if matches.get_flag("interactive") {
+            let mut extractors = Registry::with_builtins();
+            extractors.register_config(&config.extractor)?;
+            interactive::run(
+                results,
+                &mut interactive::Context {
+                    db: &mut db,
+                    extractors: &extractors,
+                    ocr: &ocr_options,
+                    languages: &LanguageHints::new(config.languages.clone())?,
+                    redact: &redact,
+                    symbols,
+                },
+            )?;
+            return Ok(());
+        }
+        if let Some(command) = command {
+            let failed = exec::exec(&command, &results)?;
+            if failed > 0 {
+                return Err(anyhow!("the command failed on {failed} files"));
+            }
+        } else if matches.get_flag("open") {
+            let max = match matches.value_source("limit") {
+                Some(ValueSource::CommandLine) => usize::MAX,
+                _ => exec::MAX_OPEN,
+            };
+            exec::open_all(&results, max)?;
+        } else if matches.get_flag("grep") {
+            let around = matches.get_one::<usize>("context").copied();
+            let context = (
+                matches
+                    .get_one::<usize>("before")
+                    .copied()
+                    .or(around)
+                    .unwrap_or(0),
+                matches
+                    .get_one::<usize>("after")
+                    .copied()
+                    .or(around)
+                    .unwrap_or(0),
+            );
+            let results = results
+                .into_iter()
+                .map(|x| {
+                    let lines = db.matching_lines(&x, &queries, kind, context)?;
+                    Ok((x, lines))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            format::write_grep(
+                &mut io::stdout().lock(),
+                &results,
+                color.is_some(),
+                context != (0, 0),
+            )?;
+        } else if matches
+            .get_one::<String>("group-by")
+            .is_some_and(|x| x == "term")
+        {
+            let terms = db.matched_terms(&mut results, &queries, kind)?;
+            if cfg!(debug_assertions) && debug {
+                println!("{:#?}", results);
             }
+            // json results have their terms already
+            if report::is_json() {
+                print_results(results, debug, show_confidence, None, None);
+            } else {
+                format::write_grouped(
+                    &mut io::stdout().lock(),
+                    &results,
+                    &terms,
+                    show_confidence,
+                    long,
+                    color.as_ref(),
+                )?;
+            }
+        } else {
+            print_results(results, debug, show_confidence, long, color.as_ref());
         }
     } else {
         return Err(anyhow!("No queries were provided"));
@@ -133,42 +891,490 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Distinctive words of the text of a --like image that are searched for
+#[cfg(feature = "engine")]
+const LIKE_TERMS: usize = 10;
+
+/// Embed the text of the files under `root` that doesn't have an embedding of the model yet
+#[cfg(feature = "semantic")]
+fn embed(db: &mut DB, root: &camino::Utf8Path, debug: bool) -> Result<()> {
+    let embedded = db.embed(root)?;
+    if debug {
+        eprintln!("Embedded the text of {embedded} rows");
+    }
+    Ok(())
+}
+
+/// Print the results of the queries for fzf, see [`format::write_fzf`], or of every line of stdin
+/// with `--query-from-stdin`, followed by an empty line. Empty queries and searches that fail, like
+/// a match query that is still being typed, print no results
+fn fzf(
+    db: &mut DB,
+    matches: &ArgMatches,
+    root: &camino::Utf8Path,
+    color: Option<&SnippetOptions>,
+) -> Result<()> {
+    let mut kind = *matches.get_one::<SearchType>("search-type").unwrap();
+    if matches.get_flag("any") {
+        if kind != SearchType::Simple {
+            return Err(anyhow!("--any can only be used with simple queries"));
+        }
+        kind = SearchType::Any;
+    }
+    // semantic results are the whole text, which has no matches
+    #[cfg(feature = "semantic")]
+    let color = color.filter(|_| kind != SearchType::Semantic);
+    let mut search = |queries: Vec<&str>| {
+        if queries.iter().all(|x| x.trim().is_empty()) {
+            return vec![];
+        }
+        db.search(
+            queries,
+            root,
+            *matches.get_one::<usize>("limit").unwrap(),
+            kind,
+            matches.get_one::<String>("exclude").map(|x| x.as_str()),
+            matches.get_one::<f32>("min-confidence").copied(),
+        )
+        .unwrap_or_else(|e| {
+            if matches.get_flag("verbose") {
+                eprintln!("[Error] {e:#}");
+            }
+            vec![]
+        })
+    };
+    let mut out = io::stdout().lock();
+    if !matches.get_flag("query-from-stdin") {
+        let queries = matches.get_many::<String>("QUERIES").into_iter().flatten();
+        format::write_fzf(
+            &mut out,
+            &search(queries.map(|x| x.as_str()).collect()),
+            color,
+        )?;
+        return Ok(());
+    }
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        format::write_fzf(&mut out, &search(vec![line.trim_end_matches('\r')]), color)?;
+        writeln!(out)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+fn parse_duration(s: &str) -> Result<Duration> {
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("invalid duration: {s}"))?;
+    let seconds = match unit {
+        "" | "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        "d" => number * 60 * 60 * 24,
+        "w" => number * 60 * 60 * 24 * 7,
+        _ => {
+            return Err(anyhow!(
+                "invalid duration unit: {unit}, should be s, m, h, d or w"
+            ))
+        }
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Seconds since the epoch of a time that long ago like `3d`, or of a date like `2024-01-01`
+fn parse_time(db: &DB, s: &str) -> Result<i64> {
+    match parse_duration(s) {
+        Ok(age) => Ok(SystemTime::now()
+            .checked_sub(age)
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .ok_or_else(|| anyhow!("invalid time: {s}"))?
+            .as_secs() as i64),
+        Err(_) => db.local_time(s),
+    }
+}
+
+/// Parse script languages like `Latin=eng,Japanese=jpn`
+fn parse_scripts(s: &str) -> Result<BTreeMap<String, String>> {
+    s.split(',')
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            let (script, lang) = x.split_once('=').ok_or_else(|| {
+                anyhow!("invalid script language: {x}, should be [script]=[lang]")
+            })?;
+            ocr::validate_lang(lang)?;
+            Ok((script.to_owned(), lang.to_owned()))
+        })
+        .collect()
+}
+
+fn print_results(
+    results: Vec<SearchResult>,
+    debug: bool,
+    show_confidence: bool,
+    long: Option<u64>,
+    color: Option<&SnippetOptions>,
+) {
+    if report::is_json() {
+        println!("{}", serde_json::to_string_pretty(&results).unwrap());
+    } else if cfg!(debug_assertions) && debug {
+        println!("{:#?}", results)
+    } else {
+        format::write_plain(
+            &mut io::stdout().lock(),
+            &results,
+            show_confidence,
+            long,
+            color,
+        )
+        .unwrap();
+    }
+}
+
+/// Write an ALTO file for every image under `root` that has word positions in the index, mirroring
+/// the directory structure in `output`
+fn export(db: &DB, root: &camino::Utf8Path, output: &PathBuf) -> Result<()> {
+    let images = db.images_with_words(root)?;
+    for (path, page) in &images {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let file = if page.is_empty() {
+            output.join(format!("{relative}.xml"))
+        } else {
+            output.join(format!("{relative}.{}.xml", page.replace(['/', '\\'], "_")))
+        };
+        // only single images have a size that can be read without decoding them
+        let size = if page.is_empty() {
+            imagesize::size(path)
+                .ok()
+                .map(|x| (x.width as i32, x.height as i32))
+        } else {
+            None
+        };
+        let words = db.words(path, page)?;
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("failed to create {parent}"))?;
+        }
+        fs::write(&file, ocr::alto_document(path.as_str(), size, &words))
+            .with_context(|| format!("failed to write {file}"))?;
+    }
+    eprintln!("Exported {} images to {}", images.len(), output);
+    Ok(())
+}
+
+/// Write every row of the index to stdout as a line of JSON
+fn export_records(db: &DB) -> Result<()> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let count = db.export_records(|record| {
+        serde_json::to_writer(&mut out, record)?;
+        writeln!(out)?;
+        Ok(())
+    })?;
+    out.flush()?;
+    eprintln!("Exported {count} rows");
+    Ok(())
+}
+
+/// Save the rows of a file from `ocrlocate db export`, or of stdin if it's `-`
+fn import_records(db: &mut DB, file: &PathBuf) -> Result<()> {
+    let input: Box<dyn BufRead> = match file.as_str() {
+        "-" => Box::new(io::stdin().lock()),
+        file => Box::new(io::BufReader::new(
+            fs::File::open(file).with_context(|| format!("failed to read {file}"))?,
+        )),
+    };
+    let mut count = 0;
+    for lines in &input.lines().enumerate().chunks(1000) {
+        let mut records = vec![];
+        for (number, line) in lines {
+            let line = line.with_context(|| format!("failed to read {file}"))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            records.push(
+                serde_json::from_str::<Record>(&line)
+                    .with_context(|| format!("invalid row on line {}", number + 1))?,
+            );
+        }
+        count += db.import_records(&records)?;
+    }
+    eprintln!("Imported {count} rows");
+    Ok(())
+}
+
+fn verify(db: &mut DB, repair: bool) -> Result<()> {
+    let report = db.verify()?;
+    println!("{} images, {} index entries", report.images, report.indexed);
+    if report.orphaned > 0 {
+        println!("{} index entries without an image", report.orphaned);
+    }
+    if report.missing > 0 {
+        println!("{} images missing from the index", report.missing);
+    }
+    for trigger in &report.missing_triggers {
+        println!("missing trigger: {trigger}");
+    }
+    if let Some(e) = &report.integrity_error {
+        println!("integrity check failed: {e}");
+    }
+    for e in &report.database_errors {
+        println!("database: {e}");
+    }
+    if report.is_ok() {
+        println!("ok");
+        return Ok(());
+    }
+    if !report.database_errors.is_empty() {
+        return Err(anyhow!(
+            "The database file is damaged, which --repair can't fix. `sqlite3 <database> .recover` can save what's left of it"
+        ));
+    }
+    if !repair {
+        return Err(anyhow!(
+            "The search index is inconsistent, run with --repair to rebuild it"
+        ));
+    }
+    db.repair()?;
+    if !db.verify()?.is_ok() {
+        return Err(anyhow!(
+            "The search index is still inconsistent after rebuilding it"
+        ));
+    }
+    println!("repaired");
+    Ok(())
+}
+
+/// OCR a random sample of the indexed files under `root` again with the settings they were indexed
+/// with, and report the pages whose text isn't the same as the stored text anymore
+#[cfg(feature = "engine")]
+fn verify_ocr(
+    db: &DB,
+    root: &camino::Utf8Path,
+    sample: usize,
+    extractors: &Registry,
+    ocr_options: &OcrOptions,
+    redact: &Redactor,
+    symbols: Symbols,
+) -> Result<()> {
+    let files = db.sample(root, sample)?;
+    let mut dir_configs = DirConfigs::new(root);
+    let (mut checked, mut drifted) = (0, 0);
+    for (path, properties) in &files {
+        if !path.exists() {
+            continue;
+        }
+        let options = match properties.get("settings") {
+            Some(settings) => ocr_options.with_settings(settings),
+            // rows from before the settings were saved, by the .ocrlocate.toml files of the
+            // directory like when indexing
+            None => {
+                let overrides = path
+                    .parent()
+                    .map(|dir| dir_configs.resolve(dir))
+                    .unwrap_or_default();
+                OcrOptions {
+                    lang: properties
+                        .get("lang")
+                        .cloned()
+                        .or(overrides.lang)
+                        .unwrap_or_else(|| ocr_options.lang.clone()),
+                    psm: overrides.psm.or(ocr_options.psm),
+                    scale: overrides.scale.or(ocr_options.scale),
+                    ..ocr_options.clone()
+                }
+            }
+        };
+        let results = match index::extract_file(path, extractors, &options, redact, symbols) {
+            Ok(x) => x,
+            Err(e) => {
+                report::error(
+                    report::Code::Extract,
+                    Some(path.as_str()),
+                    format!("{:#}", e),
+                );
+                continue;
+            }
+        };
+        checked += 1;
+        let mut changed = false;
+        for result in &results {
+            let stored = db.content(path, &result.page)?.unwrap_or_default();
+            if stored == result.contents {
+                continue;
+            }
+            changed = true;
+            let page = if result.page.is_empty() {
+                String::new()
+            } else {
+                format!(" (page {})", result.page)
+            };
+            println!(
+                "{:>3.0}% of words match: {}{}",
+                db::similarity(&stored, &result.contents) * 100.0,
+                path,
+                page
+            );
+        }
+        drifted += changed as usize;
+    }
+    println!("{checked} files checked, {drifted} changed");
+    if drifted > 0 {
+        return Err(anyhow!(
+            "OCR results drifted from the index, check the tesseract version and traineddata"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serve")]
+fn serve_auth(sub: &ArgMatches) -> Result<Option<serve::Auth>> {
+    if let Some(token) = sub.get_one::<String>("token") {
+        return Ok(Some(serve::Auth::Token(token.to_owned())));
+    }
+    let Some(auth) = sub.get_one::<String>("auth") else {
+        return Ok(None);
+    };
+    let (user, password) = auth
+        .split_once(':')
+        .ok_or_else(|| anyhow!("--auth needs to be user:password"))?;
+    Ok(Some(serve::Auth::Basic {
+        user: user.to_owned(),
+        password: password.to_owned(),
+    }))
+}
+
+/// Replace every `@FILE` argument with the lines of the file, one argument per line, so that long
+/// generated argument lists don't hit the OS limit. Blank lines are skipped. Arguments after `--`
+/// and ones that aren't an existing file are left alone, like a search for `@name`, and `@@` is
+/// an escaped `@`
+fn expand_args(args: impl IntoIterator<Item = OsString>) -> Result<Vec<OsString>> {
+    let mut expanded = vec![];
+    let mut literal = false;
+    for arg in args {
+        let file = arg
+            .to_str()
+            .and_then(|x| x.strip_prefix('@'))
+            .filter(|_| !literal);
+        if let Some(escaped) = file.filter(|x| x.starts_with('@')) {
+            expanded.push(escaped.into());
+            continue;
+        }
+        let Some(file) = file.filter(|x| !x.is_empty() && camino::Utf8Path::new(x).is_file())
+        else {
+            literal |= arg == "--";
+            expanded.push(arg);
+            continue;
+        };
+        let text = fs::read_to_string(file)
+            .with_context(|| format!("failed to read arguments from {file}"))?;
+        expanded.extend(
+            text.lines()
+                .filter(|x| !x.trim().is_empty())
+                .map(OsString::from),
+        );
+    }
+    Ok(expanded)
+}
+
 fn cli() -> Command {
     static DBPATH: OnceLock<PathBuf> = OnceLock::new();
+    static CONFIGPATH: OnceLock<PathBuf> = OnceLock::new();
 
-    DBPATH
-        .set(
-            PathBuf::try_from(
-                dirs::data_local_dir().expect("the user's local data dirctory should exist"),
-            )
-            .unwrap()
-            .join("ocrlocate/index.db"),
+    // which is called again to make the completion scripts
+    DBPATH.get_or_init(|| {
+        PathBuf::try_from(
+            dirs::data_local_dir().expect("the user's local data dirctory should exist"),
         )
-        .unwrap();
+        .unwrap()
+        .join("ocrlocate/index.db")
+    });
+    CONFIGPATH.get_or_init(|| {
+        PathBuf::try_from(dirs::config_dir().expect("the user's config directory should exist"))
+            .unwrap()
+            .join("ocrlocate/config.toml")
+    });
 
-    Command::new("ocrlocate")
+    let cmd = Command::new("ocrlocate")
         .version(crate_version!())
         .about(crate_description!())
+        .subcommand_negates_reqs(true)
         .args([
             arg!(-d --database <FILE> "Location of the index database")
                 .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath)
                 .env("OCRLOCATE_DB")
-                .default_value(DBPATH.get().unwrap().as_os_str()),
-            arg!(--lang <LANG> "Tesseract language code")
+                .default_value(DBPATH.get().unwrap().as_os_str())
+                .global(true),
+            arg!(--"db-retries" <N> "Times to try a database write while another process has it locked")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("5")
+                .global(true),
+            arg!(--"db-backoff" <MS> "Milliseconds to wait before retrying a locked database write, doubled every retry")
+                .value_parser(value_parser!(u64))
+                .default_value("100")
+                .global(true),
+            arg!(--"shared-index" "The database is shared by several users, so only show results they can read")
+                .long_help("The database is shared by several users, like on a family NAS. Saved files are tagged with the
+user that indexed them, and results are only shown if they aren't private to another user and the user
+searching can read the file.")
+                .env("OCRLOCATE_SHARED")
+                .global(true),
+            arg!(--private "With --shared-index, hide the files indexed now from other users").requires("shared-index"),
+            arg!(--everywhere "Search the whole index instead of only the files in the current directory")
+                .long_help("Search the whole index instead of only the files in the current directory, wherever ocrlocate is run.
+Also for similar-text, random and similar when it comes before them, like `ocrlocate --everywhere random`."),
+            arg!(--config <FILE> "Location of the config file")
+                .long_help(
+                    "Location of the config file. Use `[[extractor]]` tables to index other file types with an external
+command, whose standard output is stored as the text of the file:
+    [[extractor]]
+    name = \"scanner\"
+    extensions = [\"sc1\"]
+    command = [\"scan2txt\", \"{}\"]",
+                )
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath)
+                .env("OCRLOCATE_CONFIG")
+                .default_value(CONFIGPATH.get().unwrap().as_os_str())
+                .global(true),
+            arg!(--profile <NAME> "Use the database, language, excludes and roots of a profile in the config")
+                .long_help(
+                    "Use the database, language, excludes and roots of a `[profiles.<NAME>]` table in the config. Its
+roots are indexed instead of the current directory, and all of its database is searched. --database and --lang
+take priority over it:
+    [profiles.screenshots]
+    database = \"/home/me/.local/share/ocrlocate/screenshots.db\"
+    roots = [\"/home/me/Pictures/Screenshots\"]
+    lang = \"eng+jpn\"
+    exclude = [\"thumbnails\"]",
+                )
+                .global(true),
+            arg!(--lang <LANG> "Tesseract language code, or several joined with +")
                 .default_value("eng")
                 .long_help(
-                    "Tesseract language identifier. Language package must be installed (such as tesseract-ocr-eng). Only affects
-indexing of new images, so its recommended to delete the database when changed.",
+                    "Tesseract language identifier. Language package must be installed (such as tesseract-ocr-eng). Several
+languages can be combined with +, like eng+deu, which is slower but recognizes text that mixes them. Images that
+were OCRed with another language are OCRed again the next time they're indexed.",
                 ),
             arg!(index: -n --"no-index" "Do not index the directory before searching, only search an existing index").action(ArgAction::SetFalse),
             arg!(-r --rescan "When indexing, ignore file modified time and force rescan"),
             arg!(-t --threads <THREADS> "Set threads").value_parser(value_parser!(usize)),
+            arg!(--"cpu-set" <CPUS> "Pin the OCR threads to these cores, like 0-7 or 0,2,4, or `auto`").long_help(
+                "Pin the OCR threads to these cores, in the same format as taskset, like 0-7 or 0,2,4. One thread is
+started for every core unless --threads is given. `auto` uses every core except the first one, which is left
+for the thread that walks the directory and saves results."
+            ),
+            arg!(--"efficiency-cores-only" "Pin the OCR threads to the efficiency cores of an Intel hybrid cpu")
+                .conflicts_with("cpu-set"),
             arg!(-x --exclude <PATTERN> ... "Exclude directories and paths matching this pattern").long_help(
                 "Exclude directories and paths matching a `glob` pattern: https://docs.rs/glob/latest/glob/struct.Pattern.html
 Matched directories will not be descended into.  Excluded items will be removed from the index if --cleanup is specified."
             ),
             arg!(-m --"max-size" <RES> "Ignore images that are larger then [width]x[height]"),
-            arg!(-c --cleanup "Delete files that no longer exist in the current directory from the index").conflicts_with("subdirs"),
+            arg!(-c --cleanup "Delete files that no longer exist in the current directory from the index")
+                .long_help("Delete files that no longer exist in the current directory from the index, and the ones that are
+excluded. Files that weren't found while indexing are checked again before they're deleted, so the files of a
+directory that couldn't be read for a moment are kept.")
+                .conflicts_with("subdirs"),
             arg!(-v --verbose "Print debug messages"),
             arg!(-l --limit <LIMIT> "Max amount of results").value_parser(value_parser!(usize)).default_value("100"),
             arg!(subdirs: --"no-subdirs" "Do not recurse into subdirectories")
@@ -188,31 +1394,254 @@ Matched directories will not be descended into.  Excluded items will be removed
     To escape characters, include them in a set: [*], [[]
 `regex`: Runs the regular expression on every row instead of using the index
     Uses the rust regex syntax https://docs.rs/regex/latest/regex/index.html#syntax
-    This is the only search method that is case sensitive"#
-            ).value_parser(PossibleValuesParser::new(["simple", "match", "glob", "regex"]).map(|x| -> SearchType {
-                match x.to_ascii_lowercase().as_str() {
-                    "simple" => SearchType::Simple,
-                    "match" => SearchType::Match,
-                    "glob" => SearchType::Glob,
-                    #[cfg(feature = "regex")] "regex" => SearchType::Regex,
-                    #[cfg(not(feature = "regex"))] "regex" => panic!("This build was not compiled with regex support"),
-                    _ => unreachable!()
-                }
+    This is the only search method that is case sensitive, unless with --regex-ignore-case
+    It is stopped after --regex-timeout, since it reads every row
+`semantic`: Finds text with a meaning like that of the query, by its embedding of --embedding-model
+    Files are only found once their text was embedded, which indexing with the model does
+    Needs a build with the semantic feature"#
+            ).value_parser(PossibleValuesParser::new(["simple", "match", "glob", "regex", "semantic"]).map(|x| -> SearchType {
+                x.parse().unwrap_or_else(|e| panic!("{e}"))
             })),
+            arg!(--rank <RANK> "Order of the results")
+                .long_help("Order of the results. `bm25` puts the best matches first, `recency` puts the newest files first,
+and `mixed` makes matches count for less as the files get older, half as much after --half-life days")
+                .value_parser(["bm25", "recency", "mixed"])
+                .default_value("bm25"),
+            arg!(--"half-life" <DAYS> "Days it takes a match to count for half as much with --rank mixed")
+                .value_parser(value_parser!(f64))
+                .default_value("30"),
+            arg!(--sort <KEY> "What to sort the results by")
+                .long_help("What to sort the results by. `rank` orders them by --rank, `mtime` puts the newest files first,
+`path` sorts them alphabetically, and `size` puts the largest files first. Files indexed by older versions
+don't have a size until they change, and come last.")
+                .value_parser(["rank", "mtime", "path", "size"])
+                .default_value("rank"),
+            arg!(--reverse "Reverse the order of the results, like oldest first with --sort mtime"),
+            arg!(--"min-confidence" <CONF> "Hide results from images that tesseract recognized with less than this confidence, from 0 to 100")
+                .value_parser(value_parser!(f32)),
+            arg!(--"show-confidence" "Print the confidence of every result before it"),
+            arg!(--long "Print how long ago every result was modified and its size before it")
+                .long_help("Print how long ago every result was modified, like 3d ago, and the size of the file before it, to
+tell apart files with the same text. Files indexed by older versions don't have a size until they change,
+and show -.")
+                .conflicts_with("grep"),
+            arg!(--"snippet-length" <TOKENS> "Most words of text around the match to show for every result, up to 64")
+                .long_help("Most tokens of text around the match to show for every result, from 1 to 64. Tokens are words, or
+three letters with the trigram tokenizer. On a compact index it's twice the characters on each side of the match.")
+                .value_parser(value_parser!(u64).range(1..=64))
+                .default_value("64"),
+            arg!(--"highlight-open" <TEXT> "Text to put before every match in the results")
+                .long_help("Text to put before every match in the results, for scripts that need a marker that can't be in the
+text, like --highlight-open $'\\x02' --highlight-close $'\\x03'.")
+                .default_value("["),
+            arg!(--"highlight-close" <TEXT> "Text to put after every match in the results").default_value("]"),
+            arg!(--"group-by" <KEY> "List the results under each term of the query that they contain")
+                .value_parser(["term"]),
+            arg!(--grep "Print every line of the text that matches as path:line:text, like grep")
+                .long_help("Print every line of the text of the results that matches as path:line:text, like grep, for editors
+and other tools that read its output. Lines of the pages of documents start with the page. Matches are
+colored like with --color.")
+                .conflicts_with_all(["group-by", "output"]),
+            arg!(-A --after <NUM> "Lines of text to print after each matching line with --grep")
+                .value_parser(value_parser!(usize))
+                .requires("grep"),
+            arg!(-B --before <NUM> "Lines of text to print before each matching line with --grep")
+                .value_parser(value_parser!(usize))
+                .requires("grep"),
+            arg!(-C --context <NUM> "Lines of text to print before and after each matching line with --grep")
+                .value_parser(value_parser!(usize))
+                .requires("grep"),
+            arg!(--color <WHEN> "Color the matches and dim the paths of the results: auto, always or never")
+                .long_help("Color the matches and dim the paths of the results: auto, always or never. auto colors them when
+printing to a terminal, unless the NO_COLOR environment variable is set. The colors replace the markers of
+--highlight-open and --highlight-close.")
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto"),
+            arg!(--output <FORMAT> "Print results as text or json")
+                .long_help("Print results as text, or as a json array on stdout. With json, errors and warnings are printed as
+json lines on stderr, with a code, the stage of the work it happened in (setup, walk, decode, ocr, db or exec), the path
+if there is one, and a message. See the report module for the codes.")
+                .value_parser(["text", "json"])
+                .default_value("text"),
+            arg!(--exec <COMMAND> "Run a command on the file of every result instead of printing them, like --exec 'cp {} ~/found'")
+                .long_help("Run a command on the file of every result instead of printing them, like find -exec: --exec 'cp {}
+~/found'. {} is replaced with the path of the file, or the path comes last if the command has no {}. The command
+isn't run by a shell, its words are split at spaces and quotes keep words with spaces together. Files with several
+matching pages are run once, and ocrlocate fails if the command failed on any of them.")
+                .conflicts_with_all(["group-by", "grep", "interactive"]),
+            arg!(--open "Open the file of every result with its default program instead of printing them")
+                .long_help("Open the file of every result with its default program instead of printing them. When the results
+are in more than 10 files none are opened, unless --limit is given.")
+                .conflicts_with_all(["exec", "group-by", "grep", "interactive"]),
+            arg!(--fzf "Print the results as path, page and text separated by tabs, for searching with fzf")
+                .long_help("Print the results as the path, the page and the text separated by tabs, one result per line, for
+searching with fzf as the query is typed:
+    fzf --disabled --delimiter '\\t' --with-nth 3,1 --bind 'change:reload:ocrlocate --fzf -- {q}'
+The directory isn't indexed first. An empty query, and queries that fail like a match query that is still being
+typed, print no results and don't fail.")
+                .conflicts_with_all(["group-by", "grep", "interactive", "exec", "open", "long", "show-confidence", "near", "like"]),
+            arg!(--"query-from-stdin" "With --fzf, search for every line of stdin, and print an empty line after the results of each")
+                .requires("fzf")
+                .conflicts_with("QUERIES"),
+            arg!(-i --interactive "Pick a result to open, copy, OCR again or remove from the index")
+                .long_help("List the results with numbers, and ask which one to act on: open it, open its folder, copy its path
+or text to the clipboard, OCR it again, remove it from the index, or exclude its directory, which sets skip in
+its .ocrlocate.toml and removes the directory from the index.")
+                .conflicts_with_all(["group-by", "output", "grep"]),
             arg!(--binarization <METHOD> "Which leptonica thresholding method to use")
-                .value_parser(PossibleValuesParser::new(["Otsu", "LeptonicaOtsu", "Sauvola"]).map(|x| -> Binarization {
+                .long_help("Which leptonica thresholding method to use. auto OCRs every image with each of the others and keeps
+the result with the highest mean confidence, which takes three to four times as long. The method that won is
+saved as the binarization property of the file.")
+                .value_parser(PossibleValuesParser::new(["Otsu", "LeptonicaOtsu", "Sauvola", "auto"]).map(|x| -> Binarization {
                     match x.as_str() {
                         "Otsu" => Binarization::Otsu,
                         "LeptonicaOtsu" => Binarization::LeptonicaOtsu,
                         "Sauvola" => Binarization::Sauvola,
+                        "auto" => Binarization::Auto,
                         _ => unreachable!()
                     }
                 })),
             arg!(--psm <PSM> "Page segmentation mode").long_help(r#"Page segmentation mode
 Documentation of values here: https://tesseract-ocr.github.io/tessdoc/ImproveQuality.html#page-segmentation-method"#
             ).value_parser(value_parser!(i64).range(0..=13)).default_value("11"),
-            // TODO: scale by max size, scale to res, etc
             arg!(--scale <FRAC> "Fraction to scale all images down by before applying ocr").value_parser(value_parser!(f32)),
+            arg!(--"char-whitelist" <CHARS> "Only recognize these characters, like 0123456789 for numeric codes"),
+            arg!(--"char-blacklist" <CHARS> "Never recognize these characters")
+                .long_help(format!("Never recognize these characters. Replaces the default blacklist, {:?}, which are characters tesseract
+mostly finds in noise. Give an empty string to allow every character.", ocr::DEFAULT_BLACKLIST)),
+            arg!(--tessdata <DIR> "Directory of the traineddata files, instead of TESSDATA_PREFIX")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::DirPath)
+                .env("OCRLOCATE_TESSDATA"),
+            arg!(--model <MODEL> "Use the fast or the best variant of the traineddata")
+                .long_help("Use the fast or the best variant of the traineddata, from a tessdata_fast or tessdata_best directory
+next to the --tessdata directory, or a fast or best directory inside of it. Without --tessdata they are
+looked for next to TESSDATA_PREFIX and where packages install traineddata. fast is a few times quicker,
+best is slower and more accurate.")
+                .value_parser(PossibleValuesParser::new(["fast", "best"]).map(|x| match x.as_str() {
+                    "fast" => Model::Fast,
+                    "best" => Model::Best,
+                    _ => unreachable!(),
+                })),
+            arg!(--"user-words" <FILE> "Add the words in this file, one per line, to tesseract's dictionary")
+                .long_help("Add the words in this file, one per line, to tesseract's dictionary, so that words it wouldn't
+expect, like product names or usernames, are recognized better.")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath),
+            arg!(--"user-patterns" <FILE> "Add the patterns in this file, one per line, to tesseract's dictionary")
+                .long_help(r"Add the patterns in this file, one per line, to tesseract's dictionary, for codes like SKUs.
+\d is a digit, \c a letter, \a a lowercase letter, \A an uppercase letter, \p punctuation, and \*
+repeats the previous class. For example \A\A\A-\d\d\d\d matches ABC-1234.")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath),
+            arg!(--dpi <DPI> "Resolution tesseract assumes for every image")
+                .long_help("Resolution tesseract assumes for every image, instead of the one in the file. Screenshots usually don't
+say their resolution, and tesseract's guess for them can make it recognize worse. 70 to 2400.")
+                .value_parser(value_parser!(u32).range(70..=2400)),
+            arg!(--resize <SIZE> "Scale every image to a size before OCR, like 2000px, 300dpi or 0.5")
+                .long_help("Scale every image before OCR: 2000px scales the longest side to 2000 pixels, 300dpi scales images to a
+resolution of 300 dpi if the file says what its resolution is, and 0.5 is the same as --scale 0.5. The scale of
+each image is saved as its scale property, and printed with --verbose. A scale in a .ocrlocate.toml takes priority.")
+                .value_parser(|x: &str| x.parse::<Resize>())
+                .conflicts_with("scale"),
+            arg!(--osd [SCRIPTS] "Turn images upright and pick their language by their script, like Latin=eng,Japanese=jpn")
+                .long_help("Detect the rotation and script of every image with tesseract's orientation and script detection,
+which needs the osd language package. Images are turned upright before OCR, and scripts that are given a
+language, like --osd=Latin=eng,Japanese=jpn, are OCRed with it instead of --lang. Script names are the ones tesseract
+uses, such as Latin, Cyrillic, Arabic, Han, Hangul and Japanese. The stored word positions are of the turned image.")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("")
+                .value_parser(parse_scripts),
+            arg!(--denoise "Remove speckles and JPEG artifacts before OCR")
+                .long_help("Run a median filter over every image before OCR, which removes speckles and the blocky artifacts of
+heavily compressed JPEGs without blurring the letters. Images are turned grayscale for it. It can make small
+or thin text worse, so it's best used on noisy images only."),
+            arg!(--trim "Cut off the borders of images that are one solid color, like letterboxing, before OCR")
+                .long_help("Cut off the rows and columns at the edges of every image that are one solid color before OCR, like
+the black bars of letterboxed video frames or the empty margins around a window, which confuse tesseract's layout
+analysis and slow it down. The stored word positions are still of the whole image."),
+            arg!(--"crop-edges" <EDGES> "Cut off parts of the edges of images before OCR, like bottom:40px,top:5%")
+                .long_help("Cut off fixed parts of the edges of every image before OCR, like the taskbar or the title bar of
+full-screen captures: bottom:40px cuts off the bottom 40 pixels, and top:5% the top 5 percent of the height.
+Sides are top, right, bottom and left, separated by commas. With --trim, borders are trimmed from what is left.
+Images that are smaller than the edges are OCRed whole.")
+                .value_parser(|x: &str| x.parse::<Edges>()),
+            arg!(--"auto-rotate" "Turn images that are sideways or upside down upright before OCR")
+                .long_help("Detect the rotation of every image with tesseract's orientation detection, which needs the osd
+language package, and turn it upright before OCR. --osd does this too. The stored word positions are of the
+turned image."),
+            arg!(--"detect-text" "Skip the OCR of images that a quick layout analysis finds no text in, like most photos")
+                .long_help("Run tesseract's layout analysis on every image first, which is much faster than OCR, and only OCR
+the images that it finds a line of text in. Most photos have none, so indexing a photo library is a lot
+faster. Skipped images are indexed without text and with a `textless` property, and are only looked at
+again when they change or with --rescan. Only tesseract uses it."),
+            arg!(--annotations "OCR colorful images with little text again, for the text that screenshot tools draw on them")
+                .long_help("When an image has little text but a lot of color, OCR it again once for how saturated every pixel is
+and once for each of its red, green and blue channels, with the contrast stretched, and add the text that
+wasn't found the first time. This finds the arrows and notes that screenshot tools like Flameshot draw in
+bright colors, which tesseract often misses. Images that get text this way have an `annotations` property.
+It has no effect with --denoise, which turns images grayscale, and only tesseract uses it."),
+            arg!(--tables "Store text as table rows, with cells separated by tabs").long_help(
+                "Rebuild rows of tables from the positions of the recognized words, instead of storing the text in the
+order tesseract found it. Words on the same row are stored on one line, and cells that are far apart are
+separated by tabs, so a value can be found together with the label of its row."
+            ),
+            arg!(--normalize <STEPS> "How recognized text is cleaned up, like paragraphs,squash,hyphens")
+                .long_help("How recognized text is cleaned up before it's stored, as a list of steps like paragraphs,squash,hyphens.
+`paragraphs` keeps one empty line between paragraphs, which are otherwise removed. `squash` turns runs of
+spaces into one space. `hyphens` joins words that were hyphenated at the end of a line. `default` is none of
+them. Tables of --tables are stored as they are. Text is always stored in Unicode's NFC form, and with
+`nfkc` in NFKC, so that full-width letters and digits of Japanese and Chinese text are found by searches for
+plain ones. Files that were already indexed keep their text until
+they change or --rescan is used.")
+                .value_parser(|x: &str| x.parse::<Normalize>()),
+            arg!(--"min-word-confidence" <CONF> "Leave words that tesseract is less confident about than this out of the index, from 0 to 100")
+                .long_help("Leave words that tesseract is less confident about than this out of the index, from 0 to 100.
+Noise in photos is often recognized as short nonsense words with a low confidence, which then match searches.")
+                .value_parser(value_parser!(f32)),
+            arg!(--tokenizer <NAME> "Tokenizer of the search index").long_help(
+                "Tokenizer of the search index. Changing it rebuilds the index of an existing database.
+`trigram`: The default, splits text into groups of three characters
+`script`: Splits text into groups of grapheme clusters, using pairs for Korean, Thai and CJK text where
+    three characters are often too long to find a word
+`words`: Splits text into words like sqlite's unicode61 tokenizer, and CJK text into pairs of characters.
+    Finds words of one or two letters and makes a smaller index, but only matches whole words, or their
+    start with `*` in a match query, and can't find a single CJK character
+`unicode61`: sqlite's own tokenizer of words, which doesn't split CJK text"
+            ).value_parser(PossibleValuesParser::new(["trigram", "script", "words", "unicode61"]).map(|x| -> Tokenizer {
+                x.parse().unwrap_or_else(|e| panic!("{e}"))
+            })),
+            arg!(--diacritics <MODE> "Whether searches tell accented letters apart, like é from e")
+                .long_help("Whether searches tell accented letters apart. With `ignore`, the search index folds accented
+Latin letters into the ones without accents, in the text and in queries, so that `cafe` finds `café`.
+`keep` is the default. Changing it rebuilds the index of an existing database. Glob and regex searches
+always compare the text as it is.")
+                .value_parser(["keep", "ignore"]),
+            arg!(--"max-duration" <TIME> "Stop indexing new files after this long, like 90s, 15m or 2h")
+                .long_help("Stop indexing new files after this long, like 90s, 15m or 2h. Files that are being scanned
+when the time runs out are finished and saved, and the next run continues where this one stopped.")
+                .value_parser(parse_duration),
+            arg!(--"count-first" "Count the files to index before starting, so the progress bar shows an accurate ETA")
+                .long_help("Count the files to index before starting, so the progress bar shows an accurate ETA. Without it,
+the total of the progress bar grows while the directory is walked. Counting takes an extra walk over the
+directory, which can be slow on network drives."),
+            arg!(--"strip-symbols" "Remove emoji, arrows, box drawing and icon font glyphs from the text before saving it")
+                .long_help("Remove emoji, arrows, box drawing and icon font glyphs from the text before saving it, since they only
+add noise to the search index. Changing this OCRs the files that it changes again."),
+            arg!(--transliterate "Replace symbols and typographic punctuation with ASCII look-alikes, like → with ->")
+                .long_help("Replace symbols and typographic punctuation with ASCII look-alikes before saving the text, like → with
+->, curly quotes with straight ones and ﬁ with fi. Changing this OCRs the files that it changes again."),
+            arg!(--"no-redact" "Save text without applying the redact patterns of the config"),
+            arg!(--prefetch <MB> "Read upcoming files ahead of OCR, up to this many megabytes at a time")
+                .long_help("Read the files of the next chunk into the page cache while the current chunk is OCRed, up to this
+many megabytes, so the OCR doesn't wait on slow disks like hard drives and network shares. Files are read
+one chunk ahead, see --chunk-size.")
+                .value_parser(value_parser!(u64)),
+            arg!(--notes "Only index images that markdown and html notes embed, and show the notes in results")
+                .long_help("Only index the images that markdown and html notes in the directory embed, with ![](image.png),
+Obsidian's ![[image.png]] or <img src=\"image.png\">, and show the notes that embed a result under it. Images
+are found relative to the note, to the current directory, or for Obsidian links by their name anywhere in it."),
             arg!(--pwd <PWD> "Set pwd").hide(true),
             arg!(--"scan-limit" <LIMIT> "Set max amount of scanned files")
                 .hide(true)
@@ -222,6 +1651,485 @@ Documentation of values here: https://tesseract-ocr.github.io/tessdoc/ImproveQua
                 .value_parser(value_parser!(usize))
                 .default_value("900"),
             arg!(--"dump-scan" "Dump the OCR result of one file and exit"),
-            arg!(<QUERIES> ... "Strings to search for"),
+            arg!(--format <FORMAT> "Output format of --dump-scan")
+                .long_help("Output format of --dump-scan. `hocr` is a html document with the position and confidence of every
+word, which can be used with tools like hocr-tools or ocrmypdf. `alto` is an ALTO XML document")
+                .value_parser(["text", "hocr", "alto"])
+                .default_value("text")
+                .requires("dump-scan"),
+            arg!(--near <WORDS> "Only find images with these words close together, like \"total tax\"")
+                .long_help("Only find images with these words close together, like --near \"total tax\", along with the queries if
+there are any. Needs simple or match queries."),
+            arg!(--distance <N> "Most words between the words of --near")
+                .long_help("Most words between the words of --near. The default trigram and script tokenizers count characters, so
+with them it's about as many words.")
+                .value_parser(value_parser!(usize))
+                .default_value("10")
+                .requires("near"),
+            arg!(--any "Find files with any of the queries, the ones with the most of them first")
+                .long_help("Find files with any of the queries instead of all of them as one phrase, like --any invoice receipt,
+and list the files with the most of them first. Quote a query to keep its words together, like --any \"total tax\"
+receipt. Needs simple queries."),
+            arg!(--not <TERM> "Leave out files with this text, like a watermark that is on every screenshot")
+                .long_help("Leave out files with this text, like a watermark that is on every screenshot. Can be given several
+times, and a file with any of them is left out. Works with simple, match, --any and semantic queries.")
+                .action(ArgAction::Append),
+            arg!(--"newer-than" <TIME> "Only find files modified since a date like 2024-01-01, or in the last 3d or 2w")
+                .long_help("Only find files modified since a date like 2024-01-01 in local time, or in a time before now like
+12h, 3d or 2w. Files are found by the modification time they had when they were indexed."),
+            arg!(--"older-than" <TIME> "Only find files modified before a date like 2024-01-01, or more than 3d or 2w ago")
+                .long_help("Only find files modified before a date like 2024-01-01 in local time, or longer ago than 12h, 3d
+or 2w."),
+            arg!(--"lang-filter" <LANG> "Only find files that were OCRed with this language, like jpn")
+                .long_help("Only find files that were OCRed with this language, like jpn, or the language that --osd picked
+for their script. Files OCRed with several languages like eng+jpn are found by each of them. Can be given several
+times, and files with any of them are found. Helps when a query is valid text in several scripts.")
+                .action(ArgAction::Append),
+            arg!(--like <IMAGE> "Find files with the text of this image, which is OCRed now")
+                .long_help("Find files with the text of this image, which is OCRed now, like the original of a screenshot that
+was compressed again. Its most distinctive words are searched for like with similar-text, and the files with
+the most of them come first.")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath)
+                .conflicts_with_all(["QUERIES", "near", "any"]),
+            arg!(<QUERIES> ... "Strings to search for")
+                .required(false)
+                .required_unless_present_any(["near", "like", "query-from-stdin"]),
+        ]);
+
+    #[cfg(feature = "cloud")]
+    let cmd = cmd.args([
+        arg!(--cloud <SERVICE> "Cloud OCR service to send images to")
+            .long_help("Cloud OCR service to send images to: google for Google Cloud Vision, or azure for the Read API of
+Azure AI Vision, which needs --cloud-endpoint too. Every image is uploaded, and most services charge for each.")
+            .env("OCRLOCATE_CLOUD")
+            .requires("cloud-key")
+            .value_parser(PossibleValuesParser::new(["google", "azure"]).map(|x| match x.as_str() {
+                "google" => ocr::Provider::Google,
+                "azure" => ocr::Provider::Azure,
+                _ => unreachable!(),
+            })),
+        arg!(--"cloud-key" <KEY> "API key of the cloud OCR service").env("OCRLOCATE_CLOUD_KEY"),
+        arg!(--"cloud-endpoint" <URL> "URL of the cloud OCR service, like https://name.cognitiveservices.azure.com")
+            .env("OCRLOCATE_CLOUD_ENDPOINT"),
+        arg!(--"cloud-rate" <N> "Most requests per second to send to the cloud OCR service")
+            .value_parser(value_parser!(f32))
+            .default_value("5"),
+    ]);
+
+    #[cfg(feature = "onnx")]
+    let cmd = cmd.args([
+        arg!(--"onnx-models" <DIR> "Directory with the det.onnx, rec.onnx and keys.txt of the ONNX OCR models")
+            .env("OCRLOCATE_ONNX_MODELS")
+            .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::DirPath),
+        arg!(--gpu [PROVIDER] "Run the ONNX models on a GPU with cuda, directml or coreml")
+            .long_help("Run the ONNX models on a GPU, with the cuda, directml or coreml execution provider of onnxruntime, or
+the first of them that works with --gpu or --gpu=auto. onnxruntime has to be a build with the provider, like the gpu
+package for CUDA. If the provider can't be used, the models run on the CPU.")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("auto")
+            .value_parser(PossibleValuesParser::new(["auto", "cuda", "directml", "coreml"]).map(|x| match x.as_str() {
+                "auto" => ocr::Gpu::Auto,
+                "cuda" => ocr::Gpu::Cuda,
+                "directml" => ocr::Gpu::DirectMl,
+                "coreml" => ocr::Gpu::CoreMl,
+                _ => unreachable!(),
+            })),
+    ]);
+
+    #[cfg(feature = "regex")]
+    let cmd = cmd.args([
+        arg!(--"regex-ignore-case" "Match the letters of regex searches regardless of their case"),
+        arg!(--"regex-multiline" "Make ^ and $ of regex searches match at the start and end of every line"),
+        arg!(--"regex-timeout" <TIME> "Stop regex searches that take longer than this, like 30s or 2m, or 0 to let them finish")
+            .value_parser(parse_duration)
+            .default_value("30s"),
+    ]);
+
+    #[cfg(feature = "semantic")]
+    let cmd = cmd.arg(
+        arg!(--"embedding-model" <DIR> "Directory with the model.onnx and tokenizer.json of a sentence embedding model")
+            .long_help("Directory with the model.onnx and tokenizer.json of a sentence embedding model, like the ONNX export
+of all-MiniLM-L6-v2, for --search-type semantic. Indexing, add and import with it embed the text of the files
+that don't have an embedding of the model yet. Embeddings are saved by the name of the directory, so keep it
+when updating the model.")
+            .env("OCRLOCATE_EMBEDDING_MODEL")
+            .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::DirPath)
+            .global(true),
+    );
+
+    #[cfg(feature = "clip")]
+    let cmd = cmd
+        .arg(
+            arg!(--"clip-model" <DIR> "Directory with the model.onnx of the image encoder of a CLIP model")
+                .long_help("Directory with the model.onnx of the image encoder of a CLIP model, like the vision model of the ONNX
+export of clip-vit-base-patch32, for `ocrlocate similar`. Indexing with it embeds the images that don't have an
+embedding of the model yet. Embeddings are saved by the name of the directory, so keep it when updating the
+model.")
+                .env("OCRLOCATE_CLIP_MODEL")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::DirPath)
+                .global(true),
+        )
+        .subcommand(
+            Command::new("similar")
+                .about("Find images in the current directory that look like another image")
+                .long_about("Find images in the current directory that look like another image, by the embeddings of
+--clip-model. The image doesn't have to be indexed, and only images that were indexed with the model are found.")
+                .args([
+                    arg!(<IMAGE> "An image").value_hint(ValueHint::FilePath),
+                    arg!(-l --limit <LIMIT> "Max amount of results")
+                        .value_parser(value_parser!(usize))
+                        .default_value("100"),
+                ]),
+        );
+
+    let cmd = cmd.subcommand(
+        Command::new("similar-text")
+            .about("Find files in the current directory with text like that of another file")
+            .args([
+                arg!(<PATH> "An indexed file").value_hint(ValueHint::FilePath),
+                arg!(-l --limit <LIMIT> "Max amount of results")
+                    .value_parser(value_parser!(usize))
+                    .default_value("100"),
+                arg!(-k --terms <N> "How many of the most distinctive words of the file to search for")
+                    .value_parser(value_parser!(usize))
+                    .default_value("10"),
+            ]),
+    );
+
+    let cmd = cmd.subcommand(
+        Command::new("random")
+            .about("List random indexed files in the current directory")
+            .long_about("List random indexed files in the current directory, to come across old ones again or to check the
+OCR of a sample of a big index. With --matching, only files whose text matches the query, which is
+read like the queries of a search, including --search-type.")
+            .args([
+                arg!(-m --matching <QUERY> "Only pick files whose text matches this query"),
+                arg!(-n --count <N> "How many files to pick")
+                    .value_parser(value_parser!(usize))
+                    .default_value("10"),
+            ]),
+    );
+
+    #[cfg(feature = "ocr")]
+    let cmd = cmd.subcommand(
+        Command::new("crop")
+            .about("Save the part of an image around some text as a png")
+            .long_about("Save the part of an image around some text as a png. Uses the word positions stored in the
+index, or scans the image again if there are none.")
+            .args([
+                arg!(<QUERY> "Text to find in the image"),
+                arg!(<PATH> "The image").value_hint(ValueHint::FilePath),
+                arg!(-o --output <FILE> "Where to write the png, default is [name]-crop.png in the current directory")
+                    .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath),
+                arg!(--padding <PX> "Pixels of space to leave around the text")
+                    .value_parser(value_parser!(i32).range(0..))
+                    .default_value("16"),
+            ]),
+    );
+
+    #[cfg(feature = "engine")]
+    let cmd = cmd.subcommand(
+        Command::new("verify-ocr")
+            .about("OCR a random sample of indexed files again, and report any that differ from the index")
+            .long_about("OCR a random sample of the indexed files in the current directory again, and report any that differ
+from the stored text. Each file is OCRed in the language it was indexed with, and the other OCR options
+should be the same as when indexing. Differences point to a changed tesseract or leptonica, broken
+traineddata, or failing hardware. Exits with an error if any file changed.")
+            .arg(
+                arg!(--sample <N> "How many files to check")
+                    .value_parser(value_parser!(usize))
+                    .default_value("20"),
+            ),
+    );
+
+    let cmd = cmd.subcommand(
+        Command::new("add")
+            .about("Index text that was recognized somewhere else for a file")
+            .long_about("Index text that was recognized somewhere else for a file, like by a cloud OCR batch job, instead
+of OCRing it. The text is redacted and has the symbol options applied like OCRed text, and replaces
+what was indexed for the file. Indexing skips the file until it changes.")
+            .args([
+                arg!(<PATH> "The file the text is of").value_hint(ValueHint::FilePath),
+                arg!(--"text-from" <FILE> "File with the text, or - to read it from stdin")
+                    .required(true)
+                    .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath),
+                arg!(--json "The text is a JSON array of pages, with `page`, `contents`, and optionally `properties` and `words`"),
+            ]),
+    );
+
+    let cmd = cmd.subcommand(
+        Command::new("import")
+            .about("Index the text that other OCR tools made, instead of OCRing the files again")
+            .long_about("Index the text that other OCR tools made, instead of OCRing the files again. The text is redacted
+and has the symbol options applied like OCRed text, and files that are already indexed and haven't changed
+are left alone. Indexing skips the imported files until they change.")
+            .args([
+                arg!(--from <SOURCE> "Tool that made the text")
+                    .long_help("Tool that made the text: ocrmypdf-sidecars for the --sidecar files of ocrmypdf next to their PDFs,
+as name.txt or name.pdf.txt, paperless for a directory made by the document_exporter of paperless-ngx, or
+rga-cache for the cache of ripgrep-all. Text in the rga cache of files that
+changed since is skipped.")
+                    .required(true)
+                    .value_parser(PossibleValuesParser::new(["ocrmypdf-sidecars", "paperless", "rga-cache"]).map(|x| match x.as_str() {
+                        "ocrmypdf-sidecars" => import::Source::OcrmypdfSidecars,
+                        "paperless" => import::Source::Paperless,
+                        "rga-cache" => import::Source::RgaCache,
+                        _ => unreachable!(),
+                    })),
+                arg!(<PATH> "Directory with the sidecars or the export, or the rga cache, like ~/.cache/ripgrep-all")
+                    .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::AnyPath),
+            ]),
+    );
+
+    let cmd = cmd.subcommand(
+        Command::new("export")
+            .about("Write an ALTO XML file for every indexed image in the current directory")
+            .long_about("Write an ALTO XML file for every indexed image in the current directory, using the word positions
+stored in the index. Files are named after the image with .xml added, and pages of documents get the
+name of the page added too. Images indexed before word positions were stored need --rescan first.")
+            .arg(
+                arg!(-o --output <DIR> "Directory to write the files to")
+                    .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::DirPath)
+                    .default_value("alto"),
+            ),
+    );
+
+    let cmd = cmd.subcommand(
+        Command::new("native-host")
+            .about("Answer the requests of a browser extension, as its native messaging host")
+            .long_about("Answer the requests of a browser extension over stdin and stdout, as a native messaging host of
+Chrome or Firefox. Browsers start the host without arguments of their own, so the host manifest has to point
+at a script that runs `ocrlocate native-host`. Extensions can search the index, get the text of a file and
+add files to it, see the README.")
+            .args([
+                arg!(--root <DIR> "Only search files under this directory, instead of the whole index")
+                    .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::DirPath),
+                arg!(-l --limit <LIMIT> "Max amount of results per search")
+                    .value_parser(value_parser!(usize))
+                    .default_value("100"),
+                // like the origin of the extension, and --parent-window on Windows
+                arg!([CALLER] ... "Arguments that browsers pass to the host, which are ignored")
+                    .allow_hyphen_values(true)
+                    .hide(true),
+            ]),
+    );
+
+    let cmd = cmd.subcommand(
+        Command::new("db")
+            .about("Maintain the index database")
+            .subcommand_required(true)
+            .subcommand(
+                Command::new("verify")
+                    .visible_alias("check")
+                    .about("Check the database file, and that the search index matches the stored text")
+                    .long_about("Check the database file with PRAGMA integrity_check, and that the search index matches the stored
+text with the integrity check of fts5 and by looking up every row in both. --repair rebuilds the search index and
+restores its triggers, but can't fix a damaged database file.")
+                    .arg(arg!(--repair "Rebuild the search index if it doesn't match")),
+            )
+            .subcommand(
+                Command::new("optimize")
+                    .about("Merge the search index, empty the write-ahead log and vacuum the database")
+                    .long_about("Merge the segments of the search index, move the write-ahead log into the database, and rebuild
+the database without its free pages. This makes the database smaller and searches faster after a large indexing
+run, or after many files were deleted. Rebuilding the database needs about as much free disk space as it takes."),
+            )
+            .subcommand(
+                Command::new("export")
+                    .about("Print every row of the index as a line of JSON, to back it up or move it to another machine")
+                    .long_about("Print every row of the index as a line of JSON, to back it up as text, move it to another machine,
+or look at it with jq. Every line has the path, page, modtime, size, content, properties, confidence and words of
+a page of a file."),
+            )
+            .subcommand(
+                Command::new("import")
+                    .about("Save the rows printed by `db export`, replacing the ones of the same pages")
+                    .long_about("Save the rows printed by `db export`, replacing the ones of the same pages. Files whose modified
+time is the same as in the rows aren't OCRed again when they're indexed.")
+                    .arg(
+                        arg!(<FILE> "File with the rows, or - for stdin")
+                            .value_parser(value_parser!(PathBuf))
+                            .value_hint(ValueHint::FilePath),
+                    ),
+            )
+            .subcommand(
+                Command::new("stats")
+                    .about("Show how many files are indexed, how large the text and search index are, and their extensions")
+                    .arg(arg!(--json "Print the statistics as a JSON object, with the modified times in seconds since the epoch")),
+            )
+            .subcommand(
+                Command::new("init")
+                    .about("Create the database with these search index options, or rebuild the index of an existing one")
+                    .long_about("Create the database with these search index options, or rebuild the index of an existing one.
+The options are kept in the database, and options that aren't given go back to their defaults.")
+                    .args([
+                        arg!(--tokenizer <NAME> "Tokenizer of the search index, see ocrlocate --help")
+                            .value_parser(PossibleValuesParser::new(["trigram", "script", "words", "unicode61"]).map(|x| -> Tokenizer {
+                                x.parse().unwrap_or_else(|e| panic!("{e}"))
+                            }))
+                            .default_value("trigram"),
+                        arg!(--diacritics <MODE> "Whether searches tell accented letters apart, see ocrlocate --help")
+                            .value_parser(["keep", "ignore"])
+                            .default_value("keep"),
+                        arg!(--prefix <LENGTHS> "Index prefixes of words of these lengths, like 2,3")
+                            .long_help("Index prefixes of words of these lengths, like 2,3. This makes match queries like `rece*`
+faster with the `words` and `unicode61` tokenizers, at the cost of a larger index.")
+                            .value_delimiter(',')
+                            .value_parser(value_parser!(usize)),
+                        arg!(--compact "Leave the positions of words out of the search index, which makes it a few times smaller")
+                            .long_help("Leave the positions of words out of the search index, which makes it a few times smaller.
+Simple searches look up each part of the query and then check the text for all of it, which is slower for
+common words. Glob and regex searches work as before, but match queries, --near and similar-text mostly don't."),
+                        arg!(--compress "Store the text of files compressed with zstd")
+                            .long_help("Store the text of files compressed with zstd. Text that doesn't get smaller, which is often
+the case for a few words, is stored as it is. Other programs that read the database need to decompress it."),
+                    ]),
+            ),
+    );
+
+    #[cfg(feature = "serve")]
+    let cmd = cmd.subcommand(
+        Command::new("serve")
+            .about("Serve a search page for the index of the current directory")
+            .args([
+                arg!(--listen <ADDR> "Address to listen on").default_value("127.0.0.1:8080"),
+                arg!(-l --limit <LIMIT> "Max amount of results per search")
+                    .value_parser(value_parser!(usize))
+                    .default_value("100"),
+                arg!(--auth <USER_PASSWORD> "Require HTTP basic auth, given as user:password")
+                    .env("OCRLOCATE_AUTH"),
+                arg!(--token <TOKEN> "Require a bearer token, or a token query parameter")
+                    .env("OCRLOCATE_TOKEN")
+                    .conflicts_with("auth"),
+                arg!(--"allow-writes" "Allow requests that aren't GET or HEAD, and let the server write to the database"),
+                arg!(--"maintain-after" <TIME> "Maintain the database once no request came for this long, like 10m")
+                    .long_help("Maintain the database once no request came for this long, like 10m: remove files under the
+current directory that don't exist anymore, optimize the search index and checkpoint the write-ahead log.
+This runs once after every busy period, and what it did is shown at /api/status.")
+                    .value_parser(parse_duration),
+            ]),
+    );
+    #[cfg(feature = "tls")]
+    let cmd = cmd.mut_subcommand("serve", |sub| {
+        sub.args([
+            arg!(--"tls-cert" <FILE> "Serve https with this PEM certificate chain")
+                .value_hint(ValueHint::FilePath)
+                .requires("tls-key"),
+            arg!(--"tls-key" <FILE> "The PEM private key for --tls-cert")
+                .value_hint(ValueHint::FilePath)
+                .requires("tls-cert"),
         ])
+    });
+
+    let cmd = cmd.subcommand(
+        Command::new("completions")
+            .about("Print the completion script of a shell")
+            .long_about("Print the completion script of a shell, like `ocrlocate completions bash >
+~/.local/share/bash-completion/completions/ocrlocate`. --lang completes the languages that are installed when the
+script is made, in --tessdata or where tesseract looks for them, so make it again after installing more.")
+            .arg(arg!(<SHELL> "The shell").value_parser(value_parser!(Shell))),
+    );
+
+    cmd
+}
+
+/// Write the completion script of `shell`, with the languages installed in `tessdata` as the values
+/// of --lang
+fn completions(shell: Shell, tessdata: Option<&camino::Utf8Path>, out: &mut impl Write) {
+    let mut cmd = cli();
+    // clap keeps static strings, and the script is made once
+    let langs: Vec<&'static str> = ocr::installed_langs(tessdata)
+        .into_iter()
+        .map(|x| &*x.leak())
+        .collect();
+    if !langs.is_empty() {
+        cmd = cmd.mut_arg("lang", |x| x.value_parser(langs));
+    }
+    clap_complete::generate(shell, &mut cmd, "ocrlocate", out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completion_script() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("jpn.traineddata"), "")?;
+        let dir = PathBuf::try_from(dir.path().to_path_buf())?;
+        let mut out = vec![];
+        completions(Shell::Bash, Some(&dir), &mut out);
+        let script = String::from_utf8(out)?;
+        assert!(script.contains("--database"));
+        assert!(script.contains("jpn"));
+        Ok(())
+    }
+
+    #[test]
+    fn help_text() {
+        // like an escape in a normal string where a raw one was meant
+        fn check(command: &Command) {
+            for arg in command.get_arguments() {
+                for help in [arg.get_help(), arg.get_long_help()].into_iter().flatten() {
+                    let help = help.to_string();
+                    assert!(
+                        !help.chars().any(|c| c.is_control() && c != '\n'),
+                        "{}: {help:?}",
+                        arg.get_id()
+                    );
+                }
+            }
+            for sub in command.get_subcommands() {
+                check(sub);
+            }
+        }
+        check(&cli());
+    }
+
+    #[test]
+    fn argument_file() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let file = temp.path().join("args.txt");
+        fs::write(&file, "--exclude\n*/node_modules\n\n--exclude\r\n*/.git\n")?;
+        let at = format!("@{}", file.display());
+        let args = ["ocrlocate", "-v", &at, "receipt", "--", &at];
+        let expanded = expand_args(args.map(OsString::from))?;
+        assert_eq!(
+            expanded,
+            [
+                "ocrlocate",
+                "-v",
+                "--exclude",
+                "*/node_modules",
+                "--exclude",
+                "*/.git",
+                "receipt",
+                "--",
+                &at
+            ]
+        );
+        let matches = cli().try_get_matches_from(expanded)?;
+        assert_eq!(matches.get_many::<String>("exclude").unwrap().count(), 2);
+
+        // a search for a handle, which isn't a file, and an escaped one that is
+        let missing = format!("@{}", temp.path().join("missing").display());
+        let escaped = format!("@{at}");
+        let args = ["ocrlocate", "@someone", &missing, &escaped, "--", "@@x"];
+        let expanded = expand_args(args.map(OsString::from))?;
+        assert_eq!(
+            expanded,
+            ["ocrlocate", "@someone", &missing, &at, "--", "@@x"]
+        );
+        Ok(())
+    }
 }
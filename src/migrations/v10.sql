@@ -0,0 +1,5 @@
+BEGIN;
+-- `none` for compact search indexes, see `ocrlocate db init --compact`
+INSERT INTO settings (key, value) VALUES ('detail', 'full');
+PRAGMA user_version = 10;
+COMMIT;
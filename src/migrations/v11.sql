@@ -0,0 +1,7 @@
+BEGIN;
+-- `zstd` when new text is stored compressed, see `ocrlocate db init --compress`
+INSERT INTO settings (key, value) VALUES ('compression', 'none');
+-- the text of every row, which the search index of a compressed database is made from
+CREATE VIEW images_text AS SELECT id, unzstd(content) AS content FROM images;
+PRAGMA user_version = 11;
+COMMIT;
@@ -0,0 +1,19 @@
+BEGIN;
+-- sentence embeddings of the text of images, see `--search-type semantic`
+CREATE TABLE embeddings(
+    image_id INTEGER NOT NULL,
+    -- the model that made it, since embeddings of different models can't be compared
+    model TEXT NOT NULL,
+    -- little-endian f32s, normalized to a length of 1
+    vector BLOB NOT NULL,
+    PRIMARY KEY (image_id, model)
+);
+CREATE TRIGGER images_delete_embeddings AFTER DELETE ON images BEGIN
+    DELETE FROM embeddings WHERE image_id = old.id;
+END;
+CREATE TRIGGER images_update_embeddings AFTER UPDATE OF content ON images
+    WHEN old.content IS NOT new.content BEGIN
+    DELETE FROM embeddings WHERE image_id = old.id;
+END;
+PRAGMA user_version = 12;
+COMMIT;
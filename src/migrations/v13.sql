@@ -0,0 +1,19 @@
+BEGIN;
+-- embeddings of what single images look like, see `ocrlocate similar`
+CREATE TABLE image_embeddings(
+    image_id INTEGER NOT NULL,
+    model TEXT NOT NULL,
+    -- little-endian f32s normalized to a length of 1, empty for files that aren't images
+    vector BLOB NOT NULL,
+    PRIMARY KEY (image_id, model)
+);
+CREATE TRIGGER images_delete_image_embeddings AFTER DELETE ON images BEGIN
+    DELETE FROM image_embeddings WHERE image_id = old.id;
+END;
+-- the file changed
+CREATE TRIGGER images_update_image_embeddings AFTER UPDATE OF modtime ON images
+    WHEN old.modtime IS NOT new.modtime BEGIN
+    DELETE FROM image_embeddings WHERE image_id = old.id;
+END;
+PRAGMA user_version = 13;
+COMMIT;
@@ -0,0 +1,5 @@
+BEGIN;
+-- bytes of the file, NULL for rows saved before this version, see `--sort size`
+ALTER TABLE images ADD COLUMN size INTEGER;
+PRAGMA user_version = 14;
+COMMIT;
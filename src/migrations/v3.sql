@@ -0,0 +1,5 @@
+BEGIN;
+-- key/value metadata from the extractor, as a json object
+ALTER TABLE images ADD COLUMN properties TEXT;
+PRAGMA user_version = 3;
+COMMIT;
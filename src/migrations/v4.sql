@@ -0,0 +1,30 @@
+BEGIN;
+-- documents are stored as one row per page, files that aren't documents have an empty page
+CREATE TABLE images_new(
+    id INTEGER PRIMARY KEY ASC,
+    path TEXT NOT NULL,
+    page TEXT NOT NULL DEFAULT '',
+    modtime INTEGER NOT NULL,
+    mark_delete BOOL DEFAULT FALSE,
+    content TEXT NOT NULL,
+    properties TEXT,
+    UNIQUE (path, page)
+);
+-- keep the ids so the external-content search index stays valid
+INSERT INTO images_new (id, path, modtime, mark_delete, content, properties)
+    SELECT id, path, modtime, mark_delete, content, properties FROM images;
+DROP TABLE images;
+ALTER TABLE images_new RENAME TO images;
+CREATE INDEX mark_delete_idx ON images (mark_delete);
+CREATE TRIGGER images_insert AFTER INSERT ON images BEGIN
+    INSERT INTO images_fts (rowid, content) VALUES (new.id, new.content);
+END;
+CREATE TRIGGER images_delete AFTER DELETE ON images BEGIN
+    INSERT INTO images_fts (images_fts, rowid, content) VALUES ('delete', old.id, old.content);
+END;
+CREATE TRIGGER images_update AFTER UPDATE ON images BEGIN
+    INSERT INTO images_fts (images_fts, rowid, content) VALUES ('delete', old.id, old.content);
+    INSERT INTO images_fts (rowid, content) VALUES (new.id, new.content);
+END;
+PRAGMA user_version = 4;
+COMMIT;
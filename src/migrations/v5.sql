@@ -0,0 +1,17 @@
+BEGIN;
+-- position and confidence of every recognized word, in pixels of the original image
+CREATE TABLE words(
+    image_id INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    left INTEGER NOT NULL,
+    top INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    confidence REAL NOT NULL
+);
+CREATE INDEX words_image_idx ON words (image_id);
+CREATE TRIGGER images_delete_words AFTER DELETE ON images BEGIN
+    DELETE FROM words WHERE image_id = old.id;
+END;
+PRAGMA user_version = 5;
+COMMIT;
@@ -0,0 +1,5 @@
+BEGIN;
+-- mean word confidence from 0 to 100, NULL for files that weren't OCRed
+ALTER TABLE images ADD COLUMN confidence REAL;
+PRAGMA user_version = 6;
+COMMIT;
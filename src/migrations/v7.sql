@@ -0,0 +1,10 @@
+BEGIN;
+-- images embedded by markdown and html notes, see `--notes`
+CREATE TABLE note_links(
+    note TEXT NOT NULL,
+    image TEXT NOT NULL,
+    PRIMARY KEY (note, image)
+);
+CREATE INDEX note_links_image_idx ON note_links (image);
+PRAGMA user_version = 7;
+COMMIT;
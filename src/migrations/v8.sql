@@ -0,0 +1,7 @@
+BEGIN;
+-- user id of whoever saved the row into a shared database, see `--shared-index`
+ALTER TABLE images ADD COLUMN owner INTEGER;
+-- only shown to the owner
+ALTER TABLE images ADD COLUMN private BOOL NOT NULL DEFAULT FALSE;
+PRAGMA user_version = 8;
+COMMIT;
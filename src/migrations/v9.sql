@@ -0,0 +1,19 @@
+BEGIN;
+-- options that the database was created with, see `ocrlocate db init`
+CREATE TABLE settings(
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
+-- the options of search indexes from before this table, which only their schema has
+INSERT INTO settings (key, value)
+    SELECT 'tokenizer', CASE
+        WHEN instr(sql, 'tokenize=''ocrlocate words 1') THEN 'words'
+        WHEN instr(sql, 'tokenize=''ocrlocate') THEN 'script'
+        ELSE 'trigram'
+    END FROM sqlite_master WHERE name = 'images_fts';
+INSERT INTO settings (key, value)
+    SELECT 'remove_diacritics', instr(sql, ' remove_diacritics 1''') > 0
+    FROM sqlite_master WHERE name = 'images_fts';
+INSERT INTO settings (key, value) VALUES ('prefix', '');
+PRAGMA user_version = 9;
+COMMIT;
@@ -0,0 +1,279 @@
+//! A native messaging host, which lets browser extensions search and add to the index
+//!
+//! Chrome and Firefox start the host themselves and talk to it over stdin and stdout, with every
+//! message a JSON object after its length as a 32-bit integer in native byte order. Each request
+//! gets one response, with the `id` of the request if it had one:
+//!
+//! * `{"op": "search", "query": "..", "type": "simple", "limit": 20}` - `{"results": [..]}`
+//! * `{"op": "text", "path": "..", "page": ""}` - `{"text": ..}`, the stored text of an
+//!   indexed file, or null
+//! * `{"op": "add", "path": "..", "text": ".."}` - `{"changed": n}`, indexes a file with
+//!   this text, or OCRs it when there is no `text`
+//!
+//! A request that fails gets `{"error": ".."}`, and the host keeps reading.
+
+use std::io::{self, Read, Write};
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8PathBuf as PathBuf;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[cfg(feature = "engine")]
+use crate::config::LanguageHints;
+use crate::db::{SearchType, DB};
+use crate::external;
+#[cfg(feature = "engine")]
+use crate::extract::Registry;
+#[cfg(feature = "engine")]
+use crate::index;
+#[cfg(feature = "engine")]
+use crate::ocr::OcrOptions;
+use crate::redact::Redactor;
+use crate::symbols::Symbols;
+
+/// Largest message that browsers accept from a host
+const MAX_RESPONSE: usize = 1024 * 1024;
+
+/// Largest message that Chrome sends to a host
+const MAX_REQUEST: usize = 64 * 1024 * 1024;
+
+pub struct HostOptions {
+    /// Only files under this directory are searched, everything when it's empty
+    pub root: PathBuf,
+    /// Max amount of results per search, unless a request asks for fewer
+    pub limit: usize,
+    pub redact: Redactor,
+    pub symbols: Symbols,
+    #[cfg(feature = "engine")]
+    pub extractors: Registry,
+    #[cfg(feature = "engine")]
+    pub ocr: OcrOptions,
+    /// The `[languages]` of the config, which the project config of a file adds to
+    #[cfg(feature = "engine")]
+    pub languages: LanguageHints,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case", deny_unknown_fields)]
+enum Op {
+    Search {
+        query: String,
+        #[serde(rename = "type")]
+        kind: Option<String>,
+        limit: Option<usize>,
+    },
+    Text {
+        path: String,
+        #[serde(default)]
+        page: String,
+    },
+    Add {
+        path: String,
+        text: Option<String>,
+    },
+}
+
+/// Answer the requests on `input` until the browser closes it
+pub fn run(db: &mut DB, options: &HostOptions, input: impl Read, output: impl Write) -> Result<()> {
+    let (mut input, mut output) = (input, output);
+    while let Some(message) = read_message(&mut input)? {
+        let request: Value = match serde_json::from_slice(&message) {
+            Ok(x) => x,
+            Err(e) => {
+                write_message(&mut output, &json!({"error": format!("invalid json: {e}")}))?;
+                continue;
+            }
+        };
+        let id = request.get("id").cloned();
+        let op = request
+            .as_object()
+            .map(|x| {
+                let mut x = x.clone();
+                x.remove("id");
+                Value::Object(x)
+            })
+            .ok_or_else(|| anyhow!("a request should be an object"))
+            .and_then(|x| serde_json::from_value::<Op>(x).context("invalid request"));
+        let mut response = match op.and_then(|op| handle(db, options, op)) {
+            Ok(x) => x,
+            Err(e) => json!({"error": format!("{e:#}")}),
+        };
+        if let Some(id) = id {
+            response["id"] = id;
+        }
+        if serde_json::to_vec(&response)?.len() > MAX_RESPONSE {
+            response = json!({"error": "the response is larger than browsers accept"});
+        }
+        write_message(&mut output, &response)?;
+    }
+    Ok(())
+}
+
+fn handle(db: &mut DB, options: &HostOptions, op: Op) -> Result<Value> {
+    match op {
+        Op::Search { query, kind, limit } => {
+            let kind = match kind {
+                Some(x) => x.parse()?,
+                None => SearchType::Simple,
+            };
+            let limit = limit.map_or(options.limit, |x| x.min(options.limit));
+            let results = db.search(vec![&query], &options.root, limit, kind, None, None)?;
+            Ok(json!({ "results": results }))
+        }
+        Op::Text { path, page } => {
+            let path = under_root(options, path)?;
+            Ok(json!({ "text": db.content(&path, &page)? }))
+        }
+        Op::Add { path, text } => {
+            let path = under_root(options, path)?;
+            let changed = match text {
+                Some(contents) => {
+                    let page = external::Page {
+                        contents,
+                        ..Default::default()
+                    };
+                    external::upsert(db, &path, vec![page], &options.redact, options.symbols)?
+                }
+                None => ocr(db, options, &path)?,
+            };
+            Ok(json!({ "changed": changed }))
+        }
+    }
+}
+
+/// The path of a request, which has to be under the root. Paths with `..` are refused, since
+/// they can lead out of it while starting with it
+fn under_root(options: &HostOptions, path: String) -> Result<PathBuf> {
+    let path = PathBuf::from(path);
+    if path
+        .components()
+        .any(|x| x == camino::Utf8Component::ParentDir)
+        || !path.starts_with(&options.root)
+    {
+        return Err(anyhow!("not under {}: {}", options.root, path));
+    }
+    Ok(path)
+}
+
+#[cfg(feature = "engine")]
+fn ocr(db: &mut DB, options: &HostOptions, path: &camino::Utf8Path) -> Result<usize> {
+    if !path.is_file() {
+        return Err(anyhow!("not a file: {}", path));
+    }
+    let ocr = index::file_options(path, &options.ocr, &options.languages)?;
+    let results = index::extract_file(
+        path,
+        &options.extractors,
+        &ocr,
+        &options.redact,
+        options.symbols,
+    )?;
+    db.save_results(results)
+}
+
+#[cfg(not(feature = "engine"))]
+fn ocr(_: &mut DB, _: &HostOptions, path: &camino::Utf8Path) -> Result<usize> {
+    Err(anyhow!(
+        "this build can't OCR {}, send its text along with it",
+        path
+    ))
+}
+
+/// The next message, or `None` once the input is closed between messages
+fn read_message(input: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    match input.read_exact(&mut length) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > MAX_REQUEST {
+        return Err(anyhow!("message of {length} bytes is too large"));
+    }
+    let mut message = vec![0; length];
+    input
+        .read_exact(&mut message)
+        .context("the input ended in a message")?;
+    Ok(Some(message))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let message = serde_json::to_vec(message)?;
+    output.write_all(&(message.len() as u32).to_ne_bytes())?;
+    output.write_all(&message)?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn messages(requests: &[Value]) -> Vec<u8> {
+        let mut input = vec![];
+        for request in requests {
+            write_message(&mut input, request).unwrap();
+        }
+        input
+    }
+
+    #[test]
+    fn requests() -> Result<()> {
+        let mut seeded = crate::fixture::seed("search")?;
+        let options = HostOptions {
+            root: seeded.root.clone(),
+            limit: 2,
+            redact: Redactor::default(),
+            symbols: Symbols::default(),
+            #[cfg(feature = "engine")]
+            extractors: Registry::with_builtins(),
+            #[cfg(feature = "engine")]
+            ocr: OcrOptions::default(),
+            #[cfg(feature = "engine")]
+            languages: LanguageHints::default(),
+        };
+        let saved = seeded.root.join("saved.png");
+        fs::write(&saved, b"")?;
+        let mut input = messages(&[
+            json!({"op": "search", "query": "total", "id": 1}),
+            json!({"op": "add", "path": saved.as_str(), "text": "page screenshot"}),
+            json!({"op": "search", "query": "screenshot", "limit": 10}),
+            json!({"op": "text", "path": saved.as_str()}),
+            json!({"op": "text", "path": "/elsewhere.png"}),
+            json!({"op": "text", "path": seeded.root.join("../../etc/passwd").as_str()}),
+            json!({"op": "add", "path": seeded.root.join("../escaped.png").as_str(), "text": "x"}),
+            json!({"op": "delete", "path": saved.as_str()}),
+        ]);
+        input.extend(b"\x02\x00\x00\x00{]");
+        let mut output = vec![];
+        run(&mut seeded.db, &options, input.as_slice(), &mut output)?;
+
+        let mut output = output.as_slice();
+        let mut responses = vec![];
+        while let Some(message) = read_message(&mut output)? {
+            responses.push(serde_json::from_slice::<Value>(&message)?);
+        }
+        assert_eq!(responses.len(), 9);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["results"].as_array().unwrap().len(), 2);
+        assert_eq!(responses[1]["changed"], 1);
+        let found = &responses[2]["results"][0]["path"];
+        assert_eq!(found.as_str(), Some(saved.as_str()));
+        assert_eq!(responses[3]["text"], "page screenshot");
+        for response in &responses[4..] {
+            assert!(response["error"].is_string(), "{response}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn truncated() {
+        let mut input = messages(&[json!({"op": "text", "path": "/a"})]);
+        input.pop();
+        assert!(read_message(&mut input.as_slice()).is_err());
+        assert!(read_message(&mut [].as_slice()).unwrap().is_none());
+    }
+}
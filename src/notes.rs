@@ -0,0 +1,235 @@
+//! Finding the images that markdown and html notes embed, so that search results can point back
+//! to the notes
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+
+use camino::{Utf8Component, Utf8Path as Path, Utf8PathBuf as PathBuf};
+use glob::Pattern;
+use walkdir::WalkDir;
+
+use crate::report::{self, Code};
+
+const NOTE_EXTENSIONS: &[&str] = &["md", "markdown", "html", "htm"];
+
+/// The images embedded by every note under `root` that embeds any, by the path of the note.
+/// Only images under `root` are found
+pub fn scan(root: &Path, subdirs: bool, exclude: &[Pattern]) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+    let mut wd = WalkDir::new(root).follow_links(true);
+    if !subdirs {
+        wd = wd.max_depth(1);
+    }
+    let files: Vec<PathBuf> = wd
+        .into_iter()
+        .filter_entry(|entry| !exclude.iter().any(|x| x.matches_path(entry.path())))
+        .filter_map(|res| match res {
+            Ok(file) if !file.file_type().is_dir() => PathBuf::try_from(file.into_path()).ok(),
+            Ok(_) => None,
+            Err(e) => {
+                report::error(Code::Walk, None, format!("collecting notes: {}", e));
+                None
+            }
+        })
+        .collect();
+
+    let existing: HashSet<&Path> = files.iter().map(PathBuf::as_path).collect();
+    let mut by_name: HashMap<&str, Vec<&Path>> = HashMap::new();
+    for file in &files {
+        if let Some(name) = file.file_name() {
+            by_name.entry(name).or_default().push(file);
+        }
+    }
+
+    let mut links = BTreeMap::new();
+    for note in files.iter().filter(|x| is_note(x)) {
+        let text = match fs::read_to_string(note) {
+            Ok(text) => text,
+            Err(e) => {
+                report::error(
+                    Code::Note,
+                    Some(note.as_str()),
+                    format!("reading note: {}", e),
+                );
+                continue;
+            }
+        };
+        let mut images: Vec<PathBuf> = references(&text)
+            .iter()
+            .filter_map(|target| resolve(root, note, target, &existing, &by_name))
+            .collect();
+        images.sort();
+        images.dedup();
+        if !images.is_empty() {
+            links.insert(note.clone(), images);
+        }
+    }
+    links
+}
+
+fn is_note(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|x| NOTE_EXTENSIONS.contains(&x.to_ascii_lowercase().as_str()))
+}
+
+/// Targets of the images embedded in a note, as written: markdown `![alt](target)`, Obsidian
+/// `![[target]]` and html `<img src="target">`. Links to other sites are left out
+pub fn references(text: &str) -> Vec<String> {
+    let mut targets = vec![];
+
+    let mut rest = text;
+    while let Some(i) = rest.find("![") {
+        rest = &rest[i + 2..];
+        if let Some(wiki) = rest.strip_prefix('[') {
+            if let Some((inner, _)) = wiki.split_once("]]") {
+                // `![[image.png|300]]` sets the width, and `#` links into a note
+                targets.push(inner.split(['|', '#']).next().unwrap().trim().to_owned());
+            }
+            continue;
+        }
+        let Some(close) = rest.find("](") else {
+            continue;
+        };
+        let target = &rest[close + 2..];
+        let target = match target.strip_prefix('<') {
+            Some(x) => x.split_once('>').map(|x| x.0),
+            None => target
+                .split_once(|c: char| c == ')' || c.is_whitespace())
+                .map(|x| x.0),
+        };
+        if let Some(target) = target {
+            targets.push(target.replace("%20", " "));
+        }
+    }
+
+    let lower = text.to_ascii_lowercase();
+    for (i, _) in lower.match_indices("<img") {
+        let tag = &text[i..];
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        let Some(src) = tag.to_ascii_lowercase().find("src=") else {
+            continue;
+        };
+        let value = &tag[src + 4..];
+        let target = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next(),
+            _ => value.split_whitespace().next(),
+        };
+        if let Some(target) = target {
+            targets.push(target.replace("%20", " "));
+        }
+    }
+
+    targets.retain(|x| !x.is_empty() && !x.contains("://") && !x.starts_with("data:"));
+    targets
+}
+
+/// The file a target points to, relative to the note, to `root`, or as Obsidian does by only
+/// its name anywhere under `root`
+fn resolve(
+    root: &Path,
+    note: &Path,
+    target: &str,
+    existing: &HashSet<&Path>,
+    by_name: &HashMap<&str, Vec<&Path>>,
+) -> Option<PathBuf> {
+    let relative = target.trim_start_matches('/');
+    let dir = note.parent().unwrap_or(root);
+    let candidates = [
+        normalize(&dir.join(target)),
+        normalize(&root.join(relative)),
+    ];
+    if let Some(found) = candidates
+        .into_iter()
+        .find(|x| existing.contains(x.as_path()))
+    {
+        return Some(found);
+    }
+    if relative.contains('/') {
+        return None;
+    }
+    // the shortest path wins, like in Obsidian
+    by_name
+        .get(relative)?
+        .iter()
+        .min_by_key(|x| (x.components().count(), x.as_str()))
+        .map(|x| x.to_path_buf())
+}
+
+/// Remove `.` and `..` from a path without touching the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Utf8Component::CurDir => (),
+            Utf8Component::ParentDir => {
+                normalized.pop();
+            }
+            x => normalized.push(x),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::fs::File;
+
+    #[test]
+    fn parse() {
+        let note = r#"# Receipts
+![scan](img/receipt%20one.png "title") and ![](<img/two words.jpg>)
+![[pasted.png|300]] ![[other note#heading]]
+<IMG alt="x" SRC="html.png"> <img src='single.gif'/> <img src=bare.webp>
+![remote](https://example.com/a.png) ![inline](data:image/png;base64,AAAA)
+"#;
+        assert_eq!(
+            references(note),
+            [
+                "img/receipt one.png",
+                "img/two words.jpg",
+                "pasted.png",
+                "other note",
+                "html.png",
+                "single.gif",
+                "bare.webp",
+            ]
+        );
+    }
+
+    #[test]
+    fn links() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let root = Path::from_path(temp.path()).unwrap();
+        for dir in ["notes", "attachments", "notes/img"] {
+            fs::create_dir(root.join(dir))?;
+        }
+        for file in ["attachments/pasted.png", "notes/img/a.png", "b.png"] {
+            File::create(root.join(file))?;
+        }
+        fs::write(
+            root.join("notes/day.md"),
+            "![](img/a.png) ![](../b.png) ![[pasted.png]] ![](missing.png)",
+        )?;
+        fs::write(root.join("notes/empty.md"), "no images")?;
+        fs::write(root.join("index.html"), r#"<img src="/notes/img/a.png">"#)?;
+
+        let links = scan(root, true, &[]);
+        assert_eq!(
+            links,
+            BTreeMap::from([
+                (root.join("index.html"), vec![root.join("notes/img/a.png")]),
+                (
+                    root.join("notes/day.md"),
+                    vec![
+                        root.join("attachments/pasted.png"),
+                        root.join("b.png"),
+                        root.join("notes/img/a.png"),
+                    ]
+                ),
+            ])
+        );
+        temp.close()?;
+        Ok(())
+    }
+}
@@ -1,14 +1,648 @@
+#[cfg(feature = "ocr")]
+use anyhow::Context;
 use anyhow::{anyhow, Result};
-use camino::Utf8Path as Path;
-use std::ffi::CString;
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+use itertools::Itertools;
+use std::collections::BTreeMap;
+#[cfg(feature = "ocr")]
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString},
+    mem,
+};
 
+#[cfg(feature = "ocr")]
 use leptess::tesseract::TessApi;
+#[cfg(feature = "ocr")]
+use leptonica_plumbing::memory::RefCountedExclusive;
+#[cfg(feature = "ocr")]
 use leptonica_plumbing::{self, leptonica_sys};
+use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "engine")]
+use crate::extract::Extracted;
+
+#[cfg(feature = "windows-ocr")]
+mod winrt;
+#[cfg(feature = "windows-ocr")]
+pub use winrt::Ocr;
+#[cfg(feature = "vision")]
+mod vision;
+#[cfg(feature = "vision")]
+pub use vision::Ocr;
+#[cfg(feature = "cloud")]
+mod cloud;
+#[cfg(feature = "cloud")]
+pub use cloud::Ocr;
+#[cfg(feature = "onnx")]
+mod onnx;
+#[cfg(feature = "onnx")]
+pub use onnx::Ocr;
+
+#[cfg(feature = "ocr")]
 #[derive(Debug)]
 pub struct Ocr {
     leptess: TessApi,
+    /// Scale the last image was OCRed at
     scale: Option<f32>,
+    tables: bool,
+    min_word_confidence: Option<f32>,
+    options: OcrOptions,
+    /// Language `leptess` was loaded with
+    lang: String,
+    /// Loaded with the `osd` data, when `--osd` or `--auto-rotate` is used
+    osd: Option<TessApi>,
+    /// Other languages that were loaded for `--osd`, to switch back to without loading them again
+    idle: HashMap<String, TessApi>,
+    /// Method that won for the last image, with [`Binarization::Auto`]
+    picked: Option<Binarization>,
+    /// Whether no text was found in the last image, with `detect_text`
+    textless: bool,
+    /// Part of the last image that was OCRed after `trim` and `crop_edges`, as its left, top,
+    /// right and bottom, with the width and height of the whole image
+    cut: Option<([i32; 4], (i32, i32))>,
+    /// Quarter turns clockwise that the last image was rotated by to turn it upright
+    quads: i32,
+    /// Width and colors of the last image, kept with `annotations` for [`Ocr::annotations`]
+    colors: Option<(usize, Vec<[u8; 3]>)>,
+    /// Words of the last image when [`Ocr::annotations`] OCRed it again, since tesseract is left
+    /// with the last layer
+    annotated: Option<Vec<Word>>,
+}
+
+/// Characters that tesseract mostly finds in noise
+pub const DEFAULT_BLACKLIST: &str = "|®»«®©";
+
+/// How much lighter or darker a pixel of a solid border can be than the rest, since JPEG
+/// artifacts make them uneven
+#[cfg(feature = "ocr")]
+const TRIM_TOLERANCE: u8 = 12;
+
+/// Below this the result of orientation and script detection is mostly a guess
+#[cfg(feature = "ocr")]
+const MIN_OSD_CONFIDENCE: f32 = 2.0;
+
+/// Page segmentation mode without `psm`, sparse text, which finds the scattered text of
+/// screenshots
+#[cfg(feature = "ocr")]
+const DEFAULT_PSM: i64 = 11;
+
+/// Images with fewer letters than this are looked at again for annotations, with `annotations`
+#[cfg(feature = "ocr")]
+const ANNOTATION_MAX_LETTERS: usize = 50;
+
+/// Images that are less colorful than this, by [`colorfulness`], are left alone by
+/// `annotations`. 15 is about where images start to look colorful rather than gray
+#[cfg(feature = "ocr")]
+const ANNOTATION_MIN_COLORFULNESS: f32 = 15.0;
+
+#[derive(Debug, Clone)]
+pub struct OcrOptions {
+    pub lang: String,
+    pub debug: bool,
+    pub scale: Option<f32>,
+    /// Scale images to a size instead of by a fraction, when `scale` isn't set
+    pub resize: Option<Resize>,
+    pub binarization: Option<Binarization>,
+    pub psm: Option<i64>,
+    /// Resolution tesseract assumes for images, instead of guessing it for the ones that don't
+    /// say what theirs is
+    pub dpi: Option<u32>,
+    /// Only recognize these characters
+    pub char_whitelist: Option<String>,
+    /// Never recognize these characters, instead of [`DEFAULT_BLACKLIST`]
+    pub char_blacklist: Option<String>,
+    /// Extra words for tesseract's dictionary, one per line
+    pub user_words: Option<PathBuf>,
+    /// Extra patterns for tesseract's dictionary, like `\d\d\d-\A\A`
+    pub user_patterns: Option<PathBuf>,
+    /// Directory of the traineddata files, instead of the one tesseract was built with or
+    /// `TESSDATA_PREFIX`
+    pub tessdata: Option<PathBuf>,
+    /// Lay out the text as tab separated table rows, see [`tsv_to_table`]
+    pub tables: bool,
+    /// How text other than tables is cleaned up
+    pub normalize: Normalize,
+    /// Leave out words that tesseract is less confident about, from 0 to 100
+    pub min_word_confidence: Option<f32>,
+    /// Detect the rotation and script of every image and turn it upright before OCR. Scripts
+    /// in the map, named like `Latin` or `Japanese`, are OCRed with their language instead of
+    /// `lang`
+    pub osd: Option<BTreeMap<String, String>>,
+    /// Detect the rotation of every image and turn it upright before OCR, without changing the
+    /// language like `osd` does
+    pub auto_rotate: bool,
+    /// Run a median filter over every image before OCR, see [`denoise`]
+    pub denoise: bool,
+    /// Cut off the edges of every image that are one solid color, like letterboxing, see
+    /// [`solid_border`]
+    pub trim: bool,
+    /// Cut off fixed parts of every image, like a taskbar, before `trim`
+    pub crop_edges: Option<Edges>,
+    /// Only recognize images that tesseract's layout analysis finds lines of text in, which is
+    /// much faster than recognizing them
+    pub detect_text: bool,
+    /// OCR images that have little text but a lot of color again, once for every layer from
+    /// [`annotation_layers`], to find the text that screenshot tools draw in bright colors
+    pub annotations: bool,
+    /// The service that builds with the `cloud` feature send images to
+    pub cloud: Option<Cloud>,
+    /// Directory with the models of builds with the `onnx` feature
+    pub onnx_models: Option<PathBuf>,
+    /// Run the models of builds with the `onnx` feature on a GPU, or on the CPU if it can't be used
+    pub gpu: Option<Gpu>,
+}
+
+impl Default for OcrOptions {
+    fn default() -> Self {
+        OcrOptions {
+            lang: "eng".into(),
+            debug: false,
+            scale: None,
+            resize: None,
+            binarization: None,
+            psm: None,
+            dpi: None,
+            char_whitelist: None,
+            char_blacklist: None,
+            user_words: None,
+            user_patterns: None,
+            tessdata: None,
+            tables: false,
+            normalize: Normalize::default(),
+            min_word_confidence: None,
+            osd: None,
+            auto_rotate: false,
+            denoise: false,
+            trim: false,
+            crop_edges: None,
+            detect_text: false,
+            annotations: false,
+            cloud: None,
+            onnx_models: None,
+            gpu: None,
+        }
+    }
+}
+
+#[cfg(feature = "engine")]
+impl OcrOptions {
+    /// The settings that change the text of an image, like `lang=eng psm=6 engine=tesseract/5.3.0`.
+    /// They are saved with every row, so that `index` OCRs the files that were OCRed with other
+    /// ones again
+    pub fn settings(&self) -> String {
+        let mut settings = self.text_settings();
+        settings.push(format!("engine={}", Ocr::engine(self)));
+        settings.join(" ")
+    }
+
+    /// The options with the language, page segmentation mode and scale of `settings` from
+    /// [`OcrOptions::settings`], which are the ones that the files of an index can differ in
+    pub fn with_settings(&self, settings: &str) -> OcrOptions {
+        let mut options = OcrOptions {
+            psm: None,
+            scale: None,
+            ..self.clone()
+        };
+        for (name, value) in settings.split(' ').filter_map(|x| x.split_once('=')) {
+            match name {
+                "lang" => options.lang = value.into(),
+                "psm" => options.psm = value.parse().ok(),
+                "scale" => options.scale = value.parse().ok(),
+                _ => (),
+            }
+        }
+        options
+    }
+
+    /// [`OcrOptions::settings`] other than the engine, only the ones that aren't the default
+    fn text_settings(&self) -> Vec<String> {
+        let mut settings = vec![format!("lang={}", self.lang)];
+        let mut set = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                settings.push(format!("{name}={value}"));
+            }
+        };
+        let flag = |on: bool| on.then(|| "true".to_owned());
+        set("psm", self.psm.map(|x| x.to_string()));
+        set("scale", self.scale.map(|x| x.to_string()));
+        set(
+            "resize",
+            self.resize.map(|x| match x {
+                Resize::Scale(x) => x.to_string(),
+                Resize::LongestSide(x) => format!("{x}px"),
+                Resize::Dpi(x) => format!("{x}dpi"),
+            }),
+        );
+        set("binarization", self.binarization.map(|x| x.name().into()));
+        set("dpi", self.dpi.map(|x| x.to_string()));
+        set(
+            "whitelist",
+            self.char_whitelist.as_ref().map(|x| format!("{x:?}")),
+        );
+        set(
+            "blacklist",
+            self.char_blacklist.as_ref().map(|x| format!("{x:?}")),
+        );
+        set(
+            "user_words",
+            self.user_words.as_ref().map(|x| x.to_string()),
+        );
+        set(
+            "user_patterns",
+            self.user_patterns.as_ref().map(|x| x.to_string()),
+        );
+        // which is also where the variant of --model is
+        set("tessdata", self.tessdata.as_ref().map(|x| x.to_string()));
+        set("tables", flag(self.tables));
+        let normalize = self.normalize;
+        let steps = [
+            (normalize.paragraphs, "paragraphs"),
+            (normalize.squash_whitespace, "squash"),
+            (normalize.join_hyphens, "hyphens"),
+            (normalize.compatibility, "nfkc"),
+        ];
+        let steps = steps.iter().filter(|x| x.0).map(|x| x.1).join(",");
+        set("normalize", (!steps.is_empty()).then_some(steps));
+        set(
+            "min_confidence",
+            self.min_word_confidence.map(|x| x.to_string()),
+        );
+        set(
+            "osd",
+            self.osd.as_ref().map(|x| {
+                x.iter()
+                    .map(|(script, lang)| format!("{script}:{lang}"))
+                    .join(",")
+            }),
+        );
+        set("auto_rotate", flag(self.auto_rotate));
+        set("denoise", flag(self.denoise));
+        set("trim", flag(self.trim));
+        set(
+            "crop_edges",
+            self.crop_edges.map(|x| format!("{x:?}").replace(' ', "")),
+        );
+        set("detect_text", flag(self.detect_text));
+        set("annotations", flag(self.annotations));
+        set(
+            "onnx_models",
+            self.onnx_models.as_ref().map(|x| x.to_string()),
+        );
+        settings
+    }
+}
+
+/// A cloud OCR service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    /// `DOCUMENT_TEXT_DETECTION` of Google Cloud Vision
+    Google,
+    /// The Read API of Azure AI Vision
+    Azure,
+}
+
+/// A GPU execution provider of onnxruntime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gpu {
+    /// The first of the others that works on this platform
+    Auto,
+    /// NVIDIA GPUs on Linux and Windows
+    Cuda,
+    /// Any GPU with DirectX 12 on Windows
+    DirectMl,
+    /// The GPU and neural engine of Apple devices
+    CoreMl,
+}
+
+/// How to reach a cloud OCR service
+#[derive(Clone)]
+pub struct Cloud {
+    pub provider: Provider,
+    pub key: String,
+    /// Base URL of the service, which Azure needs to find the resource
+    pub endpoint: Option<String>,
+    /// Most requests per second, shared by every thread
+    pub rate: f32,
+}
+
+impl std::fmt::Debug for Cloud {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // keep the key out of debug output
+        f.debug_struct("Cloud")
+            .field("provider", &self.provider)
+            .field("endpoint", &self.endpoint)
+            .field("rate", &self.rate)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How to scale images before OCR, resolved for each image into one scaling operation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resize {
+    /// A fraction, like `0.5`
+    Scale(f32),
+    /// Pixels of the longest side, like `2000px`
+    LongestSide(u32),
+    /// Resolution, like `300dpi`. Images that don't say their resolution aren't scaled
+    Dpi(u32),
+}
+
+impl std::str::FromStr for Resize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("invalid size: {s}, should be like 2000px, 300dpi or 0.5");
+        let resize = if let Some(px) = s.strip_suffix("px") {
+            Resize::LongestSide(px.parse().map_err(|_| invalid())?)
+        } else if let Some(dpi) = s.strip_suffix("dpi") {
+            Resize::Dpi(dpi.parse().map_err(|_| invalid())?)
+        } else {
+            Resize::Scale(s.parse().map_err(|_| invalid())?)
+        };
+        match resize {
+            Resize::Scale(x) if x.is_finite() && x > 0.0 => Ok(resize),
+            Resize::LongestSide(x) | Resize::Dpi(x) if x > 0 => Ok(resize),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Resize {
+    /// The fraction to scale an image of this size and horizontal resolution by, if any
+    pub fn scale(self, width: i32, height: i32, dpi: i32) -> Option<f32> {
+        match self {
+            Resize::Scale(x) => Some(x),
+            Resize::LongestSide(px) => Some(px as f32 / width.max(height).max(1) as f32),
+            Resize::Dpi(target) => (dpi > 0).then(|| target as f32 / dpi as f32),
+        }
+    }
+}
+
+/// How the text that tesseract recognized is cleaned up before it's stored, see
+/// [`Normalize::apply`]. Tables are always stored as they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Normalize {
+    /// Keep the empty lines between paragraphs. Without it, every empty line is removed
+    pub paragraphs: bool,
+    /// Turn runs of spaces and tabs into one space, and drop the spaces at the ends of lines
+    pub squash_whitespace: bool,
+    /// Join the words that were hyphenated at the end of a line
+    pub join_hyphens: bool,
+    /// Store text in Unicode's NFKC form instead of NFC, which turns full-width letters and
+    /// other compatibility characters into the plain ones, see [`normalize_unicode`]. Unlike the
+    /// other steps, this applies to the text of every engine and of tables
+    pub compatibility: bool,
+}
+
+impl std::str::FromStr for Normalize {
+    type Err = anyhow::Error;
+
+    /// Parse a list of steps like `paragraphs,squash,hyphens`, or `default` for none of them
+    fn from_str(s: &str) -> Result<Self> {
+        let mut normalize = Normalize::default();
+        for step in s.split(',').map(str::trim) {
+            match step {
+                "paragraphs" => normalize.paragraphs = true,
+                "squash" => normalize.squash_whitespace = true,
+                "hyphens" => normalize.join_hyphens = true,
+                "nfkc" => normalize.compatibility = true,
+                "default" => (),
+                _ => return Err(anyhow!(
+                    "invalid normalization: {step}, should be paragraphs, squash, hyphens or nfkc"
+                )),
+            }
+        }
+        Ok(normalize)
+    }
+}
+
+impl Normalize {
+    pub fn apply(self, text: &str) -> String {
+        let mut text = text.to_owned();
+        if self.squash_whitespace || self.join_hyphens {
+            let mut lines: Vec<String> = text.lines().map(String::from).collect();
+            if self.squash_whitespace {
+                for line in &mut lines {
+                    *line = line.split_whitespace().join(" ");
+                }
+            }
+            if self.join_hyphens {
+                join_hyphens(&mut lines);
+            }
+            text = lines.join("\n");
+        }
+        if self.paragraphs {
+            // one empty line is enough between paragraphs
+            text = text.trim_matches('\n').to_owned();
+            while text.contains("\n\n\n") {
+                text = text.replace("\n\n\n", "\n\n");
+            }
+            text
+        } else {
+            // what was always stored, so that files OCRed before don't look changed
+            text.replace("\n\n", "\n")
+        }
+    }
+}
+
+/// Compose `text` into Unicode's NFC form, so that an accented letter is always stored the same
+/// way, or with `compatibility` into NFKC, so that `ＡＢＣ１２３` from a Japanese screenshot is
+/// stored as `ABC123`
+pub fn normalize_unicode(text: &str, compatibility: bool) -> String {
+    use unicode_normalization::UnicodeNormalization;
+    match compatibility {
+        true => text.nfkc().collect(),
+        false => text.nfc().collect(),
+    }
+}
+
+/// Move the rest of a word that was hyphenated at the end of a line up to the line, like
+/// `exam-` and `ple text` to `example` and `text`. Only hyphens after a letter and before a
+/// lowercase letter count, so that dashes and compound names stay
+fn join_hyphens(lines: &mut [String]) {
+    for i in 1..lines.len() {
+        let (before, after) = lines.split_at_mut(i);
+        let line = &mut before[i - 1];
+        let next = &mut after[0];
+        let hyphenated = line
+            .strip_suffix('-')
+            .and_then(|x| x.chars().last())
+            .is_some_and(char::is_alphabetic);
+        let next_trimmed = next.trim_start();
+        if !hyphenated || !next_trimmed.chars().next().is_some_and(char::is_lowercase) {
+            continue;
+        }
+        let end = next_trimmed
+            .find(char::is_whitespace)
+            .unwrap_or(next_trimmed.len());
+        line.pop();
+        line.push_str(&next_trimmed[..end]);
+        *next = next_trimmed[end..].trim_start().to_owned();
+    }
+}
+
+/// Parts of the edges of an image to cut off, like `bottom:40px,top:5%`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Edges {
+    pub top: Length,
+    pub right: Length,
+    pub bottom: Length,
+    pub left: Length,
+}
+
+/// A distance from an edge of an image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Length {
+    Pixels(u32),
+    /// Percent of the width or height of the image
+    Percent(f32),
+}
+
+impl Default for Length {
+    fn default() -> Self {
+        Length::Pixels(0)
+    }
+}
+
+impl Length {
+    fn pixels(self, size: i32) -> i32 {
+        match self {
+            Length::Pixels(x) => x as i32,
+            Length::Percent(x) => (size as f32 * x / 100.0).round() as i32,
+        }
+    }
+}
+
+impl std::str::FromStr for Edges {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut edges = Edges::default();
+        for rule in s.split(',') {
+            let invalid = || anyhow!("invalid edge: {rule}, should be like bottom:40px or top:5%");
+            let (side, length) = rule.split_once(':').ok_or_else(invalid)?;
+            let length = if let Some(px) = length.strip_suffix("px") {
+                Length::Pixels(px.parse().map_err(|_| invalid())?)
+            } else if let Some(percent) = length.strip_suffix('%') {
+                match percent.parse() {
+                    Ok(x) if (0.0..100.0).contains(&x) => Length::Percent(x),
+                    _ => return Err(invalid()),
+                }
+            } else {
+                return Err(invalid());
+            };
+            match side {
+                "top" => edges.top = length,
+                "right" => edges.right = length,
+                "bottom" => edges.bottom = length,
+                "left" => edges.left = length,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(edges)
+    }
+}
+
+impl Edges {
+    /// The part of an image of this size that is left, as its left, top, right and bottom. An
+    /// image that is smaller than the edges is left whole, instead of leaving nothing to OCR
+    pub fn inside(&self, width: i32, height: i32) -> [i32; 4] {
+        let rect = [
+            self.left.pixels(width),
+            self.top.pixels(height),
+            width - self.right.pixels(width),
+            height - self.bottom.pixels(height),
+        ];
+        match rect[0] < rect[2] && rect[1] < rect[3] {
+            true => rect,
+            false => [0, 0, width, height],
+        }
+    }
+}
+
+/// Variant of the traineddata, which trades speed for accuracy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Fast,
+    Best,
+}
+
+/// Where packages usually install traineddata
+const TESSDATA_DIRS: &[&str] = &[
+    "/usr/share/tesseract-ocr/5/tessdata",
+    "/usr/share/tesseract-ocr/4.00/tessdata",
+    "/usr/share/tessdata",
+    "/usr/local/share/tessdata",
+    "/opt/homebrew/share/tessdata",
+];
+
+impl Model {
+    pub fn name(self) -> &'static str {
+        match self {
+            Model::Fast => "fast",
+            Model::Best => "best",
+        }
+    }
+
+    /// The directory of this variant, next to or inside `tessdata` like `tessdata_best` or
+    /// `tessdata/best`. Without `tessdata`, `TESSDATA_PREFIX` and the usual install locations
+    /// are looked in
+    pub fn dir(self, tessdata: Option<&Path>) -> Result<PathBuf> {
+        let bases: Vec<PathBuf> = match tessdata {
+            Some(x) => vec![x.to_owned()],
+            None => std::env::var("TESSDATA_PREFIX")
+                .ok()
+                .map(PathBuf::from)
+                .into_iter()
+                .chain(TESSDATA_DIRS.iter().map(PathBuf::from))
+                .collect(),
+        };
+        let candidates: Vec<PathBuf> = bases
+            .iter()
+            .flat_map(|base| {
+                let sibling = base
+                    .parent()
+                    .zip(base.file_name())
+                    .map(|(parent, name)| parent.join(format!("{name}_{}", self.name())));
+                sibling.into_iter().chain([base.join(self.name())])
+            })
+            .collect();
+        candidates
+            .iter()
+            .find(|x| x.is_dir())
+            .cloned()
+            .ok_or_else(|| {
+                anyhow!(
+                    "Found no {} traineddata, looked in {}",
+                    self.name(),
+                    candidates.iter().join(", ")
+                )
+            })
+    }
+}
+
+/// Language codes of the traineddata in `tessdata`, or in `TESSDATA_PREFIX` and the usual install
+/// locations without it, for completing `--lang`. `osd` is left out, since it's no language
+pub fn installed_langs(tessdata: Option<&Path>) -> Vec<String> {
+    let dirs: Vec<PathBuf> = match tessdata {
+        Some(x) => vec![x.to_owned()],
+        None => std::env::var("TESSDATA_PREFIX")
+            .ok()
+            .map(PathBuf::from)
+            .into_iter()
+            .chain(TESSDATA_DIRS.iter().map(PathBuf::from))
+            .collect(),
+    };
+    dirs.iter()
+        .filter_map(|x| x.read_dir_utf8().ok())
+        .flatten()
+        .filter_map(|x| x.ok())
+        .filter_map(|x| x.file_name().strip_suffix(".traineddata").map(String::from))
+        .filter(|x| x != "osd" && validate_lang(x).is_ok())
+        .sorted()
+        .dedup()
+        .collect()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -16,82 +650,1214 @@ pub enum Binarization {
     Otsu = 0,
     LeptonicaOtsu = 1,
     Sauvola = 2,
+    /// Try the others, and keep the result with the highest mean confidence
+    Auto = -1,
 }
 
+impl Binarization {
+    #[cfg(feature = "ocr")]
+    const METHODS: [Binarization; 3] = [
+        Binarization::Otsu,
+        Binarization::LeptonicaOtsu,
+        Binarization::Sauvola,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Binarization::Otsu => "Otsu",
+            Binarization::LeptonicaOtsu => "LeptonicaOtsu",
+            Binarization::Sauvola => "Sauvola",
+            Binarization::Auto => "auto",
+        }
+    }
+}
+
+#[cfg(feature = "ocr")]
 impl Ocr {
-    pub fn new(
-        lang: &str,
-        debug: bool,
-        scale: Option<f32>,
-        binarization: Option<Binarization>,
-        psm: Option<i64>,
-    ) -> Result<Self> {
-        if lang.len() != 3 || lang.contains(['.', '/', '\\']) || !lang.is_ascii() {
-            return Err(anyhow!("Invalid language code: {:?}", lang));
-        }
-
-        let mut leptess = TessApi::new(None, &lang.to_ascii_lowercase())?;
-
-        if !debug {
-            leptess
-                .raw
-                .set_variable(
-                    leptess::Variable::DebugFile.as_cstr(),
-                    &CString::new("/dev/null").unwrap(),
-                )
-                .unwrap();
-            set_log_level(leptonica_sys::L_SEVERITY_ERROR);
-        }
-        if let Some(binarization) = binarization {
-            leptess
-                .raw
-                .set_variable(
-                    &CString::new("thresholding_method").unwrap(),
-                    &CString::new((binarization as u8).to_string()).unwrap(),
-                )
-                .unwrap();
+    pub fn new(options: &OcrOptions) -> Result<Self> {
+        let lang = &options.lang;
+        validate_lang(lang)?;
+        for lang in options.osd.iter().flat_map(|x| x.values()) {
+            validate_lang(lang)?;
+        }
+
+        let osd = match options.osd.is_some() || options.auto_rotate {
+            true => Some(TessApi::new(tessdata(options, "osd")?, "osd")?),
+            false => None,
+        };
+
+        Ok(Ocr {
+            leptess: init(lang, options)?,
+            scale: None,
+            tables: options.tables,
+            min_word_confidence: options.min_word_confidence,
+            options: options.clone(),
+            lang: lang.clone(),
+            osd,
+            idle: HashMap::new(),
+            picked: None,
+            textless: false,
+            cut: None,
+            quads: 0,
+            colors: None,
+            annotated: None,
+        })
+    }
+
+    pub fn scan(&mut self, img: &Path) -> Result<String> {
+        self.scan_pix(read(img)?)
+    }
+
+    /// OCR an image into a hOCR document, with the position of every word
+    pub fn scan_hocr(&mut self, img: &Path) -> Result<String> {
+        self.set_pix(read(img)?)?;
+        Ok(hocr_document(img.as_str(), &self.leptess.get_hocr_text(0)?))
+    }
+
+    /// OCR an image into an ALTO document, see [`alto_document`]
+    pub fn scan_alto(&mut self, img: &Path) -> Result<String> {
+        let cpix = read(img)?;
+        let size = (cpix.get_width(), cpix.get_height());
+        self.scan_pix(cpix)?;
+        Ok(alto_document(img.as_str(), Some(size), &self.words()))
+    }
+
+    fn set_pix(&mut self, mut cpix: RefCountedExclusive<leptonica_plumbing::Pix>) -> Result<()> {
+        self.cut = None;
+        self.quads = 0;
+        self.annotated = None;
+        if self.options.trim || self.options.crop_edges.is_some() {
+            cpix = self.cut_edges(cpix)?;
+        }
+        let resize = self
+            .options
+            .scale
+            .map(Resize::Scale)
+            .or(self.options.resize);
+        self.scale = resize.and_then(|x| {
+            let dpi = unsafe {
+                leptonica_sys::pixGetXRes(*AsRef::<*mut leptonica_sys::Pix>::as_ref(&*cpix))
+            };
+            x.scale(cpix.get_width(), cpix.get_height(), dpi)
+        });
+        if self.options.debug {
+            match self.scale {
+                Some(scale) => eprintln!("scale: {scale:.3}"),
+                None if resize.is_some() => eprintln!("scale: unknown resolution, not scaled"),
+                None => (),
+            }
+        }
+        if let Some(scale) = self.scale.filter(|x| *x != 1.0) {
+            cpix.scale_general(scale, scale)?;
+        }
+        if self.options.denoise {
+            cpix = denoise(&cpix)?;
+        }
+        if self.osd.is_some() {
+            cpix = self.orient(cpix)?;
+        }
+        self.colors = match self.options.annotations {
+            true => Some((cpix.get_width() as usize, rgb_pixels(&cpix)?)),
+            false => None,
+        };
+
+        let pix = leptess::leptonica::Pix {
+            raw: cpix.to_ref_counted(),
+        };
+        self.leptess.set_image(&pix);
+        self.textless = self.options.detect_text && !self.has_text();
+        if self.textless {
+            if self.options.debug {
+                eprintln!("no text detected, not recognized");
+            }
+            self.picked = None;
+        } else if self.options.binarization == Some(Binarization::Auto) {
+            self.pick_binarization(&pix);
+        }
+        Ok(())
+    }
+
+    /// Whether the layout analysis of the image finds a line of text. Recognizing the image
+    /// afterwards reuses the lines that were found
+    fn has_text(&self) -> bool {
+        use leptess::capi::TessPageIteratorLevel_RIL_TEXTLINE as TEXTLINE;
+        self.leptess
+            .get_component_images(TEXTLINE, true)
+            .is_some_and(|x| x.get_n() > 0)
+    }
+
+    /// Cut off `crop_edges` and then, with `trim`, the solid border of what is left
+    fn cut_edges(
+        &mut self,
+        cpix: RefCountedExclusive<leptonica_plumbing::Pix>,
+    ) -> Result<RefCountedExclusive<leptonica_plumbing::Pix>> {
+        let (width, height) = (cpix.get_width(), cpix.get_height());
+        let mut rect = match self.options.crop_edges {
+            Some(edges) => edges.inside(width, height),
+            None => [0, 0, width, height],
+        };
+        if self.options.trim {
+            rect = solid_border(&gray_pixels(&cpix)?, width as usize, rect);
+        }
+        if rect == [0, 0, width, height] {
+            return Ok(cpix);
+        }
+        if self.options.debug {
+            eprintln!("cut edges: {rect:?}");
+        }
+        let [left, top, right, bottom] = rect;
+        let clip_box =
+            leptonica_plumbing::Box::create_valid(left, top, right - left, bottom - top)?;
+        self.cut = Some((rect, (width, height)));
+        unsafe {
+            let ptr = leptonica_sys::pixClipRectangle(
+                *AsRef::<*mut leptonica_sys::Pix>::as_ref(&*cpix),
+                AsRef::<leptonica_sys::Box>::as_ref(&*clip_box) as *const _ as *mut _,
+                std::ptr::null_mut(),
+            );
+            if ptr.is_null() {
+                return Err(anyhow!("failed to cut the edges of the image"));
+            }
+            Ok(RefCountedExclusive::new(
+                leptonica_plumbing::Pix::new_from_pointer(ptr),
+            ))
+        }
+    }
+
+    /// Recognize the image with every binarization method, and leave the results of the one with
+    /// the highest mean confidence
+    fn pick_binarization(&mut self, pix: &leptess::leptonica::Pix) {
+        let mut best = (Binarization::Otsu, -1);
+        for method in Binarization::METHODS {
+            set_binarization(&mut self.leptess, method);
+            // the thresholded image is kept until the image is set again
+            self.leptess.set_image(pix);
+            self.leptess.recognize();
+            let confidence = self.leptess.mean_text_conf();
+            if self.options.debug {
+                eprintln!("binarization {}: {confidence}", method.name());
+            }
+            if confidence > best.1 {
+                best = (method, confidence);
+            }
+        }
+        if best.0 != Binarization::METHODS[2] {
+            set_binarization(&mut self.leptess, best.0);
+            self.leptess.set_image(pix);
+            self.leptess.recognize();
+        }
+        self.picked = Some(best.0);
+    }
+
+    /// Name and version of the engine, for [`OcrOptions::settings`]
+    pub fn engine(_options: &OcrOptions) -> String {
+        // a static string of the library
+        let version = unsafe { CStr::from_ptr(leptess::capi::TessVersion()) };
+        format!("tesseract/{}", version.to_string_lossy())
+    }
+
+    /// Settings that were picked for the last image, to save with its text
+    pub fn properties(&self) -> BTreeMap<String, String> {
+        let mut properties = BTreeMap::new();
+        // the language of the script that `osd` detected
+        if self.options.osd.is_some() {
+            properties.insert("lang".to_owned(), self.lang.clone());
+        }
+        if let Some(picked) = self.picked {
+            properties.insert("binarization".to_owned(), picked.name().to_owned());
+        }
+        if let Some(scale) = self.scale {
+            properties.insert("scale".to_owned(), format!("{scale:.3}"));
+        }
+        if self.textless {
+            properties.insert("textless".to_owned(), "true".to_owned());
         }
-        if let Some(psm) = psm {
-            leptess.raw.set_page_seg_mode(psm.try_into().unwrap());
+        if self.annotated.is_some() {
+            properties.insert("annotations".to_owned(), "true".to_owned());
+        }
+        properties
+    }
+
+    /// Rotate the image upright, and with `--osd` switch to the language of its script, using
+    /// tesseract's orientation and script detection. Word positions are of the rotated image
+    fn orient(
+        &mut self,
+        cpix: RefCountedExclusive<leptonica_plumbing::Pix>,
+    ) -> Result<RefCountedExclusive<leptonica_plumbing::Pix>> {
+        let osd = self.osd.as_mut().expect("osd is loaded");
+        osd.raw.set_image_2(&cpix);
+        let Some((rotation, rotation_confidence, script, script_confidence)) =
+            osd.raw.detect_orientation_script()
+        else {
+            return Ok(cpix);
+        };
+        if self.options.debug {
+            eprintln!(
+                "osd: rotated {rotation} ({rotation_confidence:.1}), script {script} ({script_confidence:.1})"
+            );
+        }
+
+        if let Some(scripts) = &self.options.osd {
+            let lang = match scripts.get(&script) {
+                Some(lang) if script_confidence >= MIN_OSD_CONFIDENCE => lang.clone(),
+                _ => self.options.lang.clone(),
+            };
+            self.switch_lang(&lang)?;
+        }
+
+        if rotation % 360 == 0 || rotation_confidence < MIN_OSD_CONFIDENCE {
+            return Ok(cpix);
+        }
+        // the rotation is clockwise, and so is pixRotateOrth
+        let quads = (360 - rotation.rem_euclid(360)) / 90;
+        self.quads = quads;
+        unsafe {
+            let ptr = leptonica_sys::pixRotateOrth(
+                *AsRef::<*mut leptonica_sys::Pix>::as_ref(&*cpix),
+                quads,
+            );
+            if ptr.is_null() {
+                return Err(anyhow!("failed to rotate image"));
+            }
+            Ok(RefCountedExclusive::new(
+                leptonica_plumbing::Pix::new_from_pointer(ptr),
+            ))
+        }
+    }
+
+    fn switch_lang(&mut self, lang: &str) -> Result<()> {
+        if lang == self.lang {
+            return Ok(());
+        }
+        let api = match self.idle.remove(lang) {
+            Some(api) => api,
+            None => init(lang, &self.options)?,
+        };
+        let previous = mem::replace(&mut self.leptess, api);
+        let previous_lang = mem::replace(&mut self.lang, lang.to_owned());
+        self.idle.insert(previous_lang, previous);
+        Ok(())
+    }
+
+    /// OCR an image that is still encoded, like the bytes of a png
+    pub fn scan_mem(&mut self, data: &[u8]) -> Result<String> {
+        self.scan_pix(leptonica_plumbing::Pix::read_mem(data)?)
+    }
+
+    /// OCR an image that has already been decoded
+    pub fn scan_pix(
+        &mut self,
+        cpix: RefCountedExclusive<leptonica_plumbing::Pix>,
+    ) -> Result<String> {
+        self.set_pix(cpix)?;
+        if self.textless {
+            return Ok(String::new());
+        }
+        let text = self.text()?;
+        match self.colors.take() {
+            Some((width, colors)) => self.annotations(text, width, &colors),
+            None => Ok(text),
+        }
+    }
+
+    /// Text of the image that tesseract was last given
+    fn text(&mut self) -> Result<String> {
+        if self.tables || self.min_word_confidence.is_some() {
+            let mut tsv = self.leptess.get_tsv_text(0)?;
+            if let Some(min) = self.min_word_confidence {
+                tsv = drop_low_confidence(&tsv, min);
+            }
+            return Ok(if self.tables {
+                tsv_to_table(&tsv)
+            } else {
+                self.options.normalize.apply(&tsv_to_text(&tsv))
+            });
+        }
+        Ok(self.options.normalize.apply(&self.leptess.get_utf8_text()?))
+    }
+
+    /// When the first pass found little `text` in a colorful image, OCR every layer from
+    /// [`annotation_layers`] and add the lines and words that it didn't find
+    fn annotations(&mut self, text: String, width: usize, colors: &[[u8; 3]]) -> Result<String> {
+        let letters = text.chars().filter(|x| x.is_alphanumeric()).count();
+        if letters >= ANNOTATION_MAX_LETTERS {
+            return Ok(text);
+        }
+        let colorfulness = colorfulness(colors);
+        if self.options.debug {
+            eprintln!("annotations: {letters} letters, colorfulness {colorfulness:.1}");
+        }
+        if colorfulness < ANNOTATION_MIN_COLORFULNESS {
+            return Ok(text);
+        }
+
+        let mut words = self.words();
+        let mut text = text;
+        let height = colors.len() / width.max(1);
+        for layer in annotation_layers(colors) {
+            let pix = leptess::leptonica::Pix {
+                raw: pix_from_gray(width as u32, height as u32, &layer)?.to_ref_counted(),
+            };
+            self.leptess.set_image(&pix);
+            text = merge_text(&text, &self.text()?);
+            for word in self.words() {
+                if !words.iter().any(|x| x.text == word.text) {
+                    words.push(word);
+                }
+            }
+        }
+        self.annotated = Some(words);
+        Ok(text)
+    }
+
+    /// Words of the last scanned image, with their boxes scaled back to the size of the image
+    /// before `--scale`
+    pub fn words(&mut self) -> Vec<Word> {
+        use leptess::capi::TessPageIteratorLevel_RIL_WORD as WORD;
+        if self.textless {
+            return vec![];
+        }
+        if let Some(words) = &self.annotated {
+            return words.clone();
+        }
+        let Some(mut iter) = self.leptess.raw.get_iterator() else {
+            return vec![];
+        };
+        let scale = self.scale.unwrap_or(1.0);
+        let unscale = |x: i32| (x as f32 / scale).round() as i32;
+        let (x, y) = self.cut.map_or((0, 0), |(rect, size)| {
+            rotated_offset(rect, size, self.quads)
+        });
+        let mut words = vec![];
+        loop {
+            if let (Some(text), Some((left, top, right, bottom))) =
+                (iter.get_utf8_text(WORD), iter.bounding_box(WORD))
+            {
+                let text = text.as_ref().to_string_lossy();
+                let confidence = iter.confidence(WORD);
+                if !text.trim().is_empty()
+                    && self.min_word_confidence.is_none_or(|min| confidence >= min)
+                {
+                    words.push(Word {
+                        text: text.into_owned(),
+                        left: unscale(left) + x,
+                        top: unscale(top) + y,
+                        width: unscale(right - left),
+                        height: unscale(bottom - top),
+                        confidence,
+                    });
+                }
+            }
+            if !iter.next(WORD) {
+                break;
+            }
+        }
+        words
+    }
+}
+
+/// Formats of encoded images, which [`Ocr::scan_bytes`] is told what to expect with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Bmp,
+    Tiff,
+    Webp,
+    /// Whatever the first bytes say it is
+    Any,
+}
+
+impl ImageFormat {
+    /// The format of an encoded image, from its first bytes
+    pub fn detect(data: &[u8]) -> Option<ImageFormat> {
+        Some(match data {
+            [0x89, b'P', b'N', b'G', ..] => ImageFormat::Png,
+            [0xff, 0xd8, 0xff, ..] => ImageFormat::Jpeg,
+            [b'G', b'I', b'F', b'8', ..] => ImageFormat::Gif,
+            [b'B', b'M', ..] => ImageFormat::Bmp,
+            [b'I', b'I', b'*', 0, ..] | [b'M', b'M', 0, b'*', ..] => ImageFormat::Tiff,
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => ImageFormat::Webp,
+            _ => return None,
+        })
+    }
+
+    /// Fail unless `data` is an image of this format, so that a wrong buffer is reported as such
+    /// instead of as an engine error
+    pub fn check(self, data: &[u8]) -> Result<()> {
+        match ImageFormat::detect(data) {
+            Some(_) if self == ImageFormat::Any => Ok(()),
+            Some(found) if found == self => Ok(()),
+            Some(found) => Err(anyhow!("expected a {self:?} image, got a {found:?} image")),
+            None => Err(anyhow!(
+                "not an image that can be read: the format is unknown"
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "engine")]
+impl Ocr {
+    /// OCR an encoded image in memory, like a screenshot that was never saved, along with the
+    /// positions of its words and the settings that were picked for it
+    pub fn scan_bytes(&mut self, data: &[u8], hint: ImageFormat) -> Result<Extracted> {
+        hint.check(data)?;
+        Ok(Extracted {
+            contents: self.scan_mem(data)?,
+            words: self.words(),
+            properties: self.properties(),
+        })
+    }
+}
+
+/// OCR many encoded images at once on the rayon thread pool, loading an engine for every thread
+/// instead of for every image. Results are in the order of `images`, and an image that can't be
+/// read doesn't stop the others. If no engine can be loaded with `options`, every result is
+/// that error
+#[cfg(feature = "engine")]
+pub fn scan_many(options: &OcrOptions, images: &[(&[u8], ImageFormat)]) -> Vec<Result<Extracted>> {
+    use rayon::prelude::*;
+    // cloud services read several images in one request
+    #[cfg(feature = "cloud")]
+    return images
+        .par_chunks(Ocr::batch(options))
+        .map_init(
+            || Ocr::new(options),
+            |ocr, chunk| match ocr {
+                Ok(ocr) => ocr.scan_batch(chunk),
+                Err(e) => chunk.iter().map(|_| Err(anyhow!("{e:#}"))).collect(),
+            },
+        )
+        .flatten_iter()
+        .collect();
+    #[cfg(not(feature = "cloud"))]
+    images
+        .par_iter()
+        .map_init(
+            || Ocr::new(options),
+            |ocr, (data, hint)| match ocr {
+                Ok(ocr) => ocr.scan_bytes(data, *hint),
+                Err(e) => Err(anyhow!("{e:#}")),
+            },
+        )
+        .collect()
+}
+
+/// Load `lang` and apply the settings in `options` to it
+#[cfg(feature = "ocr")]
+fn init(lang: &str, options: &OcrOptions) -> Result<TessApi> {
+    // dictionaries are loaded while initializing, so they can't be set afterwards
+    let mut variables = vec![];
+    for (name, file) in [
+        ("user_words_file", &options.user_words),
+        ("user_patterns_file", &options.user_patterns),
+    ] {
+        if let Some(file) = file {
+            if !file.is_file() {
+                return Err(anyhow!("{} is not a file", file));
+            }
+            variables.push((name, file.as_str()));
         }
+    }
+    let lang = lang.to_ascii_lowercase();
+    let mut leptess = TessApi::with_variables(tessdata(options, &lang)?, &lang, &variables)?;
 
+    if !options.debug {
+        leptess
+            .raw
+            .set_variable(
+                leptess::Variable::DebugFile.as_cstr(),
+                &CString::new("/dev/null").unwrap(),
+            )
+            .unwrap();
+        set_log_level(leptonica_sys::L_SEVERITY_ERROR);
+    }
+    if let Some(binarization) = options.binarization.filter(|x| *x != Binarization::Auto) {
+        set_binarization(&mut leptess, binarization);
+    }
+    if let Some(dpi) = options.dpi {
         leptess
             .raw
             .set_variable(
-                leptess::Variable::TesseditPagesegMode.as_cstr(),
-                &CString::new("11").unwrap(),
+                &CString::new("user_defined_dpi").unwrap(),
+                &CString::new(dpi.to_string()).unwrap(),
             )
             .unwrap();
+    }
+    leptess
+        .raw
+        .set_page_seg_mode(page_seg_mode(options).try_into().unwrap());
 
+    let blacklist = options
+        .char_blacklist
+        .as_deref()
+        .unwrap_or(DEFAULT_BLACKLIST);
+    leptess
+        .raw
+        .set_variable(
+            leptess::Variable::TesseditCharBlacklist.as_cstr(),
+            &CString::new(blacklist).context("null in --char-blacklist")?,
+        )
+        .unwrap();
+    if let Some(whitelist) = &options.char_whitelist {
         leptess
             .raw
             .set_variable(
-                leptess::Variable::TesseditCharBlacklist.as_cstr(),
-                &CString::new("|®»«®©").unwrap(),
+                leptess::Variable::TesseditCharWhitelist.as_cstr(),
+                &CString::new(whitelist.as_str()).context("null in --char-whitelist")?,
             )
             .unwrap();
+    }
+
+    Ok(leptess)
+}
+
+/// The page segmentation mode tesseract is set to, `psm` or [`DEFAULT_PSM`]
+#[cfg(feature = "ocr")]
+fn page_seg_mode(options: &OcrOptions) -> i64 {
+    options.psm.unwrap_or(DEFAULT_PSM)
+}
 
-        Ok(Ocr { leptess, scale })
+#[cfg(feature = "ocr")]
+fn set_binarization(leptess: &mut TessApi, binarization: Binarization) {
+    leptess
+        .raw
+        .set_variable(
+            &CString::new("thresholding_method").unwrap(),
+            &CString::new((binarization as u8).to_string()).unwrap(),
+        )
+        .unwrap();
+}
+
+#[cfg(feature = "ocr")]
+fn read(img: &Path) -> Result<RefCountedExclusive<leptonica_plumbing::Pix>> {
+    let filename = CString::new(img.as_str()).expect("null in filename");
+    Ok(leptonica_plumbing::Pix::read_with_hint(
+        &filename,
+        leptonica_sys::L_JPEG_CONTINUE_WITH_BAD_DATA,
+    )?)
+}
+
+/// Remove speckles and compression artifacts with a 3x3 median filter, which keeps the edges of
+/// letters sharp unlike a blur. The image is turned grayscale first
+#[cfg(feature = "ocr")]
+fn denoise(
+    cpix: &RefCountedExclusive<leptonica_plumbing::Pix>,
+) -> Result<RefCountedExclusive<leptonica_plumbing::Pix>> {
+    unsafe {
+        let ptr =
+            leptonica_sys::pixConvertTo8(*AsRef::<*mut leptonica_sys::Pix>::as_ref(&**cpix), 0);
+        if ptr.is_null() {
+            return Err(anyhow!("failed to convert image to grayscale"));
+        }
+        let gray = RefCountedExclusive::new(leptonica_plumbing::Pix::new_from_pointer(ptr));
+        let ptr =
+            leptonica_sys::pixMedianFilter(*AsRef::<*mut leptonica_sys::Pix>::as_ref(&*gray), 1, 1);
+        if ptr.is_null() {
+            return Err(anyhow!("failed to denoise image"));
+        }
+        Ok(RefCountedExclusive::new(
+            leptonica_plumbing::Pix::new_from_pointer(ptr),
+        ))
     }
-    pub fn scan(&mut self, img: &Path) -> Result<String> {
-        let filename = CString::new(img.as_str()).expect("null in filename");
-        let mut cpix = leptonica_plumbing::Pix::read_with_hint(
-            &filename,
-            leptonica_sys::L_JPEG_CONTINUE_WITH_BAD_DATA,
-        )?;
+}
 
-        if let Some(scale) = self.scale {
-            cpix.scale_general(scale, scale)?;
+/// The pixels of an image in grayscale, row by row
+#[cfg(feature = "ocr")]
+fn gray_pixels(cpix: &RefCountedExclusive<leptonica_plumbing::Pix>) -> Result<Vec<u8>> {
+    unsafe {
+        let ptr =
+            leptonica_sys::pixConvertTo8(*AsRef::<*mut leptonica_sys::Pix>::as_ref(&**cpix), 0);
+        if ptr.is_null() {
+            return Err(anyhow!("failed to convert image to grayscale"));
         }
+        let gray = RefCountedExclusive::new(leptonica_plumbing::Pix::new_from_pointer(ptr));
+        let (width, height) = (gray.get_width() as usize, gray.get_height() as usize);
+        let wpl = leptonica_sys::pixGetWpl(*AsRef::<*mut leptonica_sys::Pix>::as_ref(&*gray));
+        let data = std::slice::from_raw_parts(gray.get_data(), wpl as usize * height);
+        let mut pixels = Vec::with_capacity(width * height);
+        for line in data.chunks(wpl as usize) {
+            // the first pixel is the most significant byte of a word
+            pixels.extend(line.iter().flat_map(|x| x.to_be_bytes()).take(width));
+        }
+        Ok(pixels)
+    }
+}
 
-        self.leptess.set_image(&leptess::leptonica::Pix {
-            raw: cpix.to_ref_counted(),
+/// The pixels of an image in color, row by row
+#[cfg(feature = "ocr")]
+fn rgb_pixels(cpix: &RefCountedExclusive<leptonica_plumbing::Pix>) -> Result<Vec<[u8; 3]>> {
+    unsafe {
+        let ptr = leptonica_sys::pixConvertTo32(*AsRef::<*mut leptonica_sys::Pix>::as_ref(&**cpix));
+        if ptr.is_null() {
+            return Err(anyhow!("failed to convert image to color"));
+        }
+        let rgb = RefCountedExclusive::new(leptonica_plumbing::Pix::new_from_pointer(ptr));
+        let (width, height) = (rgb.get_width() as usize, rgb.get_height() as usize);
+        let data = std::slice::from_raw_parts(rgb.get_data(), width * height);
+        // red is the most significant byte
+        Ok(data
+            .iter()
+            .map(|x| {
+                let [r, g, b, _] = x.to_be_bytes();
+                [r, g, b]
+            })
+            .collect())
+    }
+}
+
+/// Hasler and Süsstrunk's colorfulness of some pixels: 0 for grays, and higher the more and the
+/// brighter colors there are
+#[cfg(feature = "ocr")]
+fn colorfulness(colors: &[[u8; 3]]) -> f32 {
+    if colors.is_empty() {
+        return 0.0;
+    }
+    let n = colors.len() as f32;
+    let opponents = colors.iter().map(|&[r, g, b]| {
+        let (r, g, b) = (r as f32, g as f32, b as f32);
+        (r - g, (r + g) / 2.0 - b)
+    });
+    let (rg_mean, yb_mean) = opponents
+        .clone()
+        .fold((0.0, 0.0), |(a, b), (rg, yb)| (a + rg / n, b + yb / n));
+    let (rg_var, yb_var) = opponents.fold((0.0, 0.0), |(a, b), (rg, yb)| {
+        (
+            a + (rg - rg_mean).powi(2) / n,
+            b + (yb - yb_mean).powi(2) / n,
+        )
+    });
+    (rg_var + yb_var).sqrt() + 0.3 * (rg_mean.powi(2) + yb_mean.powi(2)).sqrt()
+}
+
+/// Grayscale layers of an image that make colored annotations stand out: one where the most
+/// saturated pixels are black and grays are white, and then each of the red, green and blue
+/// channels stretched to the full range of contrast
+#[cfg(feature = "ocr")]
+fn annotation_layers(colors: &[[u8; 3]]) -> Vec<Vec<u8>> {
+    let stretch = |values: Vec<u8>| {
+        let (Some(&min), Some(&max)) = (values.iter().min(), values.iter().max()) else {
+            return values;
+        };
+        if min == max {
+            return values;
+        }
+        let range = (max - min) as u32;
+        values
+            .into_iter()
+            .map(|x| ((x - min) as u32 * 255 / range) as u8)
+            .collect()
+    };
+    let chroma = colors
+        .iter()
+        .map(|x| x.iter().max().unwrap() - x.iter().min().unwrap())
+        .collect();
+    let mut layers = vec![stretch(chroma).into_iter().map(|x| 255 - x).collect()];
+    for channel in 0..3 {
+        layers.push(stretch(colors.iter().map(|x| x[channel]).collect()));
+    }
+    layers
+}
+
+/// `text` followed by the lines of `extra` that aren't in it already
+#[cfg(feature = "ocr")]
+fn merge_text(text: &str, extra: &str) -> String {
+    let mut merged = text.trim_end().to_owned();
+    for line in extra.lines().map(str::trim) {
+        if line.is_empty() || merged.to_lowercase().contains(&line.to_lowercase()) {
+            continue;
+        }
+        if !merged.is_empty() {
+            merged.push('\n');
+        }
+        merged.push_str(line);
+    }
+    merged
+}
+
+/// Top left corner of `rect` of an image of `size` once the image is turned clockwise by `quads`
+/// quarter turns, which is where the words of the part of the turned image start
+#[cfg(feature = "ocr")]
+fn rotated_offset(rect: [i32; 4], size: (i32, i32), quads: i32) -> (i32, i32) {
+    let [left, top, right, bottom] = rect;
+    let (width, height) = size;
+    match quads.rem_euclid(4) {
+        1 => (height - bottom, left),
+        2 => (width - right, height - bottom),
+        3 => (top, width - right),
+        _ => (left, top),
+    }
+}
+
+/// Shrink `rect`, the left, top, right and bottom of part of a grayscale image that is `width`
+/// wide, past the rows and columns at its edges that are one color. Returns `rect` if it's all
+/// one color
+#[cfg(feature = "ocr")]
+fn solid_border(gray: &[u8], width: usize, rect: [i32; 4]) -> [i32; 4] {
+    let at = |x: i32, y: i32| gray[y as usize * width + x as usize];
+    let same = |x: u8, color: u8| x.abs_diff(color) <= TRIM_TOLERANCE;
+    let [mut left, mut top, mut right, mut bottom] = rect;
+
+    let row = |y: i32, color: u8| (left..right).all(|x| same(at(x, y), color));
+    let color = at(left, top);
+    while top < bottom && row(top, color) {
+        top += 1;
+    }
+    let color = at(left, rect[3] - 1);
+    while bottom > top && row(bottom - 1, color) {
+        bottom -= 1;
+    }
+    if top == bottom {
+        return rect;
+    }
+
+    let column = |x: i32, color: u8| (top..bottom).all(|y| same(at(x, y), color));
+    let color = at(left, top);
+    while left < right && column(left, color) {
+        left += 1;
+    }
+    let color = at(right - 1, top);
+    while right > left && column(right - 1, color) {
+        right -= 1;
+    }
+    if left == right {
+        return rect;
+    }
+    [left, top, right, bottom]
+}
+
+/// The `--tessdata` directory, after checking that it has every language of `lang`, since
+/// tesseract only prints a warning when one is missing
+#[cfg(feature = "ocr")]
+fn tessdata<'a>(options: &'a OcrOptions, lang: &str) -> Result<Option<&'a str>> {
+    let Some(dir) = &options.tessdata else {
+        return Ok(None);
+    };
+    for code in lang.split('+') {
+        let file = dir.join(format!("{code}.traineddata"));
+        if !file.is_file() {
+            return Err(anyhow!("{} doesn't exist", file));
+        }
+    }
+    Ok(Some(dir.as_str()))
+}
+
+/// Check that `lang` is a tesseract language code, or several joined with `+` like `eng+deu`,
+/// and not a path to some other file
+///
+/// Each code is three letters, optionally followed by variants like `chi_sim` or `chi_tra_vert`.
+pub fn validate_lang(lang: &str) -> Result<()> {
+    let valid = |code: &str| {
+        let mut parts = code.split('_');
+        parts
+            .next()
+            .is_some_and(|x| x.len() == 3 && x.chars().all(|c| c.is_ascii_alphabetic()))
+            && parts.all(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_alphanumeric()))
+    };
+    if let Some(code) = lang.split('+').find(|x| !valid(x)) {
+        return Err(anyhow!("Invalid language code: {:?} in {:?}", code, lang));
+    }
+    Ok(())
+}
+
+/// The BCP-47 tag of a tesseract language code, for the languages that Windows has OCR packs
+/// for. Cloud services read all of them too
+#[cfg(any(feature = "windows-ocr", feature = "cloud"))]
+fn language_tag(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "ara" => "ar",
+        "bos" => "bs",
+        "ces" => "cs",
+        "chi_sim" => "zh-Hans",
+        "chi_tra" => "zh-Hant",
+        "dan" => "da",
+        "deu" => "de",
+        "ell" => "el",
+        "eng" => "en",
+        "fin" => "fi",
+        "fra" => "fr",
+        "hrv" => "hr",
+        "hun" => "hu",
+        "ita" => "it",
+        "jpn" => "ja",
+        "kor" => "ko",
+        "nld" => "nl",
+        "nor" => "nb",
+        "pol" => "pl",
+        "por" => "pt",
+        "ron" => "ro",
+        "rus" => "ru",
+        "slk" => "sk",
+        "slv" => "sl",
+        "spa" => "es",
+        "srp" => "sr-Cyrl",
+        "srp_latn" => "sr-Latn",
+        "swe" => "sv",
+        "tur" => "tr",
+        _ => return None,
+    })
+}
+
+/// Escape text for xml attributes and content
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Wrap the `ocr_page` div that tesseract returns into a whole hOCR document, like the one
+/// `tesseract image out hocr` writes
+#[cfg(feature = "ocr")]
+fn hocr_document(title: &str, page: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Transitional//EN"
+    "http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd">
+<html xmlns="http://www.w3.org/1999/xhtml" xml:lang="en" lang="en">
+ <head>
+  <title>{}</title>
+  <meta http-equiv="Content-Type" content="text/html;charset=utf-8"/>
+  <meta name='ocr-system' content='tesseract' />
+  <meta name='ocr-capabilities' content='ocr_page ocr_carea ocr_par ocr_line ocrx_word ocrp_wconf'/>
+ </head>
+ <body>
+{} </body>
+</html>
+"#,
+        xml_escape(title),
+        page
+    )
+}
+
+/// Serialize words into an ALTO v4 document of a single page
+///
+/// Only words and their positions are stored, so the page is one `TextBlock` and lines are
+/// rebuilt from the order of the words: a word starts a new line when it is left of the word
+/// before it or doesn't overlap it vertically. Without `size` the page is as large as the
+/// words on it.
+pub fn alto_document(filename: &str, size: Option<(i32, i32)>, words: &[Word]) -> String {
+    let bounds = |words: &[Word]| {
+        let left = words.iter().map(|x| x.left).min().unwrap_or(0);
+        let top = words.iter().map(|x| x.top).min().unwrap_or(0);
+        let right = words.iter().map(|x| x.left + x.width).max().unwrap_or(0);
+        let bottom = words.iter().map(|x| x.top + x.height).max().unwrap_or(0);
+        format!(
+            r#"HPOS="{left}" VPOS="{top}" WIDTH="{}" HEIGHT="{}""#,
+            right - left,
+            bottom - top
+        )
+    };
+    let (width, height) = size.unwrap_or_else(|| {
+        (
+            words.iter().map(|x| x.left + x.width).max().unwrap_or(0),
+            words.iter().map(|x| x.top + x.height).max().unwrap_or(0),
+        )
+    });
+
+    let mut lines: Vec<&[Word]> = vec![];
+    let mut start = 0;
+    for i in 1..=words.len() {
+        let new_line = words.get(i).is_none_or(|word| {
+            let prev = &words[i - 1];
+            let center = word.top + word.height / 2;
+            word.left < prev.left + prev.width / 2
+                || center < prev.top
+                || center > prev.top + prev.height
         });
+        if new_line {
+            lines.push(&words[start..i]);
+            start = i;
+        }
+    }
+
+    let mut layout = String::new();
+    if !words.is_empty() {
+        layout += &format!("        <TextBlock ID=\"block_0\" {}>\n", bounds(words));
+        let mut id = 0;
+        for (i, line) in lines.iter().enumerate() {
+            layout += &format!("          <TextLine ID=\"line_{i}\" {}>\n", bounds(line));
+            for word in line.iter() {
+                layout += &format!(
+                    "            <String ID=\"string_{id}\" {} WC=\"{:.2}\" CONTENT=\"{}\"/>\n",
+                    bounds(std::slice::from_ref(word)),
+                    word.confidence / 100.0,
+                    xml_escape(&word.text)
+                );
+                id += 1;
+            }
+            layout += "          </TextLine>\n";
+        }
+        layout += "        </TextBlock>\n";
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<alto xmlns="http://www.loc.gov/standards/alto/ns-v4#">
+  <Description>
+    <MeasurementUnit>pixel</MeasurementUnit>
+    <sourceImageInformation>
+      <fileName>{}</fileName>
+    </sourceImageInformation>
+    <OCRProcessing ID="ocr_0">
+      <ocrProcessingStep>
+        <processingSoftware>
+          <softwareName>ocrlocate</softwareName>
+          <softwareVersion>{}</softwareVersion>
+        </processingSoftware>
+      </ocrProcessingStep>
+    </OCRProcessing>
+  </Description>
+  <Layout>
+    <Page ID="page_0" PHYSICAL_IMG_NR="1" WIDTH="{width}" HEIGHT="{height}">
+      <PrintSpace HPOS="0" VPOS="0" WIDTH="{width}" HEIGHT="{height}">
+{layout}      </PrintSpace>
+    </Page>
+  </Layout>
+</alto>
+"#,
+        xml_escape(filename),
+        env!("CARGO_PKG_VERSION"),
+    )
+}
 
-        Ok(self.leptess.get_utf8_text()?.replace("\n\n", "\n"))
+/// A word recognized by tesseract and where it is on the image
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Word {
+    pub text: String,
+    pub left: i32,
+    pub top: i32,
+    pub width: i32,
+    pub height: i32,
+    /// From 0 to 100
+    pub confidence: f32,
+}
+
+/// The box around the first run of words that contains `query`, ignoring case and spacing
+///
+/// Returns `(left, top, width, height)`.
+pub fn find_words(words: &[Word], query: &str) -> Option<(i32, i32, i32, i32)> {
+    let query = query.split_whitespace().join(" ").to_lowercase();
+    let query_words = query.split(' ').count();
+    // find the shortest run of words that ends first. A match can start and end in the middle
+    // of a word, so a run has at most one more word than the query
+    for end in 0..words.len() {
+        for start in (end.saturating_sub(query_words)..=end).rev() {
+            let text = words[start..=end]
+                .iter()
+                .map(|x| x.text.to_lowercase())
+                .join(" ");
+            if text.contains(&query) {
+                let words = &words[start..=end];
+                let left = words.iter().map(|x| x.left).min()?;
+                let top = words.iter().map(|x| x.top).min()?;
+                let right = words.iter().map(|x| x.left + x.width).max()?;
+                let bottom = words.iter().map(|x| x.top + x.height).max()?;
+                return Some((left, top, right - left, bottom - top));
+            }
+        }
+    }
+    None
+}
+
+/// Write the part of the image at `input` inside of `rect` to `output` as a png
+#[cfg(feature = "ocr")]
+pub fn crop(input: &Path, rect: (i32, i32, i32, i32), output: &Path) -> Result<()> {
+    let pix = leptonica_plumbing::Pix::read(&CString::new(input.as_str())?)?;
+    let (left, top, width, height) = rect;
+    let clip_box = leptonica_plumbing::Box::create_valid(left, top, width, height)?;
+    let cropped = unsafe {
+        let ptr = leptonica_sys::pixClipRectangle(
+            *AsRef::<*mut leptonica_sys::Pix>::as_ref(&*pix),
+            AsRef::<leptonica_sys::Box>::as_ref(&*clip_box) as *const _ as *mut _,
+            std::ptr::null_mut(),
+        );
+        if ptr.is_null() {
+            return Err(anyhow!("crop is outside of the image"));
+        }
+        RefCountedExclusive::new(leptonica_plumbing::Pix::new_from_pointer(ptr))
+    };
+    let ret = unsafe {
+        leptonica_sys::pixWrite(
+            CString::new(output.as_str())?.as_ptr(),
+            *AsRef::<*mut leptonica_sys::Pix>::as_ref(&*cropped),
+            leptonica_sys::IFF_PNG as i32,
+        )
+    };
+    if ret != 0 {
+        return Err(anyhow!("failed to write {}", output));
     }
+    Ok(())
+}
+
+/// A recognized word from tesseract's tsv output
+struct TsvWord<'a> {
+    left: i32,
+    top: i32,
+    width: i32,
+    height: i32,
+    text: &'a str,
 }
 
+/// Remove the words with less than `min` confidence from tesseract's tsv output
+#[cfg(feature = "ocr")]
+fn drop_low_confidence(tsv: &str, min: f32) -> String {
+    tsv.lines()
+        .filter(|line| {
+            let cols: Vec<&str> = line.splitn(12, '\t').collect();
+            // level 5 is a word
+            !(cols.len() == 12 && cols[0] == "5" && cols[10].parse::<f32>().is_ok_and(|x| x < min))
+        })
+        .join("\n")
+}
+
+/// The text of tesseract's tsv output, with a line for every line tesseract found and an empty
+/// line between paragraphs
+#[cfg(feature = "ocr")]
+fn tsv_to_text(tsv: &str) -> String {
+    let mut text = String::new();
+    let mut last_paragraph = None;
+    let lines = tsv
+        .lines()
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.splitn(12, '\t').collect();
+            if cols.len() != 12 || cols[0] != "5" || cols[11].trim().is_empty() {
+                return None;
+            }
+            // block, paragraph and line number
+            Some(((cols[2], cols[3], cols[4]), cols[11].trim()))
+        })
+        .group_by(|(line, _)| *line);
+    for ((block, paragraph, _), words) in &lines {
+        match last_paragraph {
+            Some(last) if last != (block, paragraph) => text.push_str("\n\n"),
+            Some(_) => text.push('\n'),
+            None => (),
+        }
+        last_paragraph = Some((block, paragraph));
+        text.push_str(&words.map(|(_, word)| word).join(" "));
+    }
+    text
+}
+
+/// Rebuild table rows from tesseract's tsv output
+///
+/// Words are grouped into rows by their vertical position, regardless of which block tesseract
+/// put them in, since sparse page segmentation tends to split every cell into its own block.
+/// Words in a row that are further apart than the height of the text are separated by a tab.
+pub fn tsv_to_table(tsv: &str) -> String {
+    let mut words: Vec<TsvWord> = tsv
+        .lines()
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.splitn(12, '\t').collect();
+            // level 5 is a word
+            if cols.len() != 12 || cols[0] != "5" || cols[11].trim().is_empty() {
+                return None;
+            }
+            Some(TsvWord {
+                left: cols[6].parse().ok()?,
+                top: cols[7].parse().ok()?,
+                width: cols[8].parse().ok()?,
+                height: cols[9].parse().ok()?,
+                text: cols[11].trim(),
+            })
+        })
+        .collect();
+    words.sort_by_key(|w| w.top + w.height / 2);
+
+    let mut rows: Vec<Vec<TsvWord>> = vec![];
+    for word in words {
+        let center = word.top + word.height / 2;
+        match rows.last_mut() {
+            Some(row) if center <= row[0].top + row[0].height => row.push(word),
+            _ => rows.push(vec![word]),
+        }
+    }
+
+    rows.into_iter()
+        .map(|mut row| {
+            row.sort_by_key(|w| w.left);
+            let mut line = String::new();
+            for (i, word) in row.iter().enumerate() {
+                if i > 0 {
+                    let prev = &row[i - 1];
+                    let gap = word.left - (prev.left + prev.width);
+                    line.push(if gap > prev.height.max(word.height) {
+                        '\t'
+                    } else {
+                        ' '
+                    });
+                }
+                line.push_str(word.text);
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Copy 8-bit interleaved RGB rows into a new 32bpp Pix
+#[cfg(feature = "ocr")]
+pub fn pix_from_rgb(
+    width: u32,
+    height: u32,
+    stride: usize,
+    data: &[u8],
+) -> Result<RefCountedExclusive<leptonica_plumbing::Pix>> {
+    if data.len() < stride * height as usize || stride < width as usize * 3 {
+        return Err(anyhow!("image buffer is too small for {width}x{height}"));
+    }
+    let pix = unsafe {
+        let ptr = leptonica_sys::pixCreate(width.try_into()?, height.try_into()?, 32);
+        if ptr.is_null() {
+            return Err(anyhow!("failed to allocate {width}x{height} image"));
+        }
+        RefCountedExclusive::new(leptonica_plumbing::Pix::new_from_pointer(ptr))
+    };
+    // leptonica stores 32bpp pixels as one word each, with red in the most significant byte
+    let words =
+        unsafe { std::slice::from_raw_parts_mut(pix.get_data(), width as usize * height as usize) };
+    for (y, row) in data.chunks(stride).take(height as usize).enumerate() {
+        for (x, rgb) in row.chunks_exact(3).take(width as usize).enumerate() {
+            words[y * width as usize + x] = u32::from_be_bytes([rgb[0], rgb[1], rgb[2], 0]);
+        }
+    }
+    Ok(pix)
+}
+
+/// Copy 8-bit grayscale rows into a new 8bpp Pix
+#[cfg(feature = "ocr")]
+fn pix_from_gray(
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Result<RefCountedExclusive<leptonica_plumbing::Pix>> {
+    if data.len() < width as usize * height as usize {
+        return Err(anyhow!("image buffer is too small for {width}x{height}"));
+    }
+    unsafe {
+        let ptr = leptonica_sys::pixCreate(width.try_into()?, height.try_into()?, 8);
+        if ptr.is_null() {
+            return Err(anyhow!("failed to allocate {width}x{height} image"));
+        }
+        let pix = RefCountedExclusive::new(leptonica_plumbing::Pix::new_from_pointer(ptr));
+        let wpl = leptonica_sys::pixGetWpl(ptr) as usize;
+        let words = std::slice::from_raw_parts_mut(pix.get_data(), wpl * height as usize);
+        for (line, row) in words.chunks_mut(wpl).zip(data.chunks(width as usize)) {
+            // the first pixel is the most significant byte of a word
+            for (word, pixels) in line.iter_mut().zip(row.chunks(4)) {
+                let mut bytes = [0; 4];
+                bytes[..pixels.len()].copy_from_slice(pixels);
+                *word = u32::from_be_bytes(bytes);
+            }
+        }
+        Ok(pix)
+    }
+}
+
+#[cfg(feature = "ocr")]
 fn set_log_level(level: u32) {
     unsafe {
         leptonica_sys::setMsgSeverity(level.try_into().unwrap());
@@ -101,11 +1867,12 @@ fn set_log_level(level: u32) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::iter;
-    use std::process::Command;
-    use tempfile::NamedTempFile;
-    use tempfile::TempPath;
+    #[cfg(feature = "ocr")]
+    use std::{iter, process::Command};
+    #[cfg(feature = "ocr")]
+    use tempfile::{NamedTempFile, TempPath};
 
+    #[cfg(feature = "ocr")]
     fn test_image() -> TempPath {
         let path = NamedTempFile::new().unwrap().into_temp_path();
         let result = Command::new("convert")
@@ -125,13 +1892,484 @@ mod tests {
         assert!(result.success());
         path
     }
+
+    #[cfg(feature = "ocr")]
+    #[test]
+    fn hocr() {
+        let page = "  <div class='ocr_page' id='page_1'></div>\n";
+        let doc = hocr_document("a&b <1>.png", page);
+        assert!(doc.contains("<title>a&amp;b &lt;1&gt;.png</title>"));
+        assert!(doc.contains(page));
+        assert!(doc.trim_end().ends_with("</html>"));
+    }
+
+    #[test]
+    fn alto() {
+        let word = |text: &str, left, top| Word {
+            text: text.into(),
+            left,
+            top,
+            width: 40,
+            height: 10,
+            confidence: 90.0,
+        };
+        let words = [
+            word("one", 0, 0),
+            word("two", 50, 2),
+            word("<three>", 0, 20),
+        ];
+        let doc = alto_document("a.png", Some((100, 50)), &words);
+        assert!(doc.contains(r#"<Page ID="page_0" PHYSICAL_IMG_NR="1" WIDTH="100" HEIGHT="50">"#));
+        assert!(doc.contains(r#"<TextLine ID="line_0" HPOS="0" VPOS="0" WIDTH="90" HEIGHT="12">"#));
+        assert!(doc.contains(
+            r#"<String ID="string_2" HPOS="0" VPOS="20" WIDTH="40" HEIGHT="10" WC="0.90" CONTENT="&lt;three&gt;"/>"#
+        ));
+        assert_eq!(doc.matches("<TextLine ").count(), 2);
+
+        let empty = alto_document("a.png", None, &[]);
+        assert!(empty.contains(r#"WIDTH="0" HEIGHT="0""#));
+        assert!(!empty.contains("<TextBlock"));
+    }
+
+    #[test]
+    fn alto_golden() -> Result<()> {
+        let seeded = crate::fixture::seed("search")?;
+        for (name, image) in [("alto.xml", 0), ("alto-escaping.xml", 1)] {
+            let image = &seeded.fixture.image[image];
+            let doc = alto_document(&image.path, Some((320, 180)), &image.words);
+            crate::fixture::assert_golden(
+                name,
+                &doc.replace(env!("CARGO_PKG_VERSION"), "$VERSION"),
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn find() {
+        let word = |text: &str, left| Word {
+            text: text.into(),
+            left,
+            top: 10,
+            width: 40,
+            height: 10,
+            confidence: 90.0,
+        };
+        let words = [word("Your", 0), word("code:", 50), word("XK42-9", 100)];
+        assert_eq!(find_words(&words, "xk42"), Some((100, 10, 40, 10)));
+        assert_eq!(find_words(&words, "code:  XK42"), Some((50, 10, 90, 10)));
+        assert_eq!(find_words(&words, "missing"), None);
+    }
+
+    #[test]
+    fn table() {
+        let tsv = [
+            "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext",
+            "1\t1\t0\t0\t0\t0\t0\t0\t400\t100\t-1\t",
+            "5\t1\t1\t1\t1\t1\t10\t10\t40\t12\t96\tTotal",
+            "5\t1\t1\t1\t1\t2\t55\t11\t30\t12\t96\tdue",
+            "5\t1\t2\t1\t1\t1\t200\t12\t20\t12\t95\t42",
+            "5\t1\t3\t1\t1\t1\t10\t40\t40\t12\t91\tTax",
+            "5\t1\t4\t1\t1\t1\t200\t39\t20\t12\t90\t7",
+        ]
+        .join("\n");
+        assert_eq!(tsv_to_table(&tsv), "Total due\t42\nTax\t7");
+    }
+
+    #[test]
+    fn languages() {
+        for lang in ["eng", "eng+deu", "chi_sim+eng", "chi_tra_vert", "ENG"] {
+            assert!(validate_lang(lang).is_ok(), "{lang}");
+        }
+        for lang in [
+            "",
+            "en",
+            "eng+",
+            "+eng",
+            "../eng",
+            "eng.traineddata",
+            "chi_",
+            "ëng",
+        ] {
+            assert!(validate_lang(lang).is_err(), "{lang}");
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "eng.traineddata",
+            "osd.traineddata",
+            "chi_sim.traineddata",
+            "eng.user-words",
+        ] {
+            std::fs::write(dir.path().join(file), "").unwrap();
+        }
+        let dir = PathBuf::try_from(dir.path().to_path_buf()).unwrap();
+        assert_eq!(installed_langs(Some(&dir)), ["chi_sim", "eng"]);
+    }
+
+    #[cfg(feature = "ocr")]
+    #[test]
+    fn low_confidence() {
+        let tsv = [
+            "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext",
+            "5\t1\t1\t1\t1\t1\t10\t10\t40\t12\t96\tTotal",
+            "5\t1\t1\t1\t1\t2\t55\t11\t30\t12\t12.5\t~%",
+            "5\t1\t1\t1\t1\t3\t90\t11\t30\t12\t91\tdue",
+            "5\t1\t1\t1\t2\t1\t10\t40\t40\t12\t8\tWy",
+            "5\t1\t2\t1\t1\t1\t200\t39\t20\t12\t90\t7",
+        ]
+        .join("\n");
+        assert_eq!(tsv_to_text(&tsv), "Total ~% due\nWy\n\n7");
+        assert_eq!(
+            tsv_to_text(&drop_low_confidence(&tsv, 50.0)),
+            "Total due\n\n7"
+        );
+    }
+
+    #[test]
+    fn normalize() -> Result<()> {
+        let text =
+            "Dear  customer,\nyour exam-\nple   order\n\nhas  shipped.\n\n\n\nA - B\nnon-\nStop\n";
+        assert_eq!(
+            Normalize::default().apply(text),
+            "Dear  customer,\nyour exam-\nple   order\nhas  shipped.\n\nA - B\nnon-\nStop\n"
+        );
+        let all: Normalize = "paragraphs,squash,hyphens".parse()?;
+        assert_eq!(
+            all.apply(text),
+            "Dear customer,\nyour example\norder\n\nhas shipped.\n\nA - B\nnon-\nStop"
+        );
+        assert_eq!("default".parse::<Normalize>()?, Normalize::default());
+        assert!("nfkc".parse::<Normalize>()?.compatibility);
+        assert!("paragraph".parse::<Normalize>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn edges() -> Result<()> {
+        let edges: Edges = "bottom:40px,top:5%".parse()?;
+        assert_eq!(edges.bottom, Length::Pixels(40));
+        assert_eq!(edges.top, Length::Percent(5.0));
+        assert_eq!(edges.inside(200, 100), [0, 5, 200, 60]);
+        // an image smaller than the edges isn't cropped
+        assert_eq!(edges.inside(200, 40), [0, 0, 200, 40]);
+        assert_eq!(
+            "left:300px".parse::<Edges>()?.inside(200, 100),
+            [0, 0, 200, 100]
+        );
+        for invalid in ["bottom", "bottom:40", "middle:1px", "top:100%", "left:-1px"] {
+            assert!(invalid.parse::<Edges>().is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "ocr")]
+    #[test]
+    fn border() {
+        // a black bar on top, and a white bar on the left with an uneven pixel, of text
+        let mut gray = vec![0; 8 * 6];
+        for y in 2..6 {
+            for x in 0..8 {
+                gray[y * 8 + x] = match x {
+                    0 | 1 => 250,
+                    _ if (x + y) % 2 == 0 => 40,
+                    _ => 200,
+                };
+            }
+        }
+        gray[3 * 8] = 255;
+        assert_eq!(solid_border(&gray, 8, [0, 0, 8, 6]), [2, 2, 8, 6]);
+        assert_eq!(solid_border(&gray, 8, [0, 0, 8, 2]), [0, 0, 8, 2]);
+        assert_eq!(solid_border(&gray, 8, [0, 2, 8, 6]), [2, 2, 8, 6]);
+    }
+
+    #[cfg(feature = "ocr")]
+    #[test]
+    fn rotated_crop() {
+        // the bottom 40 pixels of a 200x100 image cut off, and the right 10
+        let rect = [0, 0, 190, 60];
+        assert_eq!(rotated_offset(rect, (200, 100), 0), (0, 0));
+        // turned clockwise the cut off bottom is on the left
+        assert_eq!(rotated_offset(rect, (200, 100), 1), (40, 0));
+        assert_eq!(rotated_offset(rect, (200, 100), 2), (10, 40));
+        assert_eq!(rotated_offset(rect, (200, 100), 3), (0, 10));
+    }
+
+    #[cfg(feature = "ocr")]
+    #[test]
+    fn annotations() {
+        // a gray screenshot with a red arrow over a tenth of it
+        let mut colors = vec![[200, 200, 200]; 50];
+        colors.extend([[120, 120, 120]; 40]);
+        colors.extend([[230, 40, 40]; 10]);
+        assert_eq!(colorfulness(&colors[..90]), 0.0);
+        assert!(colorfulness(&colors) > ANNOTATION_MIN_COLORFULNESS);
+
+        let layers = annotation_layers(&colors);
+        assert_eq!(layers.len(), 4);
+        assert_eq!((layers[0][0], layers[0][60], layers[0][95]), (255, 255, 0));
+        // red is brightest on the red channel, and darkest on the others
+        assert_eq!((layers[1][0], layers[1][60], layers[1][95]), (185, 0, 255));
+        assert_eq!((layers[2][0], layers[2][95]), (255, 0));
+
+        assert_eq!(merge_text("", "\nDONE\n"), "DONE");
+        assert_eq!(
+            merge_text("Total 12\n", "total 12\nfix this\nFix this"),
+            "Total 12\nfix this"
+        );
+    }
+
+    #[cfg(feature = "ocr")]
+    #[test]
+    fn psm() {
+        assert_eq!(page_seg_mode(&OcrOptions::default()), DEFAULT_PSM);
+        let options = OcrOptions {
+            psm: Some(6),
+            ..Default::default()
+        };
+        assert_eq!(page_seg_mode(&options), 6);
+    }
+
+    #[cfg(feature = "engine")]
+    #[test]
+    fn settings() {
+        let default = OcrOptions::default();
+        assert_eq!(default.text_settings(), ["lang=eng"]);
+        let changed = [
+            OcrOptions {
+                lang: "deu".into(),
+                ..Default::default()
+            },
+            OcrOptions {
+                psm: Some(6),
+                ..Default::default()
+            },
+            OcrOptions {
+                scale: Some(0.5),
+                ..Default::default()
+            },
+            OcrOptions {
+                resize: Some(Resize::LongestSide(2000)),
+                ..Default::default()
+            },
+            OcrOptions {
+                resize: Some(Resize::Dpi(2000)),
+                ..Default::default()
+            },
+            OcrOptions {
+                binarization: Some(Binarization::Sauvola),
+                ..Default::default()
+            },
+            OcrOptions {
+                dpi: Some(300),
+                ..Default::default()
+            },
+            OcrOptions {
+                char_whitelist: Some("0123456789".into()),
+                ..Default::default()
+            },
+            OcrOptions {
+                char_blacklist: Some("|".into()),
+                ..Default::default()
+            },
+            OcrOptions {
+                user_words: Some("words.txt".into()),
+                ..Default::default()
+            },
+            OcrOptions {
+                user_patterns: Some("patterns.txt".into()),
+                ..Default::default()
+            },
+            OcrOptions {
+                tessdata: Some("/usr/share/tessdata_best".into()),
+                ..Default::default()
+            },
+            OcrOptions {
+                tables: true,
+                ..Default::default()
+            },
+            OcrOptions {
+                normalize: Normalize {
+                    compatibility: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            OcrOptions {
+                normalize: Normalize {
+                    join_hyphens: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            OcrOptions {
+                min_word_confidence: Some(50.0),
+                ..Default::default()
+            },
+            OcrOptions {
+                osd: Some(BTreeMap::from([("Japanese".into(), "jpn".into())])),
+                ..Default::default()
+            },
+            OcrOptions {
+                auto_rotate: true,
+                ..Default::default()
+            },
+            OcrOptions {
+                denoise: true,
+                ..Default::default()
+            },
+            OcrOptions {
+                trim: true,
+                ..Default::default()
+            },
+            OcrOptions {
+                crop_edges: Some("bottom:40px".parse().unwrap()),
+                ..Default::default()
+            },
+            OcrOptions {
+                detect_text: true,
+                ..Default::default()
+            },
+            OcrOptions {
+                annotations: true,
+                ..Default::default()
+            },
+            OcrOptions {
+                onnx_models: Some("models".into()),
+                ..Default::default()
+            },
+        ];
+        let settings = changed
+            .iter()
+            .map(|x| x.text_settings().join(" "))
+            .collect_vec();
+        assert!(settings.iter().all_unique(), "{settings:#?}");
+        assert!(!settings.contains(&default.text_settings().join(" ")));
+        assert!(settings.contains(&"lang=eng resize=2000px".to_owned()));
+
+        // the options of a file are rebuilt from its settings, and not from the current ones
+        let indexed = OcrOptions {
+            lang: "jpn".into(),
+            psm: Some(5),
+            scale: Some(0.5),
+            ..Default::default()
+        };
+        let current = OcrOptions {
+            psm: Some(6),
+            denoise: true,
+            ..Default::default()
+        };
+        let options = current.with_settings(&indexed.text_settings().join(" "));
+        assert_eq!(
+            (options.lang.as_str(), options.psm, options.scale),
+            ("jpn", Some(5), Some(0.5))
+        );
+        assert!(options.denoise);
+        let options = current.with_settings("lang=eng engine=tesseract/5.3.0");
+        assert_eq!((options.lang.as_str(), options.psm), ("eng", None));
+    }
+
+    #[test]
+    fn resize() -> Result<()> {
+        assert_eq!("2000px".parse::<Resize>()?, Resize::LongestSide(2000));
+        assert_eq!("300dpi".parse::<Resize>()?, Resize::Dpi(300));
+        assert_eq!("0.5".parse::<Resize>()?, Resize::Scale(0.5));
+        for invalid in ["0px", "-1", "big", "px", "NaN"] {
+            assert!(invalid.parse::<Resize>().is_err(), "{invalid}");
+        }
+        assert_eq!(Resize::LongestSide(2000).scale(4000, 3000, 0), Some(0.5));
+        assert_eq!(Resize::LongestSide(2000).scale(500, 1000, 0), Some(2.0));
+        assert_eq!(Resize::Dpi(300).scale(100, 100, 150), Some(2.0));
+        assert_eq!(Resize::Dpi(300).scale(100, 100, 0), None);
+        Ok(())
+    }
+
+    #[cfg(feature = "ocr")]
     #[test]
     #[ignore]
     fn scan() -> Result<()> {
-        let mut ocr = Ocr::new("eng", true, None, None, Some(11)).unwrap();
+        let mut ocr = Ocr::new(&OcrOptions {
+            debug: true,
+            psm: Some(11),
+            ..Default::default()
+        })
+        .unwrap();
         let image = test_image();
         let result = ocr.scan(Path::from_path(&image).unwrap()).unwrap();
         assert!(result.contains("needle"));
+
+        let mut ocr = Ocr::new(&OcrOptions {
+            detect_text: true,
+            ..Default::default()
+        })?;
+        let result = ocr.scan(Path::from_path(&image).unwrap())?;
+        assert!(result.contains("needle"));
+        let blank = NamedTempFile::new()?.into_temp_path();
+        let status = Command::new("convert")
+            .args(["-size", "300x70", "xc:lightblue"])
+            .arg(format!("png:{}", blank.to_str().unwrap()))
+            .status()?;
+        assert!(status.success());
+        assert_eq!(ocr.scan(Path::from_path(&blank).unwrap())?, "");
+        assert!(ocr.words().is_empty());
+        assert_eq!(ocr.properties()["textless"], "true");
+
+        let png = std::fs::read(&image)?;
+        let scanned = ocr.scan_bytes(&png, ImageFormat::Png)?;
+        assert!(scanned.contents.contains("needle"));
+        assert!(!scanned.words.is_empty());
+        assert!(ocr.scan_bytes(&png, ImageFormat::Jpeg).is_err());
+        let results = scan_many(
+            &OcrOptions::default(),
+            &[(&png, ImageFormat::Any), (b"text", ImageFormat::Any)],
+        );
+        assert!(results[0].as_ref().unwrap().contents.contains("needle"));
+        assert!(results[1].is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unicode() {
+        assert_eq!(normalize_unicode("cafe\u{301}", false), "café");
+        assert_eq!(
+            normalize_unicode("ＡＢＣ１２３ ｶﾀｶﾅ", false),
+            "ＡＢＣ１２３ ｶﾀｶﾅ"
+        );
+        assert_eq!(
+            normalize_unicode("ＡＢＣ１２３ ｶﾀｶﾅ", true),
+            "ABC123 カタカナ"
+        );
+        assert_eq!(normalize_unicode("ﬁle", true), "file");
+    }
+
+    #[test]
+    fn image_format() {
+        let png = b"\x89PNG\r\n\x1a\n";
+        assert_eq!(ImageFormat::detect(png), Some(ImageFormat::Png));
+        assert_eq!(
+            ImageFormat::detect(b"RIFF\0\0\0\0WEBPVP8 "),
+            Some(ImageFormat::Webp)
+        );
+        assert_eq!(ImageFormat::detect(b"RIFF\0\0\0\0WAVE"), None);
+        assert!(ImageFormat::Png.check(png).is_ok());
+        assert!(ImageFormat::Any.check(png).is_ok());
+        assert!(ImageFormat::Jpeg.check(png).is_err());
+        assert!(ImageFormat::Any.check(b"").is_err());
+    }
+
+    #[test]
+    fn model_dir() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let root = Path::from_path(temp.path()).unwrap();
+        let tessdata = root.join("tessdata");
+        std::fs::create_dir_all(tessdata.join("fast"))?;
+        std::fs::create_dir(root.join("tessdata_best"))?;
+        assert_eq!(
+            Model::Best.dir(Some(&tessdata))?,
+            root.join("tessdata_best")
+        );
+        assert_eq!(Model::Fast.dir(Some(&tessdata))?, tessdata.join("fast"));
+        assert!(Model::Fast.dir(Some(&root.join("missing"))).is_err());
         Ok(())
     }
 }
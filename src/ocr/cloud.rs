@@ -0,0 +1,526 @@
+//! OCR with a cloud service, Google Cloud Vision or the Read API of Azure AI Vision. Images are
+//! uploaded as they are, so the service reads every format it knows
+//!
+//! Requests of every thread share one rate limit, and the ones that failed with a rate limit, a
+//! server error or a dropped connection are sent again after a backoff. Only the language of
+//! [`OcrOptions`] applies, as a hint. [`super::scan_many`] sends images to Google [`BATCH`] at a
+//! time.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use camino::Utf8Path as Path;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{language_tag, validate_lang, Cloud, ImageFormat, OcrOptions, Provider, Word};
+use crate::extract::Extracted;
+
+/// How many times a request is sent again
+const RETRIES: u32 = 4;
+
+/// How many times the result of Azure is asked for, half a second apart, before giving up on it
+const POLLS: u32 = 120;
+
+/// How many images Google reads in one request
+pub const BATCH: usize = 16;
+
+/// Text of an image and its words
+type Scanned = (String, Vec<Word>);
+
+/// When the next request may be sent, shared by every thread
+static NEXT: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Debug)]
+pub struct Ocr {
+    cloud: Cloud,
+    /// Hint for the service, which finds the language by itself without one
+    language: Option<&'static str>,
+    agent: ureq::Agent,
+    words: Vec<Word>,
+}
+
+impl Ocr {
+    pub fn new(options: &OcrOptions) -> Result<Self> {
+        validate_lang(&options.lang)?;
+        let cloud = options
+            .cloud
+            .clone()
+            .ok_or_else(|| anyhow!("--cloud is needed to pick the OCR service"))?;
+        if cloud.provider == Provider::Azure && cloud.endpoint.is_none() {
+            return Err(anyhow!(
+                "Azure needs the URL of the resource in --cloud-endpoint"
+            ));
+        }
+        if cloud.rate.is_nan() || cloud.rate <= 0.0 {
+            return Err(anyhow!("--cloud-rate should be more than 0"));
+        }
+        Ok(Ocr {
+            cloud,
+            language: options.lang.split('+').next().and_then(language_tag),
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(60))
+                .build(),
+            words: vec![],
+        })
+    }
+
+    pub fn scan(&mut self, img: &Path) -> Result<String> {
+        self.scan_mem(&fs::read(img)?)
+    }
+
+    /// OCR an image that is still encoded, like the bytes of a png
+    pub fn scan_mem(&mut self, data: &[u8]) -> Result<String> {
+        self.words.clear();
+        let (text, words) = match self.cloud.provider {
+            Provider::Google => self.google(&[data])?.remove(0)?,
+            Provider::Azure => self.azure(data)?,
+        };
+        self.words = words;
+        Ok(text)
+    }
+
+    /// How many images [`Ocr::scan_batch`] sends at once with `options`
+    pub fn batch(options: &OcrOptions) -> usize {
+        match options.cloud.as_ref().map(|x| x.provider) {
+            Some(Provider::Google) => BATCH,
+            _ => 1,
+        }
+    }
+
+    /// OCR encoded images in as few requests as the service takes, with a result for every image.
+    /// Images that can't be read aren't sent, and a request that fails is the error of its images
+    pub fn scan_batch(&mut self, images: &[(&[u8], ImageFormat)]) -> Vec<Result<Extracted>> {
+        if self.cloud.provider == Provider::Azure {
+            return images
+                .iter()
+                .map(|(data, hint)| self.scan_bytes(data, *hint))
+                .collect();
+        }
+        let properties = self.properties();
+        let mut results = Vec::with_capacity(images.len());
+        for chunk in images.chunks(BATCH) {
+            let checked: Vec<_> = chunk.iter().map(|(data, hint)| hint.check(data)).collect();
+            let readable: Vec<&[u8]> = chunk
+                .iter()
+                .zip(&checked)
+                .filter(|(_, check)| check.is_ok())
+                .map(|((data, _), _)| *data)
+                .collect();
+            let scanned = if readable.is_empty() {
+                vec![]
+            } else {
+                self.google(&readable)
+                    .unwrap_or_else(|e| readable.iter().map(|_| Err(anyhow!("{e:#}"))).collect())
+            };
+            let mut scanned = scanned.into_iter();
+            for check in checked {
+                results.push(check.and_then(|()| {
+                    let (contents, words) = scanned.next().unwrap()?;
+                    Ok(Extracted {
+                        contents,
+                        words,
+                        properties: properties.clone(),
+                    })
+                }));
+            }
+        }
+        results
+    }
+
+    /// The text and words of every image, in one request
+    fn google(&self, images: &[&[u8]]) -> Result<Vec<Result<Scanned>>> {
+        let requests: Vec<_> = images
+            .iter()
+            .map(|data| {
+                let mut request = json!({
+                    "image": {"content": base64::engine::general_purpose::STANDARD.encode(data)},
+                    "features": [{"type": "DOCUMENT_TEXT_DETECTION"}],
+                });
+                if let Some(language) = self.language {
+                    request["imageContext"] = json!({"languageHints": [language]});
+                }
+                request
+            })
+            .collect();
+        let body = serde_json::to_vec(&json!({ "requests": requests }))?;
+        let url = format!(
+            "{}/v1/images:annotate",
+            self.endpoint("https://vision.googleapis.com")
+        );
+        let request = self
+            .agent
+            .post(&url)
+            .set("X-Goog-Api-Key", &self.cloud.key)
+            .set("Content-Type", "application/json");
+        let response: GoogleResponse = self.send(&request, Some(&body))?.into_json()?;
+        response.into_texts(images.len())
+    }
+
+    fn azure(&self, data: &[u8]) -> Result<Scanned> {
+        let mut request = self
+            .agent
+            .post(&format!("{}/vision/v3.2/read/analyze", self.endpoint("")))
+            .set("Ocp-Apim-Subscription-Key", &self.cloud.key)
+            .set("Content-Type", "application/octet-stream");
+        if let Some(language) = self.language {
+            request = request.query("language", language);
+        }
+        let response = self.send(&request, Some(data))?;
+        // the text is read in the background, and its result has to be asked for until it's done
+        let operation = response
+            .header("Operation-Location")
+            .ok_or_else(|| anyhow!("the service didn't say where the result will be"))?
+            .to_owned();
+        let poll = self
+            .agent
+            .get(&operation)
+            .set("Ocp-Apim-Subscription-Key", &self.cloud.key);
+        for _ in 0..POLLS {
+            thread::sleep(Duration::from_millis(500));
+            let result: AzureOperation = self.send(&poll, None)?.into_json()?;
+            if let Some(scanned) = result.into_text()? {
+                return Ok(scanned);
+            }
+        }
+        Err(anyhow!(
+            "the OCR service didn't finish reading the image in {} seconds",
+            POLLS / 2
+        ))
+    }
+
+    fn endpoint<'a>(&'a self, default: &'a str) -> &'a str {
+        self.cloud
+            .endpoint
+            .as_deref()
+            .unwrap_or(default)
+            .trim_end_matches('/')
+    }
+
+    /// Send a request once it's its turn, and again after a backoff if it failed in a way that
+    /// might go away by itself
+    fn send(&self, request: &ureq::Request, body: Option<&[u8]>) -> Result<ureq::Response> {
+        let mut backoff = Duration::from_secs(1);
+        let mut attempt = 0;
+        loop {
+            wait_turn(self.cloud.rate);
+            let sent = match body {
+                Some(body) => request.clone().send_bytes(body),
+                None => request.clone().call(),
+            };
+            let retry_after = match sent {
+                Ok(response) => return Ok(response),
+                Err(ureq::Error::Status(code, response))
+                    if (code == 429 || code >= 500) && attempt < RETRIES =>
+                {
+                    response
+                        .header("Retry-After")
+                        .and_then(|x| x.parse().ok())
+                        .map(Duration::from_secs)
+                }
+                Err(ureq::Error::Transport(_)) if attempt < RETRIES => None,
+                Err(ureq::Error::Status(code, response)) => {
+                    let body = response.into_string().unwrap_or_default();
+                    return Err(anyhow!("the OCR service returned {code}: {}", body.trim()));
+                }
+                Err(e) => return Err(e.into()),
+            };
+            thread::sleep(retry_after.unwrap_or(backoff));
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// Name of the engine, for [`OcrOptions::settings`]
+    pub fn engine(options: &OcrOptions) -> String {
+        match options.cloud.as_ref().map(|x| x.provider) {
+            Some(Provider::Azure) => "azure".to_owned(),
+            _ => "google".to_owned(),
+        }
+    }
+
+    /// Settings that were picked for the last image, to save with its text
+    pub fn properties(&self) -> BTreeMap<String, String> {
+        let engine = match self.cloud.provider {
+            Provider::Google => "google",
+            Provider::Azure => "azure",
+        };
+        BTreeMap::from([("engine".to_owned(), engine.to_owned())])
+    }
+
+    /// Words of the last scanned image
+    pub fn words(&mut self) -> Vec<Word> {
+        std::mem::take(&mut self.words)
+    }
+}
+
+/// Wait until `rate` allows another request
+fn wait_turn(rate: f32) {
+    let interval = Duration::from_secs_f32(1.0 / rate);
+    let wait = {
+        let mut next = NEXT.lock().unwrap();
+        let now = Instant::now();
+        let turn = next.map_or(now, |x| x.max(now));
+        *next = Some(turn + interval);
+        turn - now
+    };
+    thread::sleep(wait);
+}
+
+/// A word in the box around `points`, with a confidence from 0 to 1
+fn to_word(text: String, points: &[(f32, f32)], confidence: f32) -> Word {
+    let (mut left, mut top) = (f32::MAX, f32::MAX);
+    let (mut right, mut bottom) = (0f32, 0f32);
+    for &(x, y) in points {
+        (left, top) = (left.min(x), top.min(y));
+        (right, bottom) = (right.max(x), bottom.max(y));
+    }
+    if points.is_empty() {
+        (left, top) = (0.0, 0.0);
+    }
+    Word {
+        text,
+        left: left.round() as i32,
+        top: top.round() as i32,
+        width: (right - left).round() as i32,
+        height: (bottom - top).round() as i32,
+        confidence: confidence * 100.0,
+    }
+}
+
+#[derive(Deserialize)]
+struct GoogleResponse {
+    responses: Vec<GoogleResult>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleResult {
+    full_text_annotation: Option<GoogleAnnotation>,
+    error: Option<GoogleError>,
+}
+
+#[derive(Deserialize)]
+struct GoogleError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct GoogleAnnotation {
+    text: String,
+    #[serde(default)]
+    pages: Vec<GooglePage>,
+}
+
+#[derive(Deserialize)]
+struct GooglePage {
+    #[serde(default)]
+    blocks: Vec<GoogleBlock>,
+}
+
+#[derive(Deserialize)]
+struct GoogleBlock {
+    #[serde(default)]
+    paragraphs: Vec<GoogleParagraph>,
+}
+
+#[derive(Deserialize)]
+struct GoogleParagraph {
+    #[serde(default)]
+    words: Vec<GoogleWord>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleWord {
+    bounding_box: GooglePoly,
+    #[serde(default)]
+    symbols: Vec<GoogleSymbol>,
+    #[serde(default)]
+    confidence: f32,
+}
+
+#[derive(Deserialize)]
+struct GooglePoly {
+    #[serde(default)]
+    vertices: Vec<GoogleVertex>,
+}
+
+/// Coordinates that are 0 are left out
+#[derive(Deserialize)]
+struct GoogleVertex {
+    #[serde(default)]
+    x: f32,
+    #[serde(default)]
+    y: f32,
+}
+
+#[derive(Deserialize)]
+struct GoogleSymbol {
+    text: String,
+}
+
+impl GoogleResponse {
+    /// The text and words of each of the `count` images that were sent
+    fn into_texts(self, count: usize) -> Result<Vec<Result<Scanned>>> {
+        if self.responses.len() != count {
+            return Err(anyhow!(
+                "the OCR service returned {} results for {count} images",
+                self.responses.len()
+            ));
+        }
+        Ok(self
+            .responses
+            .into_iter()
+            .map(GoogleResult::into_text)
+            .collect())
+    }
+}
+
+impl GoogleResult {
+    fn into_text(self) -> Result<Scanned> {
+        if let Some(error) = self.error {
+            return Err(anyhow!("the OCR service failed: {}", error.message));
+        }
+        let Some(annotation) = self.full_text_annotation else {
+            return Ok((String::new(), vec![]));
+        };
+        let words = annotation
+            .pages
+            .into_iter()
+            .flat_map(|x| x.blocks)
+            .flat_map(|x| x.paragraphs)
+            .flat_map(|x| x.words)
+            .map(|word| {
+                let points: Vec<_> = word
+                    .bounding_box
+                    .vertices
+                    .iter()
+                    .map(|v| (v.x, v.y))
+                    .collect();
+                let text = word.symbols.into_iter().map(|x| x.text).collect();
+                to_word(text, &points, word.confidence)
+            })
+            .collect();
+        Ok((annotation.text.trim_end().to_owned(), words))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureOperation {
+    status: String,
+    analyze_result: Option<AzureAnalyzeResult>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureAnalyzeResult {
+    read_results: Vec<AzureReadResult>,
+}
+
+#[derive(Deserialize)]
+struct AzureReadResult {
+    lines: Vec<AzureLine>,
+}
+
+#[derive(Deserialize)]
+struct AzureLine {
+    text: String,
+    words: Vec<AzureWord>,
+}
+
+/// `bounding_box` is the four corners, as x and y one after the other
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureWord {
+    text: String,
+    bounding_box: Vec<f32>,
+    confidence: f32,
+}
+
+impl AzureOperation {
+    /// The text and words, `None` if the service is still reading
+    fn into_text(self) -> Result<Option<Scanned>> {
+        match self.status.as_str() {
+            "succeeded" => (),
+            "failed" => return Err(anyhow!("the OCR service failed to read the image")),
+            _ => return Ok(None),
+        }
+        let lines: Vec<AzureLine> = self
+            .analyze_result
+            .into_iter()
+            .flat_map(|x| x.read_results)
+            .flat_map(|x| x.lines)
+            .collect();
+        let text = lines.iter().map(|x| x.text.as_str()).collect::<Vec<_>>();
+        let words = lines
+            .iter()
+            .flat_map(|x| &x.words)
+            .map(|word| {
+                let points: Vec<_> = word
+                    .bounding_box
+                    .chunks_exact(2)
+                    .map(|x| (x[0], x[1]))
+                    .collect();
+                to_word(word.text.clone(), &points, word.confidence)
+            })
+            .collect();
+        Ok(Some((text.join("\n"), words)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses() -> Result<()> {
+        let google = || -> Result<GoogleResponse> {
+            Ok(serde_json::from_value(json!({"responses": [{
+                "fullTextAnnotation": {"text": "Total 12\n", "pages": [{"blocks": [{"paragraphs": [{"words": [{
+                    "boundingBox": {"vertices": [{"y": 4}, {"x": 40, "y": 4}, {"x": 40, "y": 20}, {"y": 20}]},
+                    "symbols": [{"text": "T"}, {"text": "otal"}],
+                    "confidence": 0.5,
+                }]}]}]}]},
+            }, {
+                "error": {"message": "Bad image data."},
+            }]}))?)
+        };
+        assert!(google()?.into_texts(1).is_err());
+        let mut texts = google()?.into_texts(2)?;
+        assert!(texts.pop().unwrap().is_err());
+        let (text, words) = texts.pop().unwrap()?;
+        assert_eq!(text, "Total 12");
+        assert_eq!(
+            words,
+            [Word {
+                text: "Total".into(),
+                left: 0,
+                top: 4,
+                width: 40,
+                height: 16,
+                confidence: 50.0,
+            }]
+        );
+
+        let running: AzureOperation = serde_json::from_value(json!({"status": "running"}))?;
+        assert!(running.into_text()?.is_none());
+        let azure: AzureOperation = serde_json::from_value(json!({
+            "status": "succeeded",
+            "analyzeResult": {"readResults": [{"lines": [
+                {"text": "a b", "words": [{"text": "a", "boundingBox": [1, 2, 5, 2, 5, 9, 1, 9], "confidence": 1.0}]},
+                {"text": "c", "words": []},
+            ]}]},
+        }))?;
+        let (text, words) = azure.into_text()?.unwrap();
+        assert_eq!(text, "a b\nc");
+        assert_eq!((words[0].left, words[0].width, words[0].height), (1, 4, 7));
+        Ok(())
+    }
+}
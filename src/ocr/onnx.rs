@@ -0,0 +1,409 @@
+//! OCR with ONNX models in the style of PaddleOCR: a DB model finds the lines of text, and a CRNN
+//! model reads each of them. It reads scene text and stylized fonts much better than tesseract
+//!
+//! The models are loaded from a directory with `det.onnx`, `rec.onnx` and `keys.txt`, the
+//! characters of the recognition model one per line, like the exports of PP-OCR and their
+//! dictionaries. onnxruntime is loaded when the first image is read, from `ORT_DYLIB_PATH` or the
+//! library path. Only the models and the GPU of [`OcrOptions`] apply, the other options are
+//! tesseract settings.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Once;
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path as Path;
+use image::imageops::{self, FilterType};
+use image::RgbImage;
+use itertools::Itertools;
+use ort::execution_providers::{
+    CUDAExecutionProvider, CoreMLExecutionProvider, DirectMLExecutionProvider, ExecutionProvider,
+};
+use ort::session::builder::SessionBuilder;
+use ort::session::Session;
+use ort::value::Tensor;
+
+use super::{Gpu, OcrOptions, Word};
+
+/// Longest side of the image the detection model sees, in pixels
+const DET_MAX_SIDE: u32 = 960;
+/// Mean and standard deviation of the channels the detection model was trained with
+const DET_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const DET_STD: [f32; 3] = [0.229, 0.224, 0.225];
+/// Pixels more likely than this to be text are part of a line
+const DET_THRESHOLD: f32 = 0.3;
+/// Lines whose pixels are on average less likely than this to be text are noise
+const BOX_THRESHOLD: f32 = 0.6;
+/// How far to grow the boxes of lines, since the model finds the middle of the text
+const UNCLIP_RATIO: f32 = 1.5;
+/// Height of the lines the recognition model reads, and the widest one it is given
+const REC_HEIGHT: u32 = 48;
+const REC_MAX_WIDTH: u32 = 1600;
+/// Lines that were read with a lower mean probability than this are left out
+const MIN_LINE_CONFIDENCE: f32 = 0.5;
+/// Boxes whose tops are closer than this, in pixels, are on the same line
+const LINE_TOLERANCE: f32 = 10.0;
+
+pub struct Ocr {
+    detector: Session,
+    recognizer: Session,
+    /// Characters of the classes of the recognition model, after the blank of class 0
+    keys: Vec<String>,
+    /// The execution provider the models run with, or None for the CPU
+    gpu: Option<Gpu>,
+    debug: bool,
+    words: Vec<Word>,
+}
+
+impl Ocr {
+    pub fn new(options: &OcrOptions) -> Result<Self> {
+        let dir = options
+            .onnx_models
+            .as_ref()
+            .ok_or_else(|| anyhow!("--onnx-models is needed to find the models"))?;
+        let mut gpu = None;
+        let mut session = |name: &str| -> Result<Session> {
+            // every indexing thread has its own sessions
+            let mut builder = Session::builder()?.with_intra_threads(1)?;
+            if let Some(requested) = options.gpu {
+                gpu = register(&mut builder, requested, options.debug);
+            }
+            builder
+                .commit_from_file(dir.join(name))
+                .with_context(|| format!("failed to load {}", dir.join(name)))
+        };
+        let (detector, recognizer) = (session("det.onnx")?, session("rec.onnx")?);
+        if options.gpu.is_some() && gpu.is_none() {
+            static FALLBACK: Once = Once::new();
+            FALLBACK
+                .call_once(|| eprintln!("Note: no GPU could be used, the models run on the CPU"));
+        }
+        let keys = fs::read_to_string(dir.join("keys.txt"))
+            .with_context(|| format!("failed to read {}", dir.join("keys.txt")))?;
+        Ok(Ocr {
+            detector,
+            recognizer,
+            keys: keys
+                .lines()
+                .map(String::from)
+                // PaddleOCR adds the space after the dictionary
+                .chain([" ".to_owned()])
+                .collect(),
+            gpu,
+            debug: options.debug,
+            words: vec![],
+        })
+    }
+
+    pub fn scan(&mut self, img: &Path) -> Result<String> {
+        self.scan_mem(&fs::read(img)?)
+    }
+
+    /// OCR an image that is still encoded, like the bytes of a png
+    pub fn scan_mem(&mut self, data: &[u8]) -> Result<String> {
+        let image = image::load_from_memory(data)
+            .context("failed to read image")?
+            .to_rgb8();
+        self.words.clear();
+        let mut text = String::new();
+        let mut previous: Option<f32> = None;
+        for [left, top, right, bottom] in self.detect(&image)? {
+            let (x, y) = (left as u32, top as u32);
+            let (width, height) = ((right - left) as u32, (bottom - top) as u32);
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let line = imageops::crop_imm(&image, x, y, width, height).to_image();
+            let (chars, steps) = self.recognize(&line)?;
+            let confidence = chars.iter().map(|x| x.2).sum::<f32>() / chars.len().max(1) as f32;
+            if chars.is_empty() || confidence < MIN_LINE_CONFIDENCE {
+                continue;
+            }
+            if self.debug {
+                eprintln!("line at {left},{top}: {confidence:.2}");
+            }
+
+            match previous {
+                Some(previous) if (top - previous).abs() < LINE_TOLERANCE => text.push(' '),
+                Some(_) => text.push('\n'),
+                None => (),
+            }
+            previous = Some(top);
+            text.extend(chars.iter().map(|x| x.0));
+
+            // a step of the output is a slice of the width of the line
+            let step = width as f32 / steps as f32;
+            for (is_space, group) in &chars.iter().group_by(|x| x.0.is_whitespace()) {
+                if is_space {
+                    continue;
+                }
+                let group: Vec<_> = group.collect();
+                let (first, last) = (group[0].1, group[group.len() - 1].1);
+                self.words.push(Word {
+                    text: group.iter().map(|x| x.0).collect(),
+                    left: x as i32 + (first as f32 * step).round() as i32,
+                    top: y as i32,
+                    width: ((last + 1 - first) as f32 * step).round() as i32,
+                    height: height as i32,
+                    confidence: group.iter().map(|x| x.2).sum::<f32>() / group.len() as f32 * 100.0,
+                });
+            }
+        }
+        Ok(text)
+    }
+
+    /// Boxes of the lines of text in the image, as their left, top, right and bottom, in
+    /// reading order
+    fn detect(&mut self, image: &RgbImage) -> Result<Vec<[f32; 4]>> {
+        let (width, height) = image.dimensions();
+        let ratio = (DET_MAX_SIDE as f32 / width.max(height) as f32).min(1.0);
+        // the model halves the image five times
+        let side = |x: u32| ((x as f32 * ratio / 32.0).round() as u32).max(1) * 32;
+        let (det_width, det_height) = (side(width), side(height));
+        let resized = imageops::resize(image, det_width, det_height, FilterType::Triangle);
+        let input = to_tensor(&resized, |x, channel| {
+            (x / 255.0 - DET_MEAN[channel]) / DET_STD[channel]
+        })?;
+        let outputs = self.detector.run(ort::inputs![input])?;
+        let (_, probabilities) = outputs[0].try_extract_tensor::<f32>()?;
+
+        let (x_scale, y_scale) = (
+            width as f32 / det_width as f32,
+            height as f32 / det_height as f32,
+        );
+        let mut boxes: Vec<[f32; 4]> = text_boxes(probabilities, det_width as usize)
+            .into_iter()
+            .map(|[left, top, right, bottom]| {
+                [
+                    (left * x_scale).clamp(0.0, width as f32).floor(),
+                    (top * y_scale).clamp(0.0, height as f32).floor(),
+                    (right * x_scale).clamp(0.0, width as f32).ceil(),
+                    (bottom * y_scale).clamp(0.0, height as f32).ceil(),
+                ]
+            })
+            .collect();
+        reading_order(&mut boxes);
+        Ok(boxes)
+    }
+
+    /// The characters of a line with the step of the output they were read at and their
+    /// probability, and how many steps there were
+    #[allow(clippy::type_complexity)]
+    fn recognize(&mut self, line: &RgbImage) -> Result<(Vec<(char, usize, f32)>, usize)> {
+        let (width, height) = line.dimensions();
+        let rec_width = ((REC_HEIGHT as f32 * width as f32 / height as f32).ceil() as u32)
+            .clamp(1, REC_MAX_WIDTH);
+        let resized = imageops::resize(line, rec_width, REC_HEIGHT, FilterType::Triangle);
+        let input = to_tensor(&resized, |x, _| x / 127.5 - 1.0)?;
+        let outputs = self.recognizer.run(ort::inputs![input])?;
+        let (shape, probabilities) = outputs[0].try_extract_tensor::<f32>()?;
+        let [_, steps, classes] = shape[..] else {
+            return Err(anyhow!(
+                "unexpected output of the recognition model: {shape:?}"
+            ));
+        };
+        if classes as usize != self.keys.len() + 1 {
+            return Err(anyhow!(
+                "keys.txt has {} characters, but the recognition model reads {}",
+                self.keys.len() - 1,
+                classes - 2
+            ));
+        }
+        Ok((
+            ctc_decode(probabilities, classes as usize, &self.keys),
+            steps as usize,
+        ))
+    }
+
+    /// Name of the engine, for [`OcrOptions::settings`]
+    pub fn engine(_options: &OcrOptions) -> String {
+        "onnx".to_owned()
+    }
+
+    /// Settings that were picked for the last image, to save with its text
+    pub fn properties(&self) -> BTreeMap<String, String> {
+        let mut properties = BTreeMap::from([("engine".to_owned(), "onnx".to_owned())]);
+        if let Some(gpu) = self.gpu {
+            properties.insert("gpu".to_owned(), format!("{gpu:?}").to_lowercase());
+        }
+        properties
+    }
+
+    /// Words of the last scanned image
+    pub fn words(&mut self) -> Vec<Word> {
+        std::mem::take(&mut self.words)
+    }
+}
+
+/// Add the first execution provider of `gpu` that works on this platform and in this build of
+/// onnxruntime to a session, returning it. Returns None if none work, so the session runs on the
+/// CPU
+fn register(builder: &mut SessionBuilder, gpu: Gpu, debug: bool) -> Option<Gpu> {
+    let candidates = match gpu {
+        Gpu::Auto => vec![Gpu::Cuda, Gpu::DirectMl, Gpu::CoreMl],
+        gpu => vec![gpu],
+    };
+    for candidate in candidates {
+        let provider: Box<dyn ExecutionProvider> = match candidate {
+            Gpu::Auto => unreachable!(),
+            Gpu::Cuda => Box::new(CUDAExecutionProvider::default()),
+            Gpu::DirectMl => Box::new(DirectMLExecutionProvider::default()),
+            Gpu::CoreMl => Box::new(CoreMLExecutionProvider::default()),
+        };
+        if !provider.supported_by_platform() {
+            continue;
+        }
+        match provider.register(builder) {
+            Ok(()) => return Some(candidate),
+            Err(e) if debug => eprintln!("{} can't be used: {e}", provider.name()),
+            Err(_) => (),
+        }
+    }
+    None
+}
+
+/// An image as a 1×3×height×width tensor, with every value mapped by `normalize`
+fn to_tensor(image: &RgbImage, normalize: impl Fn(f32, usize) -> f32) -> Result<Tensor<f32>> {
+    let (width, height) = image.dimensions();
+    let plane = (width * height) as usize;
+    let mut data = vec![0.0; plane * 3];
+    for (i, pixel) in image.pixels().enumerate() {
+        for channel in 0..3 {
+            data[channel * plane + i] = normalize(pixel[channel] as f32, channel);
+        }
+    }
+    Ok(Tensor::from_array((
+        [1, 3, height as usize, width as usize],
+        data,
+    ))?)
+}
+
+/// Boxes around the groups of touching pixels of a probability map that are likely to be text,
+/// grown by [`UNCLIP_RATIO`], as their left, top, right and bottom
+fn text_boxes(probabilities: &[f32], width: usize) -> Vec<[f32; 4]> {
+    let height = probabilities.len() / width;
+    let mut seen = vec![false; probabilities.len()];
+    let mut boxes = vec![];
+    let mut stack = vec![];
+    for start in 0..probabilities.len() {
+        if seen[start] || probabilities[start] <= DET_THRESHOLD {
+            continue;
+        }
+        seen[start] = true;
+        stack.push(start);
+        let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+        let (mut sum, mut count) = (0.0, 0);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            (left, top, right, bottom) = (left.min(x), top.min(y), right.max(x), bottom.max(y));
+            sum += probabilities[i];
+            count += 1;
+            let neighbors = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ];
+            for j in neighbors.into_iter().flatten() {
+                if !seen[j] && probabilities[j] > DET_THRESHOLD {
+                    seen[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+
+        let (box_width, box_height) = ((right - left + 1) as f32, (bottom - top + 1) as f32);
+        if sum / (count as f32) < BOX_THRESHOLD || box_width.min(box_height) < 3.0 {
+            continue;
+        }
+        let grow = box_width * box_height * UNCLIP_RATIO / (2.0 * (box_width + box_height));
+        boxes.push([
+            (left as f32 - grow).max(0.0),
+            (top as f32 - grow).max(0.0),
+            ((right + 1) as f32 + grow).min(width as f32),
+            ((bottom + 1) as f32 + grow).min(height as f32),
+        ]);
+    }
+    boxes
+}
+
+/// Sort boxes from top to bottom, and boxes on the same line from left to right
+fn reading_order(boxes: &mut [[f32; 4]]) {
+    boxes.sort_by(|a, b| a[1].total_cmp(&b[1]).then(a[0].total_cmp(&b[0])));
+    for i in 1..boxes.len() {
+        let mut j = i;
+        while j > 0
+            && (boxes[j][1] - boxes[j - 1][1]).abs() < LINE_TOLERANCE
+            && boxes[j][0] < boxes[j - 1][0]
+        {
+            boxes.swap(j, j - 1);
+            j -= 1;
+        }
+    }
+}
+
+/// Read the output of a CTC model, the probability of every class at every step: the most
+/// likely class of each step, with repeats merged and the blank of class 0 left out. Returns
+/// the characters with the step they were read at and their probability
+fn ctc_decode(probabilities: &[f32], classes: usize, keys: &[String]) -> Vec<(char, usize, f32)> {
+    let mut chars = vec![];
+    let mut previous = 0;
+    for (step, row) in probabilities.chunks(classes).enumerate() {
+        let (class, probability) = row
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or_default();
+        if class != 0 && class != previous {
+            for c in keys[class - 1].chars() {
+                chars.push((c, step, probability));
+            }
+        }
+        previous = class;
+    }
+    chars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boxes() {
+        // a line of text and a speck of noise, on a map 12 wide
+        let mut map = vec![0.0; 12 * 8];
+        for y in 2..5 {
+            for x in 3..9 {
+                map[y * 12 + x] = 0.9;
+            }
+        }
+        map[7 * 12 + 11] = 0.9;
+        let boxes = text_boxes(&map, 12);
+        assert_eq!(boxes.len(), 1);
+        // grown by the area over the perimeter, 6 × 3 × 1.5 / 18
+        assert_eq!(boxes[0], [1.5, 0.5, 10.5, 6.5]);
+
+        let mut boxes = vec![
+            [50.0, 12.0, 60.0, 20.0],
+            [0.0, 40.0, 9.0, 50.0],
+            [0.0, 10.0, 9.0, 20.0],
+        ];
+        reading_order(&mut boxes);
+        assert_eq!(boxes.iter().map(|x| x[0]).collect_vec(), [0.0, 50.0, 0.0]);
+    }
+
+    #[test]
+    fn decode() {
+        let keys = ["a", "b", " "].map(String::from);
+        // a, a, blank, a, b, space, space, b
+        let steps = [1, 1, 0, 1, 2, 3, 3, 2];
+        let probabilities: Vec<f32> = steps
+            .iter()
+            .flat_map(|&class| (0..4).map(move |x| if x == class { 0.9 } else { 0.1 / 3.0 }))
+            .collect();
+        let chars = ctc_decode(&probabilities, 4, &keys);
+        assert_eq!(chars.iter().map(|x| x.0).collect::<String>(), "aab b");
+        assert_eq!(chars.iter().map(|x| x.1).collect_vec(), [0, 3, 4, 5, 7]);
+    }
+}
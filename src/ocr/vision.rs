@@ -0,0 +1,170 @@
+//! OCR with the Vision framework of macOS 10.15 and later. It reads photos and handwriting better
+//! than tesseract, needs no tessdata, and decodes the images itself with ImageIO
+//!
+//! Only the languages of [`OcrOptions`] apply, the other options are tesseract settings.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use anyhow::{anyhow, Result};
+use camino::Utf8Path as Path;
+use itertools::Itertools;
+use objc2::rc::{Allocated, Retained};
+use objc2::runtime::AnyObject;
+use objc2::{class, msg_send};
+use objc2_core_foundation::CGRect;
+use objc2_foundation::{NSArray, NSData, NSDictionary, NSError, NSRange, NSString};
+
+use super::{validate_lang, OcrOptions, Word};
+
+#[link(name = "Vision", kind = "framework")]
+extern "C" {}
+
+/// `VNRequestTextRecognitionLevelAccurate`
+const ACCURATE: isize = 0;
+
+#[derive(Debug)]
+pub struct Ocr {
+    languages: Retained<NSArray<NSString>>,
+    words: Vec<Word>,
+}
+
+impl Ocr {
+    pub fn new(options: &OcrOptions) -> Result<Self> {
+        validate_lang(&options.lang)?;
+        let languages: Vec<Retained<NSString>> = options
+            .lang
+            .split('+')
+            .map(|code| {
+                language_tag(code)
+                    .map(NSString::from_str)
+                    .ok_or_else(|| anyhow!("{code} isn't a language that Vision can read"))
+            })
+            .try_collect()?;
+        Ok(Ocr {
+            languages: NSArray::from_retained_slice(&languages),
+            words: vec![],
+        })
+    }
+
+    pub fn scan(&mut self, img: &Path) -> Result<String> {
+        self.scan_mem(&fs::read(img)?)
+    }
+
+    /// OCR an image that is still encoded, like the bytes of a png
+    pub fn scan_mem(&mut self, data: &[u8]) -> Result<String> {
+        // boxes are fractions of the image, with the origin at the bottom left
+        let size = imagesize::blob_size(data).map_err(|e| anyhow!("failed to read image: {e}"))?;
+        let (width, height) = (size.width as f64, size.height as f64);
+
+        let mut lines = vec![];
+        self.words.clear();
+        unsafe {
+            let request: Retained<AnyObject> = msg_send![class!(VNRecognizeTextRequest), new];
+            let () = msg_send![&request, setRecognitionLevel: ACCURATE];
+            let () = msg_send![&request, setUsesLanguageCorrection: true];
+            let () = msg_send![&request, setRecognitionLanguages: &*self.languages];
+
+            let data = NSData::with_bytes(data);
+            let options = NSDictionary::<NSString, AnyObject>::new();
+            let handler: Allocated<AnyObject> = msg_send![class!(VNImageRequestHandler), alloc];
+            let handler: Retained<AnyObject> =
+                msg_send![handler, initWithData: &*data, options: &*options];
+            let requests = NSArray::from_slice(&[&*request]);
+            let performed: Result<(), Retained<NSError>> =
+                msg_send![&handler, performRequests: &*requests, error: _];
+            performed.map_err(|e| anyhow!("Vision failed: {}", e.localizedDescription()))?;
+
+            let observations: Option<Retained<NSArray<AnyObject>>> = msg_send![&request, results];
+            for observation in observations.iter().flat_map(|x| x.iter()) {
+                let candidates: Retained<NSArray<AnyObject>> =
+                    msg_send![&*observation, topCandidates: 1usize];
+                let Some(candidate) = candidates.firstObject() else {
+                    continue;
+                };
+                let text: Retained<NSString> = msg_send![&candidate, string];
+                let confidence: f32 = msg_send![&candidate, confidence];
+                let text = text.to_string();
+                for (range, word) in word_ranges(&text) {
+                    let found: Result<Retained<AnyObject>, Retained<NSError>> =
+                        msg_send![&candidate, boundingBoxForRange: range, error: _];
+                    let Ok(found) = found else {
+                        continue;
+                    };
+                    let rect: CGRect = msg_send![&found, boundingBox];
+                    self.words.push(Word {
+                        text: word.to_owned(),
+                        left: (rect.origin.x * width).round() as i32,
+                        top: ((1.0 - rect.origin.y - rect.size.height) * height).round() as i32,
+                        width: (rect.size.width * width).round() as i32,
+                        height: (rect.size.height * height).round() as i32,
+                        confidence: confidence * 100.0,
+                    });
+                }
+                lines.push(text);
+            }
+        }
+        Ok(lines.into_iter().join("\n"))
+    }
+
+    /// Name of the engine, for [`OcrOptions::settings`]
+    pub fn engine(_options: &OcrOptions) -> String {
+        "vision".to_owned()
+    }
+
+    /// Settings that were picked for the last image, to save with its text
+    pub fn properties(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([("engine".to_owned(), "vision".to_owned())])
+    }
+
+    /// Words of the last scanned image
+    pub fn words(&mut self) -> Vec<Word> {
+        std::mem::take(&mut self.words)
+    }
+}
+
+/// The words of a line, with their range in UTF-16 code units like `NSString` counts
+fn word_ranges(text: &str) -> Vec<(NSRange, &str)> {
+    text.split_whitespace()
+        .map(|word| {
+            let start = word.as_ptr() as usize - text.as_ptr() as usize;
+            let location = text[..start].encode_utf16().count();
+            (NSRange::new(location, word.encode_utf16().count()), word)
+        })
+        .collect()
+}
+
+/// The language Vision has for a tesseract language code
+fn language_tag(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "chi_sim" => "zh-Hans",
+        "chi_tra" => "zh-Hant",
+        "deu" => "de-DE",
+        "eng" => "en-US",
+        "fra" => "fr-FR",
+        "ita" => "it-IT",
+        "jpn" => "ja-JP",
+        "kor" => "ko-KR",
+        "por" => "pt-BR",
+        "rus" => "ru-RU",
+        "spa" => "es-ES",
+        "tha" => "th-TH",
+        "ukr" => "uk-UA",
+        "vie" => "vi-VT",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        let ranges: Vec<_> = word_ranges("  日本 café\tok")
+            .into_iter()
+            .map(|(range, word)| (range.location, range.length, word))
+            .collect();
+        assert_eq!(ranges, [(2, 2, "日本"), (5, 4, "café"), (10, 2, "ok")]);
+    }
+}
@@ -0,0 +1,158 @@
+//! OCR with the engine that comes with Windows 10 and later, through WinRT. It needs no tessdata,
+//! only the OCR language packs of Windows, and reads the images itself with the Windows Imaging
+//! Component
+//!
+//! Only the language, `--scale` and `--resize` of [`OcrOptions`] apply, the other options are
+//! tesseract settings.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path as Path;
+use itertools::Itertools;
+use windows::core::HSTRING;
+use windows::Globalization::Language;
+use windows::Graphics::Imaging::{
+    BitmapAlphaMode, BitmapDecoder, BitmapInterpolationMode, BitmapPixelFormat, BitmapTransform,
+    ColorManagementMode, ExifOrientationMode, SoftwareBitmap,
+};
+use windows::Media::Ocr::OcrEngine;
+use windows::Storage::Streams::{DataWriter, InMemoryRandomAccessStream};
+
+use super::{language_tag, validate_lang, OcrOptions, Resize, Word};
+
+#[derive(Debug)]
+pub struct Ocr {
+    engine: OcrEngine,
+    options: OcrOptions,
+    scale: Option<f32>,
+    words: Vec<Word>,
+}
+
+impl Ocr {
+    pub fn new(options: &OcrOptions) -> Result<Self> {
+        validate_lang(&options.lang)?;
+        // the engine reads one language, so `eng+deu` is read as english
+        let code = options.lang.split('+').next().unwrap_or_default();
+        let tag = language_tag(code)
+            .ok_or_else(|| anyhow!("{code} isn't a language that Windows OCR can read"))?;
+        let language = Language::CreateLanguage(&HSTRING::from(tag))?;
+        if !OcrEngine::IsLanguageSupported(&language)? {
+            return Err(anyhow!(
+                "the {tag} OCR language isn't installed, add it with `Add-WindowsCapability -Online -Name Language.OCR~~~{tag}~0.0.1.0`"
+            ));
+        }
+        Ok(Ocr {
+            engine: OcrEngine::TryCreateFromLanguage(&language)
+                .with_context(|| format!("failed to create the {tag} OCR engine"))?,
+            options: options.clone(),
+            scale: None,
+            words: vec![],
+        })
+    }
+
+    pub fn scan(&mut self, img: &Path) -> Result<String> {
+        self.scan_mem(&fs::read(img)?)
+    }
+
+    /// OCR an image that is still encoded, like the bytes of a png
+    pub fn scan_mem(&mut self, data: &[u8]) -> Result<String> {
+        let bitmap = self.decode(data).context("failed to read image")?;
+        let result = self.engine.RecognizeAsync(&bitmap)?.get()?;
+
+        let scale = self.scale.unwrap_or(1.0);
+        let unscale = |x: f32| (x / scale).round() as i32;
+        let mut lines = vec![];
+        self.words.clear();
+        for line in result.Lines()? {
+            lines.push(line.Text()?.to_string_lossy());
+            for word in line.Words()? {
+                let rect = word.BoundingRect()?;
+                self.words.push(Word {
+                    text: word.Text()?.to_string_lossy(),
+                    left: unscale(rect.X),
+                    top: unscale(rect.Y),
+                    width: unscale(rect.Width),
+                    height: unscale(rect.Height),
+                    // the engine doesn't say how sure it is
+                    confidence: 100.0,
+                });
+            }
+        }
+        Ok(lines.into_iter().join("\n"))
+    }
+
+    /// Decode an image, scaled by `--scale` or `--resize` and then down to the largest size the
+    /// engine accepts
+    fn decode(&mut self, data: &[u8]) -> Result<SoftwareBitmap> {
+        let stream = InMemoryRandomAccessStream::new()?;
+        let writer = DataWriter::CreateDataWriter(&stream)?;
+        writer.WriteBytes(data)?;
+        writer.StoreAsync()?.get()?;
+        writer.DetachStream()?;
+        stream.Seek(0)?;
+        let decoder = BitmapDecoder::CreateAsync(&stream)?.get()?;
+
+        let (width, height) = (decoder.PixelWidth()?, decoder.PixelHeight()?);
+        let resize = self
+            .options
+            .scale
+            .map(Resize::Scale)
+            .or(self.options.resize);
+        let requested = resize.and_then(|x| {
+            x.scale(
+                width as i32,
+                height as i32,
+                decoder.DpiX().unwrap_or_default() as i32,
+            )
+        });
+        let max = OcrEngine::MaxImageDimension()? as f32;
+        let fit = max / width.max(height).max(1) as f32;
+        self.scale = match requested {
+            Some(x) => Some(x.min(fit)),
+            None if fit < 1.0 => Some(fit),
+            None => None,
+        };
+        if self.options.debug {
+            if let Some(scale) = self.scale {
+                eprintln!("scale: {scale:.3}");
+            }
+        }
+
+        let transform = BitmapTransform::new()?;
+        if let Some(scale) = self.scale {
+            transform.SetScaledWidth(((width as f32 * scale).round() as u32).max(1))?;
+            transform.SetScaledHeight(((height as f32 * scale).round() as u32).max(1))?;
+            transform.SetInterpolationMode(BitmapInterpolationMode::Fant)?;
+        }
+        Ok(decoder
+            .GetSoftwareBitmapTransformedAsync(
+                BitmapPixelFormat::Bgra8,
+                BitmapAlphaMode::Premultiplied,
+                &transform,
+                ExifOrientationMode::RespectExifOrientation,
+                ColorManagementMode::DoNotColorManage,
+            )?
+            .get()?)
+    }
+
+    /// Name of the engine, for [`OcrOptions::settings`]
+    pub fn engine(_options: &OcrOptions) -> String {
+        "windows".to_owned()
+    }
+
+    /// Settings that were picked for the last image, to save with its text
+    pub fn properties(&self) -> BTreeMap<String, String> {
+        let mut properties = BTreeMap::from([("engine".to_owned(), "windows".to_owned())]);
+        if let Some(scale) = self.scale {
+            properties.insert("scale".to_owned(), format!("{scale:.3}"));
+        }
+        properties
+    }
+
+    /// Words of the last scanned image, with their boxes scaled back to the size of the image
+    pub fn words(&mut self) -> Vec<Word> {
+        std::mem::take(&mut self.words)
+    }
+}
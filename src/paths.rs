@@ -0,0 +1,120 @@
+//! Windows path forms
+//!
+//! Paths are stored in the index without the `\\?\` prefix of extended-length paths, so a file has
+//! one path whether it was found through `canonicalize`, the current directory or a walk. Walks
+//! start from the extended-length form, so that files deeper than `MAX_PATH` can still be read.
+//!
+//! On filesystems that ignore case, like those of macOS and Windows, directories are indexed with
+//! the case they have on disk, see [`on_disk`], and searched in without case.
+
+use std::borrow::Cow;
+use std::fs;
+
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+
+const VERBATIM: &str = r"\\?\";
+const VERBATIM_UNC: &str = r"\\?\UNC\";
+
+/// The form of a path that is stored in the index: `\\?\C:\a` becomes `C:\a`, and
+/// `\\?\UNC\server\share\a` becomes `\\server\share\a`. Other paths are unchanged
+pub fn normalize(path: &str) -> Cow<'_, str> {
+    if let Some(rest) = path.strip_prefix(VERBATIM_UNC) {
+        return Cow::Owned(format!(r"\\{rest}"));
+    }
+    match path.strip_prefix(VERBATIM) {
+        // other verbatim paths, like `\\?\Volume{...}\`, have no shorter form
+        Some(rest) if is_drive(rest) => Cow::Borrowed(rest),
+        _ => Cow::Borrowed(path),
+    }
+}
+
+/// Whether the filesystems of this platform usually ignore case, like APFS and NTFS
+pub const CASE_INSENSITIVE: bool = cfg!(any(windows, target_os = "macos"));
+
+/// `path` with the case its directories and file have on disk, like `~/Pictures` for
+/// `~/pictures` on a filesystem that ignores case, and with symlinks resolved. Paths that can't
+/// be resolved are unchanged
+pub fn on_disk(path: &Path) -> PathBuf {
+    match fs::canonicalize(path).map(PathBuf::try_from) {
+        Ok(Ok(resolved)) => PathBuf::from(normalize(resolved.as_str()).into_owned()),
+        _ => path.to_owned(),
+    }
+}
+
+/// On Windows, the extended-length form of an absolute path, which can be longer than `MAX_PATH`.
+/// Other paths are unchanged
+pub fn extended(path: &Path) -> Cow<'_, Path> {
+    if !cfg!(windows) {
+        return Cow::Borrowed(path);
+    }
+    match extend(path.as_str()) {
+        Some(x) => Cow::Owned(PathBuf::from(x)),
+        None => Cow::Borrowed(path),
+    }
+}
+
+fn extend(path: &str) -> Option<String> {
+    // the prefix turns off all parsing, so only paths without `/`, `.` or `..` can have it
+    if path.contains('/') || path.split('\\').any(|x| x == "." || x == "..") {
+        return None;
+    }
+    if path.starts_with(VERBATIM) || path.starts_with(r"\\.\") {
+        return None;
+    }
+    if let Some(rest) = path.strip_prefix(r"\\") {
+        return Some(format!("{VERBATIM_UNC}{rest}"));
+    }
+    is_drive(path).then(|| format!("{VERBATIM}{path}"))
+}
+
+/// The separator after the directories of `path`: `\` in Windows paths that start with a drive
+/// or a server, like `C:\a` and `\\server\share`, and `/` in others
+pub fn separator(path: &str) -> char {
+    if is_drive(path) || path.starts_with(r"\\") {
+        '\\'
+    } else {
+        '/'
+    }
+}
+
+/// Whether a path starts with a drive, like `C:\`
+fn is_drive(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && bytes[2] == b'\\'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forms() {
+        for (path, stored, long) in [
+            (
+                r"C:\shots\a.png",
+                r"C:\shots\a.png",
+                Some(r"\\?\C:\shots\a.png"),
+            ),
+            (r"\\?\C:\shots\a.png", r"C:\shots\a.png", None),
+            (
+                r"\\nas\share\a.png",
+                r"\\nas\share\a.png",
+                Some(r"\\?\UNC\nas\share\a.png"),
+            ),
+            (r"\\?\UNC\nas\share\a.png", r"\\nas\share\a.png", None),
+            (r"\\?\Volume{1234}\a.png", r"\\?\Volume{1234}\a.png", None),
+            (r"C:\shots\..\a.png", r"C:\shots\..\a.png", None),
+            (r"C:relative.png", r"C:relative.png", None),
+            ("/home/a.png", "/home/a.png", None),
+        ] {
+            assert_eq!(normalize(path), stored);
+            assert_eq!(extend(path).as_deref(), long, "{path}");
+            if let Some(long) = long {
+                assert_eq!(normalize(long), stored);
+            }
+        }
+        assert_eq!(separator(r"C:\shots"), '\\');
+        assert_eq!(separator(r"\\nas\share"), '\\');
+        assert_eq!(separator("/home"), '/');
+    }
+}
@@ -0,0 +1,177 @@
+//! Finds the JPEG previews that cameras embed in their RAW files
+//!
+//! CR2, NEF and ARW are all TIFF files underneath. Each of them keeps one or more full JPEGs in
+//! its IFDs, either as a JPEG thumbnail (tags 0x201/0x202) or as a single JPEG compressed strip,
+//! and the largest one is usually the full resolution preview.
+
+const SUB_IFDS: u16 = 0x14a;
+const EXIF_IFD: u16 = 0x8769;
+const COMPRESSION: u16 = 0x103;
+const STRIP_OFFSETS: u16 = 0x111;
+const STRIP_BYTE_COUNTS: u16 = 0x117;
+const JPEG_OFFSET: u16 = 0x201;
+const JPEG_LENGTH: u16 = 0x202;
+
+/// Don't follow IFD chains forever in broken or malicious files
+const MAX_IFDS: usize = 64;
+
+/// The largest embedded JPEG in a TIFF based RAW file
+pub fn embedded_preview(data: &[u8]) -> Option<&[u8]> {
+    let tiff = Tiff::new(data)?;
+    let mut previews = vec![];
+    let mut queue = vec![tiff.u32(4)? as usize];
+    let mut seen = 0;
+    while let Some(offset) = queue.pop() {
+        seen += 1;
+        if offset == 0 || seen > MAX_IFDS {
+            continue;
+        }
+        let Some(count) = tiff.u16(offset) else {
+            continue;
+        };
+        let mut ifd = Ifd::default();
+        for i in 0..count as usize {
+            let entry = offset + 2 + i * 12;
+            let (Some(tag), Some(value)) = (tiff.u16(entry), tiff.value(entry)) else {
+                break;
+            };
+            match tag {
+                SUB_IFDS => queue.extend(tiff.values(entry).into_iter().flatten()),
+                EXIF_IFD => queue.push(value),
+                COMPRESSION => ifd.compression = value,
+                STRIP_OFFSETS => ifd.strip_offset = Some(value),
+                STRIP_BYTE_COUNTS => ifd.strip_length = Some(value),
+                JPEG_OFFSET => ifd.jpeg_offset = Some(value),
+                JPEG_LENGTH => ifd.jpeg_length = Some(value),
+                _ => (),
+            }
+        }
+        if let (Some(start), Some(len)) = (ifd.jpeg_offset, ifd.jpeg_length) {
+            previews.push((start, len));
+        }
+        // 6 and 7 are old and new style JPEG compression
+        if let (6 | 7, Some(start), Some(len)) =
+            (ifd.compression, ifd.strip_offset, ifd.strip_length)
+        {
+            previews.push((start, len));
+        }
+        if let Some(next) = tiff.u32(offset + 2 + count as usize * 12) {
+            queue.push(next as usize);
+        }
+    }
+
+    previews
+        .into_iter()
+        .filter_map(|(start, len)| data.get(start..start.checked_add(len)?))
+        .filter(|x| x.starts_with(&[0xff, 0xd8]))
+        .max_by_key(|x| x.len())
+}
+
+#[derive(Default)]
+struct Ifd {
+    compression: usize,
+    strip_offset: Option<usize>,
+    strip_length: Option<usize>,
+    jpeg_offset: Option<usize>,
+    jpeg_length: Option<usize>,
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let big_endian = match data.get(..2)? {
+            b"II" => false,
+            b"MM" => true,
+            _ => return None,
+        };
+        Some(Tiff { data, big_endian })
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.data.get(at..at.checked_add(2)?)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes = self.data.get(at..at.checked_add(4)?)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// The first value of the IFD entry at `entry`, if it is a SHORT, LONG or IFD
+    fn value(&self, entry: usize) -> Option<usize> {
+        match self.u16(entry + 2)? {
+            3 => self.u16(entry + 8).map(usize::from),
+            4 | 13 => self.u32(entry + 8).map(|x| x as usize),
+            _ => None,
+        }
+    }
+
+    /// All values of a LONG or IFD entry, which are stored elsewhere if there are more than one
+    fn values(&self, entry: usize) -> Option<Vec<usize>> {
+        if !matches!(self.u16(entry + 2)?, 4 | 13) {
+            return None;
+        }
+        let count = self.u32(entry + 4)? as usize;
+        let start = if count > 1 {
+            self.u32(entry + 8)? as usize
+        } else {
+            entry + 8
+        };
+        (0..count.min(MAX_IFDS))
+            .map(|i| self.u32(start + i * 4).map(|x| x as usize))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn largest_preview() {
+        let thumb = [0xff, 0xd8, 1, 0xff, 0xd9];
+        let preview = [0xff, 0xd8, 1, 2, 3, 4, 0xff, 0xd9];
+
+        // header, IFD0 with a jpeg thumbnail and a subifd, then the subifd with a jpeg strip
+        let mut data = b"II*\0".to_vec();
+        data.extend(8u32.to_le_bytes());
+        let entry = |data: &mut Vec<u8>, tag: u16, kind: u16, value: u32| {
+            data.extend(tag.to_le_bytes());
+            data.extend(kind.to_le_bytes());
+            data.extend(1u32.to_le_bytes());
+            data.extend(value.to_le_bytes());
+        };
+        let ifd0 = 8;
+        let subifd = ifd0 + 2 + 3 * 12 + 4;
+        let thumb_at = subifd + 2 + 3 * 12 + 4;
+        let preview_at = thumb_at + thumb.len() as u32;
+        data.extend(3u16.to_le_bytes());
+        entry(&mut data, SUB_IFDS, 4, subifd);
+        entry(&mut data, JPEG_OFFSET, 4, thumb_at);
+        entry(&mut data, JPEG_LENGTH, 4, thumb.len() as u32);
+        data.extend(0u32.to_le_bytes());
+        data.extend(3u16.to_le_bytes());
+        entry(&mut data, COMPRESSION, 3, 6);
+        entry(&mut data, STRIP_OFFSETS, 4, preview_at);
+        entry(&mut data, STRIP_BYTE_COUNTS, 4, preview.len() as u32);
+        data.extend(0u32.to_le_bytes());
+        data.extend(thumb);
+        data.extend(preview);
+
+        assert_eq!(embedded_preview(&data), Some(&preview[..]));
+        assert_eq!(embedded_preview(&data[..thumb_at as usize]), None);
+        assert_eq!(embedded_preview(b"not a tiff"), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! Removing sensitive text, like card numbers on receipts, before it is saved to the index
+
+use anyhow::Result;
+
+use crate::db::OcrResult;
+
+/// What matches are replaced with
+pub const MARKER: &str = "<redacted>";
+
+/// Replaces the matches of the `redact` patterns of the config
+#[derive(Debug, Default, Clone)]
+pub struct Redactor {
+    #[cfg(feature = "regex")]
+    patterns: Vec<regex::Regex>,
+}
+
+impl Redactor {
+    #[cfg(feature = "regex")]
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        use anyhow::Context;
+
+        let patterns = patterns
+            .iter()
+            .map(|x| regex::Regex::new(x).with_context(|| format!("invalid redact pattern: {x}")))
+            .collect::<Result<_>>()?;
+        Ok(Redactor { patterns })
+    }
+
+    #[cfg(not(feature = "regex"))]
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        if !patterns.is_empty() {
+            return Err(anyhow::anyhow!(
+                "This build was not compiled with regex support, which redact patterns need"
+            ));
+        }
+        Ok(Redactor {})
+    }
+
+    /// Replace the matches in the text and words of a result. How many were replaced is stored in
+    /// the `redacted` property, so it's clear that the text was changed
+    pub fn apply(&self, result: &mut OcrResult) {
+        #[cfg(feature = "regex")]
+        {
+            let mut count = 0;
+            for pattern in &self.patterns {
+                let matches = pattern
+                    .find_iter(&result.contents)
+                    .map(|x| x.range())
+                    .collect::<Vec<_>>();
+                count += matches.len();
+                // a match can span several words, like a card number with spaces, and every word
+                // it overlaps is replaced
+                let ranges = word_ranges(&result.contents, &result.words);
+                for (word, range) in result.words.iter_mut().zip(ranges) {
+                    let overlaps = range.is_some_and(|range| {
+                        matches
+                            .iter()
+                            .any(|x| x.start < range.end && range.start < x.end)
+                    });
+                    if overlaps || pattern.is_match(&word.text) {
+                        word.text = MARKER.to_owned();
+                    }
+                }
+                if let std::borrow::Cow::Owned(x) = pattern.replace_all(&result.contents, MARKER) {
+                    result.contents = x;
+                }
+            }
+            if count > 0 {
+                result
+                    .properties
+                    .insert("redacted".into(), count.to_string());
+            }
+        }
+        #[cfg(not(feature = "regex"))]
+        let _ = result;
+    }
+}
+
+/// Where each word is in `contents`, found in order from where the last one ended. Words that
+/// aren't found, like ones the text was changed around, have none
+#[cfg(feature = "regex")]
+fn word_ranges(contents: &str, words: &[crate::ocr::Word]) -> Vec<Option<std::ops::Range<usize>>> {
+    let mut cursor = 0;
+    words
+        .iter()
+        .map(|word| {
+            if word.text.is_empty() {
+                return None;
+            }
+            let start = cursor + contents[cursor..].find(&word.text)?;
+            cursor = start + word.text.len();
+            Some(start..cursor)
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "regex"))]
+mod tests {
+    use super::*;
+    use crate::ocr::Word;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn redact() -> Result<()> {
+        let redactor = Redactor::new(&[r"\b\d{16}\b".into(), r"\b\d{3}-\d{2}-\d{4}\b".into()])?;
+        let word = |text: &str| Word {
+            text: text.into(),
+            left: 0,
+            top: 0,
+            width: 1,
+            height: 1,
+            confidence: 90.0,
+        };
+        let mut result = OcrResult {
+            path: "/receipt.png".into(),
+            page: String::new(),
+            metadata: std::fs::metadata(".")?,
+            contents: "CARD 4111111111111111 SSN 078-05-1120 TOTAL 12.00".into(),
+            properties: BTreeMap::new(),
+            words: vec![word("CARD"), word("4111111111111111"), word("12.00")],
+        };
+        redactor.apply(&mut result);
+        assert_eq!(
+            result.contents,
+            "CARD <redacted> SSN <redacted> TOTAL 12.00"
+        );
+        assert_eq!(result.words[1].text, MARKER);
+        assert_eq!(result.words[2].text, "12.00");
+        assert_eq!(result.properties["redacted"], "2");
+
+        // a match across several words, and a word that is the same text somewhere else
+        let redactor = Redactor::new(&[r"\b\d{4}( \d{4}){3}\b".into()])?;
+        let mut spaced = OcrResult {
+            contents: "1234 CARD 4111 1111 1111 1111\nTOTAL 1234".into(),
+            properties: BTreeMap::new(),
+            words: [
+                "1234", "CARD", "4111", "1111", "1111", "1111", "TOTAL", "1234",
+            ]
+            .map(word)
+            .to_vec(),
+            path: "/card.png".into(),
+            page: String::new(),
+            metadata: std::fs::metadata(".")?,
+        };
+        redactor.apply(&mut spaced);
+        assert_eq!(spaced.contents, "1234 CARD <redacted>\nTOTAL 1234");
+        let words = spaced
+            .words
+            .iter()
+            .map(|x| x.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            words,
+            ["1234", "CARD", MARKER, MARKER, MARKER, MARKER, "TOTAL", "1234"]
+        );
+
+        let mut clean = OcrResult {
+            contents: "nothing here".into(),
+            properties: BTreeMap::new(),
+            ..result
+        };
+        redactor.apply(&mut clean);
+        assert!(!clean.properties.contains_key("redacted"));
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! Reporting errors and warnings, as text for people or as json for programs
+//!
+//! With [`set_json`], every report is one json object per line on stderr:
+//!
+//! ```json
+//! {"severity":"error","code":"extract","stage":"ocr","path":"/a/b.png","message":"..."}
+//! ```
+//!
+//! The codes and stages are stable, new ones may be added but existing ones won't change meaning.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Print reports as json lines instead of text
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Part of the work that a report comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Reading the command line and config files, and setting up
+    Setup,
+    /// Walking the directory and reading file metadata
+    Walk,
+    /// Reading images before OCR
+    Decode,
+    /// Extracting text from a file, with OCR or an extractor
+    Ocr,
+    Db,
+    /// Running the command of `--exec` on the results
+    Exec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Code {
+    /// A config file couldn't be read or is invalid
+    Config,
+    /// A worker couldn't be pinned to its cpu core
+    Affinity,
+    /// A directory entry couldn't be read
+    Walk,
+    /// The metadata of a file couldn't be read
+    Metadata,
+    /// A note couldn't be read to find the images it embeds
+    Note,
+    /// The dimensions of an image couldn't be read for `--max-size`
+    ImageSize,
+    /// An extractor failed on a file, including OCR errors
+    Extract,
+    /// A database error that stopped ocrlocate
+    Database,
+    /// Indexing stopped before every file was scanned, because of `--max-duration` or because it
+    /// was cancelled
+    Incomplete,
+    /// The command of `--exec` failed on a file
+    Exec,
+    /// Any other error that stopped ocrlocate
+    Fatal,
+}
+
+impl Code {
+    pub fn stage(self) -> Stage {
+        match self {
+            Code::Config | Code::Affinity | Code::Fatal => Stage::Setup,
+            Code::Walk | Code::Metadata | Code::Note => Stage::Walk,
+            Code::ImageSize => Stage::Decode,
+            Code::Extract | Code::Incomplete => Stage::Ocr,
+            Code::Database => Stage::Db,
+            Code::Exec => Stage::Exec,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    pub severity: Severity,
+    pub code: Code,
+    pub stage: Stage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<&'a str>,
+    pub message: String,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(
+        severity: Severity,
+        code: Code,
+        path: Option<&'a str>,
+        message: impl Display,
+    ) -> Self {
+        Report {
+            severity,
+            code,
+            stage: code.stage(),
+            path,
+            message: message.to_string(),
+        }
+    }
+
+    /// Print the report on stderr, as text or as a json line
+    pub fn print(&self) {
+        if is_json() {
+            eprintln!("{}", serde_json::to_string(self).unwrap());
+            return;
+        }
+        let prefix = match self.severity {
+            Severity::Error => "[Error] ",
+            Severity::Warning => "",
+        };
+        match self.path {
+            Some(path) => eprintln!("{prefix}{}: {}", self.message, path),
+            None => eprintln!("{prefix}{}", self.message),
+        }
+    }
+}
+
+/// Report an error that ocrlocate carries on after
+pub fn error(code: Code, path: Option<&str>, message: impl Display) {
+    Report::new(Severity::Error, code, path, message).print();
+}
+
+pub fn warning(code: Code, path: Option<&str>, message: impl Display) {
+    Report::new(Severity::Warning, code, path, message).print();
+}
+
+/// Report the error that stopped ocrlocate, like returning it from `main` would
+pub fn fatal(e: &anyhow::Error) {
+    let code = if e.chain().any(|x| x.is::<rusqlite::Error>()) {
+        Code::Database
+    } else {
+        Code::Fatal
+    };
+    if !is_json() {
+        eprintln!("Error: {:?}", e);
+        return;
+    }
+    Report {
+        severity: Severity::Error,
+        code,
+        stage: code.stage(),
+        path: None,
+        message: format!("{:#}", e),
+    }
+    .print();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize() {
+        let report = Report {
+            severity: Severity::Error,
+            code: Code::ImageSize,
+            stage: Code::ImageSize.stage(),
+            path: Some("/a/b.png"),
+            message: "failed to read image".into(),
+        };
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"severity":"error","code":"image_size","stage":"decode","path":"/a/b.png","message":"failed to read image"}"#
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Sentence embeddings of the stored text, for `--search-type semantic`
+//!
+//! The model is loaded from a directory with `model.onnx` and the `tokenizer.json` of its
+//! tokenizer, like the ONNX exports of sentence-transformers models such as all-MiniLM-L6-v2.
+//! The embedding of a text is the mean of the embeddings of its tokens. onnxruntime is loaded from
+//! `ORT_DYLIB_PATH` or the library path, like for the onnx engine.
+
+use std::borrow::Cow;
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+use ort::session::{Session, SessionInputValue};
+use ort::value::Tensor;
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+
+/// Most tokens of a text that are embedded, unless `tokenizer.json` says otherwise. Most
+/// sentence models were trained on shorter texts than they accept
+const MAX_TOKENS: usize = 256;
+
+pub struct Embedder {
+    session: Session,
+    tokenizer: Tokenizer,
+    name: String,
+}
+
+impl Embedder {
+    pub fn new(dir: &Path) -> Result<Self> {
+        let dir = PathBuf::try_from(
+            fs::canonicalize(dir).with_context(|| format!("failed to find {dir}"))?,
+        )?;
+        let session = Session::builder()?
+            .commit_from_file(dir.join("model.onnx"))
+            .with_context(|| format!("failed to load {}", dir.join("model.onnx")))?;
+        let mut tokenizer = Tokenizer::from_file(dir.join("tokenizer.json"))
+            .map_err(|e| anyhow!("failed to load {}: {e}", dir.join("tokenizer.json")))?;
+        // batches are padded to their longest text, and the padding is masked out
+        let padding = tokenizer.get_padding().cloned().unwrap_or_default();
+        tokenizer.with_padding(Some(PaddingParams {
+            strategy: PaddingStrategy::BatchLongest,
+            ..padding
+        }));
+        if tokenizer.get_truncation().is_none() {
+            tokenizer
+                .with_truncation(Some(TruncationParams {
+                    max_length: MAX_TOKENS,
+                    ..Default::default()
+                }))
+                .map_err(|e| anyhow!("{e}"))?;
+        }
+        Ok(Embedder {
+            session,
+            tokenizer,
+            name: dir.file_name().unwrap_or(dir.as_str()).to_owned(),
+        })
+    }
+
+    /// Name that the embeddings of this model are saved with, which is the name of its directory
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The embeddings of the texts, in their order
+    pub fn embed(&mut self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(vec![]);
+        }
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(|e| anyhow!("failed to tokenize text: {e}"))?;
+        let shape = [texts.len(), encodings[0].len()];
+        let flat = |f: fn(&tokenizers::Encoding) -> &[u32]| -> Vec<i64> {
+            encodings
+                .iter()
+                .flat_map(|x| f(x).iter().map(|&x| x as i64))
+                .collect()
+        };
+        let mask = flat(tokenizers::Encoding::get_attention_mask);
+        // models differ in which of these they take
+        let mut inputs: Vec<(Cow<str>, SessionInputValue)> = vec![];
+        for input in &self.session.inputs {
+            let values = match input.name.as_str() {
+                "input_ids" => flat(tokenizers::Encoding::get_ids),
+                "attention_mask" => mask.clone(),
+                "token_type_ids" => flat(tokenizers::Encoding::get_type_ids),
+                x => return Err(anyhow!("unknown input of the embedding model: {x}")),
+            };
+            let tensor = Tensor::from_array((shape, values))?;
+            inputs.push((input.name.clone().into(), tensor.into()));
+        }
+        let outputs = self.session.run(inputs)?;
+        let (shape, values) = outputs[0].try_extract_tensor::<f32>()?;
+        match shape[..] {
+            // token embeddings
+            [_, tokens, dims] => Ok(mean_pool(values, &mask, tokens as usize, dims as usize)),
+            // a model that pools them itself
+            [_, dims] => Ok(values
+                .chunks_exact(dims as usize)
+                .map(|x| x.to_vec())
+                .collect()),
+            _ => Err(anyhow!(
+                "unexpected output of the embedding model: {shape:?}"
+            )),
+        }
+    }
+}
+
+/// The mean of the embeddings of the tokens of every text that aren't padding.
+/// `values` are the embeddings of the `tokens` of every text one after another, of `dims` values
+/// each, and `mask` is 1 for the tokens that aren't padding
+fn mean_pool(values: &[f32], mask: &[i64], tokens: usize, dims: usize) -> Vec<Vec<f32>> {
+    (0..mask.len() / tokens)
+        .map(|text| {
+            let mut sum = vec![0.0; dims];
+            let mut count = 0.0;
+            for token in 0..tokens {
+                if mask[text * tokens + token] == 0 {
+                    continue;
+                }
+                let start = (text * tokens + token) * dims;
+                for (sum, value) in sum.iter_mut().zip(&values[start..start + dims]) {
+                    *sum += value;
+                }
+                count += 1.0;
+            }
+            sum.into_iter().map(|x| x / f32::max(count, 1.0)).collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pooling() {
+        // two texts of two tokens, the second token of the second one is padding
+        let values = [1.0, 0.0, 3.0, 3.0, 0.0, 2.0, 9.0, 9.0];
+        let pooled = mean_pool(&values, &[1, 1, 1, 0], 2, 2);
+        assert_eq!(pooled, [vec![2.0, 1.5], vec![0.0, 2.0]]);
+    }
+}
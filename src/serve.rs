@@ -0,0 +1,397 @@
+use std::fs::File;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server};
+
+use crate::db::{Maintenance, SearchType, DB};
+
+const INDEX_HTML: &str = include_str!("web/index.html");
+
+pub struct ServeOptions {
+    /// Address to listen on, like `127.0.0.1:8080`
+    pub listen: String,
+    /// Only files under this directory are searched and served
+    pub root: PathBuf,
+    /// The directories that were indexed, which maintenance removes the deleted files of
+    pub roots: Vec<PathBuf>,
+    /// Max amount of results per search
+    pub limit: usize,
+    /// Credentials every request has to carry
+    pub auth: Option<Auth>,
+    /// Certificate chain and private key files in PEM format, to serve https
+    #[cfg(feature = "tls")]
+    pub tls: Option<(PathBuf, PathBuf)>,
+    /// Only allow GET and HEAD requests, and make the database connection refuse writes
+    pub read_only: bool,
+    /// Run [`DB::maintain`] once the server has had no requests for this long
+    pub maintain_after: Option<Duration>,
+}
+
+/// Served at `/api/status`
+#[derive(Debug, Default, Serialize)]
+struct Status {
+    /// When maintenance last ran, in seconds since the epoch
+    maintained_at: Option<u64>,
+    maintenance: Option<Maintenance>,
+    /// Why the last maintenance failed
+    maintenance_error: Option<String>,
+}
+
+pub enum Auth {
+    /// HTTP basic auth, which browsers ask for by themselves
+    Basic { user: String, password: String },
+    /// `Authorization: Bearer <token>`, or a `token` query parameter for links and images
+    Token(String),
+}
+
+/// Serve the search UI and its json api until the process is killed
+///
+/// * `GET /` - the search page
+/// * `GET /api/search?q=..&type=simple` - json array of results
+/// * `GET /api/text?path=..&page=..` - the stored text of one file, or one page of a document
+/// * `GET /api/image?path=..` - the file itself, if it is in the index
+/// * `GET /api/status` - what the last maintenance did, see [`ServeOptions::maintain_after`]
+pub fn serve(db: &mut DB, options: &ServeOptions) -> Result<()> {
+    db.set_read_only(options.read_only)?;
+
+    #[cfg(feature = "tls")]
+    let (server, scheme) = match &options.tls {
+        Some((cert, key)) => {
+            let ssl = tiny_http::SslConfig {
+                certificate: std::fs::read(cert)
+                    .map_err(|e| anyhow!("failed to read {}: {}", cert, e))?,
+                private_key: std::fs::read(key)
+                    .map_err(|e| anyhow!("failed to read {}: {}", key, e))?,
+            };
+            (Server::https(&options.listen, ssl), "https")
+        }
+        None => (Server::http(&options.listen), "http"),
+    };
+    #[cfg(not(feature = "tls"))]
+    let (server, scheme) = (Server::http(&options.listen), "http");
+
+    let server = server.map_err(|e| anyhow!("failed to listen on {}: {}", options.listen, e))?;
+    eprintln!("Listening on {}://{}", scheme, server.server_addr());
+    let local = server
+        .server_addr()
+        .to_ip()
+        .is_none_or(|x| x.ip().is_loopback());
+    if options.auth.is_none() && !local {
+        eprintln!("Note: anyone who can reach this address can search the index, see --auth");
+    }
+
+    let mut status = Status::default();
+    let mut last_request = Instant::now();
+    let mut maintained = false;
+    loop {
+        // wake up when the server has been idle for long enough, once after every busy period
+        let timeout = match options.maintain_after {
+            Some(idle) if !maintained => idle.saturating_sub(last_request.elapsed()),
+            _ => Duration::from_secs(3600),
+        };
+        let Some(request) = server.recv_timeout(timeout)? else {
+            if options.maintain_after.is_some() && !maintained {
+                maintain(db, options, &mut status);
+                maintained = true;
+            }
+            continue;
+        };
+        last_request = Instant::now();
+        maintained = false;
+        let response = match handle(db, options, &status, &request) {
+            Ok(response) => response,
+            Err(e) => Response::from_string(format!("{:#}", e))
+                .with_status_code(400)
+                .boxed(),
+        };
+        if let Err(e) = request.respond(response) {
+            eprintln!("[Error] responding to request: {}", e);
+        }
+    }
+}
+
+/// Run [`DB::maintain`] on the roots, which needs writes even when the server is read-only
+fn maintain(db: &mut DB, options: &ServeOptions, status: &mut Status) {
+    let result = db
+        .set_read_only(false)
+        .and_then(|_| db.maintain(&options.roots));
+    if let Err(e) = db.set_read_only(options.read_only) {
+        eprintln!("[Error] making the database read-only again: {:#}", e);
+    }
+    status.maintained_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|x| x.as_secs());
+    match result {
+        Ok(maintenance) => {
+            status.maintenance = Some(maintenance);
+            status.maintenance_error = None;
+        }
+        Err(e) => {
+            eprintln!("[Error] maintaining the database: {:#}", e);
+            status.maintenance_error = Some(format!("{:#}", e));
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Text<'a> {
+    path: &'a str,
+    page: &'a str,
+    content: String,
+}
+
+fn handle(
+    db: &mut DB,
+    options: &ServeOptions,
+    status: &Status,
+    request: &Request,
+) -> Result<ResponseBox> {
+    let (route, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+    if let Some(auth) = &options.auth {
+        if !authorized(auth, request, query) {
+            let response = Response::from_string("unauthorized").with_status_code(401);
+            return Ok(match auth {
+                Auth::Basic { .. } => response
+                    .with_header(
+                        Header::from_bytes("WWW-Authenticate", r#"Basic realm="ocrlocate""#)
+                            .unwrap(),
+                    )
+                    .boxed(),
+                Auth::Token(_) => response.boxed(),
+            });
+        }
+    }
+    // none of the routes change the index yet, so allowing writes only turns the 403 into a 405
+    if !matches!(request.method(), Method::Get | Method::Head) {
+        let status = if options.read_only { 403 } else { 405 };
+        return Ok(Response::empty(status).boxed());
+    }
+    match route {
+        "/" => Ok(Response::from_string(INDEX_HTML)
+            .with_header(content_type("text/html; charset=utf-8"))
+            .boxed()),
+        "/api/search" => {
+            let q = param(query, "q").ok_or_else(|| anyhow!("missing parameter: q"))?;
+            let kind = match param(query, "type") {
+                Some(x) => x.parse()?,
+                None => SearchType::Simple,
+            };
+            let results = db.search(vec![&q], &options.root, options.limit, kind, None, None)?;
+            json(&results)
+        }
+        "/api/text" => {
+            let path = indexed_path(db, options, query)?;
+            let page = param(query, "page").unwrap_or_default();
+            match db.content(&path, &page)? {
+                Some(content) => json(&Text {
+                    path: path.as_str(),
+                    page: &page,
+                    content,
+                }),
+                None => Ok(Response::empty(404).boxed()),
+            }
+        }
+        "/api/image" => {
+            let path = indexed_path(db, options, query)?;
+            file(&path)
+        }
+        "/api/status" => json(status),
+        _ => Ok(Response::empty(404).boxed()),
+    }
+}
+
+/// The `path` parameter, which has to be an indexed file under the root so that the server can't
+/// be used to read arbitrary files
+fn indexed_path(db: &DB, options: &ServeOptions, query: &str) -> Result<PathBuf> {
+    let path =
+        PathBuf::from(param(query, "path").ok_or_else(|| anyhow!("missing parameter: path"))?);
+    if !path.starts_with(&options.root) || !db.is_path_indexed(&path)? {
+        return Err(anyhow!("not an indexed file: {}", path));
+    }
+    Ok(path)
+}
+
+fn authorized(auth: &Auth, request: &Request, query: &str) -> bool {
+    let header = request
+        .headers()
+        .iter()
+        .find(|x| x.field.equiv("Authorization"))
+        .map(|x| x.value.as_str().trim());
+    match auth {
+        Auth::Basic { user, password } => header
+            .and_then(|x| x.strip_prefix("Basic "))
+            .and_then(|x| base64_decode(x.trim()))
+            .is_some_and(|x| constant_eq(&x, format!("{user}:{password}").as_bytes())),
+        Auth::Token(token) => header
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .map(|x| x.trim().to_owned())
+            .or_else(|| param(query, "token"))
+            .is_some_and(|x| constant_eq(x.as_bytes(), token.as_bytes())),
+    }
+}
+
+/// Compare without stopping at the first difference, so the time it takes doesn't tell how much
+/// of a guess was right
+fn constant_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in s.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6 | value as u32) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+fn json<T: Serialize>(value: &T) -> Result<ResponseBox> {
+    Ok(Response::from_string(serde_json::to_string(value)?)
+        .with_header(content_type("application/json"))
+        .boxed())
+}
+
+/// An indexed file, with headers that keep it from running scripts on the origin of the server,
+/// where they could use the credentials of the page. An SVG can have scripts, so it's sandboxed
+/// and downloaded when it's opened on its own, which an `<img>` of it ignores
+fn file(path: &Path) -> Result<ResponseBox> {
+    let mime = mime_type(path);
+    let mut response = Response::from_file(File::open(path)?)
+        .with_header(content_type(mime))
+        .with_header(Header::from_bytes("X-Content-Type-Options", "nosniff").unwrap());
+    if mime == "image/svg+xml" {
+        response = response
+            .with_header(Header::from_bytes("Content-Security-Policy", "sandbox").unwrap())
+            .with_header(Header::from_bytes("Content-Disposition", "attachment").unwrap());
+    }
+    Ok(response.boxed())
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).unwrap()
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    match path.extension().map(|x| x.to_ascii_lowercase()).as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("heic" | "heif") => "image/heic",
+        Some("avif") => "image/avif",
+        Some("svg") => "image/svg+xml",
+        Some("cbz") => "application/vnd.comicbook+zip",
+        Some("epub") => "application/epub+zip",
+        Some("djvu" | "djv") => "image/vnd.djvu",
+        Some("docx") => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        Some("pptx") => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        Some("odt") => "application/vnd.oasis.opendocument.text",
+        Some("cr2") => "image/x-canon-cr2",
+        Some("nef") => "image/x-nikon-nef",
+        Some("arw") => "image/x-sony-arw",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Find and percent-decode a parameter of a query string
+fn param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+fn percent_decode(s: &str) -> String {
+    let s = s.as_bytes();
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .filter(|x| x.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|x| u8::from_str_radix(std::str::from_utf8(x).ok()?, 16).ok());
+        match (s[i], hex) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                i += 2;
+            }
+            (b'+', _) => bytes.push(b' '),
+            (b, _) => bytes.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_params() {
+        let query = "q=hello+world%21&path=%2Fhome%2Fa%20b.png&empty";
+        assert_eq!(param(query, "q").unwrap(), "hello world!");
+        assert_eq!(param(query, "path").unwrap(), "/home/a b.png");
+        assert_eq!(param(query, "empty").unwrap(), "");
+        assert_eq!(param(query, "missing"), None);
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("%e2%9c%93"), "✓");
+    }
+
+    #[test]
+    fn file_headers() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let root = PathBuf::try_from(temp.path().to_owned())?;
+        fn header(response: &ResponseBox, name: &'static str) -> Option<String> {
+            response
+                .headers()
+                .iter()
+                .find(|x| x.field.equiv(name))
+                .map(|x| x.value.to_string())
+        }
+        for name in ["a.png", "b.SVG"] {
+            File::create(root.join(name))?;
+        }
+
+        let png = file(&root.join("a.png"))?;
+        assert_eq!(header(&png, "Content-Type").unwrap(), "image/png");
+        assert_eq!(header(&png, "X-Content-Type-Options").unwrap(), "nosniff");
+        assert_eq!(header(&png, "Content-Security-Policy"), None);
+
+        let svg = file(&root.join("b.SVG"))?;
+        assert_eq!(header(&svg, "Content-Type").unwrap(), "image/svg+xml");
+        assert_eq!(header(&svg, "X-Content-Type-Options").unwrap(), "nosniff");
+        assert_eq!(header(&svg, "Content-Security-Policy").unwrap(), "sandbox");
+        assert_eq!(header(&svg, "Content-Disposition").unwrap(), "attachment");
+        Ok(())
+    }
+
+    #[test]
+    fn basic_auth() {
+        assert_eq!(base64_decode("dXNlcjpwYXNz").unwrap(), b"user:pass");
+        assert_eq!(base64_decode("dXNlcjpwYXNzMQ==").unwrap(), b"user:pass1");
+        assert_eq!(base64_decode("dXNlcjpw YXNz"), None);
+        assert!(constant_eq(b"secret", b"secret"));
+        assert!(!constant_eq(b"secret", b"secreT"));
+        assert!(!constant_eq(b"secret", b"secret2"));
+    }
+}
@@ -0,0 +1,164 @@
+//! Removing the emoji and UI glyphs of screenshots from the text before it is saved, since every
+//! one of them adds trigrams that only match noise
+
+use std::borrow::Cow;
+
+use crate::db::OcrResult;
+
+/// What is done with symbols. The policy is saved as the `symbols` property of every file, so
+/// that files saved with another one can be found and OCRed again
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Symbols {
+    /// Replace symbols and typographic punctuation that have an ASCII look-alike, like `→`
+    pub transliterate: bool,
+    /// Remove emoji, pictographs, arrows, box drawing and private use characters of icon fonts
+    pub strip: bool,
+}
+
+impl Symbols {
+    /// Name of the policy, `None` if text is saved as it was recognized
+    pub fn policy(self) -> Option<&'static str> {
+        match (self.transliterate, self.strip) {
+            (false, false) => None,
+            (true, false) => Some("transliterate"),
+            (false, true) => Some("strip"),
+            (true, true) => Some("transliterate+strip"),
+        }
+    }
+
+    /// The text with the policy applied, and how many characters were changed
+    pub fn apply_text<'a>(self, text: &'a str) -> (Cow<'a, str>, usize) {
+        if self.policy().is_none() || !text.chars().any(|c| self.affects(c)) {
+            return (Cow::Borrowed(text), 0);
+        }
+        let mut out = String::with_capacity(text.len());
+        let mut count = 0;
+        for c in text.chars() {
+            if !self.affects(c) {
+                out.push(c);
+                continue;
+            }
+            count += 1;
+            if let Some(x) = transliteration(c).filter(|_| self.transliterate) {
+                out.push_str(x);
+            }
+        }
+        (Cow::Owned(out), count)
+    }
+
+    /// Apply the policy to the text and words of a result, and save it in its properties along
+    /// with how many characters were changed
+    pub fn apply(self, result: &mut OcrResult) {
+        let Some(policy) = self.policy() else {
+            return;
+        };
+        let (contents, count) = self.apply_text(&result.contents);
+        if let Cow::Owned(x) = contents {
+            result.contents = x;
+        }
+        for word in &mut result.words {
+            if let (Cow::Owned(x), _) = self.apply_text(&word.text) {
+                word.text = x;
+            }
+        }
+        result.words.retain(|x| !x.text.trim().is_empty());
+        result.properties.insert("symbols".into(), policy.into());
+        if count > 0 {
+            result
+                .properties
+                .insert("symbols_replaced".into(), count.to_string());
+        }
+    }
+
+    fn affects(self, c: char) -> bool {
+        (self.transliterate && transliteration(c).is_some()) || (self.strip && is_symbol(c))
+    }
+}
+
+/// ASCII look-alike of a character
+fn transliteration(c: char) -> Option<&'static str> {
+    Some(match c {
+        '‘' | '’' | '‚' | '‛' | '′' => "'",
+        '“' | '”' | '„' | '‟' | '″' => "\"",
+        '‐' | '‑' | '‒' | '–' | '—' | '―' | '−' => "-",
+        '…' => "...",
+        '•' | '·' | '∙' | '●' | '▪' => "*",
+        '→' | '⇒' | '➔' | '➜' => "->",
+        '←' | '⇐' => "<-",
+        '↔' => "<->",
+        '×' => "x",
+        '÷' => "/",
+        '≤' => "<=",
+        '≥' => ">=",
+        '≠' => "!=",
+        '©' => "(c)",
+        '®' => "(r)",
+        '™' => "TM",
+        '½' => "1/2",
+        '¼' => "1/4",
+        '¾' => "3/4",
+        'ﬀ' => "ff",
+        'ﬁ' => "fi",
+        'ﬂ' => "fl",
+        'ﬃ' => "ffi",
+        'ﬄ' => "ffl",
+        '\u{a0}' | '\u{2007}' | '\u{202f}' => " ",
+        _ => return None,
+    })
+}
+
+/// Emoji, pictographs and glyphs of UIs, by their blocks
+fn is_symbol(c: char) -> bool {
+    matches!(c,
+        // arrows, technical symbols, box drawing, blocks and geometric shapes
+        '\u{2190}'..='\u{21ff}'
+        | '\u{2300}'..='\u{23ff}'
+        | '\u{2500}'..='\u{25ff}'
+        // miscellaneous symbols, dingbats and more arrows
+        | '\u{2600}'..='\u{27bf}'
+        | '\u{2b00}'..='\u{2bff}'
+        // joiners and variation selectors that emoji are made of
+        | '\u{200d}'
+        | '\u{20e3}'
+        | '\u{fe00}'..='\u{fe0f}'
+        // private use, where icon fonts put their glyphs
+        | '\u{e000}'..='\u{f8ff}'
+        | '\u{f0000}'..='\u{10ffff}'
+        // emoji and pictographs
+        | '\u{1f000}'..='\u{1faff}'
+        | '\u{e0020}'..='\u{e007f}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies() {
+        let text = "Settings ⚙️ → “Wi‑Fi” 📶 \u{e5cd}";
+        let strip = Symbols {
+            strip: true,
+            ..Default::default()
+        };
+        let transliterate = Symbols {
+            transliterate: true,
+            ..Default::default()
+        };
+        let both = Symbols {
+            transliterate: true,
+            strip: true,
+        };
+        assert_eq!(Symbols::default().apply_text(text), (text.into(), 0));
+        assert_eq!(strip.apply_text(text), ("Settings   “Wi‑Fi”  ".into(), 5));
+        assert_eq!(
+            transliterate.apply_text(text),
+            ("Settings ⚙️ -> \"Wi-Fi\" 📶 \u{e5cd}".into(), 4)
+        );
+        assert_eq!(
+            both.apply_text(text),
+            ("Settings  -> \"Wi-Fi\"  ".into(), 8)
+        );
+        assert_eq!(both.apply_text("plain"), (Cow::Borrowed("plain"), 0));
+    }
+}
@@ -0,0 +1,385 @@
+//! The `ocrlocate` fts5 tokenizer, an alternative to the builtin trigram tokenizer
+//!
+//! The trigram tokenizer splits text into groups of three codepoints, which cuts Thai vowel marks
+//! off their consonants and makes every two syllable Korean or Chinese word unsearchable. This
+//! tokenizer works on grapheme clusters instead, and takes smaller n-grams of the scripts where a
+//! single grapheme carries more meaning.
+//!
+//! Text is split into runs of the same script, with punctuation and spaces joining the run they
+//! are in. Each run is tokenized into overlapping lowercase n-grams, or a single token if it is
+//! shorter than that. Just like with trigrams, queries shorter than the n-gram size of their
+//! script can't match anything.
+//!
+//! With `tokenize='ocrlocate remove_diacritics 1'`, accented Latin letters are folded into the
+//! letters without the accents, see [`remove_diacritics`].
+//!
+//! With `tokenize='ocrlocate words 1'`, it works like the builtin unicode61 tokenizer instead, see
+//! [`tokenize_words`].
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use anyhow::{anyhow, Result};
+use rusqlite::{ffi, Connection};
+use unicode_script::{Script, UnicodeScript};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Name the tokenizer is registered as, used in `tokenize='ocrlocate'`
+pub const NAME: &str = "ocrlocate";
+
+/// Amount of graphemes in each token of a run of `script`
+fn gram_size(script: Option<Script>) -> usize {
+    match script {
+        Some(
+            Script::Hangul
+            | Script::Han
+            | Script::Hiragana
+            | Script::Katakana
+            | Script::Thai
+            | Script::Lao
+            | Script::Khmer
+            | Script::Myanmar,
+        ) => 2,
+        _ => 3,
+    }
+}
+
+/// Call `token` with the text and byte range of every token in `text`, folding accented letters
+/// with `fold`
+pub fn tokenize<E>(
+    text: &str,
+    fold: bool,
+    mut token: impl FnMut(&str, usize, usize) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut run: Vec<(usize, &str)> = vec![];
+    let mut script = None;
+    for (start, grapheme) in text.grapheme_indices(true) {
+        let current = match grapheme.chars().next().map(|c| c.script()) {
+            Some(Script::Common | Script::Inherited | Script::Unknown) | None => None,
+            x => x,
+        };
+        match (script, current) {
+            (Some(a), Some(b)) if a != b => {
+                emit(&run, gram_size(script), fold, &mut token)?;
+                run.clear();
+                script = current;
+            }
+            (None, Some(_)) => script = current,
+            _ => (),
+        }
+        run.push((start, grapheme));
+    }
+    emit(&run, gram_size(script), fold, &mut token)
+}
+
+/// Whether `c` is written without spaces between words, so that it can't be split into words
+fn is_cjk(c: char) -> bool {
+    matches!(
+        c.script(),
+        Script::Han | Script::Hiragana | Script::Katakana | Script::Hangul
+    )
+}
+
+/// Call `token` with every lowercase word in `text`, and every pair of letters in runs of CJK
+/// text, which has no spaces to split it into words
+///
+/// This makes a smaller index than n-grams, where words of one or two letters can be found and
+/// results are ranked by whole words, but searches only match whole words, and a single CJK letter
+/// matches no pair.
+pub fn tokenize_words<E>(
+    text: &str,
+    fold: bool,
+    mut token: impl FnMut(&str, usize, usize) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut word: Vec<(usize, &str)> = vec![];
+    let mut cjk = false;
+    for (start, grapheme) in text.grapheme_indices(true) {
+        let c = grapheme.chars().next().unwrap_or(' ');
+        let current = is_cjk(c);
+        if !c.is_alphanumeric() || (current != cjk && !word.is_empty()) {
+            emit(&word, if cjk { 2 } else { word.len() }, fold, &mut token)?;
+            word.clear();
+        }
+        if c.is_alphanumeric() {
+            cjk = current;
+            word.push((start, grapheme));
+        }
+    }
+    emit(&word, if cjk { 2 } else { word.len() }, fold, &mut token)
+}
+
+fn emit<E>(
+    run: &[(usize, &str)],
+    size: usize,
+    fold: bool,
+    token: &mut impl FnMut(&str, usize, usize) -> Result<(), E>,
+) -> Result<(), E> {
+    if run.is_empty() {
+        return Ok(());
+    }
+    for window in run.windows(size.min(run.len())) {
+        let (start, _) = window[0];
+        let (last, grapheme) = window[window.len() - 1];
+        let mut text: String = window.iter().map(|(_, x)| x.to_lowercase()).collect();
+        if fold {
+            text = remove_diacritics(&text);
+        }
+        token(&text, start, last + grapheme.len())?;
+    }
+    Ok(())
+}
+
+/// Lowercase `text` with the accents taken off of Latin letters, like `café` to `cafe`, the way
+/// the `remove_diacritics` option of fts5's tokenizers does
+pub fn remove_diacritics(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        // combining marks, which decomposed text has instead of accented letters
+        .filter(|c| !('\u{300}'..='\u{36f}').contains(c))
+        .map(|c| match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' | 'ǎ' | 'ǟ' | 'ǡ' | 'ǻ' | 'ȁ'
+            | 'ȃ' | 'ȧ' | 'ḁ' | 'ạ' | 'ả' | 'ấ' | 'ầ' | 'ẩ' | 'ẫ' | 'ậ' | 'ắ' | 'ằ' | 'ẳ' | 'ẵ'
+            | 'ặ' => 'a',
+            'ƀ' | 'ḃ' | 'ḅ' | 'ḇ' => 'b',
+            'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' | 'ḉ' => 'c',
+            'ď' | 'đ' | 'ḋ' | 'ḍ' | 'ḏ' | 'ḑ' | 'ḓ' => 'd',
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' | 'ȅ' | 'ȇ' | 'ȩ' | 'ḕ' | 'ḗ'
+            | 'ḙ' | 'ḛ' | 'ḝ' | 'ẹ' | 'ẻ' | 'ẽ' | 'ế' | 'ề' | 'ể' | 'ễ' | 'ệ' => {
+                'e'
+            }
+            'ḟ' => 'f',
+            'ĝ' | 'ğ' | 'ġ' | 'ģ' | 'ǧ' | 'ǵ' | 'ḡ' => 'g',
+            'ĥ' | 'ħ' | 'ȟ' | 'ḣ' | 'ḥ' | 'ḧ' | 'ḩ' | 'ḫ' | 'ẖ' => 'h',
+            'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ǐ' | 'ȉ' | 'ȋ' | 'ḭ' | 'ḯ' | 'ỉ'
+            | 'ị' => 'i',
+            'ĵ' | 'ǰ' => 'j',
+            'ķ' | 'ǩ' | 'ḱ' | 'ḳ' | 'ḵ' => 'k',
+            'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' | 'ḷ' | 'ḹ' | 'ḻ' | 'ḽ' => 'l',
+            'ḿ' | 'ṁ' | 'ṃ' => 'm',
+            'ñ' | 'ń' | 'ņ' | 'ň' | 'ǹ' | 'ṅ' | 'ṇ' | 'ṉ' | 'ṋ' => 'n',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' | 'ơ' | 'ǒ' | 'ǫ' | 'ǭ' | 'ȍ'
+            | 'ȏ' | 'ȫ' | 'ȭ' | 'ȯ' | 'ȱ' | 'ṍ' | 'ṏ' | 'ṑ' | 'ṓ' | 'ọ' | 'ỏ' | 'ố' | 'ồ' | 'ổ'
+            | 'ỗ' | 'ộ' | 'ớ' | 'ờ' | 'ở' | 'ỡ' | 'ợ' => 'o',
+            'ṕ' | 'ṗ' => 'p',
+            'ŕ' | 'ŗ' | 'ř' | 'ȑ' | 'ȓ' | 'ṙ' | 'ṛ' | 'ṝ' | 'ṟ' => 'r',
+            'ś' | 'ŝ' | 'ş' | 'š' | 'ș' | 'ṡ' | 'ṣ' | 'ṥ' | 'ṧ' | 'ṩ' => 's',
+            'ţ' | 'ť' | 'ŧ' | 'ț' | 'ṫ' | 'ṭ' | 'ṯ' | 'ṱ' | 'ẗ' => 't',
+            'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' | 'ư' | 'ǔ' | 'ǖ' | 'ǘ'
+            | 'ǚ' | 'ǜ' | 'ȕ' | 'ȗ' | 'ṳ' | 'ṵ' | 'ṷ' | 'ṹ' | 'ṻ' | 'ụ' | 'ủ' | 'ứ' | 'ừ' | 'ử'
+            | 'ữ' | 'ự' => 'u',
+            'ṽ' | 'ṿ' => 'v',
+            'ŵ' | 'ẁ' | 'ẃ' | 'ẅ' | 'ẇ' | 'ẉ' | 'ẘ' => 'w',
+            'ẋ' | 'ẍ' => 'x',
+            'ý' | 'ÿ' | 'ŷ' | 'ȳ' | 'ẏ' | 'ẙ' | 'ỳ' | 'ỵ' | 'ỷ' | 'ỹ' => 'y',
+            'ź' | 'ż' | 'ž' | 'ƶ' | 'ẑ' | 'ẓ' | 'ẕ' => 'z',
+            c => c,
+        })
+        .collect()
+}
+
+/// Register the tokenizer with a connection. This has to happen before the connection touches a
+/// table that uses it
+pub fn register(conn: &Connection) -> Result<()> {
+    unsafe {
+        let db = conn.handle();
+        let api = fts5_api(db)?;
+        let mut tokenizer = ffi::fts5_tokenizer {
+            xCreate: Some(create),
+            xDelete: Some(delete),
+            xTokenize: Some(tokenize_ffi),
+        };
+        let name = CString::new(NAME).unwrap();
+        let rc = ((*api).xCreateTokenizer.unwrap())(
+            api,
+            name.as_ptr(),
+            ptr::null_mut(),
+            &mut tokenizer,
+            None,
+        );
+        if rc != ffi::SQLITE_OK {
+            return Err(anyhow!(
+                "failed to register fts5 tokenizer: error code {}",
+                rc
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Fetch the fts5 api struct, which is handed out through a pointer bound to `SELECT fts5(?)`
+unsafe fn fts5_api(db: *mut ffi::sqlite3) -> Result<*mut ffi::fts5_api> {
+    let mut stmt = ptr::null_mut();
+    let sql = c"SELECT fts5(?1)";
+    if ffi::sqlite3_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, ptr::null_mut()) != ffi::SQLITE_OK {
+        return Err(anyhow!("fts5 is not available"));
+    }
+    let mut api: *mut ffi::fts5_api = ptr::null_mut();
+    ffi::sqlite3_bind_pointer(
+        stmt,
+        1,
+        &mut api as *mut _ as *mut c_void,
+        c"fts5_api_ptr".as_ptr(),
+        None,
+    );
+    ffi::sqlite3_step(stmt);
+    ffi::sqlite3_finalize(stmt);
+    if api.is_null() {
+        return Err(anyhow!("fts5 is not available"));
+    }
+    Ok(api)
+}
+
+/// The options of the tokenizer of one table
+struct Tokenizer {
+    remove_diacritics: bool,
+    words: bool,
+}
+
+unsafe extern "C" fn create(
+    _user_data: *mut c_void,
+    args: *mut *const c_char,
+    nargs: c_int,
+    out: *mut *mut ffi::Fts5Tokenizer,
+) -> c_int {
+    let args: Vec<&[u8]> = (0..nargs as usize)
+        .map(|i| CStr::from_ptr(*args.add(i)).to_bytes())
+        .collect();
+    let mut options = Tokenizer {
+        remove_diacritics: false,
+        words: false,
+    };
+    let pairs = args.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return ffi::SQLITE_ERROR;
+    }
+    for pair in pairs {
+        match pair[0] {
+            b"remove_diacritics" => options.remove_diacritics = pair[1] != b"0",
+            b"words" => options.words = pair[1] != b"0",
+            _ => return ffi::SQLITE_ERROR,
+        }
+    }
+    *out = Box::into_raw(Box::new(options)) as *mut ffi::Fts5Tokenizer;
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn delete(tokenizer: *mut ffi::Fts5Tokenizer) {
+    drop(Box::from_raw(tokenizer as *mut Tokenizer));
+}
+
+unsafe extern "C" fn tokenize_ffi(
+    tokenizer: *mut ffi::Fts5Tokenizer,
+    ctx: *mut c_void,
+    _flags: c_int,
+    text: *const c_char,
+    len: c_int,
+    callback: Option<
+        unsafe extern "C" fn(*mut c_void, c_int, *const c_char, c_int, c_int, c_int) -> c_int,
+    >,
+) -> c_int {
+    let (Some(callback), Ok(len)) = (callback, usize::try_from(len)) else {
+        return ffi::SQLITE_ERROR;
+    };
+    let bytes = if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(text as *const u8, len)
+    };
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return ffi::SQLITE_ERROR;
+    };
+    let options = &*(tokenizer as *mut Tokenizer);
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        let emit = |token: &str, start: usize, end: usize| {
+            let rc = callback(
+                ctx,
+                0,
+                token.as_ptr() as *const c_char,
+                token.len() as c_int,
+                start as c_int,
+                end as c_int,
+            );
+            if rc == ffi::SQLITE_OK {
+                Ok(())
+            } else {
+                Err(rc)
+            }
+        };
+        match options.words {
+            true => tokenize_words(text, options.remove_diacritics, emit),
+            false => tokenize(text, options.remove_diacritics, emit),
+        }
+    }));
+    match res {
+        Ok(Ok(())) => ffi::SQLITE_OK,
+        Ok(Err(rc)) => rc,
+        Err(_) => ffi::SQLITE_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(text: &str) -> Vec<String> {
+        let mut tokens = vec![];
+        tokenize::<()>(text, false, |token, start, end| {
+            assert_eq!(text[start..end].to_lowercase(), token);
+            tokens.push(token.to_owned());
+            Ok(())
+        })
+        .unwrap();
+        tokens
+    }
+
+    #[test]
+    fn scripts() {
+        assert_eq!(tokens("Ab"), ["ab"]);
+        assert_eq!(tokens("ABCD"), ["abc", "bcd"]);
+        assert_eq!(tokens("한국어"), ["한국", "국어"]);
+        assert_eq!(tokens("ab 한국"), ["ab ", "한국"]);
+        // the vowel marks stay with their consonants
+        assert_eq!(tokens("ที่นี่"), ["ที่นี่"]);
+        assert_eq!(tokens(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn words() {
+        let words = |text: &str| {
+            let mut tokens = vec![];
+            tokenize_words::<()>(text, false, |token, start, end| {
+                assert_eq!(text[start..end].to_lowercase(), token);
+                tokens.push(token.to_owned());
+                Ok(())
+            })
+            .unwrap();
+            tokens
+        };
+        assert_eq!(words("Total: 3,50 EUR"), ["total", "3", "50", "eur"]);
+        assert_eq!(
+            words("東京都に行く"),
+            ["東京", "京都", "都に", "に行", "行く"]
+        );
+        assert_eq!(words("OK 한국어"), ["ok", "한국", "국어"]);
+        assert_eq!(words("A4東"), ["a4", "東"]);
+        assert_eq!(words(" - "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn diacritics() {
+        assert_eq!(remove_diacritics("Café Ørsted Łódź"), "cafe orsted lodz");
+        assert_eq!(remove_diacritics("Tiếng Việt"), "tieng viet");
+        // decomposed, with a combining acute accent
+        assert_eq!(remove_diacritics("cafe\u{301}"), "cafe");
+        assert_eq!(remove_diacritics("straße æ ñ"), "straße æ n");
+
+        let mut tokens = vec![];
+        tokenize::<()>("CAFÉ", true, |token, start, end| {
+            tokens.push((token.to_owned(), start, end));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(tokens, [("caf".into(), 0, 3), ("afe".into(), 1, 5)]);
+    }
+}
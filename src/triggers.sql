@@ -0,0 +1,11 @@
+-- the triggers from db_create.sql, used to restore them if they went missing
+CREATE TRIGGER IF NOT EXISTS images_insert AFTER INSERT ON images BEGIN
+    INSERT INTO images_fts (rowid, content) VALUES (new.id, new.content);
+END;
+CREATE TRIGGER IF NOT EXISTS images_delete AFTER DELETE ON images BEGIN
+    INSERT INTO images_fts (images_fts, rowid, content) VALUES ('delete', old.id, old.content);
+END;
+CREATE TRIGGER IF NOT EXISTS images_update AFTER UPDATE ON images BEGIN
+    INSERT INTO images_fts (images_fts, rowid, content) VALUES ('delete', old.id, old.content);
+    INSERT INTO images_fts (rowid, content) VALUES (new.id, new.content);
+END;